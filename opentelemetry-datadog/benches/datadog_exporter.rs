@@ -6,7 +6,7 @@ use std::{
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use http::Request;
 use opentelemetry::{
-    trace::{SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState},
+    trace::{Event, Link, SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState},
     Array, InstrumentationLibrary, KeyValue, Value,
 };
 use opentelemetry_datadog::{new_pipeline, ApiVersion};
@@ -117,7 +117,57 @@ fn get_array_of_booleans(rng: &mut ThreadRng) -> Value {
     ))
 }
 
-fn get_span(trace_id: u128, parent_span_id: u64, span_id: u64, rng: &mut ThreadRng) -> SpanData {
+fn get_events(start_time: SystemTime, rng: &mut ThreadRng) -> SpanEvents {
+    let mut events = SpanEvents::default();
+    events.events.push(Event::new(
+        "cache miss",
+        start_time,
+        vec![
+            KeyValue::new("cache.key", get_http_target(rng)),
+            KeyValue::new("cache.size", get_int_value(rng)),
+            KeyValue::new("cache.hit_ratio", get_float_value(rng)),
+        ],
+        0,
+    ));
+    events.events.push(Event::new(
+        "exception",
+        start_time,
+        vec![
+            KeyValue::new("exception.type", "io::Error"),
+            KeyValue::new("exception.message", "connection reset by peer"),
+            KeyValue::new("exception.retries", get_array_of_ints(rng)),
+        ],
+        0,
+    ));
+    events
+}
+
+fn get_links(trace_id: u128, span_id: u64, rng: &mut ThreadRng) -> SpanLinks {
+    let mut links = SpanLinks::default();
+    links.links.push(Link::new(
+        SpanContext::new(
+            TraceId::from_u128(trace_id + 1),
+            SpanId::from_u64(span_id + 1),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::from_key_value([("dd", "s:1")]).unwrap(),
+        ),
+        vec![
+            KeyValue::new("link.kind", "follows_from"),
+            KeyValue::new("link.weight", get_int_value(rng)),
+            KeyValue::new("link.sampled", get_boolean_value(rng)),
+        ],
+    ));
+    links
+}
+
+fn get_span(
+    trace_id: u128,
+    parent_span_id: u64,
+    span_id: u64,
+    with_events_and_links: bool,
+    rng: &mut ThreadRng,
+) -> SpanData {
     let span_context = SpanContext::new(
         TraceId::from_u128(trace_id),
         SpanId::from_u64(span_id),
@@ -152,8 +202,14 @@ fn get_span(trace_id: u128, parent_span_id: u64, span_id: u64, rng: &mut ThreadR
         KeyValue::new("property_int_array", get_array_of_ints(rng)),
         KeyValue::new("property_float_array", get_array_of_floats(rng)),
     ];
-    let events = SpanEvents::default();
-    let links = SpanLinks::default();
+    let (events, links) = if with_events_and_links {
+        (
+            get_events(start_time, rng),
+            get_links(trace_id, span_id, rng),
+        )
+    } else {
+        (SpanEvents::default(), SpanLinks::default())
+    };
     let resource = Resource::new(vec![KeyValue::new("host.name", "test")]);
 
     SpanData {
@@ -178,14 +234,26 @@ fn get_span(trace_id: u128, parent_span_id: u64, span_id: u64, rng: &mut ThreadR
     }
 }
 
-fn generate_traces(number_of_traces: usize, spans_per_trace: usize) -> Vec<SpanData> {
+fn generate_traces(
+    number_of_traces: usize,
+    spans_per_trace: usize,
+    with_events_and_links: bool,
+) -> Vec<SpanData> {
     let mut rng = thread_rng();
 
     let mut result: Vec<SpanData> = (0..number_of_traces)
         .flat_map(|trace_id| {
             let id = &trace_id;
             (0..spans_per_trace)
-                .map(|span_id| get_span(*id as u128, span_id as u64, span_id as u64, &mut rng))
+                .map(|span_id| {
+                    get_span(
+                        *id as u128,
+                        span_id as u64,
+                        span_id as u64,
+                        with_events_and_links,
+                        &mut rng,
+                    )
+                })
                 .collect::<Vec<_>>()
         })
         .collect();
@@ -206,7 +274,7 @@ fn criterion_benchmark(c: &mut Criterion) {
     let patterns: [(usize, usize); 5] = [(128, 4), (256, 4), (512, 4), (512, 2), (512, 1)];
 
     for (number_of_traces, spans_per_trace) in patterns {
-        let data = generate_traces(number_of_traces, spans_per_trace);
+        let data = generate_traces(number_of_traces, spans_per_trace, false);
         let data_ref = &data;
 
         c.bench_function(
@@ -214,6 +282,13 @@ fn criterion_benchmark(c: &mut Criterion) {
             |b| b.iter(|| exporter.export(black_box(data_ref.clone()))),
         );
     }
+
+    let data = generate_traces(256, 4, true);
+    let data_ref = &data;
+    c.bench_function(
+        "export 256 traces with 4 spans with events and links",
+        |b| b.iter(|| exporter.export(black_box(data_ref.clone()))),
+    );
 }
 
 criterion_group!(benches, criterion_benchmark);
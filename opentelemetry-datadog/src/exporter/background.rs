@@ -0,0 +1,219 @@
+use once_cell::sync::Lazy;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::future::Future;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+/// Timers waited on by [`delay`], all handled by a single thread.
+static TIMER_THREAD: Lazy<Mutex<Sender<Timer>>> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("opentelemetry-datadog-timer".to_string())
+        .spawn(move || run_timers(receiver))
+        .expect("failed to spawn the timer thread");
+    Mutex::new(sender)
+});
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Jobs run by [`blocking_io`], one after the other on a single thread.
+static IO_THREAD: Lazy<Mutex<Sender<Job>>> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    std::thread::Builder::new()
+        .name("opentelemetry-datadog-io".to_string())
+        .spawn(move || {
+            for job in receiver {
+                // a panicking job drops its completer, failing its future instead of the thread
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+        })
+        .expect("failed to spawn the I/O thread");
+    Mutex::new(sender)
+});
+
+/// Wait for `duration` on the timer thread, leaving the executor polling the future free to run
+/// other tasks.
+pub(crate) fn delay(duration: Duration) -> impl Future<Output = ()> {
+    let (completion, completer) = completion();
+    let timer = Timer {
+        deadline: Instant::now() + duration,
+        completer,
+    };
+    let _ = TIMER_THREAD
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .send(timer);
+    async move {
+        completion.await;
+    }
+}
+
+/// Run blocking file system operations on the I/O thread rather than on the executor polling
+/// the returned future.
+pub(crate) fn blocking_io<T, F>(f: F) -> impl Future<Output = io::Result<T>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    let (completion, completer) = completion();
+    let _ = IO_THREAD
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .send(Box::new(move || completer.complete(f())));
+    async move {
+        completion.await.unwrap_or_else(|| {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the I/O thread failed to run the operation",
+            ))
+        })
+    }
+}
+
+fn run_timers(receiver: Receiver<Timer>) {
+    let mut timers = BinaryHeap::new();
+    loop {
+        let received = match timers.peek() {
+            Some(Timer { deadline, .. }) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(timer) => timers.push(timer),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+        let now = Instant::now();
+        while timers.peek().map_or(false, |timer| timer.deadline <= now) {
+            if let Some(timer) = timers.pop() {
+                timer.completer.complete(());
+            }
+        }
+    }
+}
+
+/// A pending [`delay`], ordered so that the heap of timers pops the earliest deadline first.
+struct Timer {
+    deadline: Instant,
+    completer: Completer<()>,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+/// The value a background thread completes, whether it was completed, and the waker of the task
+/// waiting for it.
+type CompletionState<T> = Arc<Mutex<(Option<T>, bool, Option<Waker>)>>;
+
+fn completion<T>() -> (Completion<T>, Completer<T>) {
+    let state = Arc::new(Mutex::new((None, false, None)));
+    (
+        Completion {
+            state: state.clone(),
+        },
+        Completer { state },
+    )
+}
+
+/// Future resolving to the value completed by a background thread, `None` if the thread dropped
+/// its [`Completer`] without completing it.
+struct Completion<T> {
+    state: CompletionState<T>,
+}
+
+impl<T> Future for Completion<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.1 {
+            Poll::Ready(state.0.take())
+        } else {
+            state.2 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+struct Completer<T> {
+    state: CompletionState<T>,
+}
+
+impl<T> Completer<T> {
+    fn complete(self, value: T) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).0 = Some(value);
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.1 = true;
+        if let Some(waker) = state.2.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delays_complete_in_deadline_order() {
+        let completed = Arc::new(Mutex::new(Vec::new()));
+        let delays = [300, 100, 200].map(|millis| {
+            let completed = completed.clone();
+            std::thread::spawn(move || {
+                futures_executor::block_on(delay(Duration::from_millis(millis)));
+                completed.lock().unwrap().push(millis);
+            })
+        });
+        for thread in delays {
+            thread.join().unwrap();
+        }
+        assert_eq!(*completed.lock().unwrap(), vec![100, 200, 300]);
+    }
+
+    #[test]
+    fn test_blocking_io() {
+        let thread = std::thread::current().id();
+        let result =
+            futures_executor::block_on(blocking_io(move || Ok(std::thread::current().id())));
+        assert_ne!(result.unwrap(), thread);
+
+        let result = futures_executor::block_on(blocking_io(|| -> io::Result<()> {
+            panic!("failed operation")
+        }));
+        assert!(result.is_err());
+        // the thread keeps running the next operations
+        assert_eq!(
+            futures_executor::block_on(blocking_io(|| Ok(1))).unwrap(),
+            1
+        );
+    }
+}
@@ -1,25 +1,156 @@
 use indexmap::set::IndexSet;
+use indexmap::Equivalent;
+use opentelemetry::Value;
+use std::collections::hash_map::RandomState;
+use std::fmt::Write;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
 
+/// Dictionary of the strings of a payload.
+///
+/// Interned strings are stored one after the other in a single buffer, and looked up by their
+/// hash, so interning doesn't allocate once the buffer grew large enough. Clearing the interner
+/// keeps its buffers, to be reused for the next payload.
 pub(crate) struct StringInterner {
-    data: IndexSet<String>,
+    arena: String,
+    data: IndexSet<Entry, BuildHasherDefault<PrehashedHasher>>,
+    hasher: RandomState,
+    /// Non string values are formatted here before being interned
+    scratch: String,
+    encoded_size: usize,
+}
+
+/// Position of an interned string in the arena.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    hash: u64,
+    start: usize,
+    end: usize,
+}
+
+impl Hash for Entry {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash)
+    }
+}
+
+/// A string looked up in the interner, compared to the entries having the same hash.
+struct Lookup<'a> {
+    hash: u64,
+    data: &'a str,
+    arena: &'a str,
+}
+
+impl Hash for Lookup<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash)
+    }
+}
+
+impl Equivalent<Entry> for Lookup<'_> {
+    fn equivalent(&self, entry: &Entry) -> bool {
+        self.hash == entry.hash && &self.arena[entry.start..entry.end] == self.data
+    }
+}
+
+/// Entries and lookups carry the hash of their string already.
+#[derive(Default)]
+struct PrehashedHasher(u64);
+
+impl Hasher for PrehashedHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _bytes: &[u8]) {
+        unreachable!("only u64 hashes are written")
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
 }
 
 impl StringInterner {
     pub(crate) fn new() -> StringInterner {
         StringInterner {
+            arena: String::new(),
             data: Default::default(),
+            hasher: RandomState::new(),
+            scratch: String::new(),
+            encoded_size: 0,
         }
     }
 
     pub(crate) fn intern(&mut self, data: &str) -> u32 {
-        if let Some(idx) = self.data.get_index_of(data) {
+        let mut hasher = self.hasher.build_hasher();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+        let lookup = Lookup {
+            hash,
+            data,
+            arena: &self.arena,
+        };
+        if let Some(idx) = self.data.get_index_of(&lookup) {
             return idx as u32;
         }
-        self.data.insert_full(data.to_string()).0 as u32
+
+        self.encoded_size += encoded_str_size(data);
+        let start = self.arena.len();
+        self.arena.push_str(data);
+        let entry = Entry {
+            hash,
+            start,
+            end: self.arena.len(),
+        };
+        self.data.insert_full(entry).0 as u32
+    }
+
+    /// Intern the string representation of `value`, without allocating for non string values.
+    pub(crate) fn intern_value(&mut self, value: &Value) -> u32 {
+        match value {
+            Value::String(data) => self.intern(data.as_str()),
+            value => {
+                let mut scratch = std::mem::take(&mut self.scratch);
+                scratch.clear();
+                let _ = write!(scratch, "{}", value);
+                let idx = self.intern(&scratch);
+                self.scratch = scratch;
+                idx
+            }
+        }
     }
 
-    pub(crate) fn iter(&self) -> impl Iterator<Item = &String> {
-        self.data.iter()
+    /// Forget the strings interned after the first `len` ones.
+    pub(crate) fn truncate(&mut self, len: u32) {
+        let start = match self.data.get_index(len as usize) {
+            Some(entry) => entry.start,
+            None => return,
+        };
+        self.encoded_size -= self
+            .iter()
+            .skip(len as usize)
+            .map(encoded_str_size)
+            .sum::<usize>();
+        self.arena.truncate(start);
+        self.data.truncate(len as usize);
+    }
+
+    /// Forget every string, keeping the buffers for the next payload.
+    pub(crate) fn clear(&mut self) {
+        self.arena.clear();
+        self.data.clear();
+        self.encoded_size = 0;
+    }
+
+    /// Size of the strings once encoded as message pack, without the array header.
+    pub(crate) fn encoded_size(&self) -> usize {
+        self.encoded_size
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &str> {
+        self.data
+            .iter()
+            .map(move |entry| &self.arena[entry.start..entry.end])
     }
 
     pub(crate) fn len(&self) -> u32 {
@@ -27,6 +158,22 @@ impl StringInterner {
     }
 }
 
+impl Default for StringInterner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn encoded_str_size(data: &str) -> usize {
+    let header = match data.len() {
+        0..=31 => 1,
+        32..=0xff => 2,
+        0x100..=0xffff => 3,
+        _ => 5,
+    };
+    header + data.len()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,4 +197,44 @@ mod tests {
         assert_eq!(d_idx, a_idx);
         assert_eq!(e_idx, c_idx);
     }
+
+    #[test]
+    fn test_truncate() {
+        let mut intern = StringInterner::new();
+        intern.intern("a");
+        let size = intern.encoded_size();
+        let len = intern.len();
+        intern.intern(&"b".repeat(40));
+        assert_eq!(intern.encoded_size(), size + 42);
+
+        intern.truncate(len);
+        assert_eq!(intern.len(), 1);
+        assert_eq!(intern.encoded_size(), size);
+        assert_eq!(intern.intern("c"), 1);
+        assert_eq!(intern.iter().collect::<Vec<_>>(), vec!["a", "c"]);
+    }
+
+    #[test]
+    fn test_intern_value() {
+        let mut intern = StringInterner::new();
+        let int_idx = intern.intern_value(&Value::I64(42));
+        let bool_idx = intern.intern_value(&Value::Bool(true));
+
+        assert_eq!(intern.intern("42"), int_idx);
+        assert_eq!(intern.intern_value(&"true".into()), bool_idx);
+        assert_eq!(intern.iter().collect::<Vec<_>>(), vec!["42", "true"]);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut intern = StringInterner::new();
+        intern.intern("a");
+        intern.intern("b");
+
+        intern.clear();
+        assert_eq!(intern.len(), 0);
+        assert_eq!(intern.encoded_size(), 0);
+        assert_eq!(intern.intern("b"), 0);
+        assert_eq!(intern.iter().collect::<Vec<_>>(), vec!["b"]);
+    }
 }
@@ -1,28 +1,66 @@
+mod background;
 mod intern;
-mod model;
+pub(crate) mod model;
+mod proto;
+mod spill;
+mod stats;
+#[cfg(feature = "metrics")]
+mod telemetry;
+mod transport;
+#[cfg(all(unix, feature = "uds"))]
+mod uds;
 
+pub use model::rules::{DatadogField, MappingRuleFn, MappingRules};
 pub use model::ApiVersion;
 pub use model::Error;
 pub use model::FieldMappingFn;
+pub use transport::RetryConfig;
 
-use crate::exporter::model::FieldMapping;
+use crate::exporter::model::{EncoderBuffers, FieldMapping, PayloadCounts};
+use crate::exporter::spill::SpillBuffer;
+use crate::exporter::stats::StatsAggregator;
+#[cfg(feature = "metrics")]
+use crate::exporter::telemetry::ExporterTelemetry;
+use crate::exporter::transport::{runtime_delay, thread_delay, AgentTransport};
+#[cfg(feature = "agent-sampling")]
+use crate::propagator::explicit_sampling_priority;
+#[cfg(feature = "remote-config")]
+use crate::remote_config::{self, RemoteConfigClient};
+#[cfg(feature = "agent-sampling")]
+use crate::DatadogAgentSampler;
+#[cfg(feature = "remote-config")]
+use crate::DatadogRemoteConfig;
 use futures_core::future::BoxFuture;
-use http::{Method, Request, Uri};
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use http::Uri;
 use itertools::Itertools;
-use opentelemetry::{global, trace::TraceError, KeyValue};
-use opentelemetry_http::{HttpClient, ResponseExt};
+#[cfg(feature = "metrics")]
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry::{
+    global,
+    trace::{Status, TraceError},
+    KeyValue,
+};
+use opentelemetry_http::HttpClient;
+#[cfg(not(feature = "agent-sampling"))]
+use opentelemetry_sdk::trace::Sampler;
 use opentelemetry_sdk::{
     export::trace::{ExportResult, SpanData, SpanExporter},
     resource::{ResourceDetector, SdkProvidedResourceDetector},
-    runtime::RuntimeChannel,
+    runtime::{Runtime, RuntimeChannel},
     trace::{Config, Tracer, TracerProvider},
     Resource,
 };
 use opentelemetry_semantic_conventions as semcov;
 use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt::{Debug, Formatter};
-use std::sync::Arc;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+use std::time::{Duration, SystemTime};
 use url::Url;
 
 use self::model::unified_tags::UnifiedTags;
@@ -30,18 +68,32 @@ use self::model::unified_tags::UnifiedTags;
 /// Default Datadog collector endpoint
 const DEFAULT_AGENT_ENDPOINT: &str = "http://127.0.0.1:8126";
 
-/// Header name used to inform the Datadog agent of the number of traces in the payload
-const DATADOG_TRACE_COUNT_HEADER: &str = "X-Datadog-Trace-Count";
+/// Agent host and port used when only one of `DD_AGENT_HOST` and `DD_TRACE_AGENT_PORT` is set
+const DEFAULT_AGENT_HOST: &str = "127.0.0.1";
+const DEFAULT_AGENT_PORT: &str = "8126";
+
+/// Path of the agent endpoint receiving client computed stats
+const STATS_PATH: &str = "/v0.6/stats";
+
+/// Path of the agent endpoint describing its version and features
+const INFO_PATH: &str = "/info";
+
+/// Datadog site used in agentless mode when neither configured nor set in `DD_SITE`
+const DEFAULT_SITE: &str = "datadoghq.com";
 
-/// Header name use to inform datadog as to what version
-const DATADOG_META_LANG_HEADER: &str = "Datadog-Meta-Lang";
-const DATADOG_META_TRACER_VERSION_HEADER: &str = "Datadog-Meta-Tracer-Version";
+/// Longest time shutdown waits for the last stats to be sent
+const STATS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Paths of the Datadog intake endpoints receiving traces and stats in agentless mode
+const INTAKE_TRACES_PATH: &str = "/api/v0.2/traces";
+const INTAKE_STATS_PATH: &str = "/api/v0.2/stats";
 
 // Struct to hold the mapping between Opentelemetry spans and datadog spans.
 pub struct Mapping {
     resource: Option<FieldMapping>,
     name: Option<FieldMapping>,
     service_name: Option<FieldMapping>,
+    rules: MappingRules,
 }
 
 impl Mapping {
@@ -54,6 +106,7 @@ impl Mapping {
             resource,
             name,
             service_name,
+            rules: MappingRules::empty(),
         }
     }
     pub fn empty() -> Self {
@@ -61,58 +114,223 @@ impl Mapping {
     }
 }
 
+type SpawnFn = Arc<dyn Fn(BoxFuture<'static, ()>) + Send + Sync>;
+
 /// Datadog span exporter
 pub struct DatadogExporter {
-    client: Arc<dyn HttpClient>,
-    request_url: Uri,
+    transport: AgentTransport,
     model_config: ModelConfig,
     api_version: ApiVersion,
     mapping: Mapping,
     unified_tags: UnifiedTags,
+    /// Shared with the task flushing closed buckets when installed on a runtime
+    stats: Option<Arc<Mutex<StatsAggregator>>>,
+    /// Reused from one export to the next
+    encoder_buffers: EncoderBuffers,
+    enabled: bool,
+    /// Spawns tasks on the runtime the exporter is installed on, if any
+    spawn: Option<SpawnFn>,
+    /// Shared with the task polling the agent when installed on a runtime
+    #[cfg(feature = "remote-config")]
+    remote_config: Option<Arc<RemoteConfigClient>>,
+    /// Whether a runtime task polls the agent, rather than exports
+    #[cfg(feature = "remote-config")]
+    remote_config_task: bool,
+    /// Tags configured locally, replaced by the remote configuration ones when set
+    #[cfg(feature = "remote-config")]
+    local_tags: Vec<(String, String)>,
 }
 
 impl DatadogExporter {
     fn new(
         model_config: ModelConfig,
-        request_url: Uri,
-        api_version: ApiVersion,
-        client: Arc<dyn HttpClient>,
+        transport: AgentTransport,
         mapping: Mapping,
         unified_tags: UnifiedTags,
+        stats: Option<StatsAggregator>,
     ) -> Self {
         DatadogExporter {
-            client,
-            request_url,
+            api_version: transport.api_version,
+            transport,
             model_config,
-            api_version,
             mapping,
             unified_tags,
+            stats: stats.map(|stats| Arc::new(Mutex::new(stats))),
+            encoder_buffers: EncoderBuffers::default(),
+            enabled: true,
+            spawn: None,
+            #[cfg(feature = "remote-config")]
+            remote_config: None,
+            #[cfg(feature = "remote-config")]
+            remote_config_task: false,
+            #[cfg(feature = "remote-config")]
+            local_tags: Vec::new(),
+        }
+    }
+
+    /// Apply the tags of the remote configuration, then return the client to poll the agent with
+    /// if a poll is due and no runtime task polls it.
+    #[cfg(feature = "remote-config")]
+    fn update_remote_config(&mut self) -> Option<RemoteConfigClient> {
+        let remote_config = self.remote_config.as_ref()?;
+        self.unified_tags.tags = remote_config
+            .tags()
+            .unwrap_or_else(|| self.local_tags.clone());
+        (!self.remote_config_task && remote_config.poll_due())
+            .then(|| RemoteConfigClient::clone(remote_config))
+    }
+
+    /// Poll the agent for remote configuration every poll interval, independently of exports.
+    /// The task stops once the exporter is dropped.
+    #[cfg(feature = "remote-config")]
+    fn spawn_remote_config_poll<R: Runtime>(&mut self, runtime: &R) {
+        let (remote_config, poll_interval) = match &self.remote_config {
+            Some(remote_config) => (Arc::downgrade(remote_config), remote_config.poll_interval()),
+            None => return,
+        };
+        self.remote_config_task = true;
+        let mut ticks = Box::pin(runtime.interval(poll_interval));
+        runtime.spawn(Box::pin(async move {
+            while ticks.next().await.is_some() {
+                match remote_config.upgrade() {
+                    Some(remote_config) => RemoteConfigClient::clone(&remote_config).poll().await,
+                    None => break,
+                }
+            }
+        }));
+    }
+
+    /// Aggregate the batch into the stats, then drop the spans the agent would only use for
+    /// stats if it allows it, i.e. traces rejected by priority sampling without errors. Returns
+    /// the stats payload if a bucket is over.
+    fn compute_stats(&mut self, batch: &mut Vec<SpanData>) -> Option<Vec<u8>> {
+        let stats = self.stats.as_ref()?;
+        {
+            let mut stats = stats.lock().unwrap_or_else(PoisonError::into_inner);
+            for span in batch.iter() {
+                stats.add(span, &self.mapping, &self.model_config);
+            }
+        }
+        if self.transport.agent_info.client_drop_p0s() {
+            let kept_traces = batch
+                .iter()
+                .filter(|span| {
+                    !rejected_by_priority(span) || matches!(span.status, Status::Error { .. })
+                })
+                .map(|span| span.span_context.trace_id())
+                .collect::<HashSet<_>>();
+            batch.retain(|span| kept_traces.contains(&span.span_context.trace_id()));
         }
+
+        flush_stats(stats, Some(SystemTime::now()))
+    }
+
+    /// Send the stats buckets once their window is over every [`stats::BUCKET_DURATION`], even
+    /// when no span is exported. The task stops once the exporter is dropped.
+    fn spawn_stats_flush<R: Runtime>(&self, runtime: &R) {
+        let stats = match &self.stats {
+            Some(stats) => Arc::downgrade(stats),
+            None => return,
+        };
+        let transport = self.transport.clone();
+        let mut ticks = Box::pin(runtime.interval(stats::BUCKET_DURATION));
+        runtime.spawn(Box::pin(async move {
+            transport.fetch_info().await;
+            while ticks.next().await.is_some() {
+                let payload = match stats.upgrade() {
+                    Some(stats) => flush_stats(&stats, Some(SystemTime::now())),
+                    None => break,
+                };
+                if let Some(payload) = payload {
+                    transport.send_stats(payload).await;
+                }
+            }
+        }));
     }
 
-    fn build_request(&self, batch: Vec<SpanData>) -> Result<http::Request<Vec<u8>>, TraceError> {
+    /// Encode the batch into the payloads to send, along with their trace and span counts.
+    fn encode_batch(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Result<Vec<(PayloadCounts, Vec<u8>)>, TraceError> {
         let traces: Vec<Vec<SpanData>> = group_into_traces(batch);
-        let trace_count = traces.len();
-        let data = self.api_version.encode(
+        if self.transport.api_key.is_some() {
+            return Ok(model::agentless::encode(
+                &self.model_config,
+                traces,
+                &self.mapping,
+                &self.unified_tags,
+                model::agentless::MAX_PAYLOAD_SIZE,
+            )?);
+        }
+
+        Ok(self.api_version.encode(
+            &mut self.encoder_buffers,
             &self.model_config,
             traces,
             &self.mapping,
             &self.unified_tags,
-        )?;
-        let req = Request::builder()
-            .method(Method::POST)
-            .uri(self.request_url.clone())
-            .header(http::header::CONTENT_TYPE, self.api_version.content_type())
-            .header(DATADOG_TRACE_COUNT_HEADER, trace_count)
-            .header(DATADOG_META_LANG_HEADER, "rust")
-            .header(
-                DATADOG_META_TRACER_VERSION_HEADER,
-                env!("CARGO_PKG_VERSION"),
-            )
-            .body(data)
-            .map_err::<Error, _>(Into::into)?;
+            model::MAX_PAYLOAD_SIZE,
+        )?)
+    }
 
-        Ok(req)
+    /// Record the encoded payloads, and the traces dropped for being too large or because the
+    /// batch couldn't be encoded.
+    #[cfg(feature = "metrics")]
+    fn record_encoded(
+        &self,
+        batch: PayloadCounts,
+        payloads: &Result<Vec<(PayloadCounts, Vec<u8>)>, TraceError>,
+    ) {
+        let telemetry = match &self.transport.telemetry {
+            Some(telemetry) => telemetry,
+            None => return,
+        };
+        match payloads {
+            Ok(payloads) => {
+                let mut dropped = batch;
+                for (counts, data) in payloads {
+                    telemetry.record_encoded(data.len());
+                    dropped.traces -= counts.traces;
+                    dropped.spans -= counts.spans;
+                }
+                telemetry.record_dropped(dropped);
+            }
+            Err(_) => {
+                telemetry.record_encode_error();
+                telemetry.record_dropped(batch);
+            }
+        }
+    }
+}
+
+/// Whether a Datadog sampler rejected the trace of the span, with the `AUTO_REJECT` or
+/// `USER_REJECT` priority. Spans without a sampling priority were never rejected.
+#[cfg(feature = "agent-sampling")]
+fn rejected_by_priority(span: &SpanData) -> bool {
+    explicit_sampling_priority(span.span_context.trace_state())
+        .map_or(false, |priority| priority <= 0)
+}
+
+#[cfg(not(feature = "agent-sampling"))]
+fn rejected_by_priority(_span: &SpanData) -> bool {
+    false
+}
+
+/// Encode and remove the stats buckets whose window ended before `now`, or all of them if `now`
+/// is `None`.
+fn flush_stats(stats: &Mutex<StatsAggregator>, now: Option<SystemTime>) -> Option<Vec<u8>> {
+    let mut stats = stats.lock().unwrap_or_else(PoisonError::into_inner);
+    let flushed = match now {
+        Some(now) => stats.flush(now),
+        None => stats.flush_all(),
+    };
+    match flushed {
+        Ok(payload) => payload,
+        Err(err) => {
+            global::handle_error(TraceError::from(err));
+            None
+        }
     }
 }
 
@@ -120,15 +338,17 @@ impl Debug for DatadogExporter {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("DatadogExporter")
             .field("model_config", &self.model_config)
-            .field("request_url", &self.request_url)
+            .field("request_url", &self.transport.request_url)
             .field("api_version", &self.api_version)
-            .field("client", &self.client)
+            .field("client", &self.transport.client)
+            .field("retry_config", &self.transport.retry_config)
             .field("resource_mapping", &mapping_debug(&self.mapping.resource))
             .field("name_mapping", &mapping_debug(&self.mapping.name))
             .field(
                 "service_name_mapping",
                 &mapping_debug(&self.mapping.service_name),
             )
+            .field("mapping_rules", &self.mapping.rules)
             .finish()
     }
 }
@@ -146,6 +366,19 @@ pub struct DatadogPipelineBuilder {
     client: Option<Arc<dyn HttpClient>>,
     mapping: Mapping,
     unified_tags: UnifiedTags,
+    retry_config: RetryConfig,
+    spill_directory: Option<(PathBuf, u64)>,
+    client_side_stats: bool,
+    api_key: Option<String>,
+    site: Option<String>,
+    intake_endpoint: Option<String>,
+    enabled: bool,
+    #[cfg(feature = "agent-sampling")]
+    agent_sampler: Option<DatadogAgentSampler>,
+    #[cfg(feature = "remote-config")]
+    remote_config: Option<DatadogRemoteConfig>,
+    #[cfg(feature = "metrics")]
+    telemetry: Option<ExporterTelemetry>,
 }
 
 impl Default for DatadogPipelineBuilder {
@@ -156,6 +389,19 @@ impl Default for DatadogPipelineBuilder {
             mapping: Mapping::empty(),
             api_version: ApiVersion::Version05,
             unified_tags: UnifiedTags::new(),
+            retry_config: RetryConfig::disabled(),
+            spill_directory: None,
+            client_side_stats: false,
+            api_key: None,
+            site: None,
+            intake_endpoint: None,
+            enabled: true,
+            #[cfg(feature = "agent-sampling")]
+            agent_sampler: None,
+            #[cfg(feature = "remote-config")]
+            remote_config: None,
+            #[cfg(feature = "metrics")]
+            telemetry: None,
             #[cfg(all(
                 not(feature = "reqwest-client"),
                 not(feature = "reqwest-blocking-client"),
@@ -186,17 +432,104 @@ impl Debug for DatadogPipelineBuilder {
             .field("agent_endpoint", &self.agent_endpoint)
             .field("trace_config", &self.trace_config)
             .field("client", &self.client)
+            .field("retry_config", &self.retry_config)
+            .field("spill_directory", &self.spill_directory)
             .field("resource_mapping", &mapping_debug(&self.mapping.resource))
             .field("name_mapping", &mapping_debug(&self.mapping.name))
             .field(
                 "service_name_mapping",
                 &mapping_debug(&self.mapping.service_name),
             )
+            .field("mapping_rules", &self.mapping.rules)
             .finish()
     }
 }
 
 impl DatadogPipelineBuilder {
+    /// Create a pipeline builder configured from the `DD_*` environment variables used by the
+    /// other Datadog tracing libraries.
+    ///
+    /// On top of `DD_SERVICE`, `DD_ENV` and `DD_VERSION`, which are always honored, it reads:
+    /// - `DD_TRACE_AGENT_URL`, the agent endpoint.
+    /// - `DD_AGENT_HOST` and `DD_TRACE_AGENT_PORT`, the agent host and port when
+    ///   `DD_TRACE_AGENT_URL` isn't set. When neither is set and the `uds` feature is enabled,
+    ///   the agent is reached through `/var/run/datadog/apm.socket` if that socket exists.
+    /// - `DD_TAGS`, tags added to every span, as `key:value` pairs separated by commas or spaces.
+    /// - `DD_TRACE_SAMPLE_RATE`, the ratio of traces to sample, between `0.0` and `1.0`. With the
+    ///   `agent-sampling` feature, it and `DD_TRACE_SAMPLING_RULES` configure a
+    ///   `DatadogRuleSampler`, see `DatadogRuleSampler::from_env`, so that the rejected traces
+    ///   still reach the agent with a `USER_REJECT` priority and count in the trace metrics.
+    /// - `DD_TRACE_ENABLED`, set to `false` to stop exporting spans.
+    ///
+    /// Settings configured on the builder afterwards take precedence, e.g. the sampler is
+    /// replaced by the one of [`with_trace_config`].
+    ///
+    /// [`with_trace_config`]: DatadogPipelineBuilder::with_trace_config
+    pub fn from_env() -> Self {
+        Self::from_env_vars(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    fn from_env_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut builder = Self::default();
+
+        if let Some(url) = var("DD_TRACE_AGENT_URL") {
+            builder.agent_endpoint = url;
+        } else {
+            let host = var("DD_AGENT_HOST");
+            let port = var("DD_TRACE_AGENT_PORT");
+            if host.is_some() || port.is_some() {
+                let host = host.unwrap_or_else(|| DEFAULT_AGENT_HOST.to_string());
+                let port = port.unwrap_or_else(|| DEFAULT_AGENT_PORT.to_string());
+                builder.agent_endpoint = if host.contains(':') {
+                    format!("http://[{host}]:{port}")
+                } else {
+                    format!("http://{host}:{port}")
+                };
+            } else {
+                #[cfg(all(unix, feature = "uds"))]
+                if std::path::Path::new(uds::DEFAULT_SOCKET_PATH).exists() {
+                    builder.agent_endpoint =
+                        format!("{}://{}", uds::UNIX_SCHEME, uds::DEFAULT_SOCKET_PATH);
+                }
+            }
+        }
+
+        if let Some(tags) = var("DD_TAGS") {
+            for (key, value) in parse_tags(&tags) {
+                builder.unified_tags.add_tag(key, value);
+            }
+        }
+
+        #[cfg(feature = "agent-sampling")]
+        if var("DD_TRACE_SAMPLE_RATE").is_some() || var("DD_TRACE_SAMPLING_RULES").is_some() {
+            builder.trace_config = Some(
+                Config::default().with_sampler(crate::DatadogRuleSampler::from_env_vars(&var)),
+            );
+        }
+        #[cfg(not(feature = "agent-sampling"))]
+        if let Some(rate) = var("DD_TRACE_SAMPLE_RATE") {
+            match rate.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => {
+                    builder.trace_config = Some(Config::default().with_sampler(
+                        Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(rate))),
+                    ));
+                }
+                _ => global::handle_error(TraceError::Other(
+                    format!(
+                        "invalid DD_TRACE_SAMPLE_RATE {rate:?}, expected a number between 0 and 1"
+                    )
+                    .into(),
+                )),
+            }
+        }
+
+        if let Some(enabled) = var("DD_TRACE_ENABLED") {
+            builder.enabled = !matches!(enabled.to_lowercase().as_str(), "false" | "0");
+        }
+
+        builder
+    }
+
     /// Building a new exporter.
     ///
     /// This is useful if you are manually constructing a pipeline.
@@ -229,15 +562,49 @@ impl DatadogPipelineBuilder {
                 .get(semcov::resource::SERVICE_NAME.into())
                 .unwrap()
                 .to_string();
-            (
-                Config {
-                    // use a empty resource to prevent TracerProvider to assign a service name.
-                    resource: Cow::Owned(Resource::empty()),
-                    ..Default::default()
-                },
-                service_name,
-            )
+            let mut config = self.trace_config.take().unwrap_or_default();
+            // use a empty resource to prevent TracerProvider to assign a service name.
+            config.resource = Cow::Owned(Resource::empty());
+            (config, service_name)
+        }
+    }
+
+    /// The client sending requests, the built-in Unix domain socket client when the agent
+    /// endpoint is a `unix://` URL.
+    fn agent_client(&self) -> Option<Arc<dyn HttpClient>> {
+        #[cfg(all(unix, feature = "uds"))]
+        if self.api_key.is_none() {
+            if let Some(path) = uds::socket_path(&self.agent_endpoint) {
+                return Some(Arc::new(uds::UnixSocketClient::new(path)));
+            }
+        }
+        self.client.clone()
+    }
+
+    /// The URL agent request paths are appended to. Requests sent through a Unix domain socket
+    /// only use the path of the URL.
+    fn agent_base_url(&self) -> &str {
+        #[cfg(all(unix, feature = "uds"))]
+        if uds::socket_path(&self.agent_endpoint).is_some() {
+            return uds::SOCKET_BASE_URL;
         }
+        &self.agent_endpoint
+    }
+
+    fn intake_endpoint(&self) -> String {
+        if let Some(endpoint) = &self.intake_endpoint {
+            return endpoint.clone();
+        }
+        let site = self
+            .site
+            .clone()
+            .or_else(|| {
+                std::env::var("DD_SITE")
+                    .ok()
+                    .filter(|site| !site.is_empty())
+            })
+            .unwrap_or_else(|| DEFAULT_SITE.to_string());
+        format!("https://trace.agent.{site}")
     }
 
     // parse the endpoint and append the path based on versions.
@@ -263,17 +630,104 @@ impl DatadogPipelineBuilder {
         self,
         service_name: String,
     ) -> Result<DatadogExporter, TraceError> {
-        if let Some(client) = self.client {
+        #[cfg(not(all(unix, feature = "uds")))]
+        if self.api_key.is_none() && self.agent_endpoint.starts_with("unix://") {
+            return Err(Error::Other(
+                "unix:// agent endpoints require the `uds` feature on Unix".to_string(),
+            )
+            .into());
+        }
+        if let Some(client) = self.agent_client() {
             let model_config = ModelConfig { service_name };
 
-            let exporter = DatadogExporter::new(
-                model_config,
-                Self::build_endpoint(&self.agent_endpoint, self.api_version.path())?,
-                self.api_version,
+            let (request_url, stats_url, info_url, spill_extension) = match &self.api_key {
+                Some(_) => {
+                    let intake_endpoint = self.intake_endpoint();
+                    (
+                        Self::build_endpoint(&intake_endpoint, INTAKE_TRACES_PATH)?,
+                        Self::build_endpoint(&intake_endpoint, INTAKE_STATS_PATH)?,
+                        None,
+                        "agentless",
+                    )
+                }
+                None => {
+                    let agent_endpoint = self.agent_base_url();
+                    (
+                        Self::build_endpoint(agent_endpoint, self.api_version.path())?,
+                        Self::build_endpoint(agent_endpoint, STATS_PATH)?,
+                        Some(Self::build_endpoint(agent_endpoint, INFO_PATH)?),
+                        self.api_version.spill_extension(),
+                    )
+                }
+            };
+            #[cfg(feature = "remote-config")]
+            let config_url =
+                Self::build_endpoint(self.agent_base_url(), remote_config::CONFIG_PATH)?;
+            let spill = match self.spill_directory {
+                Some((directory, max_bytes)) => Some(Arc::new(
+                    SpillBuffer::new(directory, max_bytes, spill_extension)
+                        .map_err(Error::SpillError)?,
+                )),
+                None => None,
+            };
+            #[cfg(feature = "agent-sampling")]
+            if let Some(sampler) = &self.agent_sampler {
+                sampler.set_service(
+                    &model_config.service_name,
+                    self.unified_tags.env.value.as_deref(),
+                );
+            }
+            let transport = AgentTransport {
                 client,
+                request_url,
+                api_version: self.api_version,
+                retry_config: self.retry_config,
+                delay: thread_delay(),
+                spill,
+                stats_url: self.client_side_stats.then_some(stats_url),
+                info_url: info_url.filter(|_| self.client_side_stats),
+                agent_info: Arc::default(),
+                api_key: self.api_key,
+                #[cfg(feature = "agent-sampling")]
+                agent_sampler: self.agent_sampler,
+                #[cfg(feature = "metrics")]
+                telemetry: self.telemetry,
+            };
+            let stats = self.client_side_stats.then(|| {
+                let stats =
+                    StatsAggregator::new(model_config.service_name.clone(), &self.unified_tags);
+                match &transport.api_key {
+                    Some(_) => stats.for_intake(),
+                    None => stats,
+                }
+            });
+            #[cfg(feature = "remote-config")]
+            let remote_config = match (self.remote_config, &transport.api_key) {
+                (Some(config), None) => Some(RemoteConfigClient::new(
+                    config,
+                    transport.client.clone(),
+                    config_url,
+                    model_config.service_name.clone(),
+                    &self.unified_tags,
+                )),
+                _ => None,
+            };
+            #[cfg(feature = "remote-config")]
+            let local_tags = self.unified_tags.tags.clone();
+
+            let mut exporter = DatadogExporter::new(
+                model_config,
+                transport,
                 self.mapping,
                 self.unified_tags,
+                stats,
             );
+            exporter.enabled = self.enabled;
+            #[cfg(feature = "remote-config")]
+            {
+                exporter.remote_config = remote_config.map(Arc::new);
+                exporter.local_tags = local_tags;
+            }
             Ok(exporter)
         } else {
             Err(Error::NoHttpClient.into())
@@ -302,7 +756,13 @@ impl DatadogPipelineBuilder {
     /// runtime.
     pub fn install_batch<R: RuntimeChannel>(mut self, runtime: R) -> Result<Tracer, TraceError> {
         let (config, service_name) = self.build_config_and_service_name();
-        let exporter = self.build_exporter_with_service_name(service_name)?;
+        let mut exporter = self.build_exporter_with_service_name(service_name)?;
+        exporter.transport.delay = runtime_delay(runtime.clone());
+        exporter.spawn_stats_flush(&runtime);
+        let spawn_runtime = runtime.clone();
+        exporter.spawn = Some(Arc::new(move |future| spawn_runtime.spawn(future)));
+        #[cfg(feature = "remote-config")]
+        exporter.spawn_remote_config_poll(&runtime);
         let mut provider_builder = TracerProvider::builder().with_batch_exporter(exporter, runtime);
         provider_builder = provider_builder.with_config(config);
         let provider = provider_builder.build();
@@ -337,7 +797,14 @@ impl DatadogPipelineBuilder {
 
     /// Assign the Datadog collector endpoint.
     ///
-    /// The endpoint of the datadog agent, by default it is `http://127.0.0.1:8126`.
+    /// The endpoint of the datadog agent, by default it is `http://127.0.0.1:8126`. See
+    /// [`from_env`] to follow the endpoint configured for other Datadog tracing libraries.
+    ///
+    /// On Unix and with the `uds` feature, `unix:///path/to/socket` endpoints are reached through
+    /// the Unix domain socket with a built-in client, regardless of the configured HTTP client.
+    /// That client runs on Tokio, exports have to run on a Tokio runtime.
+    ///
+    /// [`from_env`]: DatadogPipelineBuilder::from_env
     pub fn with_agent_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
         self.agent_endpoint = endpoint.into();
         self
@@ -355,6 +822,132 @@ impl DatadogPipelineBuilder {
         self
     }
 
+    /// Retry requests the agent failed to accept, see [`RetryConfig`] for details.
+    ///
+    /// When installed with [`install_batch`], the backoff between retries waits on the given
+    /// runtime. Otherwise it waits on a single timer thread shared by the exporters, without
+    /// blocking the exporting one.
+    ///
+    /// [`install_batch`]: DatadogPipelineBuilder::install_batch
+    pub fn with_retry_config(mut self, retry_config: RetryConfig) -> Self {
+        self.retry_config = retry_config;
+        self
+    }
+
+    /// Keep payloads the agent failed to accept in `directory` and send them again once the
+    /// agent accepts a request.
+    ///
+    /// Payloads are only spilled after all retries failed with a retryable error. At most
+    /// `max_bytes` of payloads are kept, the oldest ones are dropped first. Each accepted request
+    /// replays a few of them, oldest first, so a large backlog doesn't delay a single export.
+    ///
+    /// The directory is read and written from a dedicated thread, so that exports don't block
+    /// the executor polling them on file system operations.
+    pub fn with_spill_directory<P: Into<PathBuf>>(mut self, directory: P, max_bytes: u64) -> Self {
+        self.spill_directory = Some((directory.into(), max_bytes));
+        self
+    }
+
+    /// Send traces directly to the Datadog intake instead of an agent, authenticated with
+    /// `api_key`.
+    ///
+    /// The intake is selected by the Datadog site, see [`with_site`]. Payloads are gzip compressed
+    /// protobuf, split along trace boundaries to stay under the intake size limit. The configured
+    /// API version and agent endpoint are ignored in this mode.
+    ///
+    /// [`with_site`]: DatadogPipelineBuilder::with_site
+    pub fn with_agentless<T: Into<String>>(mut self, api_key: T) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Set the Datadog site traces are sent to in agentless mode, e.g. `datadoghq.eu`.
+    ///
+    /// Defaults to the `DD_SITE` environment variable, or `datadoghq.com` if it isn't set.
+    pub fn with_site<T: Into<String>>(mut self, site: T) -> Self {
+        self.site = Some(site.into());
+        self
+    }
+
+    /// Override the intake URL derived from the site in agentless mode, e.g. to go through a
+    /// proxy.
+    pub fn with_intake_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.intake_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Compute trace metrics (hits, errors and latency distributions) in the exporter and send
+    /// them to the agent's `/v0.6/stats` endpoint, instead of letting the agent compute them from
+    /// the spans it receives.
+    ///
+    /// Stats account for every span reaching the exporter, so spans should be sampled by
+    /// priority, e.g. with the `DatadogAgentSampler`, rather than dropped by the SDK sampler.
+    /// Once the agent reports on its `/info` endpoint that it allows it, traces rejected with the
+    /// `AUTO_REJECT` or `USER_REJECT` priority are only used for stats and not sent to the agent,
+    /// unless they contain errors. The agent is asked along with the first export, or from a task
+    /// of the runtime when installed with [`install_batch`].
+    ///
+    /// Stats are sent once their 10 seconds window is over, along with the next export and, when
+    /// installed with [`install_batch`], from a task of the runtime even while no span is
+    /// exported. The stats of the current window are sent on shutdown and force flush.
+    ///
+    /// [`install_batch`]: DatadogPipelineBuilder::install_batch
+    pub fn with_client_side_stats(mut self) -> Self {
+        self.client_side_stats = true;
+        self
+    }
+
+    /// Feed the sampling rates returned by the agent to `sampler`.
+    ///
+    /// The sampler still has to be set on the trace config, see [`DatadogAgentSampler`].
+    #[cfg(feature = "agent-sampling")]
+    pub fn with_agent_sampler(mut self, sampler: DatadogAgentSampler) -> Self {
+        self.agent_sampler = Some(sampler);
+        self
+    }
+
+    /// Apply the settings of Datadog remote configuration at runtime, polling the agent from a
+    /// task of the runtime when installed with [`install_batch`], along with exports otherwise.
+    /// See [`DatadogRemoteConfig`].
+    ///
+    /// [`install_batch`]: DatadogPipelineBuilder::install_batch
+    #[cfg(feature = "remote-config")]
+    pub fn with_remote_config(mut self, remote_config: DatadogRemoteConfig) -> Self {
+        self.remote_config = Some(remote_config);
+        self
+    }
+
+    /// Record the health of the exporter as metrics of `meter_provider`: the spans and traces
+    /// sent, spilled or dropped, the size of the encoded and sent payloads, encoding errors, the
+    /// requests by response status and the time spent sending payloads.
+    ///
+    /// |metric|description|
+    /// |------|-----------|
+    /// |`datadog.exporter.spans`|spans exported, by `outcome`: `sent`, `spilled` or `dropped`|
+    /// |`datadog.exporter.traces`|traces exported, by `outcome`: `sent`, `spilled` or `dropped`|
+    /// |`datadog.exporter.encoded.bytes`|size of the encoded payloads|
+    /// |`datadog.exporter.sent.bytes`|size of the payloads accepted by Datadog, replayed ones included|
+    /// |`datadog.exporter.encode.errors`|batches which couldn't be encoded|
+    /// |`datadog.exporter.requests`|requests sent, replays included, by `http.response.status_code`, or `error.type` when no response was received|
+    /// |`datadog.exporter.send.duration`|time spent sending a payload in seconds, retries included|
+    ///
+    /// Spans and traces are dropped when they can't be encoded, are larger than the payload
+    /// size limit, or when their payload isn't accepted and can't be spilled. Payloads written
+    /// to the spill directory are counted as `spilled`, then as `sent` once replayed, or
+    /// `dropped` when the agent rejects them for good or they are evicted to make room for newer
+    /// ones.
+    #[cfg(feature = "metrics")]
+    pub fn with_meter_provider<M: MeterProvider>(mut self, meter_provider: &M) -> Self {
+        self.telemetry = Some(ExporterTelemetry::new(meter_provider));
+        self
+    }
+
+    /// Add a tag to every span, the same as the tags set in `DD_TAGS`.
+    pub fn with_tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.unified_tags.add_tag(key.into(), value.into());
+        self
+    }
+
     /// Set version of Datadog trace ingestion API
     pub fn with_api_version(mut self, api_version: ApiVersion) -> Self {
         self.api_version = api_version;
@@ -390,6 +983,30 @@ impl DatadogPipelineBuilder {
         self.mapping.service_name = Some(Arc::new(f));
         self
     }
+
+    /// Derive the `service`, `name`, `resource` and `type` fields of datadog spans from
+    /// OpenTelemetry semantic conventions, see [`MappingRules`] for details.
+    ///
+    /// Custom mappings set with [`with_resource_mapping`](Self::with_resource_mapping),
+    /// [`with_name_mapping`](Self::with_name_mapping) and
+    /// [`with_service_name_mapping`](Self::with_service_name_mapping) take precedence over the
+    /// rules.
+    pub fn with_mapping_rules(mut self, rules: MappingRules) -> Self {
+        self.mapping.rules = rules;
+        self
+    }
+}
+
+/// Parse `DD_TAGS`, `key:value` pairs separated by commas or spaces. Tags without a value are
+/// kept with an empty value.
+fn parse_tags(tags: &str) -> Vec<(String, String)> {
+    tags.split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|tag| {
+            let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+            let key = key.trim();
+            (!key.is_empty()).then(|| (key.to_string(), value.trim().to_string()))
+        })
+        .collect()
 }
 
 fn group_into_traces(spans: Vec<SpanData>) -> Vec<Vec<SpanData>> {
@@ -400,24 +1017,107 @@ fn group_into_traces(spans: Vec<SpanData>) -> Vec<Vec<SpanData>> {
         .collect()
 }
 
-async fn send_request(
-    client: Arc<dyn HttpClient>,
-    request: http::Request<Vec<u8>>,
-) -> ExportResult {
-    let _ = client.send(request).await?.error_for_status()?;
-    Ok(())
-}
-
 impl SpanExporter for DatadogExporter {
     /// Export spans to datadog-agent
-    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
-        let request = match self.build_request(batch) {
-            Ok(req) => req,
-            Err(err) => return Box::pin(std::future::ready(Err(err))),
+    fn export(&mut self, mut batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        if !self.enabled {
+            return Box::pin(std::future::ready(Ok(())));
+        }
+
+        #[cfg(feature = "remote-config")]
+        let remote_config = self.update_remote_config();
+        let stats = self.compute_stats(&mut batch);
+        let payloads = if batch.is_empty() {
+            Vec::new()
+        } else {
+            #[cfg(feature = "metrics")]
+            let batch_counts = PayloadCounts {
+                traces: batch
+                    .iter()
+                    .map(|span| span.span_context.trace_id())
+                    .unique()
+                    .count(),
+                spans: batch.len(),
+            };
+            let payloads = self.encode_batch(batch);
+            #[cfg(feature = "metrics")]
+            self.record_encoded(batch_counts, &payloads);
+            match payloads {
+                Ok(payloads) => payloads,
+                Err(err) => return Box::pin(std::future::ready(Err(err))),
+            }
         };
 
-        let client = self.client.clone();
-        Box::pin(send_request(client, request))
+        let transport = self.transport.clone();
+        let send = async move {
+            transport.fetch_info().await;
+            if let Some(stats) = stats {
+                transport.send_stats(stats).await;
+            }
+            let mut result = Ok(());
+            for (counts, data) in payloads {
+                #[cfg(feature = "metrics")]
+                let start = Instant::now();
+                let sent = transport.clone().send(counts, data).await;
+                #[cfg(feature = "metrics")]
+                if let Some(telemetry) = &transport.telemetry {
+                    telemetry.record_send_duration(start.elapsed());
+                }
+                if let Err(err) = sent {
+                    result = Err(err);
+                }
+            }
+            result
+        };
+        #[cfg(feature = "remote-config")]
+        if let Some(remote_config) = remote_config {
+            // poll concurrently rather than delaying the payloads
+            return Box::pin(async move { future::join(remote_config.poll(), send).await.1 });
+        }
+        Box::pin(send)
+    }
+
+    /// Send the stats of every window, including the current one, from a task of the runtime
+    /// when installed with [`install_batch`], or blocking for at most 5 seconds otherwise.
+    ///
+    /// The batch span processor shuts the exporter down from a task of its runtime, where
+    /// blocking would stall the I/O of the send, e.g. on a current thread runtime.
+    ///
+    /// [`install_batch`]: DatadogPipelineBuilder::install_batch
+    fn shutdown(&mut self) {
+        let payload = match self.stats.take() {
+            Some(stats) => flush_stats(&stats, None),
+            None => None,
+        };
+        if let Some(payload) = payload {
+            let transport = self.transport.clone();
+            let send = Box::pin(async move { transport.send_stats(payload).await });
+            if let Some(spawn) = &self.spawn {
+                spawn(send);
+                return;
+            }
+            let timeout = thread_delay()(STATS_SHUTDOWN_TIMEOUT);
+            if let Either::Right(_) = futures_executor::block_on(future::select(send, timeout)) {
+                global::handle_error(TraceError::Other(
+                    "timed out sending the last stats on shutdown".into(),
+                ));
+            }
+        }
+    }
+
+    /// Send the stats of every window, including the current one.
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        let payload = self
+            .stats
+            .as_ref()
+            .and_then(|stats| flush_stats(stats, None));
+        let transport = self.transport.clone();
+        Box::pin(async move {
+            if let Some(payload) = payload {
+                transport.send_stats(payload).await;
+            }
+            Ok(())
+        })
     }
 }
 
@@ -443,6 +1143,7 @@ fn mapping_debug(f: &Option<FieldMapping>) -> String {
 mod tests {
     use super::*;
     use crate::ApiVersion::Version05;
+    use http::Request;
 
     use crate::exporter::model::tests::get_span;
 
@@ -511,4 +1212,955 @@ mod tests {
             .build_exporter()
             .unwrap();
     }
+
+    #[cfg(feature = "agent-sampling")]
+    #[derive(Debug)]
+    struct RatesClient;
+
+    #[cfg(feature = "agent-sampling")]
+    #[async_trait::async_trait]
+    impl HttpClient for RatesClient {
+        async fn send(
+            &self,
+            _request: Request<Vec<u8>>,
+        ) -> Result<http::Response<bytes::Bytes>, opentelemetry_http::HttpError> {
+            Ok(http::Response::new(
+                r#"{"rate_by_service":{"service:rates,env:":0}}"#.into(),
+            ))
+        }
+    }
+
+    #[cfg(feature = "agent-sampling")]
+    #[test]
+    fn test_agent_sampler_fed_by_response() {
+        use crate::DatadogTraceState;
+        use opentelemetry::trace::{SpanKind, TraceId};
+        use opentelemetry_sdk::trace::ShouldSample;
+
+        let sampler = DatadogAgentSampler::new();
+        let mut exporter = new_pipeline()
+            .with_service_name("rates")
+            .with_http_client(RatesClient)
+            .with_agent_sampler(sampler.clone())
+            .build_exporter()
+            .unwrap();
+
+        let should_keep = || {
+            sampler
+                .should_sample(
+                    None,
+                    TraceId::from_u128(1),
+                    "",
+                    &SpanKind::Internal,
+                    &[],
+                    &[],
+                )
+                .trace_state
+                .priority_sampling_enabled()
+        };
+        assert!(should_keep());
+        futures_executor::block_on(exporter.export(vec![get_span(1, 1, 1)])).unwrap();
+        assert!(!should_keep());
+    }
+
+    /// Agent answering remote configuration requests with `config` and recording trace payloads.
+    #[cfg(feature = "remote-config")]
+    #[derive(Debug)]
+    struct RemoteConfigAgent {
+        config: serde_json::Value,
+        traces: Arc<std::sync::Mutex<Vec<Vec<u8>>>>,
+    }
+
+    #[cfg(feature = "remote-config")]
+    #[async_trait::async_trait]
+    impl HttpClient for RemoteConfigAgent {
+        async fn send(
+            &self,
+            request: Request<Vec<u8>>,
+        ) -> Result<http::Response<bytes::Bytes>, opentelemetry_http::HttpError> {
+            if request.uri().path() == remote_config::CONFIG_PATH {
+                return Ok(http::Response::new(
+                    serde_json::to_vec(&self.config)?.into(),
+                ));
+            }
+            self.traces.lock().unwrap().push(request.into_body());
+            Ok(http::Response::new("{}".into()))
+        }
+    }
+
+    #[cfg(feature = "remote-config")]
+    #[test]
+    fn test_remote_config_tags() {
+        use crate::remote_config::tests::{agent_response, apm_tracing};
+
+        let traces = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let agent = RemoteConfigAgent {
+            config: agent_response(
+                1,
+                &[(
+                    "datadog/2/APM_TRACING/config-id/config",
+                    apm_tracing(
+                        "remote",
+                        serde_json::json!({"tracing_tags": ["remote-tag:remote-value"]}),
+                    ),
+                )],
+            ),
+            traces: traces.clone(),
+        };
+        let mut exporter = new_pipeline()
+            .with_service_name("remote")
+            .with_tag("local-tag", "local-value")
+            .with_http_client(agent)
+            .with_remote_config(DatadogRemoteConfig::new().with_poll_interval(Duration::ZERO))
+            .build_exporter()
+            .unwrap();
+
+        // the configuration is received along with the first export and applied from the next one
+        futures_executor::block_on(exporter.export(vec![get_span(1, 0, 1)])).unwrap();
+        futures_executor::block_on(exporter.export(vec![get_span(2, 0, 2)])).unwrap();
+
+        let contains = |payload: &[u8], tag: &[u8]| payload.windows(tag.len()).any(|w| w == tag);
+        let traces = traces.lock().unwrap();
+        assert!(contains(&traces[0], b"local-tag"));
+        assert!(!contains(&traces[0], b"remote-tag"));
+        assert!(contains(&traces[1], b"remote-tag"));
+        assert!(!contains(&traces[1], b"local-tag"));
+    }
+
+    #[cfg(feature = "remote-config")]
+    #[test]
+    fn test_remote_config_polled_from_runtime() {
+        use crate::remote_config::tests::{agent_response, apm_tracing};
+
+        let agent = RemoteConfigAgent {
+            config: agent_response(
+                1,
+                &[(
+                    "datadog/2/APM_TRACING/config-id/config",
+                    apm_tracing(
+                        "remote",
+                        serde_json::json!({"tracing_tags": ["remote-tag:remote-value"]}),
+                    ),
+                )],
+            ),
+            traces: Arc::default(),
+        };
+        let mut exporter = new_pipeline()
+            .with_service_name("remote")
+            .with_http_client(agent)
+            .with_remote_config(DatadogRemoteConfig::new())
+            .build_exporter()
+            .unwrap();
+        let remote_config = exporter.remote_config.clone().unwrap();
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                exporter.spawn_remote_config_poll(&opentelemetry_sdk::runtime::Tokio);
+                // applied without any export
+                for _ in 0..100 {
+                    if remote_config.tags().is_some() {
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            });
+        assert_eq!(
+            remote_config.tags(),
+            Some(vec![("remote-tag".to_string(), "remote-value".to_string())])
+        );
+        // exports no longer poll
+        assert!(exporter.update_remote_config().is_none());
+    }
+
+    #[test]
+    fn test_agentless_intake_url() {
+        let exporter = new_pipeline()
+            .with_http_client(DummyClient)
+            .with_agentless("api-key")
+            .with_site("datadoghq.eu")
+            .build_exporter()
+            .unwrap();
+        assert_eq!(
+            exporter.transport.request_url.to_string(),
+            "https://trace.agent.datadoghq.eu/api/v0.2/traces"
+        );
+    }
+
+    #[cfg(all(unix, feature = "uds"))]
+    #[test]
+    fn test_unix_socket_agent_endpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("apm.socket");
+        let server = uds::tests::mock_agent(&path);
+
+        let mut exporter = new_pipeline()
+            .with_agent_endpoint(format!("unix://{}", path.display()))
+            .build_exporter()
+            .unwrap();
+        assert_eq!(
+            exporter.transport.request_url.to_string(),
+            "http://localhost/v0.5/traces"
+        );
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(exporter.export(vec![get_span(1, 0, 1)]))
+            .unwrap();
+        assert_eq!(server.join().unwrap().0, "POST /v0.5/traces HTTP/1.1");
+    }
+
+    #[cfg(not(all(unix, feature = "uds")))]
+    #[test]
+    fn test_unix_socket_agent_endpoint_requires_uds_feature() {
+        let result = new_pipeline()
+            .with_http_client(DummyClient)
+            .with_agent_endpoint("unix:///var/run/datadog/apm.socket")
+            .build_exporter();
+        assert!(result.is_err());
+    }
+
+    #[derive(Debug)]
+    struct IsahcClient(isahc::HttpClient);
+
+    #[async_trait::async_trait]
+    impl HttpClient for IsahcClient {
+        async fn send(
+            &self,
+            request: Request<Vec<u8>>,
+        ) -> Result<http::Response<bytes::Bytes>, opentelemetry_http::HttpError> {
+            let mut response = self.0.send_async(request).await?;
+            let mut body = Vec::new();
+            isahc::AsyncReadResponseExt::copy_to(&mut response, &mut body).await?;
+            Ok(http::Response::builder()
+                .status(response.status())
+                .body(body.into())?)
+        }
+    }
+
+    /// A request received by [`mock_http_server`].
+    struct ReceivedRequest {
+        request_line: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    /// Serve `request_count` requests on a local port with an empty `200` response, returning the
+    /// server URL and the received requests once done.
+    fn mock_http_server(
+        request_count: usize,
+    ) -> (String, std::thread::JoinHandle<Vec<ReceivedRequest>>) {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(request_count) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    match line.trim_end().split_once(": ") {
+                        Some((name, value)) => {
+                            headers.push((name.to_lowercase(), value.to_string()))
+                        }
+                        None => break,
+                    }
+                }
+                let content_length = headers
+                    .iter()
+                    .find(|(name, _)| name == "content-length")
+                    .map_or(0, |(_, value)| value.parse().unwrap());
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .unwrap();
+                requests.push(ReceivedRequest {
+                    request_line: request_line.trim_end().to_string(),
+                    headers,
+                    body,
+                });
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn test_agentless_export_to_intake() {
+        use std::io::Read;
+
+        let (url, server) = mock_http_server(1);
+        let mut exporter = new_pipeline()
+            .with_service_name("agentless")
+            .with_http_client(IsahcClient(isahc::HttpClient::new().unwrap()))
+            .with_agentless("api-key")
+            .with_intake_endpoint(url)
+            .build_exporter()
+            .unwrap();
+
+        futures_executor::block_on(exporter.export(vec![get_span(1, 0, 1), get_span(2, 0, 2)]))
+            .unwrap();
+
+        let requests = server.join().unwrap();
+        let request = &requests[0];
+        assert_eq!(request.request_line, "POST /api/v0.2/traces HTTP/1.1");
+        let header = |name: &str| {
+            request
+                .headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(header("dd-api-key"), Some("api-key"));
+        assert_eq!(header("content-encoding"), Some("gzip"));
+        assert_eq!(header("content-type"), Some("application/x-protobuf"));
+        assert_eq!(header("x-datadog-trace-count"), Some("2"));
+
+        let mut payload = Vec::new();
+        flate2::read::GzDecoder::new(request.body.as_slice())
+            .read_to_end(&mut payload)
+            .unwrap();
+        assert!(payload.windows(9).any(|w| w == b"agentless"));
+    }
+
+    #[test]
+    fn test_agentless_stats_to_intake() {
+        let (url, server) = mock_http_server(2);
+        let mut exporter = new_pipeline()
+            .with_http_client(IsahcClient(isahc::HttpClient::new().unwrap()))
+            .with_agentless("api-key")
+            .with_intake_endpoint(url)
+            .with_client_side_stats()
+            .build_exporter()
+            .unwrap();
+
+        // the span ended long ago, so its stats bucket is flushed right away
+        futures_executor::block_on(exporter.export(vec![kept_span(get_span(1, 0, 1))])).unwrap();
+
+        let requests = server.join().unwrap();
+        let request = &requests[0];
+        assert_eq!(request.request_line, "POST /api/v0.2/stats HTTP/1.1");
+        assert!(request
+            .headers
+            .contains(&("content-encoding".to_string(), "gzip".to_string())));
+        assert!(flate2::read::GzDecoder::new(request.body.as_slice())
+            .header()
+            .is_some());
+    }
+
+    fn env_vars(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<std::collections::HashMap<_, _>>();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_from_env_agent_endpoint() {
+        let builder = DatadogPipelineBuilder::from_env_vars(env_vars(&[
+            ("DD_AGENT_HOST", "datadog-agent"),
+            ("DD_TRACE_AGENT_PORT", "9126"),
+        ]));
+        assert_eq!(builder.agent_endpoint, "http://datadog-agent:9126");
+
+        let builder = DatadogPipelineBuilder::from_env_vars(env_vars(&[("DD_AGENT_HOST", "::1")]));
+        assert_eq!(builder.agent_endpoint, "http://[::1]:8126");
+
+        let builder = DatadogPipelineBuilder::from_env_vars(env_vars(&[
+            ("DD_TRACE_AGENT_URL", "unix:///var/run/datadog/apm.socket"),
+            ("DD_AGENT_HOST", "datadog-agent"),
+        ]));
+        assert_eq!(builder.agent_endpoint, "unix:///var/run/datadog/apm.socket");
+    }
+
+    #[test]
+    fn test_from_env_settings() {
+        let builder = DatadogPipelineBuilder::from_env_vars(env_vars(&[
+            ("DD_TAGS", "team:apm, layer:api,region:eu-west-1 flag"),
+            ("DD_TRACE_SAMPLE_RATE", "0.25"),
+            ("DD_TRACE_ENABLED", "false"),
+        ]));
+        assert_eq!(
+            builder.unified_tags.tags,
+            vec![
+                ("team".to_string(), "apm".to_string()),
+                ("layer".to_string(), "api".to_string()),
+                ("region".to_string(), "eu-west-1".to_string()),
+                ("flag".to_string(), "".to_string()),
+            ]
+        );
+        #[cfg(not(feature = "agent-sampling"))]
+        assert!(format!("{:?}", builder.trace_config).contains("TraceIdRatioBased(0.25)"));
+        #[cfg(feature = "agent-sampling")]
+        assert!(format!("{:?}", builder.trace_config).contains("sample_rate: Some(0.25)"));
+        assert!(!builder.enabled);
+
+        let builder =
+            DatadogPipelineBuilder::from_env_vars(env_vars(&[("DD_TRACE_SAMPLE_RATE", "2")]));
+        #[cfg(not(feature = "agent-sampling"))]
+        assert!(builder.trace_config.is_none());
+        #[cfg(feature = "agent-sampling")]
+        assert!(format!("{:?}", builder.trace_config).contains("sample_rate: None"));
+        assert!(builder.enabled);
+
+        assert!(DatadogPipelineBuilder::from_env_vars(env_vars(&[]))
+            .trace_config
+            .is_none());
+    }
+
+    #[test]
+    fn test_disabled_exporter_sends_nothing() {
+        let client = RecordingClient::default();
+        let mut exporter =
+            DatadogPipelineBuilder::from_env_vars(env_vars(&[("DD_TRACE_ENABLED", "false")]))
+                .with_http_client(client.clone())
+                .build_exporter()
+                .unwrap();
+
+        futures_executor::block_on(exporter.export(vec![get_span(1, 0, 1)])).unwrap();
+        assert!(client.requests.lock().unwrap().is_empty());
+    }
+
+    /// Path, trace count and computed stats header of a request.
+    type RecordedRequest = (String, Option<String>, Option<String>);
+
+    /// Records every request it receives, but the `/info` one it answers with whether the agent
+    /// allows dropping P0 traces.
+    #[derive(Debug, Default, Clone)]
+    struct RecordingClient {
+        requests: Arc<std::sync::Mutex<Vec<RecordedRequest>>>,
+        client_drop_p0s: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for RecordingClient {
+        async fn send(
+            &self,
+            request: Request<Vec<u8>>,
+        ) -> Result<http::Response<bytes::Bytes>, opentelemetry_http::HttpError> {
+            let header = |name: &str| {
+                request
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(ToString::to_string)
+            };
+            if request.uri().path() == INFO_PATH {
+                let info = format!(r#"{{"client_drop_p0s":{}}}"#, self.client_drop_p0s);
+                return Ok(http::Response::new(info.into()));
+            }
+            let entry = (
+                request.uri().path().to_string(),
+                header("X-Datadog-Trace-Count"),
+                header("Datadog-Client-Computed-Stats"),
+            );
+            self.requests.lock().unwrap().push(entry);
+            Ok(http::Response::new("".into()))
+        }
+    }
+
+    /// Set the sampling priority of the span, or leave it unset if `None`.
+    #[cfg(feature = "agent-sampling")]
+    fn with_priority(mut span: SpanData, keep: Option<bool>, user: bool) -> SpanData {
+        use crate::DatadogTraceState;
+        use opentelemetry::trace::SpanContext;
+
+        let context = &span.span_context;
+        let trace_state = match keep {
+            Some(keep) if user => context.trace_state().with_user_priority_sampling(keep),
+            Some(keep) => context.trace_state().with_priority_sampling(keep),
+            None => context.trace_state().clone(),
+        };
+        span.span_context = SpanContext::new(
+            context.trace_id(),
+            context.span_id(),
+            context.trace_flags(),
+            context.is_remote(),
+            trace_state,
+        );
+        span
+    }
+
+    /// Export `spans` once the agent reported whether it allows dropping P0 traces, returning the
+    /// trace count of the traces request.
+    fn export_with_client_side_stats(client_drop_p0s: bool, spans: Vec<SpanData>) -> String {
+        let client = RecordingClient {
+            client_drop_p0s,
+            ..Default::default()
+        };
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_client_side_stats()
+            .build_exporter()
+            .unwrap();
+
+        // the first export asks the agent whether it allows dropping P0 traces
+        futures_executor::block_on(exporter.export(vec![])).unwrap();
+        futures_executor::block_on(exporter.export(spans)).unwrap();
+
+        let requests = client.requests.lock().unwrap();
+        let (path, trace_count, computed_stats) = requests
+            .iter()
+            .find(|(path, _, _)| path != "/v0.6/stats")
+            .unwrap();
+        assert_eq!(path, "/v0.5/traces");
+        assert_eq!(computed_stats.as_deref(), Some("yes"));
+        trace_count.clone().unwrap()
+    }
+
+    #[test]
+    fn test_client_side_stats() {
+        let client = RecordingClient::default();
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_client_side_stats()
+            .build_exporter()
+            .unwrap();
+
+        // the spans ended long ago, so their stats bucket is flushed right away
+        futures_executor::block_on(exporter.export(vec![get_span(1, 0, 1), get_span(1, 1, 2)]))
+            .unwrap();
+
+        assert_eq!(
+            *client.requests.lock().unwrap(),
+            vec![
+                ("/v0.6/stats".to_string(), None, None),
+                (
+                    "/v0.5/traces".to_string(),
+                    Some("1".to_string()),
+                    Some("yes".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_client_side_stats_keep_traces_without_priority() {
+        // spans not sampled by a Datadog sampler have no sampling priority
+        let spans = vec![get_span(1, 0, 1), get_span(1, 1, 2), get_span(2, 0, 3)];
+        assert_eq!(export_with_client_side_stats(true, spans), "2");
+    }
+
+    #[cfg(feature = "agent-sampling")]
+    #[test]
+    fn test_client_side_stats_drop_rejected_traces() {
+        let mut failed = with_priority(get_span(4, 0, 5), Some(false), false);
+        failed.status = Status::error("boom");
+        let spans = || {
+            vec![
+                with_priority(get_span(1, 0, 1), Some(true), false),
+                with_priority(get_span(1, 1, 2), Some(true), false),
+                with_priority(get_span(2, 0, 3), Some(false), false),
+                with_priority(get_span(3, 0, 4), None, false),
+                failed.clone(),
+                with_priority(get_span(5, 0, 6), Some(false), true),
+            ]
+        };
+
+        // rejected traces are only dropped once the agent allows it, unless they failed
+        assert_eq!(export_with_client_side_stats(true, spans()), "3");
+        assert_eq!(export_with_client_side_stats(false, spans()), "5");
+    }
+
+    /// A span ending in the current stats window, whose bucket isn't over on export.
+    fn current_span() -> SpanData {
+        let mut span = get_span(1, 0, 1);
+        span.start_time = SystemTime::now() + Duration::from_secs(3600);
+        span.end_time = span.start_time + Duration::from_millis(1);
+        kept_span(span)
+    }
+
+    /// Keep the span by priority sampling, so it is exported along with its stats.
+    fn kept_span(span: SpanData) -> SpanData {
+        #[cfg(feature = "agent-sampling")]
+        let span = with_priority(span, Some(true), false);
+        span
+    }
+
+    #[test]
+    fn test_client_side_stats_sent_on_shutdown() {
+        let client = RecordingClient::default();
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_client_side_stats()
+            .build_exporter()
+            .unwrap();
+
+        futures_executor::block_on(exporter.export(vec![current_span()])).unwrap();
+        assert_eq!(client.requests.lock().unwrap().len(), 1);
+
+        exporter.shutdown();
+        let requests = client.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].0, "/v0.6/stats");
+    }
+
+    #[test]
+    fn test_client_side_stats_sent_by_the_runtime_on_shutdown() {
+        let client = RecordingClient::default();
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_client_side_stats()
+            .build_exporter()
+            .unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let handle = runtime.handle().clone();
+        exporter.spawn = Some(Arc::new(move |future| {
+            handle.spawn(future);
+        }));
+
+        runtime.block_on(async {
+            exporter.export(vec![current_span()]).await.unwrap();
+
+            // shutdown runs on the single thread of the runtime, which sends the stats once
+            // shutdown returned rather than blocking
+            exporter.shutdown();
+            assert_eq!(client.requests.lock().unwrap().len(), 1);
+            tokio::task::yield_now().await;
+        });
+        let requests = client.requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].0, "/v0.6/stats");
+    }
+
+    #[test]
+    fn test_client_side_stats_sent_on_force_flush() {
+        let client = RecordingClient::default();
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_client_side_stats()
+            .build_exporter()
+            .unwrap();
+
+        futures_executor::block_on(exporter.export(vec![current_span()])).unwrap();
+        futures_executor::block_on(exporter.force_flush()).unwrap();
+        futures_executor::block_on(exporter.force_flush()).unwrap();
+
+        let paths = client
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, _, _)| path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/v0.5/traces", "/v0.6/stats"]);
+    }
+
+    /// Answers with `503` until `available` is set, recording the trace count of every request.
+    #[derive(Debug, Default, Clone)]
+    struct FlakyClient {
+        available: Arc<std::sync::atomic::AtomicBool>,
+        requests: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for FlakyClient {
+        async fn send(
+            &self,
+            request: Request<Vec<u8>>,
+        ) -> Result<http::Response<bytes::Bytes>, opentelemetry_http::HttpError> {
+            let trace_count = request.headers()["X-Datadog-Trace-Count"]
+                .to_str()?
+                .to_string();
+            self.requests.lock().unwrap().push(trace_count);
+            let status = if self.available.load(std::sync::atomic::Ordering::SeqCst) {
+                http::StatusCode::OK
+            } else {
+                http::StatusCode::SERVICE_UNAVAILABLE
+            };
+            Ok(http::Response::builder().status(status).body("".into())?)
+        }
+    }
+
+    #[test]
+    fn test_retry_then_spill_and_replay() {
+        let client = FlakyClient::default();
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_retry_config(
+                RetryConfig::new(2)
+                    .with_initial_backoff(Duration::from_millis(1))
+                    .with_max_backoff(Duration::from_millis(1)),
+            )
+            .with_spill_directory(spill_dir.path(), 1024 * 1024)
+            .build_exporter()
+            .unwrap();
+
+        let result =
+            futures_executor::block_on(exporter.export(vec![get_span(1, 1, 1), get_span(2, 2, 2)]));
+        assert!(result.is_err());
+        // one attempt and two retries
+        assert_eq!(client.requests.lock().unwrap().len(), 3);
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 1);
+
+        client
+            .available
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        client.requests.lock().unwrap().clear();
+
+        let result = futures_executor::block_on(exporter.export(vec![get_span(3, 3, 3)]));
+        assert!(result.is_ok());
+        // the new batch, then the spilled one
+        assert_eq!(*client.requests.lock().unwrap(), vec!["1", "2"]);
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_replay_is_bounded() {
+        let client = FlakyClient::default();
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_spill_directory(spill_dir.path(), 1024 * 1024)
+            .build_exporter()
+            .unwrap();
+
+        for id in 1..=6u64 {
+            let result =
+                futures_executor::block_on(exporter.export(vec![get_span(id.into(), id, id)]));
+            assert!(result.is_err());
+        }
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 6);
+
+        client
+            .available
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        client.requests.lock().unwrap().clear();
+
+        futures_executor::block_on(exporter.export(vec![get_span(7, 7, 7)])).unwrap();
+        // the new batch, then the four oldest spilled ones
+        assert_eq!(client.requests.lock().unwrap().len(), 5);
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 2);
+
+        futures_executor::block_on(exporter.export(vec![get_span(8, 8, 8)])).unwrap();
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+    }
+
+    /// Shares a [`ManualReader`] between the meter provider and the test.
+    #[cfg(feature = "metrics")]
+    #[derive(Debug, Clone)]
+    struct SharedReader(Arc<opentelemetry_sdk::metrics::ManualReader>);
+
+    #[cfg(feature = "metrics")]
+    mod shared_reader {
+        use super::SharedReader;
+        use opentelemetry::metrics::Result;
+        use opentelemetry_sdk::metrics::data::{ResourceMetrics, Temporality};
+        use opentelemetry_sdk::metrics::reader::{
+            AggregationSelector, MetricReader, TemporalitySelector,
+        };
+        use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind, Pipeline};
+        use std::sync::Weak;
+
+        impl AggregationSelector for SharedReader {
+            fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+                self.0.aggregation(kind)
+            }
+        }
+
+        impl TemporalitySelector for SharedReader {
+            fn temporality(&self, kind: InstrumentKind) -> Temporality {
+                self.0.temporality(kind)
+            }
+        }
+
+        impl MetricReader for SharedReader {
+            fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+                self.0.register_pipeline(pipeline)
+            }
+
+            fn collect(&self, rm: &mut ResourceMetrics) -> Result<()> {
+                self.0.collect(rm)
+            }
+
+            fn force_flush(&self) -> Result<()> {
+                self.0.force_flush()
+            }
+
+            fn shutdown(&self) -> Result<()> {
+                self.0.shutdown()
+            }
+        }
+    }
+
+    /// The data points of the `name` sum, as their attributes and value sorted by attributes.
+    #[cfg(feature = "metrics")]
+    fn collected_sum(
+        metrics: &[opentelemetry_sdk::metrics::data::Metric],
+        name: &str,
+    ) -> Vec<(Vec<(String, opentelemetry::Value)>, u64)> {
+        let metric = metrics.iter().find(|metric| metric.name == name).unwrap();
+        let sum = metric
+            .data
+            .as_any()
+            .downcast_ref::<opentelemetry_sdk::metrics::data::Sum<u64>>()
+            .unwrap();
+        let mut points = sum
+            .data_points
+            .iter()
+            .map(|point| {
+                let attributes = point
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.clone()))
+                    .collect::<Vec<_>>();
+                (attributes, point.value)
+            })
+            .collect::<Vec<_>>();
+        points.sort_by_key(|(attributes, _)| format!("{attributes:?}"));
+        points
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_exporter_telemetry() {
+        use opentelemetry::Value;
+        use opentelemetry_sdk::metrics::data::ResourceMetrics;
+        use opentelemetry_sdk::metrics::reader::MetricReader;
+        use opentelemetry_sdk::metrics::{ManualReader, SdkMeterProvider};
+        use opentelemetry_sdk::Resource;
+
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        let mut exporter = new_pipeline()
+            .with_http_client(DummyClient)
+            .with_meter_provider(&meter_provider)
+            .build_exporter()
+            .unwrap();
+
+        futures_executor::block_on(exporter.export(vec![
+            get_span(1, 1, 1),
+            get_span(1, 1, 2),
+            get_span(2, 2, 3),
+        ]))
+        .unwrap();
+
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: vec![],
+        };
+        reader.0.collect(&mut metrics).unwrap();
+        let metrics = &metrics.scope_metrics[0].metrics;
+        let sum = |name: &str| collected_sum(metrics, name);
+        let sent = vec![(
+            vec![("outcome".to_string(), Value::from("sent"))],
+            // both traces fit in a single payload
+            3,
+        )];
+
+        assert_eq!(sum("datadog.exporter.spans"), sent);
+        assert_eq!(sum("datadog.exporter.traces"), vec![(sent[0].0.clone(), 2)]);
+        assert_eq!(
+            sum("datadog.exporter.requests"),
+            vec![(
+                vec![("http.response.status_code".to_string(), Value::I64(200))],
+                1
+            )]
+        );
+        assert!(sum("datadog.exporter.encoded.bytes")[0].1 > 0);
+        assert!(metrics
+            .iter()
+            .all(|metric| metric.name != "datadog.exporter.encode.errors"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_spill_and_replay_telemetry() {
+        use opentelemetry::Value;
+        use opentelemetry_sdk::metrics::data::ResourceMetrics;
+        use opentelemetry_sdk::metrics::reader::MetricReader;
+        use opentelemetry_sdk::metrics::{ManualReader, SdkMeterProvider};
+        use opentelemetry_sdk::Resource;
+
+        let reader = SharedReader(Arc::new(ManualReader::builder().build()));
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        let client = FlakyClient::default();
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut exporter = new_pipeline()
+            .with_http_client(client.clone())
+            .with_retry_config(RetryConfig::new(0))
+            .with_spill_directory(spill_dir.path(), 1024 * 1024)
+            .with_meter_provider(&meter_provider)
+            .build_exporter()
+            .unwrap();
+        let collect = || {
+            let mut metrics = ResourceMetrics {
+                resource: Resource::empty(),
+                scope_metrics: vec![],
+            };
+            reader.0.collect(&mut metrics).unwrap();
+            metrics.scope_metrics.remove(0).metrics
+        };
+        let outcome =
+            |outcome: &str| vec![("outcome".to_string(), Value::from(outcome.to_string()))];
+        let status =
+            |status: i64| vec![("http.response.status_code".to_string(), Value::I64(status))];
+
+        let result = futures_executor::block_on(exporter.export(vec![
+            get_span(1, 1, 1),
+            get_span(1, 1, 2),
+            get_span(2, 2, 3),
+        ]));
+        assert!(result.is_err());
+
+        let metrics = collect();
+        assert_eq!(
+            collected_sum(&metrics, "datadog.exporter.spans"),
+            vec![(outcome("spilled"), 3)]
+        );
+        assert_eq!(
+            collected_sum(&metrics, "datadog.exporter.traces"),
+            vec![(outcome("spilled"), 2)]
+        );
+        assert_eq!(
+            collected_sum(&metrics, "datadog.exporter.requests"),
+            vec![(status(503), 1)]
+        );
+        assert!(metrics
+            .iter()
+            .all(|metric| metric.name != "datadog.exporter.sent.bytes"));
+
+        client
+            .available
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        futures_executor::block_on(exporter.export(vec![get_span(3, 3, 4)])).unwrap();
+
+        // the new batch, then the replayed one
+        let metrics = collect();
+        assert_eq!(
+            collected_sum(&metrics, "datadog.exporter.spans"),
+            vec![(outcome("sent"), 4), (outcome("spilled"), 3)]
+        );
+        assert_eq!(
+            collected_sum(&metrics, "datadog.exporter.traces"),
+            vec![(outcome("sent"), 3), (outcome("spilled"), 2)]
+        );
+        assert_eq!(
+            collected_sum(&metrics, "datadog.exporter.requests"),
+            vec![(status(200), 2), (status(503), 1)]
+        );
+        assert_eq!(
+            collected_sum(&metrics, "datadog.exporter.sent.bytes"),
+            collected_sum(&metrics, "datadog.exporter.encoded.bytes")
+        );
+    }
 }
@@ -0,0 +1,339 @@
+use crate::exporter::model::v04::{write_array, TypedAttributes};
+use crate::exporter::model::{
+    dd_span_id, dd_trace_id, get_sampling_priority, Error, PayloadCounts,
+};
+use crate::exporter::proto;
+use crate::exporter::{Mapping, ModelConfig};
+use crate::propagator::DatadogTraceState;
+use flate2::{write::GzEncoder, Compression};
+use opentelemetry::{global, trace::Status, trace::TraceError};
+use opentelemetry_sdk::export::trace::SpanData;
+use std::io::Write;
+use std::time::SystemTime;
+
+use super::unified_tags::UnifiedTags;
+
+/// Largest uncompressed payload accepted by the trace intake.
+///
+/// See https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/writer/trace.go#L28
+pub(crate) const MAX_PAYLOAD_SIZE: usize = 3_200_000;
+
+// Protocol documentation sourced from https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/proto/datadog/trace/agent_payload.proto
+//
+// The trace intake receives the payloads the agent forwards: a gzip compressed `AgentPayload`
+// protobuf message holding a single `TracerPayload`, whose `TraceChunk`s each contain the spans
+// of a trace. Spans carry the same fields as in v0.4.
+//
+// Traces are split over several payloads when they would exceed `max_payload_size` bytes once
+// encoded. A trace larger than the limit by itself can't be sent and is dropped.
+pub(crate) fn encode(
+    model_config: &ModelConfig,
+    traces: Vec<Vec<SpanData>>,
+    mapping: &Mapping,
+    unified_tags: &UnifiedTags,
+    max_payload_size: usize,
+) -> Result<Vec<(PayloadCounts, Vec<u8>)>, Error> {
+    let env = unified_tags.env.value.as_deref().unwrap_or_default();
+    let version = unified_tags.version.value.as_deref().unwrap_or_default();
+
+    let mut tracer_payload_header = Vec::new();
+    // languageName
+    proto::write_string(&mut tracer_payload_header, 2, "rust");
+    // tracerVersion
+    proto::write_string(&mut tracer_payload_header, 4, env!("CARGO_PKG_VERSION"));
+    // env
+    proto::write_string(&mut tracer_payload_header, 8, env);
+    // appVersion
+    proto::write_string(&mut tracer_payload_header, 10, version);
+    // overhead of the AgentPayload and TracerPayload headers and lengths
+    let overhead = tracer_payload_header.len() + env.len() + 32;
+
+    let mut payloads = Vec::new();
+    let mut chunks = Vec::new();
+    let mut counts = PayloadCounts::default();
+    for trace in traces.into_iter() {
+        let span_count = trace.len();
+        let mut chunk = Vec::new();
+        // chunks
+        proto::write_bytes(
+            &mut chunk,
+            6,
+            &encode_chunk(model_config, trace, mapping, unified_tags),
+        );
+
+        if chunk.len() + overhead > max_payload_size {
+            global::handle_error(TraceError::Other(
+                format!(
+                    "dropping a trace of {} bytes, larger than the intake limit of {} bytes",
+                    chunk.len(),
+                    max_payload_size
+                )
+                .into(),
+            ));
+            continue;
+        }
+        if chunks.len() + chunk.len() + overhead > max_payload_size {
+            payloads.push((
+                counts,
+                encode_payload(env, &tracer_payload_header, &chunks)?,
+            ));
+            chunks.clear();
+            counts = PayloadCounts::default();
+        }
+        chunks.append(&mut chunk);
+        counts.traces += 1;
+        counts.spans += span_count;
+    }
+    if counts.traces > 0 {
+        payloads.push((
+            counts,
+            encode_payload(env, &tracer_payload_header, &chunks)?,
+        ));
+    }
+
+    Ok(payloads)
+}
+
+fn encode_payload(
+    env: &str,
+    tracer_payload_header: &[u8],
+    chunks: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let mut tracer_payload = Vec::with_capacity(tracer_payload_header.len() + chunks.len());
+    tracer_payload.extend_from_slice(tracer_payload_header);
+    tracer_payload.extend_from_slice(chunks);
+
+    let mut agent_payload = Vec::with_capacity(tracer_payload.len() + env.len() + 16);
+    // env
+    proto::write_string(&mut agent_payload, 2, env);
+    // tracerPayloads
+    proto::write_bytes(&mut agent_payload, 5, &tracer_payload);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&agent_payload)
+        .map_err(|err| Error::Other(err.to_string()))?;
+    encoder
+        .finish()
+        .map_err(|err| Error::Other(err.to_string()))
+}
+
+fn encode_chunk(
+    model_config: &ModelConfig,
+    trace: Vec<SpanData>,
+    mapping: &Mapping,
+    unified_tags: &UnifiedTags,
+) -> Vec<u8> {
+    let mut chunk = Vec::new();
+    if let Some(span) = trace.first() {
+        // priority
+        proto::write_int64(&mut chunk, 1, get_sampling_priority(span) as i64);
+        // origin
+        proto::write_string(
+            &mut chunk,
+            2,
+            span.span_context.trace_state().origin().unwrap_or_default(),
+        );
+    }
+    for span in trace.iter() {
+        // spans
+        proto::write_bytes(
+            &mut chunk,
+            3,
+            &encode_span(model_config, span, mapping, unified_tags),
+        );
+    }
+    chunk
+}
+
+fn encode_span(
+    model_config: &ModelConfig,
+    span: &SpanData,
+    mapping: &Mapping,
+    unified_tags: &UnifiedTags,
+) -> Vec<u8> {
+    // Safe until the year 2262 when Datadog will need to change their API
+    let start = span
+        .start_time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as i64;
+
+    let duration = span
+        .end_time
+        .duration_since(span.start_time)
+        .map(|x| x.as_nanos() as i64)
+        .unwrap_or(0);
+
+    let mut encoded = Vec::new();
+    proto::write_string(
+        &mut encoded,
+        1,
+        &mapping.map_service_name(span, model_config),
+    );
+    proto::write_string(&mut encoded, 2, &mapping.map_name(span, model_config));
+    proto::write_string(&mut encoded, 3, &mapping.map_resource(span, model_config));
+    proto::write_uint64(&mut encoded, 4, dd_trace_id(span.span_context.trace_id()));
+    proto::write_uint64(&mut encoded, 5, dd_span_id(span.span_context.span_id()));
+    proto::write_uint64(&mut encoded, 6, dd_span_id(span.parent_span_id));
+    proto::write_int64(&mut encoded, 7, start);
+    proto::write_int64(&mut encoded, 8, duration);
+    proto::write_int64(
+        &mut encoded,
+        9,
+        match span.status {
+            Status::Error { .. } => 1,
+            _ => 0,
+        },
+    );
+
+    let attributes = TypedAttributes::from_span(span, unified_tags);
+    for (key, value) in attributes.meta.iter() {
+        proto::write_map_entry(&mut encoded, 10, key, value.as_bytes());
+    }
+    for (key, value) in attributes.metrics.iter() {
+        proto::write_double_map_entry(&mut encoded, 11, key, *value);
+    }
+    if let Some(span_type) = mapping.map_span_type(span, model_config) {
+        proto::write_string(&mut encoded, 12, &span_type);
+    }
+    let mut value = Vec::new();
+    for (key, array) in attributes.meta_struct.iter() {
+        value.clear();
+        // arrays are message pack encoded, the same as in v0.4
+        if write_array(&mut value, array).is_ok() {
+            proto::write_map_entry(&mut encoded, 13, key, &value);
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::model::tests::get_span;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn decompress(payload: &[u8]) -> Vec<u8> {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(payload)
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        decompressed
+    }
+
+    #[test]
+    fn test_encode_agent_payload() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let mut unified_tags = UnifiedTags::new();
+        unified_tags.set_env(Some("prod".to_string()));
+        let payloads = encode(
+            &model_config,
+            vec![vec![get_span(7, 1, 99)]],
+            &Mapping::empty(),
+            &unified_tags,
+            MAX_PAYLOAD_SIZE,
+        )?;
+        assert_eq!(payloads.len(), 1);
+        assert_eq!(payloads[0].0.traces, 1);
+
+        let payload = decompress(&payloads[0].1);
+        // env
+        assert_eq!(&payload[..6], b"\x12\x04prod");
+        // tracerPayloads
+        assert_eq!(payload[6], 0x2a);
+        let contains = |needle: &[u8]| payload.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"\x12\x04rust"));
+        assert!(contains(b"\x0a\x0cservice_name"));
+        assert!(contains(b"\x1a\x08resource"));
+        assert!(contains(b"\x62\x03web"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "agent-sampling")]
+    #[test]
+    fn test_user_sampling_priority() {
+        use crate::DatadogTraceState;
+        use opentelemetry::trace::{SpanContext, TraceFlags, TraceState};
+
+        let mut span = get_span(7, 1, 99);
+        span.span_context = SpanContext::new(
+            span.span_context.trace_id(),
+            span.span_context.span_id(),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default().with_user_priority_sampling(false),
+        );
+        let chunk = encode_chunk(
+            &ModelConfig {
+                service_name: "service_name".to_string(),
+            },
+            vec![span],
+            &Mapping::empty(),
+            &UnifiedTags::new(),
+        );
+        // priority, -1 as a ten bytes varint
+        assert_eq!(chunk[0], 0x08);
+        assert_eq!(
+            &chunk[1..11],
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_split_along_traces() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let traces = (1..=4)
+            .map(|trace_id| vec![get_span(trace_id, 0, 1), get_span(trace_id, 1, 2)])
+            .collect::<Vec<_>>();
+        let chunk_size = {
+            let mut chunk = Vec::new();
+            proto::write_bytes(
+                &mut chunk,
+                6,
+                &encode_chunk(
+                    &model_config,
+                    traces[0].clone(),
+                    &Mapping::empty(),
+                    &UnifiedTags::new(),
+                ),
+            );
+            chunk.len()
+        };
+
+        // room for two traces per payload
+        let payloads = encode(
+            &model_config,
+            traces.clone(),
+            &Mapping::empty(),
+            &UnifiedTags::new(),
+            chunk_size * 2 + 64,
+        )?;
+        assert_eq!(
+            payloads
+                .iter()
+                .map(|(counts, _)| counts.traces)
+                .collect::<Vec<_>>(),
+            vec![2, 2]
+        );
+
+        // too small for any trace
+        let payloads = encode(
+            &model_config,
+            traces,
+            &Mapping::empty(),
+            &UnifiedTags::new(),
+            chunk_size,
+        )?;
+        assert!(payloads.is_empty());
+
+        Ok(())
+    }
+}
@@ -1,21 +1,34 @@
 use crate::exporter::ModelConfig;
+use crate::propagator::{
+    datadog_parent_id, raw_propagated_tags, DatadogTraceState, DATADOG_PROPAGATED_TAG_PREFIX,
+};
 use http::uri;
+use opentelemetry::global;
+use opentelemetry::trace::{SpanId, Status, TraceError, TraceId};
+use opentelemetry::{Array, KeyValue, Value};
 use opentelemetry_sdk::export::{
     trace::{self, SpanData},
     ExportError,
 };
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::fmt::{Debug, Write};
+use std::io::Write as _;
+use std::time::SystemTime;
 use url::ParseError;
 
+use self::rules::DatadogField;
 use self::unified_tags::UnifiedTags;
 
 use super::Mapping;
 
+pub(crate) mod agentless;
+pub(crate) mod rules;
 pub mod unified_tags;
 mod v03;
+mod v04;
 mod v05;
 
-// todo: we should follow the same mapping defined in https://github.com/DataDog/datadog-agent/blob/main/pkg/trace/api/otlp.go
+pub(crate) use v05::EncoderBuffers;
 
 // https://github.com/DataDog/dd-trace-js/blob/c89a35f7d27beb4a60165409376e170eacb194c5/packages/dd-trace/src/constants.js#L4
 static SAMPLING_PRIORITY_KEY: &str = "_sampling_priority_v1";
@@ -23,6 +36,38 @@ static SAMPLING_PRIORITY_KEY: &str = "_sampling_priority_v1";
 // https://github.com/DataDog/datadog-agent/blob/ec96f3c24173ec66ba235bda7710504400d9a000/pkg/trace/traceutil/span.go#L20
 static DD_MEASURED_KEY: &str = "_dd.measured";
 
+static SPAN_TYPE_KEY: &str = "span.type";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/sampler.go#L370
+pub(crate) static DD_RULE_PSR_KEY: &str = "_dd.rule_psr";
+pub(crate) static DD_LIMIT_PSR_KEY: &str = "_dd.limit_psr";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/spancontext.go#L36
+static DD_TRACE_ID_HIGH_KEY: &str = "_dd.p.tid";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/tags.go#L83
+static DD_ORIGIN_KEY: &str = "_dd.origin";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go
+static DD_PARENT_ID_KEY: &str = "_dd.parent_id";
+
+/// Span events, serialized as a JSON array
+static DD_EVENTS_KEY: &str = "events";
+
+/// Span links, serialized as a JSON array
+static DD_SPAN_LINKS_KEY: &str = "_dd.span_links";
+
+static DD_ERROR_MESSAGE_KEY: &str = "error.message";
+static DD_ERROR_TYPE_KEY: &str = "error.type";
+static DD_ERROR_STACK_KEY: &str = "error.stack";
+
+/// Name and attributes of the events recording exceptions, see
+/// https://opentelemetry.io/docs/specs/semconv/exceptions/exceptions-spans/
+static EXCEPTION_EVENT_NAME: &str = "exception";
+static EXCEPTION_MESSAGE_KEY: &str = "exception.message";
+static EXCEPTION_TYPE_KEY: &str = "exception.type";
+static EXCEPTION_STACKTRACE_KEY: &str = "exception.stacktrace";
+
 /// Custom mapping between opentelemetry spans and datadog spans.
 ///
 /// User can provide custom function to change the mapping. It currently supports customizing the following
@@ -38,6 +83,8 @@ static DD_MEASURED_KEY: &str = "_dd.measured";
 /// It should return a `&str` which will be used as the value for the field.
 ///
 /// If no custom mapping is provided. Default mapping detailed above will be used.
+/// Mapping rules derived from OpenTelemetry semantic conventions can also be configured, see
+/// [`MappingRules`](crate::MappingRules).
 ///
 /// For example,
 /// ```no_run
@@ -75,6 +122,431 @@ fn default_resource_mapping<'a>(span: &'a SpanData, _config: &'a ModelConfig) ->
     span.name.as_ref()
 }
 
+// A custom mapping function takes precedence over the mapping rules, which take precedence over
+// the default mapping.
+impl Mapping {
+    pub(crate) fn map_service_name<'a>(
+        &self,
+        span: &'a SpanData,
+        config: &'a ModelConfig,
+    ) -> Cow<'a, str> {
+        match &self.service_name {
+            Some(f) => f(span, config).into(),
+            None => self
+                .rules
+                .apply(DatadogField::Service, span, config)
+                .unwrap_or_else(|| default_service_name_mapping(span, config).into()),
+        }
+    }
+
+    pub(crate) fn map_name<'a>(&self, span: &'a SpanData, config: &'a ModelConfig) -> Cow<'a, str> {
+        match &self.name {
+            Some(f) => f(span, config).into(),
+            None => self
+                .rules
+                .apply(DatadogField::Name, span, config)
+                .unwrap_or_else(|| default_name_mapping(span, config).into()),
+        }
+    }
+
+    pub(crate) fn map_resource<'a>(
+        &self,
+        span: &'a SpanData,
+        config: &'a ModelConfig,
+    ) -> Cow<'a, str> {
+        match &self.resource {
+            Some(f) => f(span, config).into(),
+            None => self
+                .rules
+                .apply(DatadogField::Resource, span, config)
+                .unwrap_or_else(|| default_resource_mapping(span, config).into()),
+        }
+    }
+
+    /// The span type is the `span.type` attribute by default, and omitted without it.
+    pub(crate) fn map_span_type<'a>(
+        &self,
+        span: &'a SpanData,
+        config: &'a ModelConfig,
+    ) -> Option<Cow<'a, str>> {
+        self.rules
+            .apply(DatadogField::Type, span, config)
+            .or_else(|| {
+                span.attributes
+                    .iter()
+                    .find(|kv| kv.key.as_str() == SPAN_TYPE_KEY)
+                    .map(|kv| kv.value.as_str())
+            })
+    }
+}
+
+/// Largest payload accepted by the agent.
+///
+/// See https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/api/api.go
+pub(crate) const MAX_PAYLOAD_SIZE: usize = 25 * 1024 * 1024;
+
+/// Size of the message pack header of the traces array, at most that of a 32 bits length.
+pub(super) const TRACES_HEADER_SIZE: usize = 5;
+
+/// Number of traces and spans encoded in a payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct PayloadCounts {
+    pub(crate) traces: usize,
+    pub(crate) spans: usize,
+}
+
+/// Encode traces into message pack payloads of at most `max_payload_size` bytes, along with
+/// their trace and span counts. Each payload is an array of the traces written by
+/// `encode_trace`.
+///
+/// The size of a payload is measured as traces are encoded. A trace which would make it overflow
+/// starts the next payload, and a trace larger than the limit by itself can't be sent and is
+/// dropped.
+fn encode_payloads<F>(
+    traces: Vec<Vec<SpanData>>,
+    max_payload_size: usize,
+    mut encode_trace: F,
+) -> Result<Vec<(PayloadCounts, Vec<u8>)>, Error>
+where
+    F: FnMut(&mut Vec<u8>, Vec<SpanData>) -> Result<(), Error>,
+{
+    let mut payloads = Vec::new();
+    let mut encoded = Vec::new();
+    let mut counts = PayloadCounts::default();
+    for trace in traces.into_iter() {
+        let start = encoded.len();
+        let span_count = trace.len();
+        encode_trace(&mut encoded, trace)?;
+
+        let trace_size = encoded.len() - start;
+        if trace_size + TRACES_HEADER_SIZE > max_payload_size {
+            encoded.truncate(start);
+            handle_oversized_trace(trace_size, max_payload_size);
+            continue;
+        }
+        if encoded.len() + TRACES_HEADER_SIZE > max_payload_size {
+            let next = encoded.split_off(start);
+            payloads.push((counts, traces_payload(counts.traces, &encoded)?));
+            encoded = next;
+            counts = PayloadCounts::default();
+        }
+        counts.traces += 1;
+        counts.spans += span_count;
+    }
+    if counts.traces > 0 {
+        payloads.push((counts, traces_payload(counts.traces, &encoded)?));
+    }
+
+    Ok(payloads)
+}
+
+pub(super) fn traces_payload(trace_count: usize, encoded_traces: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut payload = Vec::with_capacity(encoded_traces.len() + TRACES_HEADER_SIZE);
+    rmp::encode::write_array_len(&mut payload, trace_count as u32)?;
+    payload.extend_from_slice(encoded_traces);
+    Ok(payload)
+}
+
+pub(super) fn handle_oversized_trace(trace_size: usize, max_payload_size: usize) {
+    global::handle_error(TraceError::Other(
+        format!(
+            "dropping a trace of {} bytes, larger than the payload limit of {} bytes",
+            trace_size, max_payload_size
+        )
+        .into(),
+    ));
+}
+
+/// Datadog trace ids are the lower 64 bits of OpenTelemetry trace ids.
+pub(crate) fn dd_trace_id(trace_id: TraceId) -> u64 {
+    u128::from_be_bytes(trace_id.to_bytes()) as u64
+}
+
+/// Datadog span ids are the same 64 bits as OpenTelemetry span ids.
+pub(crate) fn dd_span_id(span_id: SpanId) -> u64 {
+    u64::from_be_bytes(span_id.to_bytes())
+}
+
+/// Datadog span trace ids are 64 bits, the upper 64 bits of an OpenTelemetry trace id are sent as
+/// a hex encoded `_dd.p.tid` tag.
+fn get_trace_id_high(span: &SpanData) -> Option<String> {
+    trace_id_high(span).map(|trace_id_high| format!("{trace_id_high:016x}"))
+}
+
+fn trace_id_high(span: &SpanData) -> Option<u64> {
+    let trace_id_high = (u128::from_be_bytes(span.span_context.trace_id().to_bytes()) >> 64) as u64;
+    (trace_id_high != 0).then_some(trace_id_high)
+}
+
+/// Scratch buffers span meta is formatted into, reused from one span to the next.
+#[derive(Debug, Default)]
+pub(crate) struct MetaBuffers {
+    key: String,
+    value: String,
+    json: Vec<u8>,
+}
+
+/// Call `f` with the hex encoded upper 64 bits of the trace id, see [`get_trace_id_high`].
+fn visit_trace_id_high(span: &SpanData, buffers: &mut MetaBuffers, mut f: impl FnMut(&str)) {
+    if let Some(trace_id_high) = trace_id_high(span) {
+        buffers.value.clear();
+        let _ = write!(buffers.value, "{trace_id_high:016x}");
+        f(&buffers.value);
+    }
+}
+
+/// The origin and `_dd.p.*` tags propagated with the trace, sent as span meta. Spans whose
+/// remote parent was reached through services not propagating Datadog headers also report the
+/// last Datadog span as `_dd.parent_id`.
+fn get_trace_state_meta(span: &SpanData) -> Vec<(String, String)> {
+    let mut meta = Vec::new();
+    visit_trace_state_meta(span, &mut MetaBuffers::default(), |key, value| {
+        meta.push((key.to_string(), value.to_string()))
+    });
+    meta
+}
+
+/// Call `f` with each meta of [`get_trace_state_meta`], formatted into `buffers` rather than
+/// allocated.
+fn visit_trace_state_meta(
+    span: &SpanData,
+    buffers: &mut MetaBuffers,
+    mut f: impl FnMut(&str, &str),
+) {
+    let trace_state = span.span_context.trace_state();
+    for (key, value) in raw_propagated_tags(trace_state) {
+        buffers.key.clear();
+        buffers.key.push_str(DATADOG_PROPAGATED_TAG_PREFIX);
+        buffers.key.push_str(key);
+        if value.contains('~') {
+            buffers.value.clear();
+            buffers
+                .value
+                .extend(value.chars().map(|c| if c == '~' { '=' } else { c }));
+            f(&buffers.key, &buffers.value);
+        } else {
+            f(&buffers.key, value);
+        }
+    }
+    if let Some(origin) = trace_state.origin() {
+        f(DD_ORIGIN_KEY, origin);
+    }
+    if let Some(parent_id) = datadog_parent_id(trace_state, span.parent_span_id) {
+        buffers.value.clear();
+        let _ = write!(buffers.value, "{parent_id}");
+        f(DD_PARENT_ID_KEY, &buffers.value);
+    }
+}
+
+/// The span events and links, and the details of the exception recorded on spans in error, sent
+/// as span meta the same way the agent converts OTLP spans.
+///
+/// See https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/api/otlp.go
+fn get_events_and_links_meta(span: &SpanData) -> Vec<(&'static str, String)> {
+    let mut meta = Vec::new();
+    visit_events_and_links_meta(span, &mut MetaBuffers::default(), |key, value| {
+        meta.push((key, value.to_string()))
+    });
+    meta
+}
+
+/// Call `f` with each meta of [`get_events_and_links_meta`], serializing JSON into `buffers`
+/// rather than allocating strings.
+fn visit_events_and_links_meta(
+    span: &SpanData,
+    buffers: &mut MetaBuffers,
+    mut f: impl FnMut(&'static str, &str),
+) {
+    if !span.events.is_empty() {
+        if let Some(events) = write_events_json(span, &mut buffers.json) {
+            f(DD_EVENTS_KEY, events);
+        }
+    }
+
+    if let Status::Error { description } = &span.status {
+        let exception = span
+            .events
+            .iter()
+            .rev()
+            .find(|event| event.name == EXCEPTION_EVENT_NAME);
+        let exception_attribute = |key: &str| {
+            exception?
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.as_str())
+        };
+        let message = exception_attribute(EXCEPTION_MESSAGE_KEY)
+            .or_else(|| (!description.is_empty()).then(|| Cow::Borrowed(description.as_ref())));
+        for (key, value) in [
+            (DD_ERROR_MESSAGE_KEY, message),
+            (DD_ERROR_TYPE_KEY, exception_attribute(EXCEPTION_TYPE_KEY)),
+            (
+                DD_ERROR_STACK_KEY,
+                exception_attribute(EXCEPTION_STACKTRACE_KEY),
+            ),
+        ] {
+            // attributes set on the span take precedence
+            if let Some(value) = value {
+                if !span.attributes.iter().any(|kv| kv.key.as_str() == key) {
+                    f(key, &value);
+                }
+            }
+        }
+    }
+
+    if !span.links.is_empty() {
+        if let Some(links) = write_links_json(span, &mut buffers.json) {
+            f(DD_SPAN_LINKS_KEY, links);
+        }
+    }
+}
+
+/// Write the events of the span as a JSON array into `json`, straight from the span rather than
+/// through intermediate `serde_json` values.
+fn write_events_json<'a>(span: &SpanData, json: &'a mut Vec<u8>) -> Option<&'a str> {
+    json.clear();
+    json.push(b'[');
+    for (i, event) in span.events.iter().enumerate() {
+        if i != 0 {
+            json.push(b',');
+        }
+        json.extend_from_slice(b"{\"time_unix_nano\":");
+        serde_json::to_writer(&mut *json, &unix_nanos(event.timestamp)).ok()?;
+        json.extend_from_slice(b",\"name\":");
+        serde_json::to_writer(&mut *json, event.name.as_ref()).ok()?;
+        if !event.attributes.is_empty() {
+            json.extend_from_slice(b",\"attributes\":");
+            write_attributes_json(json, &event.attributes, false)?;
+        }
+        if event.dropped_attributes_count != 0 {
+            json.extend_from_slice(b",\"dropped_attributes_count\":");
+            serde_json::to_writer(&mut *json, &event.dropped_attributes_count).ok()?;
+        }
+        json.push(b'}');
+    }
+    json.push(b']');
+    std::str::from_utf8(json).ok()
+}
+
+/// Write the links of the span as a JSON array into `json`, like [`write_events_json`].
+fn write_links_json<'a>(span: &SpanData, json: &'a mut Vec<u8>) -> Option<&'a str> {
+    json.clear();
+    json.push(b'[');
+    for (i, link) in span.links.iter().enumerate() {
+        if i != 0 {
+            json.push(b',');
+        }
+        write!(
+            json,
+            "{{\"trace_id\":\"{}\",\"span_id\":\"{}\"",
+            link.span_context.trace_id(),
+            link.span_context.span_id()
+        )
+        .ok()?;
+        let trace_state = link.span_context.trace_state().header();
+        if !trace_state.is_empty() {
+            json.extend_from_slice(b",\"tracestate\":");
+            serde_json::to_writer(&mut *json, &trace_state).ok()?;
+        }
+        // span link attributes are a map of strings, unlike event ones
+        if !link.attributes.is_empty() {
+            json.extend_from_slice(b",\"attributes\":");
+            write_attributes_json(json, &link.attributes, true)?;
+        }
+        if link.dropped_attributes_count != 0 {
+            json.extend_from_slice(b",\"dropped_attributes_count\":");
+            serde_json::to_writer(&mut *json, &link.dropped_attributes_count).ok()?;
+        }
+        json.push(b'}');
+    }
+    json.push(b']');
+    std::str::from_utf8(json).ok()
+}
+
+/// Write the attributes as a JSON object, with their values stringified as meta or typed.
+fn write_attributes_json(
+    json: &mut Vec<u8>,
+    attributes: &[KeyValue],
+    stringify: bool,
+) -> Option<()> {
+    json.push(b'{');
+    for (i, kv) in attributes.iter().enumerate() {
+        if i != 0 {
+            json.push(b',');
+        }
+        serde_json::to_writer(&mut *json, kv.key.as_str()).ok()?;
+        json.push(b':');
+        if stringify {
+            serde_json::to_writer(&mut *json, kv.value.as_str().as_ref()).ok()?;
+        } else {
+            write_value_json(json, &kv.value)?;
+        }
+    }
+    json.push(b'}');
+    Some(())
+}
+
+fn write_value_json(json: &mut Vec<u8>, value: &Value) -> Option<()> {
+    match value {
+        Value::Bool(v) => serde_json::to_writer(json, v),
+        Value::I64(v) => serde_json::to_writer(json, v),
+        Value::F64(v) => serde_json::to_writer(json, v),
+        Value::String(v) => serde_json::to_writer(json, v.as_str()),
+        Value::Array(Array::Bool(values)) => serde_json::to_writer(json, values),
+        Value::Array(Array::I64(values)) => serde_json::to_writer(json, values),
+        Value::Array(Array::F64(values)) => serde_json::to_writer(json, values),
+        Value::Array(Array::String(values)) => {
+            json.push(b'[');
+            for (i, v) in values.iter().enumerate() {
+                if i != 0 {
+                    json.push(b',');
+                }
+                serde_json::to_writer(&mut *json, v.as_str()).ok()?;
+            }
+            json.push(b']');
+            Ok(())
+        }
+    }
+    .ok()
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(not(feature = "agent-sampling"))]
+pub(crate) fn get_sampling_priority(_span: &SpanData) -> f64 {
+    1.0
+}
+
+#[cfg(feature = "agent-sampling")]
+pub(crate) fn get_sampling_priority(span: &SpanData) -> f64 {
+    span.span_context.trace_state().sampling_priority() as f64
+}
+
+/// The sampling rate a sampler recorded in the attribute, sent as a metric rather than meta.
+fn get_sampling_rate(kv: &KeyValue) -> Option<(&'static str, f64)> {
+    let key = [DD_RULE_PSR_KEY, DD_LIMIT_PSR_KEY]
+        .into_iter()
+        .find(|key| kv.key.as_str() == *key)?;
+    match kv.value {
+        Value::F64(rate) => Some((key, rate)),
+        Value::I64(rate) => Some((key, rate as f64)),
+        _ => None,
+    }
+}
+
+fn get_measuring(span: &SpanData) -> f64 {
+    if span.span_context.trace_state().measuring_enabled() {
+        1.0
+    } else {
+        0.0
+    }
+}
+
 /// Wrap type for errors from opentelemetry datadog exporter
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -90,6 +562,9 @@ pub enum Error {
     /// The Uri was invalid
     #[error("invalid url {0}")]
     InvalidUri(String),
+    /// Reading or writing the spill directory failed
+    #[error("spill directory error: {0}")]
+    SpillError(#[from] std::io::Error),
     /// Other errors
     #[error("{0}")]
     Other(String),
@@ -125,6 +600,8 @@ impl From<uri::InvalidUri> for Error {
 pub enum ApiVersion {
     /// Version 0.3
     Version03,
+    /// Version 0.4 - encodes numeric attributes as `metrics` and array attributes as `meta_struct`
+    Version04,
     /// Version 0.5 - requires datadog-agent v7.22.0 or above
     Version05,
 }
@@ -133,57 +610,67 @@ impl ApiVersion {
     pub(crate) fn path(self) -> &'static str {
         match self {
             ApiVersion::Version03 => "/v0.3/traces",
+            ApiVersion::Version04 => "/v0.4/traces",
             ApiVersion::Version05 => "/v0.5/traces",
         }
     }
 
+    pub(crate) fn spill_extension(self) -> &'static str {
+        match self {
+            ApiVersion::Version03 => "v03",
+            ApiVersion::Version04 => "v04",
+            ApiVersion::Version05 => "v05",
+        }
+    }
+
     pub(crate) fn content_type(self) -> &'static str {
         match self {
             ApiVersion::Version03 => "application/msgpack",
+            ApiVersion::Version04 => "application/msgpack",
             ApiVersion::Version05 => "application/msgpack",
         }
     }
 
     pub(crate) fn encode(
         self,
+        buffers: &mut EncoderBuffers,
         model_config: &ModelConfig,
         traces: Vec<Vec<trace::SpanData>>,
         mapping: &Mapping,
         unified_tags: &UnifiedTags,
-    ) -> Result<Vec<u8>, Error> {
+        max_payload_size: usize,
+    ) -> Result<Vec<(PayloadCounts, Vec<u8>)>, Error> {
         match self {
             Self::Version03 => v03::encode(
                 model_config,
                 traces,
-                |span, config| match &mapping.service_name {
-                    Some(f) => f(span, config),
-                    None => default_service_name_mapping(span, config),
-                },
-                |span, config| match &mapping.name {
-                    Some(f) => f(span, config),
-                    None => default_name_mapping(span, config),
-                },
-                |span, config| match &mapping.resource {
-                    Some(f) => f(span, config),
-                    None => default_resource_mapping(span, config),
-                },
+                |span, config| mapping.map_service_name(span, config),
+                |span, config| mapping.map_name(span, config),
+                |span, config| mapping.map_resource(span, config),
+                |span, config| mapping.map_span_type(span, config),
+                unified_tags,
+                max_payload_size,
+            ),
+            Self::Version04 => v04::encode(
+                model_config,
+                traces,
+                |span, config| mapping.map_service_name(span, config),
+                |span, config| mapping.map_name(span, config),
+                |span, config| mapping.map_resource(span, config),
+                |span, config| mapping.map_span_type(span, config),
+                unified_tags,
+                max_payload_size,
             ),
             Self::Version05 => v05::encode(
+                buffers,
                 model_config,
                 traces,
-                |span, config| match &mapping.service_name {
-                    Some(f) => f(span, config),
-                    None => default_service_name_mapping(span, config),
-                },
-                |span, config| match &mapping.name {
-                    Some(f) => f(span, config),
-                    None => default_name_mapping(span, config),
-                },
-                |span, config| match &mapping.resource {
-                    Some(f) => f(span, config),
-                    None => default_resource_mapping(span, config),
-                },
+                |span, config| mapping.map_service_name(span, config),
+                |span, config| mapping.map_name(span, config),
+                |span, config| mapping.map_resource(span, config),
+                |span, config| mapping.map_span_type(span, config),
                 unified_tags,
+                max_payload_size,
             ),
         }
     }
@@ -247,6 +734,452 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn test_error_meta_falls_back_to_status() {
+        let mut span = get_span(7, 1, 99);
+        assert!(get_events_and_links_meta(&span).is_empty());
+
+        span.status = Status::error("request failed");
+        assert_eq!(
+            get_events_and_links_meta(&span),
+            vec![(DD_ERROR_MESSAGE_KEY, "request failed".to_string())]
+        );
+
+        // exceptions recorded on spans which are not in error are only kept as events
+        span.status = Status::Ok;
+        span.events.events.push(opentelemetry::trace::Event::new(
+            EXCEPTION_EVENT_NAME,
+            SystemTime::UNIX_EPOCH,
+            vec![KeyValue::new(EXCEPTION_MESSAGE_KEY, "handled")],
+            0,
+        ));
+        let meta = get_events_and_links_meta(&span);
+        assert_eq!(meta.len(), 1);
+        assert_eq!(meta[0].0, DD_EVENTS_KEY);
+    }
+
+    #[test]
+    fn test_events_and_links_json() {
+        use opentelemetry::trace::{Event, Link};
+
+        let mut span = get_span(7, 1, 99);
+        span.events.events.push(Event::new(
+            "say \"hi\"",
+            SystemTime::UNIX_EPOCH + Duration::from_nanos(5),
+            vec![
+                KeyValue::new("count", 2),
+                KeyValue::new(
+                    "tags",
+                    Value::Array(Array::String(vec!["a".into(), "b\n".into()])),
+                ),
+            ],
+            1,
+        ));
+        span.links.links.push(Link::new(
+            SpanContext::new(
+                TraceId::from_u128(0xabc),
+                SpanId::from_u64(0xdef),
+                TraceFlags::SAMPLED,
+                true,
+                TraceState::from_key_value([("dd", "s:1")]).unwrap(),
+            ),
+            vec![KeyValue::new("weight", 2.5), KeyValue::new("sampled", true)],
+        ));
+
+        let mut json = Vec::new();
+        assert_eq!(
+            write_events_json(&span, &mut json),
+            Some(
+                r#"[{"time_unix_nano":5,"name":"say \"hi\"","attributes":{"count":2,"tags":["a","b\n"]},"dropped_attributes_count":1}]"#
+            )
+        );
+        assert_eq!(
+            write_links_json(&span, &mut json),
+            Some(
+                r#"[{"trace_id":"00000000000000000000000000000abc","span_id":"0000000000000def","tracestate":"dd=s:1","attributes":{"weight":"2.5","sampled":"true"}}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_v05_meta_matches_v04() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::propagator::with_datadog_parent_id;
+        use opentelemetry::trace::{Event, Link};
+        use std::collections::BTreeMap;
+
+        let mut span = get_span(0x1234 << 64 | 7, 1, 99);
+        let trace_state = span
+            .span_context
+            .trace_state()
+            .with_origin("synthetics")
+            .with_propagated_tags(&[
+                ("_dd.p.dm".to_string(), "-4".to_string()),
+                ("_dd.p.usr.id".to_string(), "YmF6=".to_string()),
+            ]);
+        span.span_context = SpanContext::new(
+            span.span_context.trace_id(),
+            span.span_context.span_id(),
+            span.span_context.trace_flags(),
+            false,
+            with_datadog_parent_id(&trace_state, SpanId::from_u64(1), SpanId::from_u64(0xab)),
+        );
+        span.status = Status::error("request failed");
+        span.events.events.push(Event::new(
+            EXCEPTION_EVENT_NAME,
+            SystemTime::UNIX_EPOCH,
+            vec![KeyValue::new(EXCEPTION_TYPE_KEY, "io::Error")],
+            0,
+        ));
+        span.links.links.push(Link::new(
+            SpanContext::new(
+                TraceId::from_u128(0xabc),
+                SpanId::from_u64(0xdef),
+                TraceFlags::SAMPLED,
+                true,
+                TraceState::default(),
+            ),
+            vec![KeyValue::new("link.kind", "follows_from")],
+        ));
+
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let encode = |api_version: ApiVersion| {
+            api_version
+                .encode(
+                    &mut EncoderBuffers::default(),
+                    &model_config,
+                    vec![vec![span.clone()]],
+                    &Mapping::empty(),
+                    &UnifiedTags::new(),
+                    MAX_PAYLOAD_SIZE,
+                )
+                .map(|mut payloads| payloads.remove(0).1)
+        };
+
+        let v04 = encode(ApiVersion::Version04)?;
+        let v04 = rmpv::decode::read_value(&mut v04.as_slice())?;
+        let v04_meta = v04[0][0]
+            .as_map()
+            .unwrap()
+            .iter()
+            .find(|(key, _)| key.as_str() == Some("meta"))
+            .unwrap()
+            .1
+            .as_map()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<BTreeMap<_, _>>();
+
+        let v05 = encode(ApiVersion::Version05)?;
+        let v05 = rmpv::decode::read_value(&mut v05.as_slice())?;
+        let strings = v05[0].as_array().unwrap();
+        let string = |index: &rmpv::Value| strings[index.as_u64().unwrap() as usize].to_string();
+        let v05_meta = v05[1][0][0][9]
+            .as_map()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| (string(key), string(value)))
+            .collect::<BTreeMap<_, _>>();
+
+        assert_eq!(v05_meta, v04_meta);
+        for key in [
+            DD_TRACE_ID_HIGH_KEY,
+            "_dd.p.usr.id",
+            DD_ORIGIN_KEY,
+            DD_PARENT_ID_KEY,
+            DD_EVENTS_KEY,
+            DD_ERROR_MESSAGE_KEY,
+            DD_ERROR_TYPE_KEY,
+            DD_SPAN_LINKS_KEY,
+        ] {
+            assert!(v05_meta.contains_key(&format!("{key:?}")), "{key}");
+        }
+        assert_eq!(v05_meta["\"_dd.p.usr.id\""], "\"YmF6=\"");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_along_traces() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let traces = (1..=4)
+            .map(|trace_id| {
+                let mut spans = vec![get_span(trace_id, 0, 1), get_span(trace_id, 1, 2)];
+                for span in spans.iter_mut() {
+                    let tag = format!("{}{}", "x".repeat(1000), trace_id);
+                    span.attributes.push(KeyValue::new("trace.tag", tag));
+                }
+                spans
+            })
+            .collect::<Vec<_>>();
+
+        for api_version in [
+            ApiVersion::Version03,
+            ApiVersion::Version04,
+            ApiVersion::Version05,
+        ] {
+            let encode = |traces: Vec<Vec<trace::SpanData>>, max_payload_size| {
+                api_version.encode(
+                    &mut EncoderBuffers::default(),
+                    &model_config,
+                    traces,
+                    &Mapping::empty(),
+                    &UnifiedTags::new(),
+                    max_payload_size,
+                )
+            };
+            let trace_size = encode(vec![traces[0].clone()], MAX_PAYLOAD_SIZE)?[0]
+                .1
+                .len();
+
+            // room for two traces per payload
+            let payloads = encode(traces.clone(), trace_size * 2 + 16)?;
+            assert_eq!(
+                payloads
+                    .iter()
+                    .map(|(counts, _)| counts.traces)
+                    .collect::<Vec<_>>(),
+                vec![2, 2],
+                "{:?}",
+                api_version
+            );
+            for (_, payload) in payloads.iter() {
+                assert!(payload.len() <= trace_size * 2 + 16);
+                let decoded = rmpv::decode::read_value(&mut payload.as_slice())?;
+                let decoded_traces = match api_version {
+                    ApiVersion::Version05 => &decoded[1],
+                    _ => &decoded,
+                };
+                assert_eq!(decoded_traces.as_array().map(Vec::len), Some(2));
+            }
+
+            // too small for any trace
+            let payloads = encode(traces.clone(), trace_size / 2)?;
+            assert!(payloads.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reused_buffers() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let traces = (1..=3)
+            .map(|trace_id| {
+                let mut span = get_span(trace_id, 0, 1);
+                span.attributes
+                    .push(KeyValue::new("trace.id", trace_id as i64));
+                vec![span]
+            })
+            .collect::<Vec<_>>();
+        let encode = |buffers: &mut EncoderBuffers, traces: Vec<Vec<trace::SpanData>>| {
+            ApiVersion::Version05.encode(
+                buffers,
+                &model_config,
+                traces,
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                MAX_PAYLOAD_SIZE,
+            )
+        };
+
+        let mut buffers = EncoderBuffers::default();
+        encode(&mut buffers, traces[..2].to_vec())?;
+        // nothing of the previous export leaks into the next one
+        assert_eq!(
+            encode(&mut buffers, traces[2..].to_vec())?,
+            encode(&mut EncoderBuffers::default(), traces[2..].to_vec())?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sampling_rates_in_metrics() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let mut span = get_span(7, 1, 99);
+        span.attributes.push(KeyValue::new(DD_RULE_PSR_KEY, 0.25));
+
+        for api_version in [ApiVersion::Version03, ApiVersion::Version05] {
+            let payload = api_version
+                .encode(
+                    &mut EncoderBuffers::default(),
+                    &model_config,
+                    vec![vec![span.clone()]],
+                    &Mapping::empty(),
+                    &UnifiedTags::new(),
+                    MAX_PAYLOAD_SIZE,
+                )?
+                .remove(0)
+                .1;
+            let decoded = rmpv::decode::read_value(&mut payload.as_slice())?;
+            let (meta, metrics) = match api_version {
+                ApiVersion::Version05 => {
+                    let strings = decoded[0].as_array().unwrap();
+                    let string = |index: &rmpv::Value| {
+                        strings[index.as_u64().unwrap() as usize]
+                            .as_str()
+                            .unwrap()
+                            .to_string()
+                    };
+                    let span = &decoded[1][0][0];
+                    let keys = |map: &rmpv::Value| {
+                        map.as_map()
+                            .unwrap()
+                            .iter()
+                            .map(|(key, _)| string(key))
+                            .collect::<Vec<_>>()
+                    };
+                    (keys(&span[9]), keys(&span[10]))
+                }
+                _ => {
+                    let span = decoded[0][0].as_map().unwrap();
+                    let field = |name: &str| {
+                        span.iter()
+                            .find(|(key, _)| key.as_str() == Some(name))
+                            .unwrap()
+                            .1
+                            .as_map()
+                            .unwrap()
+                            .iter()
+                            .map(|(key, _)| key.as_str().unwrap().to_string())
+                            .collect::<Vec<_>>()
+                    };
+                    (field("meta"), field("metrics"))
+                }
+            };
+            assert!(
+                !meta.contains(&DD_RULE_PSR_KEY.to_string()),
+                "{api_version:?}"
+            );
+            assert!(
+                metrics.contains(&DD_RULE_PSR_KEY.to_string()),
+                "{api_version:?}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "agent-sampling")]
+    #[test]
+    fn test_user_sampling_priority() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::DatadogTraceState;
+
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let mut span = get_span(7, 1, 99);
+        span.span_context = SpanContext::new(
+            span.span_context.trace_id(),
+            span.span_context.span_id(),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default().with_user_priority_sampling(true),
+        );
+
+        for api_version in [
+            ApiVersion::Version03,
+            ApiVersion::Version04,
+            ApiVersion::Version05,
+        ] {
+            let payload = api_version
+                .encode(
+                    &mut EncoderBuffers::default(),
+                    &model_config,
+                    vec![vec![span.clone()]],
+                    &Mapping::empty(),
+                    &UnifiedTags::new(),
+                    MAX_PAYLOAD_SIZE,
+                )?
+                .remove(0)
+                .1;
+            let decoded = rmpv::decode::read_value(&mut payload.as_slice())?;
+            let priority = match api_version {
+                ApiVersion::Version05 => {
+                    let strings = decoded[0].as_array().unwrap();
+                    decoded[1][0][0][10]
+                        .as_map()
+                        .unwrap()
+                        .iter()
+                        .find(|(key, _)| {
+                            strings[key.as_u64().unwrap() as usize].as_str()
+                                == Some(SAMPLING_PRIORITY_KEY)
+                        })
+                        .and_then(|(_, value)| value.as_f64())
+                }
+                _ => decoded[0][0]
+                    .as_map()
+                    .unwrap()
+                    .iter()
+                    .find(|(key, _)| key.as_str() == Some("metrics"))
+                    .and_then(|(_, metrics)| {
+                        metrics
+                            .as_map()?
+                            .iter()
+                            .find(|(key, _)| key.as_str() == Some(SAMPLING_PRIORITY_KEY))
+                            .and_then(|(_, value)| value.as_f64())
+                    }),
+            };
+            assert_eq!(priority, Some(2.0), "{api_version:?}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_v03_sampling_priority_without_sampler() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+
+        for (trace_flags, expected) in [(TraceFlags::SAMPLED, 1.0), (TraceFlags::default(), 0.0)] {
+            let mut span = get_span(7, 1, 99);
+            span.span_context = SpanContext::new(
+                span.span_context.trace_id(),
+                span.span_context.span_id(),
+                trace_flags,
+                false,
+                TraceState::default(),
+            );
+            let payload = ApiVersion::Version03
+                .encode(
+                    &mut EncoderBuffers::default(),
+                    &model_config,
+                    vec![vec![span]],
+                    &Mapping::empty(),
+                    &UnifiedTags::new(),
+                    MAX_PAYLOAD_SIZE,
+                )?
+                .remove(0)
+                .1;
+            let decoded = rmpv::decode::read_value(&mut payload.as_slice())?;
+            let priority = decoded[0][0]
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(key, _)| key.as_str() == Some("metrics"))
+                .and_then(|(_, metrics)| {
+                    metrics
+                        .as_map()?
+                        .iter()
+                        .find(|(key, _)| key.as_str() == Some(SAMPLING_PRIORITY_KEY))
+                        .and_then(|(_, value)| value.as_f64())
+                });
+            assert_eq!(priority, Some(expected), "{trace_flags:?}");
+        }
+
+        Ok(())
+    }
+
     #[test]
     fn test_encode_v03() -> Result<(), Box<dyn std::error::Error>> {
         let traces = get_traces();
@@ -254,12 +1187,19 @@ pub(crate) mod tests {
             service_name: "service_name".to_string(),
             ..Default::default()
         };
-        let encoded = base64::encode(ApiVersion::Version03.encode(
-            &model_config,
-            traces,
-            &Mapping::empty(),
-            &UnifiedTags::new(),
-        )?);
+        let encoded = base64::encode(
+            ApiVersion::Version03
+                .encode(
+                    &mut EncoderBuffers::default(),
+                    &model_config,
+                    traces,
+                    &Mapping::empty(),
+                    &UnifiedTags::new(),
+                    MAX_PAYLOAD_SIZE,
+                )?
+                .remove(0)
+                .1,
+        );
 
         assert_eq!(encoded.as_str(), "kZGMpHR5cGWjd2Vip3NlcnZpY2Wsc2VydmljZV9uYW1lpG5hbWWpY29tcG9uZW\
         50qHJlc291cmNlqHJlc291cmNlqHRyYWNlX2lkzwAAAAAAAAAHp3NwYW5faWTPAAAAAAAAAGOpcGFyZW50X2lkzwAAAA\
@@ -282,12 +1222,19 @@ pub(crate) mod tests {
         unified_tags.set_version(Some(String::from("test-version")));
         unified_tags.set_service(Some(String::from("test-service")));
 
-        let _encoded = base64::encode(ApiVersion::Version05.encode(
-            &model_config,
-            traces,
-            &Mapping::empty(),
-            &unified_tags,
-        )?);
+        let _encoded = base64::encode(
+            ApiVersion::Version05
+                .encode(
+                    &mut EncoderBuffers::default(),
+                    &model_config,
+                    traces,
+                    &Mapping::empty(),
+                    &unified_tags,
+                    MAX_PAYLOAD_SIZE,
+                )?
+                .remove(0)
+                .1,
+        );
 
         // TODO: Need someone to generate the expected result or instructions to do so.
         // assert_eq!(encoded.as_str(), "kp6jd2VirHNlcnZpY2VfbmFtZaljb21wb25lbnSocmVzb3VyY2WpaG9zdC5uYW\
@@ -0,0 +1,565 @@
+use crate::exporter::ModelConfig;
+use opentelemetry::trace::SpanKind;
+use opentelemetry_sdk::export::trace::SpanData;
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+/// Datadog span fields [`MappingRules`] can derive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DatadogField {
+    /// The `service` of the span
+    Service,
+    /// The `name` of the span, also known as operation name
+    Name,
+    /// The `resource` of the span
+    Resource,
+    /// The `type` of the span
+    Type,
+}
+
+/// A mapping rule, returning the value of a field for the spans it applies to and `None` for the
+/// others.
+pub type MappingRuleFn =
+    dyn for<'a> Fn(&'a SpanData, &'a ModelConfig) -> Option<Cow<'a, str>> + Send + Sync;
+
+#[derive(Clone)]
+struct MappingRule {
+    field: DatadogField,
+    name: Cow<'static, str>,
+    apply: Arc<MappingRuleFn>,
+}
+
+/// Declarative mapping of OpenTelemetry spans to the Datadog `service`, `name`, `resource` and
+/// `type` fields.
+///
+/// Each field has an ordered list of named rules. The first rule returning a value sets the field,
+/// otherwise the default mapping described in [`FieldMappingFn`](crate::FieldMappingFn) applies. A custom
+/// mapping function, such as the one set with
+/// [`with_resource_mapping`](crate::DatadogPipelineBuilder::with_resource_mapping), takes
+/// precedence over the rules.
+///
+/// [`MappingRules::otlp`] follows the rules the Datadog agent applies to the spans it receives
+/// over OTLP, see
+/// <https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/traceutil/otel_util.go>
+///
+/// |field|rule|value|
+/// |-----|----|-----|
+/// |service|`peer.service`|the `peer.service` attribute of client and producer spans|
+/// |name|`operation.name`|the `operation.name` attribute|
+/// |name|`http`|`http.server.request` or `http.client.request`|
+/// |name|`database`|`<db.system>.query` for client spans|
+/// |name|`messaging`|`<messaging.system>.<messaging.operation>`|
+/// |name|`rpc`|`<rpc.system>.server.request` or `<rpc.system>.client.request`, `aws.<rpc.service>.request` for AWS calls|
+/// |name|`faas`|`<faas.trigger>.invoke` or `<faas.invoked_provider>.<faas.invoked_name>.invoke`|
+/// |name|`graphql`|`graphql.server.request`|
+/// |name|`network`|`<network.protocol.name>.server.request` or `<network.protocol.name>.client.request`|
+/// |name|`span.kind`|`server.request`, `client.request`, `producer`, `consumer` or `internal`|
+/// |resource|`resource.name`|the `resource.name` attribute|
+/// |resource|`http`|`<http.request.method>`, followed by `http.route` for server spans|
+/// |resource|`messaging`|`<messaging.operation> <messaging.destination.name>`|
+/// |resource|`rpc`|`<rpc.method> <rpc.service>`|
+/// |resource|`graphql`|`<graphql.operation.type> <graphql.operation.name>`|
+/// |resource|`database`|the `db.statement` or `db.query.text` attribute|
+/// |type|`span.type`|the `span.type` attribute|
+/// |type|`database`|`cache`, `sql` or `db` depending on `db.system` for client spans|
+/// |type|`span.kind`|`web` for server spans, `http` for client spans, `custom` otherwise|
+///
+/// Individual rules can be replaced or removed, for example
+/// ```no_run
+/// use opentelemetry_datadog::{new_pipeline, DatadogField, MappingRules};
+/// fn main() -> Result<(), opentelemetry::trace::TraceError> {
+///    let rules = MappingRules::otlp()
+///        // keep the span name as resource of database spans
+///        .without_rule(DatadogField::Resource, "database")
+///        // use a custom attribute for the service of client spans
+///        .with_rule(DatadogField::Service, "peer.service", |span, _config| {
+///            span.attributes
+///                .iter()
+///                .find(|kv| kv.key.as_str() == "my.peer")
+///                .map(|kv| kv.value.as_str())
+///        });
+///    let tracer = new_pipeline()
+///            .with_service_name("my_app")
+///            .with_mapping_rules(rules)
+///            .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+///
+///    Ok(())
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct MappingRules {
+    rules: Vec<MappingRule>,
+}
+
+impl MappingRules {
+    /// Rules without any rule, leaving every field to the default mapping.
+    pub fn empty() -> Self {
+        MappingRules::default()
+    }
+
+    /// The rules of the Datadog agent OTLP ingestion.
+    pub fn otlp() -> Self {
+        let mut rules = MappingRules::empty();
+        rules.push(DatadogField::Service, "peer.service", service_peer_service);
+        rules.push(DatadogField::Name, "operation.name", name_operation_name);
+        rules.push(DatadogField::Name, "http", name_http);
+        rules.push(DatadogField::Name, "database", name_database);
+        rules.push(DatadogField::Name, "messaging", name_messaging);
+        rules.push(DatadogField::Name, "rpc", name_rpc);
+        rules.push(DatadogField::Name, "faas", name_faas);
+        rules.push(DatadogField::Name, "graphql", name_graphql);
+        rules.push(DatadogField::Name, "network", name_network);
+        rules.push(DatadogField::Name, "span.kind", name_span_kind);
+        rules.push(
+            DatadogField::Resource,
+            "resource.name",
+            resource_resource_name,
+        );
+        rules.push(DatadogField::Resource, "http", resource_http);
+        rules.push(DatadogField::Resource, "messaging", resource_messaging);
+        rules.push(DatadogField::Resource, "rpc", resource_rpc);
+        rules.push(DatadogField::Resource, "graphql", resource_graphql);
+        rules.push(DatadogField::Resource, "database", resource_database);
+        rules.push(DatadogField::Type, "span.type", type_span_type);
+        rules.push(DatadogField::Type, "database", type_database);
+        rules.push(DatadogField::Type, "span.kind", type_span_kind);
+        rules
+    }
+
+    /// Replace the rule of `field` named `name`, or add it before the other rules of `field` if
+    /// there is none.
+    pub fn with_rule<N, F>(mut self, field: DatadogField, name: N, rule: F) -> Self
+    where
+        N: Into<Cow<'static, str>>,
+        F: for<'a> Fn(&'a SpanData, &'a ModelConfig) -> Option<Cow<'a, str>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let rule = MappingRule {
+            field,
+            name: name.into(),
+            apply: Arc::new(rule),
+        };
+        match self
+            .rules
+            .iter()
+            .position(|existing| existing.field == field && existing.name == rule.name)
+        {
+            Some(index) => self.rules[index] = rule,
+            None => {
+                let index = self
+                    .rules
+                    .iter()
+                    .position(|existing| existing.field == field)
+                    .unwrap_or(self.rules.len());
+                self.rules.insert(index, rule);
+            }
+        }
+        self
+    }
+
+    /// Remove the rule of `field` named `name`, if any.
+    pub fn without_rule(mut self, field: DatadogField, name: &str) -> Self {
+        self.rules
+            .retain(|rule| rule.field != field || rule.name != name);
+        self
+    }
+
+    /// Names of the rules of `field`, in the order they apply.
+    pub fn rule_names(&self, field: DatadogField) -> impl Iterator<Item = &str> {
+        self.rules
+            .iter()
+            .filter(move |rule| rule.field == field)
+            .map(|rule| rule.name.as_ref())
+    }
+
+    /// The value of the first rule of `field` applying to `span`.
+    pub(crate) fn apply<'a>(
+        &self,
+        field: DatadogField,
+        span: &'a SpanData,
+        config: &'a ModelConfig,
+    ) -> Option<Cow<'a, str>> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.field == field)
+            .find_map(|rule| (rule.apply)(span, config))
+    }
+
+    fn push(
+        &mut self,
+        field: DatadogField,
+        name: &'static str,
+        rule: for<'a> fn(&'a SpanData, &'a ModelConfig) -> Option<Cow<'a, str>>,
+    ) {
+        self.rules.push(MappingRule {
+            field,
+            name: Cow::Borrowed(name),
+            apply: Arc::new(rule),
+        });
+    }
+}
+
+impl Debug for MappingRules {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(
+                self.rules
+                    .iter()
+                    .map(|rule| format!("{:?}: {}", rule.field, rule.name)),
+            )
+            .finish()
+    }
+}
+
+fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<Cow<'a, str>> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| kv.value.as_str())
+        .filter(|value| !value.is_empty())
+}
+
+fn http_method(span: &SpanData) -> Option<Cow<'_, str>> {
+    attribute(span, "http.request.method").or_else(|| attribute(span, "http.method"))
+}
+
+fn service_peer_service<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    match span.span_kind {
+        SpanKind::Client | SpanKind::Producer => attribute(span, "peer.service"),
+        _ => None,
+    }
+}
+
+fn name_operation_name<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    attribute(span, "operation.name")
+}
+
+fn name_http<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    http_method(span)?;
+    match span.span_kind {
+        SpanKind::Server => Some("http.server.request".into()),
+        SpanKind::Client => Some("http.client.request".into()),
+        _ => None,
+    }
+}
+
+fn name_database<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    match span.span_kind {
+        SpanKind::Client => Some(format!("{}.query", attribute(span, "db.system")?).into()),
+        _ => None,
+    }
+}
+
+fn name_messaging<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    if span.span_kind == SpanKind::Internal {
+        return None;
+    }
+    let system = attribute(span, "messaging.system")?;
+    let operation = attribute(span, "messaging.operation")?;
+    Some(format!("{}.{}", system, operation).into())
+}
+
+fn name_rpc<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    let system = attribute(span, "rpc.system")?;
+    match span.span_kind {
+        SpanKind::Client if system == "aws-api" => match attribute(span, "rpc.service") {
+            Some(service) => Some(format!("aws.{}.request", service.to_lowercase()).into()),
+            None => Some("aws.client.request".into()),
+        },
+        SpanKind::Client => Some(format!("{}.client.request", system).into()),
+        SpanKind::Server => Some(format!("{}.server.request", system).into()),
+        _ => None,
+    }
+}
+
+fn name_faas<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    match span.span_kind {
+        SpanKind::Server => Some(format!("{}.invoke", attribute(span, "faas.trigger")?).into()),
+        SpanKind::Client => {
+            let provider = attribute(span, "faas.invoked_provider")?;
+            let name = attribute(span, "faas.invoked_name")?;
+            Some(format!("{}.{}.invoke", provider, name).into())
+        }
+        _ => None,
+    }
+}
+
+fn name_graphql<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    attribute(span, "graphql.operation.type").map(|_| "graphql.server.request".into())
+}
+
+fn name_network<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    let protocol = attribute(span, "network.protocol.name")?;
+    match span.span_kind {
+        SpanKind::Server => Some(format!("{}.server.request", protocol).into()),
+        SpanKind::Client => Some(format!("{}.client.request", protocol).into()),
+        _ => None,
+    }
+}
+
+fn name_span_kind<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    Some(
+        match span.span_kind {
+            SpanKind::Server => "server.request",
+            SpanKind::Client => "client.request",
+            SpanKind::Producer => "producer",
+            SpanKind::Consumer => "consumer",
+            SpanKind::Internal => "internal",
+        }
+        .into(),
+    )
+}
+
+fn resource_resource_name<'a>(
+    span: &'a SpanData,
+    _config: &'a ModelConfig,
+) -> Option<Cow<'a, str>> {
+    attribute(span, "resource.name")
+}
+
+fn resource_http<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    let method = http_method(span)?;
+    // unknown methods are reported as `_OTHER` by the semantic conventions
+    let method = if method == "_OTHER" {
+        "HTTP".into()
+    } else {
+        method
+    };
+    match (&span.span_kind, attribute(span, "http.route")) {
+        (SpanKind::Server, Some(route)) => Some(format!("{} {}", method, route).into()),
+        _ => Some(method),
+    }
+}
+
+fn resource_messaging<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    let operation = attribute(span, "messaging.operation")?;
+    match attribute(span, "messaging.destination.name") {
+        Some(destination) => Some(format!("{} {}", operation, destination).into()),
+        None => Some(operation),
+    }
+}
+
+fn resource_rpc<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    let method = attribute(span, "rpc.method")?;
+    match attribute(span, "rpc.service") {
+        Some(service) => Some(format!("{} {}", method, service).into()),
+        None => Some(method),
+    }
+}
+
+fn resource_graphql<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    let operation_type = attribute(span, "graphql.operation.type")?;
+    match attribute(span, "graphql.operation.name") {
+        Some(name) => Some(format!("{} {}", operation_type, name).into()),
+        None => Some(operation_type),
+    }
+}
+
+fn resource_database<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    attribute(span, "db.system")?;
+    attribute(span, "db.query.text").or_else(|| attribute(span, "db.statement"))
+}
+
+fn type_span_type<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    attribute(span, "span.type")
+}
+
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/trace/traceutil/otel_util.go#L212
+static CACHE_DB_SYSTEMS: [&str; 2] = ["redis", "memcached"];
+static SQL_DB_SYSTEMS: [&str; 36] = [
+    "other_sql",
+    "mssql",
+    "mysql",
+    "oracle",
+    "db2",
+    "postgresql",
+    "redshift",
+    "cloudscape",
+    "hsqldb",
+    "maxdb",
+    "ingres",
+    "firstsql",
+    "edb",
+    "cache",
+    "firebird",
+    "derby",
+    "informix",
+    "mariadb",
+    "sqlite",
+    "sybase",
+    "teradata",
+    "vertica",
+    "h2",
+    "coldfusion",
+    "cockroachdb",
+    "progress",
+    "hanadb",
+    "adabas",
+    "filemaker",
+    "instantdb",
+    "interbase",
+    "netezza",
+    "pervasive",
+    "pointbase",
+    "clickhouse",
+    "trino",
+];
+
+fn type_database<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    if span.span_kind != SpanKind::Client {
+        return None;
+    }
+    let system = attribute(span, "db.system")?;
+    Some(
+        if CACHE_DB_SYSTEMS.contains(&system.as_ref()) {
+            "cache"
+        } else if SQL_DB_SYSTEMS.contains(&system.as_ref()) {
+            "sql"
+        } else {
+            "db"
+        }
+        .into(),
+    )
+}
+
+fn type_span_kind<'a>(span: &'a SpanData, _config: &'a ModelConfig) -> Option<Cow<'a, str>> {
+    Some(
+        match span.span_kind {
+            SpanKind::Server => "web",
+            SpanKind::Client => "http",
+            _ => "custom",
+        }
+        .into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::model::tests::get_span;
+    use opentelemetry::KeyValue;
+
+    fn span(kind: SpanKind, attributes: Vec<KeyValue>) -> SpanData {
+        let mut span = get_span(7, 1, 99);
+        span.span_kind = kind;
+        span.attributes = attributes;
+        span
+    }
+
+    fn apply(rules: &MappingRules, field: DatadogField, span: &SpanData) -> Option<String> {
+        let config = ModelConfig {
+            service_name: "service".to_string(),
+        };
+        rules
+            .apply(field, span, &config)
+            .map(|value| value.into_owned())
+    }
+
+    #[test]
+    fn test_otlp_rules() {
+        let rules = MappingRules::otlp();
+
+        let server = span(
+            SpanKind::Server,
+            vec![
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("http.route", "/users/:id"),
+            ],
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Name, &server).as_deref(),
+            Some("http.server.request")
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Resource, &server).as_deref(),
+            Some("GET /users/:id")
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Type, &server).as_deref(),
+            Some("web")
+        );
+        assert_eq!(apply(&rules, DatadogField::Service, &server), None);
+
+        let database = span(
+            SpanKind::Client,
+            vec![
+                KeyValue::new("db.system", "postgresql"),
+                KeyValue::new("db.statement", "SELECT 1"),
+                KeyValue::new("peer.service", "users-db"),
+            ],
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Name, &database).as_deref(),
+            Some("postgresql.query")
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Resource, &database).as_deref(),
+            Some("SELECT 1")
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Type, &database).as_deref(),
+            Some("sql")
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Service, &database).as_deref(),
+            Some("users-db")
+        );
+
+        let internal = span(SpanKind::Internal, vec![]);
+        assert_eq!(
+            apply(&rules, DatadogField::Name, &internal).as_deref(),
+            Some("internal")
+        );
+        assert_eq!(apply(&rules, DatadogField::Resource, &internal), None);
+        assert_eq!(
+            apply(&rules, DatadogField::Type, &internal).as_deref(),
+            Some("custom")
+        );
+    }
+
+    #[test]
+    fn test_override_rules() {
+        let rules = MappingRules::otlp()
+            .without_rule(DatadogField::Type, "database")
+            .with_rule(DatadogField::Resource, "http", |span, _config| {
+                attribute(span, "http.route")
+            })
+            .with_rule(DatadogField::Name, "custom", |_span, _config| {
+                Some("custom".into())
+            });
+
+        assert_eq!(
+            rules.rule_names(DatadogField::Type).collect::<Vec<_>>(),
+            vec!["span.type", "span.kind"]
+        );
+        assert_eq!(rules.rule_names(DatadogField::Name).next(), Some("custom"));
+        assert_eq!(
+            rules.rule_names(DatadogField::Resource).nth(1),
+            Some("http")
+        );
+
+        let client = span(
+            SpanKind::Client,
+            vec![
+                KeyValue::new("db.system", "redis"),
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("http.route", "/users"),
+            ],
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Name, &client).as_deref(),
+            Some("custom")
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Resource, &client).as_deref(),
+            Some("/users")
+        );
+        assert_eq!(
+            apply(&rules, DatadogField::Type, &client).as_deref(),
+            Some("http")
+        );
+    }
+}
@@ -4,6 +4,8 @@ pub struct UnifiedTags {
     pub service: UnifiedTagField,
     pub env: UnifiedTagField,
     pub version: UnifiedTagField,
+    /// Global tags added to every span, e.g. from `DD_TAGS`
+    pub tags: Vec<(String, String)>,
 }
 
 impl UnifiedTags {
@@ -12,6 +14,7 @@ impl UnifiedTags {
             service: UnifiedTagField::new(UnifiedTagEnum::Service),
             env: UnifiedTagField::new(UnifiedTagEnum::Env),
             version: UnifiedTagField::new(UnifiedTagEnum::Version),
+            tags: Vec::new(),
         }
     }
     pub fn set_service(&mut self, service: Option<String>) {
@@ -23,11 +26,14 @@ impl UnifiedTags {
     pub fn set_env(&mut self, env: Option<String>) {
         self.env.value = env;
     }
+    pub fn add_tag(&mut self, key: String, value: String) {
+        self.tags.push((key, value));
+    }
     pub fn service(&self) -> Option<String> {
         self.service.value.clone()
     }
     pub fn compute_attribute_size(&self) -> u32 {
-        self.service.len() + self.env.len() + self.version.len()
+        self.service.len() + self.env.len() + self.version.len() + self.tags.len() as u32
     }
 }
 
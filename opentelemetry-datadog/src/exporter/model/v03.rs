@@ -1,26 +1,36 @@
-use crate::exporter::model::{Error, SAMPLING_PRIORITY_KEY};
+use crate::exporter::model::unified_tags::UnifiedTags;
+use crate::exporter::model::{
+    dd_span_id, dd_trace_id, encode_payloads, get_events_and_links_meta, get_sampling_rate,
+    get_trace_id_high, get_trace_state_meta, Error, PayloadCounts, DD_TRACE_ID_HIGH_KEY,
+    SAMPLING_PRIORITY_KEY,
+};
 use crate::exporter::ModelConfig;
+#[cfg(feature = "agent-sampling")]
+use crate::propagator::explicit_sampling_priority;
 use opentelemetry::trace::Status;
 use opentelemetry_sdk::export::trace::SpanData;
+use std::borrow::Cow;
 use std::time::SystemTime;
 
-pub(crate) fn encode<S, N, R>(
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode<S, N, R, T>(
     model_config: &ModelConfig,
     traces: Vec<Vec<SpanData>>,
     get_service_name: S,
     get_name: N,
     get_resource: R,
-) -> Result<Vec<u8>, Error>
+    get_span_type: T,
+    unified_tags: &UnifiedTags,
+    max_payload_size: usize,
+) -> Result<Vec<(PayloadCounts, Vec<u8>)>, Error>
 where
-    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
+    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> T: Fn(&'a SpanData, &'a ModelConfig) -> Option<Cow<'a, str>>,
 {
-    let mut encoded = Vec::new();
-    rmp::encode::write_array_len(&mut encoded, traces.len() as u32)?;
-
-    for trace in traces.into_iter() {
-        rmp::encode::write_array_len(&mut encoded, trace.len() as u32)?;
+    encode_payloads(traces, max_payload_size, |encoded, trace| {
+        rmp::encode::write_array_len(encoded, trace.len() as u32)?;
 
         for span in trace.into_iter() {
             // Safe until the year 2262 when Datadog will need to change their API
@@ -36,91 +46,113 @@ where
                 .map(|x| x.as_nanos() as i64)
                 .unwrap_or(0);
 
-            let mut span_type_found = false;
-            for kv in &span.attributes {
-                if kv.key.as_str() == "span.type" {
-                    span_type_found = true;
-                    rmp::encode::write_map_len(&mut encoded, 12)?;
-                    rmp::encode::write_str(&mut encoded, "type")?;
-                    rmp::encode::write_str(&mut encoded, kv.value.as_str().as_ref())?;
-                    break;
-                }
-            }
-
-            if !span_type_found {
-                rmp::encode::write_map_len(&mut encoded, 11)?;
+            if let Some(span_type) = get_span_type(&span, model_config) {
+                rmp::encode::write_map_len(encoded, 12)?;
+                rmp::encode::write_str(encoded, "type")?;
+                rmp::encode::write_str(encoded, &span_type)?;
+            } else {
+                rmp::encode::write_map_len(encoded, 11)?;
             }
 
             // Datadog span name is OpenTelemetry component name - see module docs for more information
-            rmp::encode::write_str(&mut encoded, "service")?;
-            rmp::encode::write_str(&mut encoded, get_service_name(&span, model_config))?;
+            rmp::encode::write_str(encoded, "service")?;
+            rmp::encode::write_str(encoded, &get_service_name(&span, model_config))?;
 
-            rmp::encode::write_str(&mut encoded, "name")?;
-            rmp::encode::write_str(&mut encoded, get_name(&span, model_config))?;
+            rmp::encode::write_str(encoded, "name")?;
+            rmp::encode::write_str(encoded, &get_name(&span, model_config))?;
 
-            rmp::encode::write_str(&mut encoded, "resource")?;
-            rmp::encode::write_str(&mut encoded, get_resource(&span, model_config))?;
+            rmp::encode::write_str(encoded, "resource")?;
+            rmp::encode::write_str(encoded, &get_resource(&span, model_config))?;
 
-            rmp::encode::write_str(&mut encoded, "trace_id")?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u128::from_be_bytes(span.span_context.trace_id().to_bytes()) as u64,
-            )?;
+            rmp::encode::write_str(encoded, "trace_id")?;
+            rmp::encode::write_u64(encoded, dd_trace_id(span.span_context.trace_id()))?;
 
-            rmp::encode::write_str(&mut encoded, "span_id")?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u64::from_be_bytes(span.span_context.span_id().to_bytes()),
-            )?;
+            rmp::encode::write_str(encoded, "span_id")?;
+            rmp::encode::write_u64(encoded, dd_span_id(span.span_context.span_id()))?;
 
-            rmp::encode::write_str(&mut encoded, "parent_id")?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u64::from_be_bytes(span.parent_span_id.to_bytes()),
-            )?;
+            rmp::encode::write_str(encoded, "parent_id")?;
+            rmp::encode::write_u64(encoded, dd_span_id(span.parent_span_id))?;
 
-            rmp::encode::write_str(&mut encoded, "start")?;
-            rmp::encode::write_i64(&mut encoded, start)?;
+            rmp::encode::write_str(encoded, "start")?;
+            rmp::encode::write_i64(encoded, start)?;
 
-            rmp::encode::write_str(&mut encoded, "duration")?;
-            rmp::encode::write_i64(&mut encoded, duration)?;
+            rmp::encode::write_str(encoded, "duration")?;
+            rmp::encode::write_i64(encoded, duration)?;
 
-            rmp::encode::write_str(&mut encoded, "error")?;
+            rmp::encode::write_str(encoded, "error")?;
             rmp::encode::write_i32(
-                &mut encoded,
+                encoded,
                 match span.status {
                     Status::Error { .. } => 1,
                     _ => 0,
                 },
             )?;
 
-            rmp::encode::write_str(&mut encoded, "meta")?;
+            let trace_id_high = get_trace_id_high(&span);
+            let trace_state_meta = get_trace_state_meta(&span);
+            let events_and_links_meta = get_events_and_links_meta(&span);
+            let sampling_rates: Vec<_> = span
+                .attributes
+                .iter()
+                .filter_map(get_sampling_rate)
+                .collect();
+
+            rmp::encode::write_str(encoded, "meta")?;
             rmp::encode::write_map_len(
-                &mut encoded,
-                (span.attributes.len() + span.resource.len()) as u32,
+                encoded,
+                (span.attributes.len() - sampling_rates.len()
+                    + span.resource.len()
+                    + unified_tags.tags.len()) as u32
+                    + trace_id_high.is_some() as u32
+                    + trace_state_meta.len() as u32
+                    + events_and_links_meta.len() as u32,
             )?;
             for (key, value) in span.resource.iter() {
-                rmp::encode::write_str(&mut encoded, key.as_str())?;
-                rmp::encode::write_str(&mut encoded, value.as_str().as_ref())?;
+                rmp::encode::write_str(encoded, key.as_str())?;
+                rmp::encode::write_str(encoded, value.as_str().as_ref())?;
             }
-            for kv in span.attributes.iter() {
-                rmp::encode::write_str(&mut encoded, kv.key.as_str())?;
-                rmp::encode::write_str(&mut encoded, kv.value.as_str().as_ref())?;
+            for (key, value) in unified_tags.tags.iter() {
+                rmp::encode::write_str(encoded, key)?;
+                rmp::encode::write_str(encoded, value)?;
+            }
+            for kv in span
+                .attributes
+                .iter()
+                .filter(|kv| get_sampling_rate(kv).is_none())
+            {
+                rmp::encode::write_str(encoded, kv.key.as_str())?;
+                rmp::encode::write_str(encoded, kv.value.as_str().as_ref())?;
+            }
+            if let Some(trace_id_high) = &trace_id_high {
+                rmp::encode::write_str(encoded, DD_TRACE_ID_HIGH_KEY)?;
+                rmp::encode::write_str(encoded, trace_id_high)?;
+            }
+            for (key, value) in trace_state_meta.iter() {
+                rmp::encode::write_str(encoded, key)?;
+                rmp::encode::write_str(encoded, value)?;
+            }
+            for (key, value) in events_and_links_meta.iter() {
+                rmp::encode::write_str(encoded, key)?;
+                rmp::encode::write_str(encoded, value)?;
             }
 
-            rmp::encode::write_str(&mut encoded, "metrics")?;
-            rmp::encode::write_map_len(&mut encoded, 1)?;
-            rmp::encode::write_str(&mut encoded, SAMPLING_PRIORITY_KEY)?;
-            rmp::encode::write_f64(
-                &mut encoded,
-                if span.span_context.is_sampled() {
-                    1.0
-                } else {
-                    0.0
-                },
-            )?;
+            rmp::encode::write_str(encoded, "metrics")?;
+            rmp::encode::write_map_len(encoded, 1 + sampling_rates.len() as u32)?;
+            rmp::encode::write_str(encoded, SAMPLING_PRIORITY_KEY)?;
+            // spans no Datadog sampler decided on keep the priority of their sampled flag
+            let sampled = i32::from(span.span_context.is_sampled());
+            #[cfg(feature = "agent-sampling")]
+            let sampling_priority =
+                explicit_sampling_priority(span.span_context.trace_state()).unwrap_or(sampled);
+            #[cfg(not(feature = "agent-sampling"))]
+            let sampling_priority = sampled;
+            rmp::encode::write_f64(encoded, sampling_priority.into())?;
+            for (key, rate) in sampling_rates {
+                rmp::encode::write_str(encoded, key)?;
+                rmp::encode::write_f64(encoded, rate)?;
+            }
         }
-    }
 
-    Ok(encoded)
+        Ok(())
+    })
 }
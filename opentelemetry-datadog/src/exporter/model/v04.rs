@@ -0,0 +1,629 @@
+use crate::exporter::model::{
+    dd_span_id, dd_trace_id, encode_payloads, get_events_and_links_meta, get_measuring,
+    get_sampling_priority, get_trace_id_high, get_trace_state_meta, PayloadCounts, DD_MEASURED_KEY,
+    DD_TRACE_ID_HIGH_KEY, SAMPLING_PRIORITY_KEY,
+};
+use crate::exporter::{Error, ModelConfig};
+use opentelemetry::trace::Status;
+use opentelemetry::{Array, Value};
+use opentelemetry_sdk::export::trace::SpanData;
+use std::borrow::Cow;
+use std::time::SystemTime;
+
+use super::unified_tags::UnifiedTags;
+
+/// Span properties which are always present in a span map.
+const SPAN_NUM_ELEMENTS: u32 = 12;
+
+/// Attributes of a span, split by the type Datadog expects them in.
+#[derive(Default)]
+pub(super) struct TypedAttributes<'a> {
+    pub(super) meta: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    pub(super) metrics: Vec<(&'a str, f64)>,
+    pub(super) meta_struct: Vec<(&'a str, &'a Array)>,
+}
+
+impl<'a> TypedAttributes<'a> {
+    /// Collect the resource, span attributes and Datadog specific tags of a span.
+    pub(super) fn from_span(span: &'a SpanData, unified_tags: &'a UnifiedTags) -> Self {
+        let mut attributes = TypedAttributes::default();
+        for (key, value) in span.resource.iter() {
+            attributes.push(key.as_str(), value);
+        }
+        for tag in [
+            &unified_tags.service,
+            &unified_tags.env,
+            &unified_tags.version,
+        ] {
+            if let Some(tag_value) = &tag.value {
+                attributes.meta.push((
+                    Cow::Borrowed(tag.get_tag_name()),
+                    Cow::Borrowed(tag_value.as_str()),
+                ));
+            }
+        }
+        for (key, value) in unified_tags.tags.iter() {
+            attributes
+                .meta
+                .push((Cow::Borrowed(key.as_str()), Cow::Borrowed(value.as_str())));
+        }
+        for kv in span.attributes.iter() {
+            attributes.push(kv.key.as_str(), &kv.value);
+        }
+        if let (Some(repository_url), Some(commit_sha)) = (
+            option_env!("DD_GIT_REPOSITORY_URL"),
+            option_env!("DD_GIT_COMMIT_SHA"),
+        ) {
+            attributes.meta.push((
+                Cow::Borrowed("git.repository_url"),
+                Cow::Borrowed(repository_url),
+            ));
+            attributes
+                .meta
+                .push((Cow::Borrowed("git.commit.sha"), Cow::Borrowed(commit_sha)));
+        }
+        if let Some(trace_id_high) = get_trace_id_high(span) {
+            attributes.meta.push((
+                Cow::Borrowed(DD_TRACE_ID_HIGH_KEY),
+                Cow::Owned(trace_id_high),
+            ));
+        }
+        for (key, value) in get_trace_state_meta(span) {
+            attributes.meta.push((Cow::Owned(key), Cow::Owned(value)));
+        }
+        for (key, value) in get_events_and_links_meta(span) {
+            attributes
+                .meta
+                .push((Cow::Borrowed(key), Cow::Owned(value)));
+        }
+        attributes
+            .metrics
+            .push((SAMPLING_PRIORITY_KEY, get_sampling_priority(span)));
+        attributes
+            .metrics
+            .push((DD_MEASURED_KEY, get_measuring(span)));
+        attributes
+    }
+
+    fn push(&mut self, key: &'a str, value: &'a Value) {
+        match value {
+            Value::I64(v) => self.metrics.push((key, *v as f64)),
+            Value::F64(v) => self.metrics.push((key, *v)),
+            Value::Array(array) => self.meta_struct.push((key, array)),
+            Value::Bool(_) | Value::String(_) => {
+                self.meta.push((Cow::Borrowed(key), value.as_str()))
+            }
+        }
+    }
+}
+
+// Protocol documentation sourced from https://github.com/DataDog/datadog-agent/blob/c076ea9a1ffbde4c76d35343dbc32aecbbf99cb9/pkg/trace/api/version.go
+//
+// The payload is an array of traces, each trace being an array of spans. Each span is a map,
+// the same as in v0.3, with a `metrics` map holding numeric tags and a `meta_struct` map
+// holding structured tags as message pack encoded bytes.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode<S, N, R, T>(
+    model_config: &ModelConfig,
+    traces: Vec<Vec<SpanData>>,
+    get_service_name: S,
+    get_name: N,
+    get_resource: R,
+    get_span_type: T,
+    unified_tags: &UnifiedTags,
+    max_payload_size: usize,
+) -> Result<Vec<(PayloadCounts, Vec<u8>)>, Error>
+where
+    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> T: Fn(&'a SpanData, &'a ModelConfig) -> Option<Cow<'a, str>>,
+{
+    encode_payloads(traces, max_payload_size, |encoded, trace| {
+        rmp::encode::write_array_len(encoded, trace.len() as u32)?;
+
+        for span in trace.into_iter() {
+            // Safe until the year 2262 when Datadog will need to change their API
+            let start = span
+                .start_time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as i64;
+
+            let duration = span
+                .end_time
+                .duration_since(span.start_time)
+                .map(|x| x.as_nanos() as i64)
+                .unwrap_or(0);
+
+            let span_type = get_span_type(&span, model_config);
+
+            let attributes = TypedAttributes::from_span(&span, unified_tags);
+
+            let mut span_len = SPAN_NUM_ELEMENTS;
+            if span_type.is_none() {
+                span_len -= 1;
+            }
+            if !attributes.meta_struct.is_empty() {
+                span_len += 1;
+            }
+            rmp::encode::write_map_len(encoded, span_len)?;
+
+            if let Some(span_type) = span_type {
+                rmp::encode::write_str(encoded, "type")?;
+                rmp::encode::write_str(encoded, span_type.as_ref())?;
+            }
+
+            // Datadog span name is OpenTelemetry component name - see module docs for more information
+            rmp::encode::write_str(encoded, "service")?;
+            rmp::encode::write_str(encoded, &get_service_name(&span, model_config))?;
+
+            rmp::encode::write_str(encoded, "name")?;
+            rmp::encode::write_str(encoded, &get_name(&span, model_config))?;
+
+            rmp::encode::write_str(encoded, "resource")?;
+            rmp::encode::write_str(encoded, &get_resource(&span, model_config))?;
+
+            rmp::encode::write_str(encoded, "trace_id")?;
+            rmp::encode::write_u64(encoded, dd_trace_id(span.span_context.trace_id()))?;
+
+            rmp::encode::write_str(encoded, "span_id")?;
+            rmp::encode::write_u64(encoded, dd_span_id(span.span_context.span_id()))?;
+
+            rmp::encode::write_str(encoded, "parent_id")?;
+            rmp::encode::write_u64(encoded, dd_span_id(span.parent_span_id))?;
+
+            rmp::encode::write_str(encoded, "start")?;
+            rmp::encode::write_i64(encoded, start)?;
+
+            rmp::encode::write_str(encoded, "duration")?;
+            rmp::encode::write_i64(encoded, duration)?;
+
+            rmp::encode::write_str(encoded, "error")?;
+            rmp::encode::write_i32(
+                encoded,
+                match span.status {
+                    Status::Error { .. } => 1,
+                    _ => 0,
+                },
+            )?;
+
+            rmp::encode::write_str(encoded, "meta")?;
+            rmp::encode::write_map_len(encoded, attributes.meta.len() as u32)?;
+            for (key, value) in attributes.meta.iter() {
+                rmp::encode::write_str(encoded, key.as_ref())?;
+                rmp::encode::write_str(encoded, value.as_ref())?;
+            }
+
+            rmp::encode::write_str(encoded, "metrics")?;
+            rmp::encode::write_map_len(encoded, attributes.metrics.len() as u32)?;
+            for (key, value) in attributes.metrics.iter() {
+                rmp::encode::write_str(encoded, key)?;
+                rmp::encode::write_f64(encoded, *value)?;
+            }
+
+            if !attributes.meta_struct.is_empty() {
+                rmp::encode::write_str(encoded, "meta_struct")?;
+                rmp::encode::write_map_len(encoded, attributes.meta_struct.len() as u32)?;
+                let mut value = Vec::new();
+                for (key, array) in attributes.meta_struct.iter() {
+                    value.clear();
+                    write_array(&mut value, array)?;
+                    rmp::encode::write_str(encoded, key)?;
+                    rmp::encode::write_bin(encoded, &value)?;
+                }
+            }
+        }
+
+        Ok(())
+    })
+}
+
+pub(super) fn write_array(encoded: &mut Vec<u8>, array: &Array) -> Result<(), Error> {
+    match array {
+        Array::Bool(values) => {
+            rmp::encode::write_array_len(encoded, values.len() as u32)?;
+            for v in values {
+                rmp::encode::write_bool(encoded, *v).map_err(|_| Error::MessagePackError)?;
+            }
+        }
+        Array::I64(values) => {
+            rmp::encode::write_array_len(encoded, values.len() as u32)?;
+            for v in values {
+                rmp::encode::write_sint(encoded, *v)?;
+            }
+        }
+        Array::F64(values) => {
+            rmp::encode::write_array_len(encoded, values.len() as u32)?;
+            for v in values {
+                rmp::encode::write_f64(encoded, *v)?;
+            }
+        }
+        Array::String(values) => {
+            rmp::encode::write_array_len(encoded, values.len() as u32)?;
+            for v in values {
+                rmp::encode::write_str(encoded, v.as_str())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::exporter::model::tests::get_span;
+    use crate::exporter::model::unified_tags::UnifiedTags;
+    use crate::exporter::model::EncoderBuffers;
+    use crate::exporter::model::MAX_PAYLOAD_SIZE;
+    use crate::exporter::{ApiVersion, Mapping, MappingRules, ModelConfig};
+    use opentelemetry::trace::SpanKind;
+    use opentelemetry::{Array, KeyValue, Value};
+
+    fn field<'a>(map: &'a rmpv::Value, key: &str) -> Option<&'a rmpv::Value> {
+        map.as_map()?
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v)
+    }
+
+    #[test]
+    fn test_encode_with_mapping_rules() -> Result<(), Box<dyn std::error::Error>> {
+        let mut span = get_span(7, 1, 99);
+        span.span_kind = SpanKind::Server;
+        span.attributes = vec![
+            KeyValue::new("http.request.method", "GET"),
+            KeyValue::new("http.route", "/users/:id"),
+        ];
+
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let mut mapping = Mapping::empty();
+        mapping.rules = MappingRules::otlp();
+        let encoded = ApiVersion::Version04
+            .encode(
+                &mut EncoderBuffers::default(),
+                &model_config,
+                vec![vec![span]],
+                &mapping,
+                &UnifiedTags::new(),
+                MAX_PAYLOAD_SIZE,
+            )?
+            .remove(0)
+            .1;
+
+        let traces = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        let span = &traces[0][0];
+        assert_eq!(
+            field(span, "name").and_then(rmpv::Value::as_str),
+            Some("http.server.request")
+        );
+        assert_eq!(
+            field(span, "resource").and_then(rmpv::Value::as_str),
+            Some("GET /users/:id")
+        );
+        assert_eq!(
+            field(span, "type").and_then(rmpv::Value::as_str),
+            Some("web")
+        );
+        assert_eq!(
+            field(span, "service").and_then(rmpv::Value::as_str),
+            Some("service_name")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_typed_attributes() -> Result<(), Box<dyn std::error::Error>> {
+        let mut span = get_span(7, 1, 99);
+        span.attributes.push(KeyValue::new("http.status_code", 200));
+        span.attributes.push(KeyValue::new("db.row_ratio", 0.5));
+        span.attributes.push(KeyValue::new("cache.hit", true));
+        span.attributes.push(KeyValue::new(
+            "http.request.header.accept",
+            Value::Array(Array::String(vec![
+                "text/html".into(),
+                "application/json".into(),
+            ])),
+        ));
+
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let encoded = ApiVersion::Version04
+            .encode(
+                &mut EncoderBuffers::default(),
+                &model_config,
+                vec![vec![span]],
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                MAX_PAYLOAD_SIZE,
+            )?
+            .remove(0)
+            .1;
+
+        let traces = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        let span = &traces[0][0];
+
+        assert_eq!(
+            field(span, "type").and_then(rmpv::Value::as_str),
+            Some("web")
+        );
+        assert_eq!(
+            field(span, "service").and_then(rmpv::Value::as_str),
+            Some("service_name")
+        );
+        assert_eq!(
+            field(span, "trace_id").and_then(rmpv::Value::as_u64),
+            Some(7)
+        );
+
+        let meta = field(span, "meta").unwrap();
+        assert_eq!(
+            field(meta, "cache.hit").and_then(rmpv::Value::as_str),
+            Some("true")
+        );
+        assert_eq!(
+            field(meta, "span.type").and_then(rmpv::Value::as_str),
+            Some("web")
+        );
+        assert!(field(meta, "http.status_code").is_none());
+
+        let metrics = field(span, "metrics").unwrap();
+        assert_eq!(
+            field(metrics, "http.status_code").and_then(rmpv::Value::as_f64),
+            Some(200.0)
+        );
+        assert_eq!(
+            field(metrics, "db.row_ratio").and_then(rmpv::Value::as_f64),
+            Some(0.5)
+        );
+
+        let meta_struct = field(span, "meta_struct").unwrap();
+        let header = field(meta_struct, "http.request.header.accept")
+            .and_then(rmpv::Value::as_slice)
+            .unwrap();
+        let header = rmpv::decode::read_value(&mut &header[..])?;
+        assert_eq!(
+            header,
+            rmpv::Value::Array(vec!["text/html".into(), "application/json".into()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_128_bit_trace_id() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let encoded = ApiVersion::Version04
+            .encode(
+                &mut EncoderBuffers::default(),
+                &model_config,
+                vec![vec![get_span(0x640cfd8d00000000_00000000000004d2, 1, 99)]],
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                MAX_PAYLOAD_SIZE,
+            )?
+            .remove(0)
+            .1;
+
+        let traces = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        let span = &traces[0][0];
+        assert_eq!(
+            field(span, "trace_id").and_then(rmpv::Value::as_u64),
+            Some(1234)
+        );
+        let meta = field(span, "meta").unwrap();
+        assert_eq!(
+            field(meta, "_dd.p.tid").and_then(rmpv::Value::as_str),
+            Some("640cfd8d00000000")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_trace_state_meta() -> Result<(), Box<dyn std::error::Error>> {
+        use crate::propagator::with_datadog_parent_id;
+        use crate::DatadogTraceState;
+        use opentelemetry::trace::{SpanContext, SpanId};
+
+        let mut span = get_span(7, 1, 99);
+        let trace_state = span
+            .span_context
+            .trace_state()
+            .with_origin("synthetics")
+            .with_propagated_tags(&[("_dd.p.dm".to_string(), "-4".to_string())]);
+        span.span_context = SpanContext::new(
+            span.span_context.trace_id(),
+            span.span_context.span_id(),
+            span.span_context.trace_flags(),
+            false,
+            with_datadog_parent_id(&trace_state, SpanId::from_u64(1), SpanId::from_u64(0xab)),
+        );
+
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let encoded = ApiVersion::Version04
+            .encode(
+                &mut EncoderBuffers::default(),
+                &model_config,
+                vec![vec![span]],
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                MAX_PAYLOAD_SIZE,
+            )?
+            .remove(0)
+            .1;
+
+        let traces = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        let meta = field(&traces[0][0], "meta").unwrap();
+        assert_eq!(
+            field(meta, "_dd.origin").and_then(rmpv::Value::as_str),
+            Some("synthetics")
+        );
+        assert_eq!(
+            field(meta, "_dd.p.dm").and_then(rmpv::Value::as_str),
+            Some("-4")
+        );
+        assert_eq!(
+            field(meta, "_dd.parent_id").and_then(rmpv::Value::as_str),
+            Some("00000000000000ab")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_global_tags() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let mut unified_tags = UnifiedTags::new();
+        unified_tags.add_tag("team".to_string(), "apm".to_string());
+        let encoded = ApiVersion::Version04
+            .encode(
+                &mut EncoderBuffers::default(),
+                &model_config,
+                vec![vec![get_span(7, 1, 99)]],
+                &Mapping::empty(),
+                &unified_tags,
+                MAX_PAYLOAD_SIZE,
+            )?
+            .remove(0)
+            .1;
+
+        let traces = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        let meta = field(&traces[0][0], "meta").unwrap();
+        assert_eq!(
+            field(meta, "team").and_then(rmpv::Value::as_str),
+            Some("apm")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_without_meta_struct() -> Result<(), Box<dyn std::error::Error>> {
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let encoded = ApiVersion::Version04
+            .encode(
+                &mut EncoderBuffers::default(),
+                &model_config,
+                vec![vec![get_span(7, 1, 99)]],
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                MAX_PAYLOAD_SIZE,
+            )?
+            .remove(0)
+            .1;
+
+        let traces = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        assert_eq!(traces[0][0].as_map().map(Vec::len), Some(12));
+        assert!(field(&traces[0][0], "meta_struct").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_events_and_links() -> Result<(), Box<dyn std::error::Error>> {
+        use opentelemetry::trace::{
+            Event, Link, SpanContext, SpanId, Status, TraceFlags, TraceId, TraceState,
+        };
+        use std::time::{Duration, SystemTime};
+
+        let mut span = get_span(7, 1, 99);
+        span.status = Status::error("request failed");
+        span.events.events.push(Event::new(
+            "exception",
+            SystemTime::UNIX_EPOCH + Duration::from_nanos(42),
+            vec![
+                KeyValue::new("exception.type", "io::Error"),
+                KeyValue::new("exception.message", "connection reset"),
+                KeyValue::new("exception.stacktrace", "at main.rs:1"),
+            ],
+            0,
+        ));
+        span.links.links.push(Link::new(
+            SpanContext::new(
+                TraceId::from_u128(0xabc),
+                SpanId::from_u64(0xdef),
+                TraceFlags::SAMPLED,
+                true,
+                TraceState::from_key_value([("dd", "s:1")])?,
+            ),
+            vec![
+                KeyValue::new("link.kind", "follows_from"),
+                KeyValue::new("link.weight", 2),
+                KeyValue::new("link.sampled", true),
+            ],
+        ));
+
+        let model_config = ModelConfig {
+            service_name: "service_name".to_string(),
+        };
+        let encoded = ApiVersion::Version04
+            .encode(
+                &mut EncoderBuffers::default(),
+                &model_config,
+                vec![vec![span]],
+                &Mapping::empty(),
+                &UnifiedTags::new(),
+                MAX_PAYLOAD_SIZE,
+            )?
+            .remove(0)
+            .1;
+
+        let traces = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        let meta = field(&traces[0][0], "meta").unwrap();
+        let meta_json = |key| -> serde_json::Value {
+            serde_json::from_str(field(meta, key).and_then(rmpv::Value::as_str).unwrap()).unwrap()
+        };
+
+        assert_eq!(
+            meta_json("events"),
+            serde_json::json!([{
+                "time_unix_nano": 42,
+                "name": "exception",
+                "attributes": {
+                    "exception.type": "io::Error",
+                    "exception.message": "connection reset",
+                    "exception.stacktrace": "at main.rs:1",
+                },
+            }])
+        );
+        assert_eq!(
+            field(meta, "error.message").and_then(rmpv::Value::as_str),
+            Some("connection reset")
+        );
+        assert_eq!(
+            field(meta, "error.type").and_then(rmpv::Value::as_str),
+            Some("io::Error")
+        );
+        assert_eq!(
+            field(meta, "error.stack").and_then(rmpv::Value::as_str),
+            Some("at main.rs:1")
+        );
+        assert_eq!(
+            meta_json("_dd.span_links"),
+            serde_json::json!([{
+                "trace_id": "00000000000000000000000000000abc",
+                "span_id": "0000000000000def",
+                "tracestate": "dd=s:1",
+                "attributes": {
+                    "link.kind": "follows_from",
+                    "link.weight": "2",
+                    "link.sampled": "true",
+                },
+            }])
+        );
+
+        Ok(())
+    }
+}
@@ -1,9 +1,14 @@
 use crate::exporter::intern::StringInterner;
-use crate::exporter::model::{DD_MEASURED_KEY, SAMPLING_PRIORITY_KEY};
+use crate::exporter::model::{
+    dd_span_id, dd_trace_id, get_measuring, get_sampling_priority, get_sampling_rate,
+    handle_oversized_trace, visit_events_and_links_meta, visit_trace_id_high,
+    visit_trace_state_meta, MetaBuffers, PayloadCounts, DD_MEASURED_KEY, DD_TRACE_ID_HIGH_KEY,
+    SAMPLING_PRIORITY_KEY, TRACES_HEADER_SIZE,
+};
 use crate::exporter::{Error, ModelConfig};
-use crate::propagator::DatadogTraceState;
 use opentelemetry::trace::Status;
 use opentelemetry_sdk::export::trace::SpanData;
+use std::borrow::Cow;
 use std::time::SystemTime;
 
 use super::unified_tags::{UnifiedTagField, UnifiedTags};
@@ -22,6 +27,17 @@ const GIT_META_TAGS_COUNT: u32 = if matches!(
     0
 };
 
+/// Buffers of the encoder, kept by the exporter so encoding doesn't allocate once they grew
+/// large enough.
+#[derive(Default)]
+pub(crate) struct EncoderBuffers {
+    interner: StringInterner,
+    traces: Vec<u8>,
+    meta_buffers: MetaBuffers,
+    /// Interned keys and values of the meta of a span computed from its context, events and links
+    meta: Vec<(u32, u32)>,
+}
+
 // Protocol documentation sourced from https://github.com/DataDog/datadog-agent/blob/c076ea9a1ffbde4c76d35343dbc32aecbbf99cb9/pkg/trace/api/version.go
 //
 // The payload is an array containing exactly 12 elements:
@@ -67,31 +83,91 @@ const GIT_META_TAGS_COUNT: u32 = if matches!(
 //
 // 		The dictionary in this case would be []string{""}, having only the empty string at index 0.
 //
-pub(crate) fn encode<S, N, R>(
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode<S, N, R, T>(
+    buffers: &mut EncoderBuffers,
     model_config: &ModelConfig,
     traces: Vec<Vec<SpanData>>,
     get_service_name: S,
     get_name: N,
     get_resource: R,
+    get_span_type: T,
     unified_tags: &UnifiedTags,
-) -> Result<Vec<u8>, Error>
+    max_payload_size: usize,
+) -> Result<Vec<(PayloadCounts, Vec<u8>)>, Error>
 where
-    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
+    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> T: Fn(&'a SpanData, &'a ModelConfig) -> Option<Cow<'a, str>>,
 {
-    let mut interner = StringInterner::new();
-    let mut encoded_traces = encode_traces(
-        &mut interner,
-        model_config,
-        get_service_name,
-        get_name,
-        get_resource,
-        traces,
-        unified_tags,
-    )?;
+    let mut payloads = Vec::new();
+    let EncoderBuffers {
+        interner,
+        traces: encoded_traces,
+        meta_buffers,
+        meta,
+    } = buffers;
+    interner.clear();
+    encoded_traces.clear();
+    let mut counts = PayloadCounts::default();
+    for trace in traces.into_iter() {
+        let interned = interner.len();
+        let start = encoded_traces.len();
+        let mut encode = |encoded_traces: &mut Vec<u8>, interner: &mut StringInterner| {
+            encode_trace(
+                encoded_traces,
+                interner,
+                meta_buffers,
+                meta,
+                model_config,
+                &get_service_name,
+                &get_name,
+                &get_resource,
+                &get_span_type,
+                &trace,
+                unified_tags,
+            )
+        };
+        encode(encoded_traces, interner)?;
+
+        if payload_size(interner, encoded_traces) > max_payload_size && counts.traces > 0 {
+            // the strings of the trace are interned again in the dictionary of the next payload
+            interner.truncate(interned);
+            encoded_traces.truncate(start);
+            payloads.push((counts, payload(interner, encoded_traces, counts.traces)?));
+            interner.clear();
+            encoded_traces.clear();
+            counts = PayloadCounts::default();
+            encode(encoded_traces, interner)?;
+        }
+        if payload_size(interner, encoded_traces) > max_payload_size {
+            handle_oversized_trace(payload_size(interner, encoded_traces), max_payload_size);
+            interner.clear();
+            encoded_traces.clear();
+            continue;
+        }
+        counts.traces += 1;
+        counts.spans += trace.len();
+    }
+    if counts.traces > 0 {
+        payloads.push((counts, payload(interner, encoded_traces, counts.traces)?));
+    }
 
-    let mut payload = Vec::new();
+    Ok(payloads)
+}
+
+/// Size of the payload holding the dictionary and encoded traces, with the largest headers.
+fn payload_size(interner: &StringInterner, encoded_traces: &[u8]) -> usize {
+    1 + TRACES_HEADER_SIZE + interner.encoded_size() + TRACES_HEADER_SIZE + encoded_traces.len()
+}
+
+fn payload(
+    interner: &StringInterner,
+    encoded_traces: &[u8],
+    trace_count: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut payload = Vec::with_capacity(payload_size(interner, encoded_traces));
     rmp::encode::write_array_len(&mut payload, 2)?;
 
     rmp::encode::write_array_len(&mut payload, interner.len())?;
@@ -99,7 +175,8 @@ where
         rmp::encode::write_str(&mut payload, data)?;
     }
 
-    payload.append(&mut encoded_traces);
+    rmp::encode::write_array_len(&mut payload, trace_count as u32)?;
+    payload.extend_from_slice(encoded_traces);
 
     Ok(payload)
 }
@@ -112,6 +189,10 @@ fn write_unified_tags(
     write_unified_tag(encoded, interner, &unified_tags.service)?;
     write_unified_tag(encoded, interner, &unified_tags.env)?;
     write_unified_tag(encoded, interner, &unified_tags.version)?;
+    for (key, value) in unified_tags.tags.iter() {
+        rmp::encode::write_u32(encoded, interner.intern(key))?;
+        rmp::encode::write_u32(encoded, interner.intern(value))?;
+    }
     Ok(())
 }
 
@@ -122,147 +203,140 @@ fn write_unified_tag(
 ) -> Result<(), Error> {
     if let Some(tag_value) = &tag.value {
         rmp::encode::write_u32(encoded, interner.intern(tag.get_tag_name()))?;
-        rmp::encode::write_u32(encoded, interner.intern(tag_value.as_str().as_ref()))?;
+        rmp::encode::write_u32(encoded, interner.intern(tag_value))?;
     }
     Ok(())
 }
 
-#[cfg(not(feature = "agent-sampling"))]
-fn get_sampling_priority(_span: &SpanData) -> f64 {
-    1.0
-}
-
-#[cfg(feature = "agent-sampling")]
-fn get_sampling_priority(span: &SpanData) -> f64 {
-    if span.span_context.trace_state().priority_sampling_enabled() {
-        1.0
-    } else {
-        0.0
-    }
-}
-
-fn get_measuring(span: &SpanData) -> f64 {
-    if span.span_context.trace_state().measuring_enabled() {
-        1.0
-    } else {
-        0.0
-    }
-}
-
-fn encode_traces<S, N, R>(
+#[allow(clippy::too_many_arguments)]
+fn encode_trace<S, N, R, T>(
+    encoded: &mut Vec<u8>,
     interner: &mut StringInterner,
+    meta_buffers: &mut MetaBuffers,
+    meta: &mut Vec<(u32, u32)>,
     model_config: &ModelConfig,
-    get_service_name: S,
-    get_name: N,
-    get_resource: R,
-    traces: Vec<Vec<SpanData>>,
+    get_service_name: &S,
+    get_name: &N,
+    get_resource: &R,
+    get_span_type: &T,
+    trace: &[SpanData],
     unified_tags: &UnifiedTags,
-) -> Result<Vec<u8>, Error>
+) -> Result<(), Error>
 where
-    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
-    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> &'a str,
+    for<'a> S: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> N: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> R: Fn(&'a SpanData, &'a ModelConfig) -> Cow<'a, str>,
+    for<'a> T: Fn(&'a SpanData, &'a ModelConfig) -> Option<Cow<'a, str>>,
 {
-    let mut encoded = Vec::new();
-    rmp::encode::write_array_len(&mut encoded, traces.len() as u32)?;
+    rmp::encode::write_array_len(encoded, trace.len() as u32)?;
 
-    for trace in traces.into_iter() {
-        rmp::encode::write_array_len(&mut encoded, trace.len() as u32)?;
+    for span in trace.iter() {
+        // Safe until the year 2262 when Datadog will need to change their API
+        let start = span
+            .start_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as i64;
 
-        for span in trace.into_iter() {
-            // Safe until the year 2262 when Datadog will need to change their API
-            let start = span
-                .start_time
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_nanos() as i64;
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .map(|x| x.as_nanos() as i64)
+            .unwrap_or(0);
 
-            let duration = span
-                .end_time
-                .duration_since(span.start_time)
-                .map(|x| x.as_nanos() as i64)
-                .unwrap_or(0);
+        let span_type = interner.intern(
+            get_span_type(span, model_config)
+                .as_deref()
+                .unwrap_or_default(),
+        );
 
-            let mut span_type = interner.intern("");
-            for kv in &span.attributes {
-                if kv.key.as_str() == "span.type" {
-                    span_type = interner.intern(kv.value.as_str().as_ref());
-                    break;
-                }
-            }
+        // Datadog span name is OpenTelemetry component name - see module docs for more information
+        rmp::encode::write_array_len(encoded, SPAN_NUM_ELEMENTS)?;
+        rmp::encode::write_u32(
+            encoded,
+            interner.intern(&get_service_name(span, model_config)),
+        )?;
+        rmp::encode::write_u32(encoded, interner.intern(&get_name(span, model_config)))?;
+        rmp::encode::write_u32(encoded, interner.intern(&get_resource(span, model_config)))?;
+        rmp::encode::write_u64(encoded, dd_trace_id(span.span_context.trace_id()))?;
+        rmp::encode::write_u64(encoded, dd_span_id(span.span_context.span_id()))?;
+        rmp::encode::write_u64(encoded, dd_span_id(span.parent_span_id))?;
+        rmp::encode::write_i64(encoded, start)?;
+        rmp::encode::write_i64(encoded, duration)?;
+        rmp::encode::write_i32(
+            encoded,
+            match span.status {
+                Status::Error { .. } => 1,
+                _ => 0,
+            },
+        )?;
 
-            // Datadog span name is OpenTelemetry component name - see module docs for more information
-            rmp::encode::write_array_len(&mut encoded, SPAN_NUM_ELEMENTS)?;
-            rmp::encode::write_u32(
-                &mut encoded,
-                interner.intern(get_service_name(&span, model_config)),
-            )?;
-            rmp::encode::write_u32(&mut encoded, interner.intern(get_name(&span, model_config)))?;
-            rmp::encode::write_u32(
-                &mut encoded,
-                interner.intern(get_resource(&span, model_config)),
-            )?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u128::from_be_bytes(span.span_context.trace_id().to_bytes()) as u64,
-            )?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u64::from_be_bytes(span.span_context.span_id().to_bytes()),
-            )?;
-            rmp::encode::write_u64(
-                &mut encoded,
-                u64::from_be_bytes(span.parent_span_id.to_bytes()),
-            )?;
-            rmp::encode::write_i64(&mut encoded, start)?;
-            rmp::encode::write_i64(&mut encoded, duration)?;
-            rmp::encode::write_i32(
-                &mut encoded,
-                match span.status {
-                    Status::Error { .. } => 1,
-                    _ => 0,
-                },
-            )?;
+        meta.clear();
+        visit_trace_id_high(span, meta_buffers, |trace_id_high| {
+            meta.push((
+                interner.intern(DD_TRACE_ID_HIGH_KEY),
+                interner.intern(trace_id_high),
+            ))
+        });
+        let mut intern_meta =
+            |key: &str, value: &str| meta.push((interner.intern(key), interner.intern(value)));
+        visit_trace_state_meta(span, meta_buffers, &mut intern_meta);
+        visit_events_and_links_meta(span, meta_buffers, &mut intern_meta);
+        let sampling_rates = || span.attributes.iter().filter_map(get_sampling_rate);
+        let sampling_rates_count = sampling_rates().count();
+
+        rmp::encode::write_map_len(
+            encoded,
+            (span.attributes.len() - sampling_rates_count + span.resource.len()) as u32
+                + unified_tags.compute_attribute_size()
+                + GIT_META_TAGS_COUNT
+                + meta.len() as u32,
+        )?;
+        for (key, value) in span.resource.iter() {
+            rmp::encode::write_u32(encoded, interner.intern(key.as_str()))?;
+            rmp::encode::write_u32(encoded, interner.intern_value(value))?;
+        }
 
-            rmp::encode::write_map_len(
-                &mut encoded,
-                (span.attributes.len() + span.resource.len()) as u32
-                    + unified_tags.compute_attribute_size()
-                    + GIT_META_TAGS_COUNT,
-            )?;
-            for (key, value) in span.resource.iter() {
-                rmp::encode::write_u32(&mut encoded, interner.intern(key.as_str()))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern(value.as_str().as_ref()))?;
-            }
+        write_unified_tags(encoded, interner, unified_tags)?;
 
-            write_unified_tags(&mut encoded, interner, unified_tags)?;
+        for kv in span
+            .attributes
+            .iter()
+            .filter(|kv| get_sampling_rate(kv).is_none())
+        {
+            rmp::encode::write_u32(encoded, interner.intern(kv.key.as_str()))?;
+            rmp::encode::write_u32(encoded, interner.intern_value(&kv.value))?;
+        }
 
-            for kv in span.attributes.iter() {
-                rmp::encode::write_u32(&mut encoded, interner.intern(kv.key.as_str()))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern(kv.value.as_str().as_ref()))?;
-            }
+        if let (Some(repository_url), Some(commit_sha)) = (
+            option_env!("DD_GIT_REPOSITORY_URL"),
+            option_env!("DD_GIT_COMMIT_SHA"),
+        ) {
+            rmp::encode::write_u32(encoded, interner.intern("git.repository_url"))?;
+            rmp::encode::write_u32(encoded, interner.intern(repository_url))?;
+            rmp::encode::write_u32(encoded, interner.intern("git.commit.sha"))?;
+            rmp::encode::write_u32(encoded, interner.intern(commit_sha))?;
+        }
 
-            if let (Some(repository_url), Some(commit_sha)) = (
-                option_env!("DD_GIT_REPOSITORY_URL"),
-                option_env!("DD_GIT_COMMIT_SHA"),
-            ) {
-                rmp::encode::write_u32(&mut encoded, interner.intern("git.repository_url"))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern(repository_url))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern("git.commit.sha"))?;
-                rmp::encode::write_u32(&mut encoded, interner.intern(commit_sha))?;
-            }
+        for (key, value) in meta.iter() {
+            rmp::encode::write_u32(encoded, *key)?;
+            rmp::encode::write_u32(encoded, *value)?;
+        }
 
-            rmp::encode::write_map_len(&mut encoded, METRICS_LEN)?;
-            rmp::encode::write_u32(&mut encoded, interner.intern(SAMPLING_PRIORITY_KEY))?;
-            let sampling_priority = get_sampling_priority(&span);
-            rmp::encode::write_f64(&mut encoded, sampling_priority)?;
+        rmp::encode::write_map_len(encoded, METRICS_LEN + sampling_rates_count as u32)?;
+        rmp::encode::write_u32(encoded, interner.intern(SAMPLING_PRIORITY_KEY))?;
+        let sampling_priority = get_sampling_priority(span);
+        rmp::encode::write_f64(encoded, sampling_priority)?;
 
-            rmp::encode::write_u32(&mut encoded, interner.intern(DD_MEASURED_KEY))?;
-            let measuring = get_measuring(&span);
-            rmp::encode::write_f64(&mut encoded, measuring)?;
-            rmp::encode::write_u32(&mut encoded, span_type)?;
+        rmp::encode::write_u32(encoded, interner.intern(DD_MEASURED_KEY))?;
+        let measuring = get_measuring(span);
+        rmp::encode::write_f64(encoded, measuring)?;
+        for (key, rate) in sampling_rates() {
+            rmp::encode::write_u32(encoded, interner.intern(key))?;
+            rmp::encode::write_f64(encoded, rate)?;
         }
+        rmp::encode::write_u32(encoded, span_type)?;
     }
 
-    Ok(encoded)
+    Ok(())
 }
@@ -0,0 +1,123 @@
+//! Minimal protobuf encoding, for the few messages the exporter writes.
+//!
+//! See https://protobuf.dev/programming-guides/encoding/
+
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_I64: u8 = 1;
+const WIRE_TYPE_LEN: u8 = 2;
+
+fn write_tag(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    write_varint(buf, ((field << 3) | wire_type as u32) as u64);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+/// Write a `uint32` or `uint64` field, skipped when zero as in proto3.
+pub(crate) fn write_uint64(buf: &mut Vec<u8>, field: u32, value: u64) {
+    if value != 0 {
+        write_tag(buf, field, WIRE_TYPE_VARINT);
+        write_varint(buf, value);
+    }
+}
+
+/// Write an `int32` or `int64` field, skipped when zero as in proto3.
+pub(crate) fn write_int64(buf: &mut Vec<u8>, field: u32, value: i64) {
+    write_uint64(buf, field, value as u64);
+}
+
+/// Write a `sint32` field, skipped when zero as in proto3.
+pub(crate) fn write_sint32(buf: &mut Vec<u8>, field: u32, value: i32) {
+    write_uint64(buf, field, zigzag(value));
+}
+
+/// Write a `double` field, skipped when zero as in proto3.
+pub(crate) fn write_double(buf: &mut Vec<u8>, field: u32, value: f64) {
+    if value != 0.0 {
+        write_tag(buf, field, WIRE_TYPE_I64);
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Write a packed `repeated double` field.
+pub(crate) fn write_packed_doubles(buf: &mut Vec<u8>, field: u32, values: &[f64]) {
+    write_tag(buf, field, WIRE_TYPE_LEN);
+    write_varint(buf, (values.len() * 8) as u64);
+    for value in values {
+        buf.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Write a `string`, `bytes` or embedded message field. Unlike scalars, empty values are written
+/// so repeated and map fields keep their entries.
+pub(crate) fn write_bytes(buf: &mut Vec<u8>, field: u32, value: &[u8]) {
+    write_tag(buf, field, WIRE_TYPE_LEN);
+    write_varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+/// Write a `string` field, skipped when empty as in proto3.
+pub(crate) fn write_string(buf: &mut Vec<u8>, field: u32, value: &str) {
+    if !value.is_empty() {
+        write_bytes(buf, field, value.as_bytes());
+    }
+}
+
+/// Write an entry of a `map<string, string>` or `map<string, bytes>` field.
+pub(crate) fn write_map_entry(buf: &mut Vec<u8>, field: u32, key: &str, value: &[u8]) {
+    let mut entry = Vec::with_capacity(key.len() + value.len() + 4);
+    write_bytes(&mut entry, 1, key.as_bytes());
+    write_bytes(&mut entry, 2, value);
+    write_bytes(buf, field, &entry);
+}
+
+/// Write an entry of a `map<string, double>` field.
+pub(crate) fn write_double_map_entry(buf: &mut Vec<u8>, field: u32, key: &str, value: f64) {
+    let mut entry = Vec::with_capacity(key.len() + 11);
+    write_bytes(&mut entry, 1, key.as_bytes());
+    write_double(&mut entry, 2, value);
+    write_bytes(buf, field, &entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        let mut buf = Vec::new();
+        write_uint64(&mut buf, 1, 300);
+        assert_eq!(buf, vec![0x08, 0xac, 0x02]);
+
+        let mut buf = Vec::new();
+        write_int64(&mut buf, 2, -1);
+        assert_eq!(
+            buf,
+            vec![0x10, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_zigzag() {
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+        assert_eq!(zigzag(-2), 3);
+    }
+
+    #[test]
+    fn test_map_entry() {
+        let mut buf = Vec::new();
+        write_map_entry(&mut buf, 10, "a", b"b");
+        assert_eq!(buf, vec![0x52, 0x06, 0x0a, 0x01, b'a', 0x12, 0x01, b'b']);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::exporter::model::PayloadCounts;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::SystemTime;
+
+/// Bounded on-disk buffer holding encoded payloads the agent could not accept.
+///
+/// Its file system operations block, exports run them on the I/O thread of
+/// [`background::blocking_io`](crate::exporter::background::blocking_io).
+///
+/// Each payload is stored in its own file named after the time it was spilled and its trace and
+/// span counts, so replaying the directory in file name order sends the oldest payloads first. Once the directory grows over
+/// `max_bytes`, the oldest payloads are dropped.
+#[derive(Debug)]
+pub(crate) struct SpillBuffer {
+    directory: PathBuf,
+    max_bytes: u64,
+    extension: &'static str,
+    sequence: AtomicU64,
+    replaying: AtomicBool,
+}
+
+/// A payload previously written to the [`SpillBuffer`].
+#[derive(Clone, Debug)]
+pub(crate) struct SpilledPayload {
+    path: PathBuf,
+    pub(crate) counts: PayloadCounts,
+}
+
+impl SpilledPayload {
+    pub(crate) fn read(&self) -> io::Result<Vec<u8>> {
+        fs::read(&self.path)
+    }
+
+    pub(crate) fn remove(self) -> io::Result<()> {
+        fs::remove_file(self.path)
+    }
+}
+
+impl SpillBuffer {
+    /// Create a spill buffer in `directory`, creating the directory if needed.
+    ///
+    /// `extension` identifies the encoding of the payloads, payloads written with another
+    /// extension are left untouched.
+    pub(crate) fn new(
+        directory: impl Into<PathBuf>,
+        max_bytes: u64,
+        extension: &'static str,
+    ) -> io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(SpillBuffer {
+            directory,
+            max_bytes,
+            extension,
+            sequence: AtomicU64::new(0),
+            replaying: AtomicBool::new(false),
+        })
+    }
+
+    /// Write a payload to disk, evicting the oldest payloads if the buffer is over its limit.
+    /// Returns the total counts of the evicted payloads.
+    pub(crate) fn push(&self, counts: PayloadCounts, payload: &[u8]) -> io::Result<PayloadCounts> {
+        if payload.len() as u64 > self.max_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "payload of {} bytes exceeds the spill buffer limit of {} bytes",
+                    payload.len(),
+                    self.max_bytes
+                ),
+            ));
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!(
+            "{timestamp:024}-{sequence:010}-{}-{}.{}",
+            counts.traces, counts.spans, self.extension
+        );
+        fs::write(self.directory.join(file_name), payload)?;
+
+        let mut entries = self.entries()?;
+        let mut total: u64 = entries.iter().map(|(_, len)| len).sum();
+        let mut evicted = PayloadCounts::default();
+        entries.reverse();
+        while total > self.max_bytes {
+            match entries.pop() {
+                Some((path, len)) => {
+                    fs::remove_file(&path)?;
+                    total -= len;
+                    if let Some(counts) = parse_counts(&path) {
+                        evicted.traces += counts.traces;
+                        evicted.spans += counts.spans;
+                    }
+                }
+                None => break,
+            }
+        }
+
+        Ok(evicted)
+    }
+
+    /// Payloads currently held on disk, oldest first.
+    pub(crate) fn pending(&self) -> io::Result<Vec<SpilledPayload>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter_map(|(path, _)| {
+                let counts = parse_counts(&path)?;
+                Some(SpilledPayload { path, counts })
+            })
+            .collect())
+    }
+
+    /// Mark the buffer as being replayed, returns `false` if a replay is already in progress.
+    pub(crate) fn start_replay(&self) -> bool {
+        !self.replaying.swap(true, Ordering::AcqRel)
+    }
+
+    pub(crate) fn finish_replay(&self) {
+        self.replaying.store(false, Ordering::Release);
+    }
+
+    fn entries(&self) -> io::Result<Vec<(PathBuf, u64)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(self.extension) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                entries.push((path, metadata.len()));
+            }
+        }
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+}
+
+fn parse_counts(path: &Path) -> Option<PayloadCounts> {
+    let mut parts = path.file_stem()?.to_str()?.rsplitn(3, '-');
+    let spans = parts.next()?.parse().ok()?;
+    let traces = parts.next()?.parse().ok()?;
+    Some(PayloadCounts { traces, spans })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(traces: usize) -> PayloadCounts {
+        PayloadCounts {
+            traces,
+            spans: traces * 2,
+        }
+    }
+
+    #[test]
+    fn test_push_and_pending_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = SpillBuffer::new(dir.path(), 1024, "v05").unwrap();
+
+        buffer.push(counts(1), b"first").unwrap();
+        buffer.push(counts(2), b"second").unwrap();
+
+        let pending = buffer.pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].counts, counts(1));
+        assert_eq!(pending[0].read().unwrap(), b"first");
+        assert_eq!(pending[1].counts, counts(2));
+        assert_eq!(pending[1].read().unwrap(), b"second");
+
+        for payload in pending {
+            payload.remove().unwrap();
+        }
+        assert!(buffer.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_evicts_oldest_over_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let buffer = SpillBuffer::new(dir.path(), 10, "v05").unwrap();
+
+        assert_eq!(buffer.push(counts(1), b"aaaa").unwrap(), counts(0));
+        assert_eq!(buffer.push(counts(2), b"bbbb").unwrap(), counts(0));
+        assert_eq!(buffer.push(counts(3), b"cccc").unwrap(), counts(1));
+
+        let pending = buffer.pending().unwrap();
+        assert_eq!(
+            pending.iter().map(|p| p.counts.traces).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        assert!(buffer.push(counts(4), b"way too large payload").is_err());
+    }
+
+    #[test]
+    fn test_ignores_other_encodings() {
+        let dir = tempfile::tempdir().unwrap();
+        SpillBuffer::new(dir.path(), 1024, "v03")
+            .unwrap()
+            .push(counts(1), b"v03 payload")
+            .unwrap();
+
+        let buffer = SpillBuffer::new(dir.path(), 1024, "v05").unwrap();
+        assert!(buffer.pending().unwrap().is_empty());
+    }
+}
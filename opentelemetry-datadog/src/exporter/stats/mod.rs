@@ -0,0 +1,412 @@
+mod sketch;
+
+use self::sketch::DDSketch;
+use crate::exporter::model::unified_tags::UnifiedTags;
+use crate::exporter::model::Error;
+use crate::exporter::{Mapping, ModelConfig};
+use crate::propagator::{datadog_parent_id, DatadogTraceState};
+use flate2::{write::GzEncoder, Compression};
+use opentelemetry::trace::{SpanKind, Status};
+use opentelemetry_sdk::export::trace::SpanData;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::time::{Duration, SystemTime};
+
+/// Width of the time windows spans are aggregated over.
+pub(crate) const BUCKET_DURATION: Duration = Duration::from_secs(10);
+
+static HTTP_STATUS_CODE_KEYS: [&str; 2] = ["http.response.status_code", "http.status_code"];
+static SYNTHETICS_ORIGIN_PREFIX: &str = "synthetics";
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct AggregationKey {
+    service: String,
+    name: String,
+    resource: String,
+    span_type: String,
+    http_status_code: u32,
+    synthetics: bool,
+}
+
+#[derive(Debug, Default)]
+struct GroupedStats {
+    hits: u64,
+    errors: u64,
+    top_level_hits: u64,
+    duration: u64,
+    ok_summary: DDSketch,
+    error_summary: DDSketch,
+}
+
+/// Aggregates the spans going through the exporter into the stats the Datadog agent would
+/// otherwise compute, so they also account for spans dropped by sampling.
+///
+/// Spans are bucketed by end time into 10 seconds windows, and grouped by service, name,
+/// resource, type, HTTP status code and synthetics origin. Only top level spans, that is local
+/// roots, server and consumer spans, and measured spans are aggregated, as the agent does.
+///
+/// See https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/proto/datadog/trace/stats.proto
+#[derive(Debug)]
+pub(crate) struct StatsAggregator {
+    buckets: BTreeMap<u64, HashMap<AggregationKey, GroupedStats>>,
+    sequence: u64,
+    service: String,
+    env: String,
+    version: String,
+    /// Whether payloads are sent to the Datadog intake rather than an agent
+    intake: bool,
+}
+
+impl StatsAggregator {
+    pub(crate) fn new(service: String, unified_tags: &UnifiedTags) -> Self {
+        StatsAggregator {
+            buckets: BTreeMap::new(),
+            sequence: 0,
+            service,
+            env: unified_tags.env.value.clone().unwrap_or_default(),
+            version: unified_tags.version.value.clone().unwrap_or_default(),
+            intake: false,
+        }
+    }
+
+    /// Encode payloads for the Datadog intake, which receives the stats agents forward: a gzip
+    /// compressed `StatsPayload` wrapping the `ClientStatsPayload` tracers send to the agent.
+    pub(crate) fn for_intake(mut self) -> Self {
+        self.intake = true;
+        self
+    }
+
+    pub(crate) fn add(&mut self, span: &SpanData, mapping: &Mapping, config: &ModelConfig) {
+        let top_level = is_top_level(span);
+        if !top_level && !span.span_context.trace_state().measuring_enabled() {
+            return;
+        }
+
+        let key = AggregationKey {
+            service: mapping.map_service_name(span, config).into_owned(),
+            name: mapping.map_name(span, config).into_owned(),
+            resource: mapping.map_resource(span, config).into_owned(),
+            span_type: mapping
+                .map_span_type(span, config)
+                .map(|span_type| span_type.into_owned())
+                .unwrap_or_default(),
+            http_status_code: span
+                .attributes
+                .iter()
+                .find(|kv| HTTP_STATUS_CODE_KEYS.contains(&kv.key.as_str()))
+                .and_then(|kv| kv.value.as_str().parse().ok())
+                .unwrap_or_default(),
+            synthetics: span
+                .span_context
+                .trace_state()
+                .origin()
+                .map_or(false, |origin| origin.starts_with(SYNTHETICS_ORIGIN_PREFIX)),
+        };
+
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        let stats = self
+            .buckets
+            .entry(bucket_start(span.end_time))
+            .or_default()
+            .entry(key)
+            .or_default();
+        stats.hits += 1;
+        if top_level {
+            stats.top_level_hits += 1;
+        }
+        stats.duration += duration;
+        if let Status::Error { .. } = span.status {
+            stats.errors += 1;
+            stats.error_summary.add(duration as f64);
+        } else {
+            stats.ok_summary.add(duration as f64);
+        }
+    }
+
+    /// Encode and remove the buckets whose window ended before `now`, if any.
+    pub(crate) fn flush(&mut self, now: SystemTime) -> Result<Option<Vec<u8>>, Error> {
+        let current = bucket_start(now);
+        let pending = self.buckets.split_off(&current);
+        let flushed = std::mem::replace(&mut self.buckets, pending);
+        self.encode_flushed(flushed)
+    }
+
+    /// Encode and remove every bucket, including the ones still open, if any.
+    pub(crate) fn flush_all(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let flushed = std::mem::take(&mut self.buckets);
+        self.encode_flushed(flushed)
+    }
+
+    fn encode_flushed(
+        &mut self,
+        flushed: BTreeMap<u64, HashMap<AggregationKey, GroupedStats>>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        if flushed.is_empty() {
+            return Ok(None);
+        }
+
+        self.sequence += 1;
+        let payload = self.encode(flushed)?;
+        if self.intake {
+            self.encode_intake_payload(&payload).map(Some)
+        } else {
+            Ok(Some(payload))
+        }
+    }
+
+    fn encode_intake_payload(&self, client_payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut encoded = Vec::with_capacity(client_payload.len() + 128);
+        rmp::encode::write_map_len(&mut encoded, 6)?;
+        rmp::encode::write_str(&mut encoded, "AgentHostname")?;
+        rmp::encode::write_str(&mut encoded, "")?;
+        rmp::encode::write_str(&mut encoded, "AgentEnv")?;
+        rmp::encode::write_str(&mut encoded, &self.env)?;
+        rmp::encode::write_str(&mut encoded, "Stats")?;
+        rmp::encode::write_array_len(&mut encoded, 1)?;
+        encoded.extend_from_slice(client_payload);
+        rmp::encode::write_str(&mut encoded, "AgentVersion")?;
+        rmp::encode::write_str(&mut encoded, "")?;
+        rmp::encode::write_str(&mut encoded, "ClientComputed")?;
+        rmp::encode::write_bool(&mut encoded, true)?;
+        rmp::encode::write_str(&mut encoded, "SplitPayload")?;
+        rmp::encode::write_bool(&mut encoded, false)?;
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&encoded)
+            .map_err(|err| Error::Other(err.to_string()))?;
+        encoder
+            .finish()
+            .map_err(|err| Error::Other(err.to_string()))
+    }
+
+    fn encode(
+        &self,
+        buckets: BTreeMap<u64, HashMap<AggregationKey, GroupedStats>>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut encoded = Vec::new();
+        rmp::encode::write_map_len(&mut encoded, 9)?;
+        rmp::encode::write_str(&mut encoded, "Hostname")?;
+        rmp::encode::write_str(&mut encoded, "")?;
+        rmp::encode::write_str(&mut encoded, "Env")?;
+        rmp::encode::write_str(&mut encoded, &self.env)?;
+        rmp::encode::write_str(&mut encoded, "Version")?;
+        rmp::encode::write_str(&mut encoded, &self.version)?;
+        rmp::encode::write_str(&mut encoded, "Service")?;
+        rmp::encode::write_str(&mut encoded, &self.service)?;
+        rmp::encode::write_str(&mut encoded, "Lang")?;
+        rmp::encode::write_str(&mut encoded, "rust")?;
+        rmp::encode::write_str(&mut encoded, "TracerVersion")?;
+        rmp::encode::write_str(&mut encoded, env!("CARGO_PKG_VERSION"))?;
+        rmp::encode::write_str(&mut encoded, "RuntimeID")?;
+        rmp::encode::write_str(&mut encoded, "")?;
+        rmp::encode::write_str(&mut encoded, "Sequence")?;
+        rmp::encode::write_uint(&mut encoded, self.sequence)?;
+
+        rmp::encode::write_str(&mut encoded, "Stats")?;
+        rmp::encode::write_array_len(&mut encoded, buckets.len() as u32)?;
+        for (start, groups) in buckets {
+            rmp::encode::write_map_len(&mut encoded, 3)?;
+            rmp::encode::write_str(&mut encoded, "Start")?;
+            rmp::encode::write_uint(&mut encoded, start)?;
+            rmp::encode::write_str(&mut encoded, "Duration")?;
+            rmp::encode::write_uint(&mut encoded, BUCKET_DURATION.as_nanos() as u64)?;
+            rmp::encode::write_str(&mut encoded, "Stats")?;
+            rmp::encode::write_array_len(&mut encoded, groups.len() as u32)?;
+            for (key, stats) in groups {
+                rmp::encode::write_map_len(&mut encoded, 13)?;
+                rmp::encode::write_str(&mut encoded, "Service")?;
+                rmp::encode::write_str(&mut encoded, &key.service)?;
+                rmp::encode::write_str(&mut encoded, "Name")?;
+                rmp::encode::write_str(&mut encoded, &key.name)?;
+                rmp::encode::write_str(&mut encoded, "Resource")?;
+                rmp::encode::write_str(&mut encoded, &key.resource)?;
+                rmp::encode::write_str(&mut encoded, "Type")?;
+                rmp::encode::write_str(&mut encoded, &key.span_type)?;
+                rmp::encode::write_str(&mut encoded, "HTTPStatusCode")?;
+                rmp::encode::write_u32(&mut encoded, key.http_status_code)?;
+                rmp::encode::write_str(&mut encoded, "Synthetics")?;
+                rmp::encode::write_bool(&mut encoded, key.synthetics)?;
+                rmp::encode::write_str(&mut encoded, "Hits")?;
+                rmp::encode::write_uint(&mut encoded, stats.hits)?;
+                rmp::encode::write_str(&mut encoded, "Errors")?;
+                rmp::encode::write_uint(&mut encoded, stats.errors)?;
+                rmp::encode::write_str(&mut encoded, "TopLevelHits")?;
+                rmp::encode::write_uint(&mut encoded, stats.top_level_hits)?;
+                rmp::encode::write_str(&mut encoded, "Duration")?;
+                rmp::encode::write_uint(&mut encoded, stats.duration)?;
+                rmp::encode::write_str(&mut encoded, "OkSummary")?;
+                rmp::encode::write_bin(&mut encoded, &stats.ok_summary.encode())?;
+                rmp::encode::write_str(&mut encoded, "ErrorSummary")?;
+                rmp::encode::write_bin(&mut encoded, &stats.error_summary.encode())?;
+                rmp::encode::write_str(&mut encoded, "DBType")?;
+                rmp::encode::write_str(&mut encoded, "")?;
+            }
+        }
+
+        Ok(encoded)
+    }
+}
+
+/// Spans starting a local trace are top level: roots, and spans whose parent is the remote parent
+/// the trace was extracted with. The SDK doesn't record whether the parent of a span is remote, so
+/// it is only known when a propagator recorded the remote parent in the trace state, and server
+/// and consumer spans, whose parent usually runs in another process, are top level otherwise.
+fn is_top_level(span: &SpanData) -> bool {
+    span.parent_span_id == opentelemetry::trace::SpanId::INVALID
+        || datadog_parent_id(span.span_context.trace_state(), span.parent_span_id).is_some()
+        || matches!(span.span_kind, SpanKind::Server | SpanKind::Consumer)
+}
+
+fn bucket_start(time: SystemTime) -> u64 {
+    let nanos = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or_default();
+    nanos - nanos % BUCKET_DURATION.as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::model::tests::get_span;
+    use rmpv::Value;
+
+    fn field<'a>(map: &'a Value, key: &str) -> &'a Value {
+        map.as_map()
+            .unwrap()
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(key))
+            .map(|(_, v)| v)
+            .unwrap_or_else(|| panic!("missing {key}"))
+    }
+
+    #[test]
+    fn test_aggregate_and_flush() -> Result<(), Box<dyn std::error::Error>> {
+        let mut unified_tags = UnifiedTags::new();
+        unified_tags.set_env(Some("prod".to_string()));
+        let mut aggregator = StatsAggregator::new("service".to_string(), &unified_tags);
+        let mapping = Mapping::empty();
+        let config = ModelConfig {
+            service_name: "service".to_string(),
+        };
+
+        let root = get_span(7, 0, 1);
+        let mut failed_root = get_span(8, 0, 2);
+        failed_root.status = Status::error("boom");
+        // internal child spans aren't aggregated
+        let child = get_span(7, 1, 3);
+        for span in [&root, &failed_root, &child] {
+            aggregator.add(span, &mapping, &config);
+        }
+
+        // the bucket of the spans isn't over yet
+        assert!(aggregator.flush(root.end_time)?.is_none());
+
+        let encoded = aggregator
+            .flush(root.end_time + BUCKET_DURATION)?
+            .expect("stats payload");
+        let payload = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        assert_eq!(field(&payload, "Env").as_str(), Some("prod"));
+        assert_eq!(field(&payload, "Sequence").as_u64(), Some(1));
+
+        let buckets = field(&payload, "Stats").as_array().unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(
+            field(&buckets[0], "Start").as_u64(),
+            Some(bucket_start(root.end_time))
+        );
+        let groups = field(&buckets[0], "Stats").as_array().unwrap();
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0];
+        assert_eq!(field(group, "Service").as_str(), Some("service"));
+        assert_eq!(field(group, "Resource").as_str(), Some("resource"));
+        assert_eq!(field(group, "Hits").as_u64(), Some(2));
+        assert_eq!(field(group, "TopLevelHits").as_u64(), Some(2));
+        assert_eq!(field(group, "Errors").as_u64(), Some(1));
+        assert!(matches!(field(group, "OkSummary"), Value::Binary(_)));
+
+        assert!(aggregator.buckets.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_level() {
+        use crate::propagator::with_datadog_parent_id;
+        use opentelemetry::trace::{SpanContext, SpanId};
+
+        assert!(is_top_level(&get_span(7, 0, 1)));
+        // the parent of the client span runs in the same process
+        assert!(!is_top_level(&get_span(7, 1, 2)));
+        let mut server = get_span(7, 1, 2);
+        server.span_kind = SpanKind::Server;
+        assert!(is_top_level(&server));
+
+        // the client span is the first one after the remote parent the trace was extracted with
+        let mut remote_child = get_span(7, 1, 2);
+        remote_child.span_context = SpanContext::new(
+            remote_child.span_context.trace_id(),
+            remote_child.span_context.span_id(),
+            remote_child.span_context.trace_flags(),
+            false,
+            with_datadog_parent_id(
+                remote_child.span_context.trace_state(),
+                SpanId::from_u64(1),
+                SpanId::from_u64(0xab),
+            ),
+        );
+        assert!(is_top_level(&remote_child));
+        let mut local_child = remote_child.clone();
+        local_child.parent_span_id = SpanId::from_u64(2);
+        assert!(!is_top_level(&local_child));
+    }
+
+    #[test]
+    fn test_flush_all() -> Result<(), Box<dyn std::error::Error>> {
+        let mut aggregator = StatsAggregator::new("service".to_string(), &UnifiedTags::new());
+        let config = ModelConfig {
+            service_name: "service".to_string(),
+        };
+        let span = get_span(7, 0, 1);
+        aggregator.add(&span, &Mapping::empty(), &config);
+
+        assert!(aggregator.flush(span.end_time)?.is_none());
+        let encoded = aggregator.flush_all()?.expect("stats payload");
+        let payload = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        assert_eq!(field(&payload, "Stats").as_array().unwrap().len(), 1);
+        assert!(aggregator.flush_all()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_intake_payload() -> Result<(), Box<dyn std::error::Error>> {
+        let mut unified_tags = UnifiedTags::new();
+        unified_tags.set_env(Some("prod".to_string()));
+        let mut aggregator =
+            StatsAggregator::new("service".to_string(), &unified_tags).for_intake();
+        let config = ModelConfig {
+            service_name: "service".to_string(),
+        };
+        aggregator.add(&get_span(7, 0, 1), &Mapping::empty(), &config);
+
+        let compressed = aggregator.flush_all()?.expect("stats payload");
+        let mut encoded = Vec::new();
+        std::io::Read::read_to_end(
+            &mut flate2::read::GzDecoder::new(compressed.as_slice()),
+            &mut encoded,
+        )?;
+        let payload = rmpv::decode::read_value(&mut encoded.as_slice())?;
+        assert_eq!(field(&payload, "AgentEnv").as_str(), Some("prod"));
+        assert_eq!(field(&payload, "ClientComputed").as_bool(), Some(true));
+
+        let stats = field(&payload, "Stats").as_array().unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(field(&stats[0], "Service").as_str(), Some("service"));
+        assert_eq!(field(&stats[0], "Sequence").as_u64(), Some(1));
+        Ok(())
+    }
+}
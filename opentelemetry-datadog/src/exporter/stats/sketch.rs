@@ -0,0 +1,141 @@
+use crate::exporter::proto;
+use std::collections::BTreeMap;
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/stats.go#L300
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// DDSketch with a logarithmic index mapping, holding latency distributions of the stats
+/// payload.
+///
+/// See [the DDSketch paper](https://arxiv.org/abs/1908.10693) and
+/// [sketches-go](https://github.com/DataDog/sketches-go) for the reference implementation.
+#[derive(Clone, Debug)]
+pub(crate) struct DDSketch {
+    gamma: f64,
+    multiplier: f64,
+    bins: BTreeMap<i32, f64>,
+    zero_count: f64,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        DDSketch {
+            gamma,
+            multiplier: 1.0 / gamma.ln(),
+            bins: BTreeMap::new(),
+            zero_count: 0.0,
+        }
+    }
+}
+
+impl DDSketch {
+    pub(crate) fn add(&mut self, value: f64) {
+        if value <= 0.0 {
+            self.zero_count += 1.0;
+        } else {
+            *self.bins.entry(self.index(value)).or_default() += 1.0;
+        }
+    }
+
+    fn index(&self, value: f64) -> i32 {
+        (value.ln() * self.multiplier).ceil() as i32
+    }
+
+    #[cfg(test)]
+    pub(crate) fn count(&self) -> f64 {
+        self.zero_count + self.bins.values().sum::<f64>()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn quantile(&self, quantile: f64) -> f64 {
+        let rank = quantile * (self.count() - 1.0);
+        let mut seen = self.zero_count;
+        if rank < seen {
+            return 0.0;
+        }
+        for (index, count) in self.bins.iter() {
+            seen += count;
+            if rank < seen {
+                // middle of the bucket, in relative error terms
+                return 2.0 * self.gamma.powi(*index) / (1.0 + self.gamma);
+            }
+        }
+        self.bins
+            .keys()
+            .next_back()
+            .map(|index| 2.0 * self.gamma.powi(*index) / (1.0 + self.gamma))
+            .unwrap_or_default()
+    }
+
+    /// Encode the sketch as a `DDSketch` protobuf message.
+    ///
+    /// See https://github.com/DataDog/sketches-go/blob/v1.4.4/ddsketch/pb/ddsketch.proto
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut mapping = Vec::new();
+        // gamma
+        proto::write_double(&mut mapping, 1, self.gamma);
+
+        let mut store = Vec::new();
+        if let (Some(&min), Some(&max)) = (self.bins.keys().next(), self.bins.keys().next_back()) {
+            let counts = (min..=max)
+                .map(|index| self.bins.get(&index).copied().unwrap_or_default())
+                .collect::<Vec<_>>();
+            // contiguousBinCounts
+            proto::write_packed_doubles(&mut store, 2, &counts);
+            // contiguousBinIndexOffset
+            proto::write_sint32(&mut store, 3, min);
+        }
+
+        let mut sketch = Vec::new();
+        // mapping
+        proto::write_bytes(&mut sketch, 1, &mapping);
+        // positiveValues
+        proto::write_bytes(&mut sketch, 2, &store);
+        // zeroCount
+        proto::write_double(&mut sketch, 4, self.zero_count);
+        sketch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantiles_within_accuracy() {
+        let mut sketch = DDSketch::default();
+        for value in 1..=1000 {
+            sketch.add(value as f64 * 1_000.0);
+        }
+        assert_eq!(sketch.count(), 1000.0);
+
+        for (quantile, expected) in [(0.5, 500_000.0), (0.9, 900_000.0), (0.99, 990_000.0)] {
+            let actual = sketch.quantile(quantile);
+            assert!(
+                (actual - expected).abs() <= expected * 0.02,
+                "p{quantile}: {actual} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut sketch = DDSketch::default();
+        sketch.add(0.0);
+        sketch.add(1.0);
+        sketch.add(1.01);
+
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        let mut expected = vec![0x0a, 0x09, 0x09];
+        expected.extend_from_slice(&gamma.to_le_bytes());
+        // store: counts of indices 0 and 1, the zero offset is omitted
+        expected.extend_from_slice(&[0x12, 0x12, 0x12, 0x10]);
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+        expected.push(0x21);
+        expected.extend_from_slice(&1.0f64.to_le_bytes());
+
+        assert_eq!(sketch.encode(), expected);
+    }
+}
@@ -0,0 +1,117 @@
+use crate::exporter::model::PayloadCounts;
+use http::StatusCode;
+use opentelemetry::metrics::{Counter, Histogram, MeterProvider, Unit};
+use opentelemetry::KeyValue;
+use opentelemetry_semantic_conventions as semcov;
+use std::time::Duration;
+
+const OUTCOME_KEY: &str = "outcome";
+const ERROR_TYPE_KEY: &str = "error.type";
+
+/// Metrics the exporter records about its own health, see
+/// [`DatadogPipelineBuilder::with_meter_provider`](crate::DatadogPipelineBuilder::with_meter_provider).
+#[derive(Clone, Debug)]
+pub(crate) struct ExporterTelemetry {
+    spans: Counter<u64>,
+    traces: Counter<u64>,
+    encoded_bytes: Counter<u64>,
+    sent_bytes: Counter<u64>,
+    encode_errors: Counter<u64>,
+    requests: Counter<u64>,
+    send_duration: Histogram<f64>,
+}
+
+impl ExporterTelemetry {
+    pub(crate) fn new<M: MeterProvider>(meter_provider: &M) -> Self {
+        let meter = meter_provider.versioned_meter(
+            "opentelemetry-datadog",
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(semcov::SCHEMA_URL),
+            None,
+        );
+        ExporterTelemetry {
+            spans: meter
+                .u64_counter("datadog.exporter.spans")
+                .with_description("Spans exported to Datadog")
+                .with_unit(Unit::new("{span}"))
+                .init(),
+            traces: meter
+                .u64_counter("datadog.exporter.traces")
+                .with_description("Traces exported to Datadog")
+                .with_unit(Unit::new("{trace}"))
+                .init(),
+            encoded_bytes: meter
+                .u64_counter("datadog.exporter.encoded.bytes")
+                .with_description("Size of the encoded payloads")
+                .with_unit(Unit::new("By"))
+                .init(),
+            sent_bytes: meter
+                .u64_counter("datadog.exporter.sent.bytes")
+                .with_description(
+                    "Size of the payloads accepted by Datadog, replayed ones included",
+                )
+                .with_unit(Unit::new("By"))
+                .init(),
+            encode_errors: meter
+                .u64_counter("datadog.exporter.encode.errors")
+                .with_description("Batches which couldn't be encoded")
+                .init(),
+            requests: meter
+                .u64_counter("datadog.exporter.requests")
+                .with_description("Requests sending payloads to Datadog")
+                .with_unit(Unit::new("{request}"))
+                .init(),
+            send_duration: meter
+                .f64_histogram("datadog.exporter.send.duration")
+                .with_description("Time spent sending a payload, retries included")
+                .with_unit(Unit::new("s"))
+                .init(),
+        }
+    }
+
+    fn record_counts(&self, counts: PayloadCounts, outcome: &'static str) {
+        let attributes = [KeyValue::new(OUTCOME_KEY, outcome)];
+        self.spans.add(counts.spans as u64, &attributes);
+        self.traces.add(counts.traces as u64, &attributes);
+    }
+
+    pub(crate) fn record_sent(&self, counts: PayloadCounts, bytes: usize) {
+        self.record_counts(counts, "sent");
+        self.sent_bytes.add(bytes as u64, &[]);
+    }
+
+    /// Record a payload written to the spill directory, to be replayed later.
+    pub(crate) fn record_spilled(&self, counts: PayloadCounts) {
+        self.record_counts(counts, "spilled");
+    }
+
+    pub(crate) fn record_dropped(&self, counts: PayloadCounts) {
+        if counts.traces > 0 {
+            self.record_counts(counts, "dropped");
+        }
+    }
+
+    pub(crate) fn record_encoded(&self, bytes: usize) {
+        self.encoded_bytes.add(bytes as u64, &[]);
+    }
+
+    pub(crate) fn record_encode_error(&self) {
+        self.encode_errors.add(1, &[]);
+    }
+
+    /// Record a request, `None` if it didn't get a response.
+    pub(crate) fn record_request(&self, status: Option<StatusCode>) {
+        let attribute = match status {
+            Some(status) => KeyValue::new(
+                semcov::trace::HTTP_RESPONSE_STATUS_CODE,
+                i64::from(status.as_u16()),
+            ),
+            None => KeyValue::new(ERROR_TYPE_KEY, "transport"),
+        };
+        self.requests.add(1, &[attribute]);
+    }
+
+    pub(crate) fn record_send_duration(&self, duration: Duration) {
+        self.send_duration.record(duration.as_secs_f64(), &[]);
+    }
+}
@@ -0,0 +1,482 @@
+use crate::exporter::background;
+use crate::exporter::model::PayloadCounts;
+use crate::exporter::spill::SpillBuffer;
+#[cfg(feature = "metrics")]
+use crate::exporter::telemetry::ExporterTelemetry;
+use crate::exporter::{ApiVersion, Error};
+#[cfg(feature = "agent-sampling")]
+use crate::DatadogAgentSampler;
+use bytes::Bytes;
+use futures_core::future::BoxFuture;
+use http::{Method, Request, Response, StatusCode, Uri};
+use opentelemetry::{global, trace::TraceError};
+use opentelemetry_http::{HttpClient, ResponseExt};
+use opentelemetry_sdk::{export::trace::ExportResult, runtime::Runtime};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Header name used to inform the Datadog agent of the number of traces in the payload
+const DATADOG_TRACE_COUNT_HEADER: &str = "X-Datadog-Trace-Count";
+
+/// Header name use to inform datadog as to what version
+const DATADOG_META_LANG_HEADER: &str = "Datadog-Meta-Lang";
+const DATADOG_META_TRACER_VERSION_HEADER: &str = "Datadog-Meta-Tracer-Version";
+
+/// Header name used to inform the Datadog agent that stats were computed by the tracer, so it
+/// doesn't compute them again from the payload
+const DATADOG_CLIENT_COMPUTED_STATS_HEADER: &str = "Datadog-Client-Computed-Stats";
+
+/// Header name used to authenticate requests sent directly to the Datadog intake
+const DATADOG_API_KEY_HEADER: &str = "DD-API-KEY";
+
+/// Spilled payloads replayed at most after an accepted request, so a large backlog is sent over
+/// several exports instead of delaying a single one
+const MAX_REPLAYED_PAYLOADS: usize = 4;
+
+/// Retry policy applied when the Datadog agent is unreachable.
+///
+/// A request is retried when the agent can't be reached or answers with a `408`, `429` or `5xx`
+/// status. Between attempts the exporter waits for an exponentially growing backoff, starting
+/// at `initial_backoff` and capped at `max_backoff`.
+///
+/// Retries are disabled unless configured with [`DatadogPipelineBuilder::with_retry_config`].
+///
+/// [`DatadogPipelineBuilder::with_retry_config`]: crate::DatadogPipelineBuilder::with_retry_config
+#[derive(Clone, Debug)]
+pub struct RetryConfig {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Retry failed requests up to `max_retries` times.
+    pub fn new(max_retries: u32) -> Self {
+        RetryConfig {
+            max_retries,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn disabled() -> Self {
+        Self::new(0)
+    }
+
+    /// Set the backoff before the first retry, `100ms` by default.
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the upper bound of the backoff between retries, `5s` by default.
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+pub(crate) type DelayFn = Arc<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Wait on the timer thread of the exporter, leaving the executor polling the export free to
+/// run other tasks. Used when no runtime is known, e.g. with [`build_exporter`] or the simple
+/// span processor.
+///
+/// [`build_exporter`]: crate::DatadogPipelineBuilder::build_exporter
+pub(crate) fn thread_delay() -> DelayFn {
+    Arc::new(|duration| Box::pin(background::delay(duration)))
+}
+
+/// Wait using the delay provided by the runtime the batch span processor runs on.
+pub(crate) fn runtime_delay<R: Runtime>(runtime: R) -> DelayFn {
+    Arc::new(move |duration| {
+        let delay = runtime.delay(duration);
+        Box::pin(async move {
+            delay.await;
+        })
+    })
+}
+
+/// Features the agent reports on its `/info` endpoint, fetched once by [`AgentTransport::fetch_info`].
+#[derive(Debug, Default)]
+pub(crate) struct AgentInfo {
+    fetched: AtomicBool,
+    client_drop_p0s: AtomicBool,
+}
+
+impl AgentInfo {
+    /// Whether the agent lets tracers computing stats drop the traces rejected by priority
+    /// sampling, rather than relying on them to compute stats itself.
+    pub(crate) fn client_drop_p0s(&self) -> bool {
+        self.client_drop_p0s.load(Ordering::Relaxed)
+    }
+}
+
+struct SendError {
+    retryable: bool,
+    error: TraceError,
+}
+
+/// What became of a payload, recorded as the `outcome` of the exporter telemetry.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+enum SendOutcome {
+    Sent { bytes: usize },
+    Spilled,
+    Dropped,
+}
+
+/// Sends encoded payloads to the Datadog agent, retrying and spilling to disk as configured.
+#[derive(Clone)]
+pub(crate) struct AgentTransport {
+    pub(crate) client: Arc<dyn HttpClient>,
+    pub(crate) request_url: Uri,
+    pub(crate) api_version: ApiVersion,
+    pub(crate) retry_config: RetryConfig,
+    pub(crate) delay: DelayFn,
+    pub(crate) spill: Option<Arc<SpillBuffer>>,
+    pub(crate) stats_url: Option<Uri>,
+    /// Set when computing stats for an agent, which tells whether traces may be dropped
+    pub(crate) info_url: Option<Uri>,
+    pub(crate) agent_info: Arc<AgentInfo>,
+    /// Set when sending to the Datadog intake instead of an agent.
+    pub(crate) api_key: Option<String>,
+    #[cfg(feature = "agent-sampling")]
+    pub(crate) agent_sampler: Option<DatadogAgentSampler>,
+    #[cfg(feature = "metrics")]
+    pub(crate) telemetry: Option<ExporterTelemetry>,
+}
+
+impl Debug for AgentTransport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentTransport")
+            .field("client", &self.client)
+            .field("request_url", &self.request_url)
+            .field("api_version", &self.api_version)
+            .field("retry_config", &self.retry_config)
+            .field("spill", &self.spill)
+            .field("stats_url", &self.stats_url)
+            .field("agent_info", &self.agent_info)
+            .field("agentless", &self.api_key.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AgentTransport {
+    pub(crate) fn build_request(
+        &self,
+        trace_count: usize,
+        data: Vec<u8>,
+    ) -> Result<Request<Vec<u8>>, TraceError> {
+        let content_type = match self.api_key {
+            Some(_) => "application/x-protobuf",
+            None => self.api_version.content_type(),
+        };
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(self.request_url.clone())
+            .header(http::header::CONTENT_TYPE, content_type)
+            .header(DATADOG_TRACE_COUNT_HEADER, trace_count)
+            .header(DATADOG_META_LANG_HEADER, "rust")
+            .header(
+                DATADOG_META_TRACER_VERSION_HEADER,
+                env!("CARGO_PKG_VERSION"),
+            );
+        if self.stats_url.is_some() {
+            req = req.header(DATADOG_CLIENT_COMPUTED_STATS_HEADER, "yes");
+        }
+        if let Some(api_key) = &self.api_key {
+            req = req
+                .header(DATADOG_API_KEY_HEADER, api_key)
+                .header(http::header::CONTENT_ENCODING, "gzip");
+        }
+
+        Ok(req.body(data).map_err::<Error, _>(Into::into)?)
+    }
+
+    /// Send a stats payload to the agent. Stats aren't retried nor spilled, as a late bucket
+    /// would be dropped by the agent anyway.
+    pub(crate) async fn send_stats(&self, data: Vec<u8>) {
+        let stats_url = match &self.stats_url {
+            Some(stats_url) => stats_url.clone(),
+            None => return,
+        };
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(stats_url)
+            .header(http::header::CONTENT_TYPE, "application/msgpack")
+            .header(DATADOG_META_LANG_HEADER, "rust")
+            .header(
+                DATADOG_META_TRACER_VERSION_HEADER,
+                env!("CARGO_PKG_VERSION"),
+            );
+        if let Some(api_key) = &self.api_key {
+            request = request
+                .header(DATADOG_API_KEY_HEADER, api_key)
+                .header(http::header::CONTENT_ENCODING, "gzip");
+        }
+        let request = request.body(data).map_err::<Error, _>(Into::into);
+        let result = match request {
+            Ok(request) => match self.client.send(request).await {
+                Ok(response) => response.error_for_status().map(|_| ()).map_err(Into::into),
+                Err(err) => Err(err.into()),
+            },
+            Err(err) => Err(TraceError::from(err)),
+        };
+        if let Err(err) = result {
+            global::handle_error(err);
+        }
+    }
+
+    /// Fetch the features of the agent from its `/info` endpoint, unless they were already
+    /// fetched. Failures aren't retried, the agent is then assumed to support none of them.
+    pub(crate) async fn fetch_info(&self) {
+        let info_url = match &self.info_url {
+            Some(info_url) if !self.agent_info.fetched.swap(true, Ordering::Relaxed) => {
+                info_url.clone()
+            }
+            _ => return,
+        };
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(info_url)
+            .body(Vec::new())
+            .map_err::<Error, _>(Into::into);
+        let result = match request {
+            Ok(request) => match self.client.send(request).await {
+                Ok(response) => response.error_for_status().map_err(Into::into),
+                Err(err) => Err(err.into()),
+            },
+            Err(err) => Err(TraceError::from(err)),
+        };
+        match result {
+            Ok(response) => {
+                let client_drop_p0s = serde_json::from_slice::<serde_json::Value>(response.body())
+                    .ok()
+                    .and_then(|info| info.get("client_drop_p0s")?.as_bool())
+                    .unwrap_or(false);
+                self.agent_info
+                    .client_drop_p0s
+                    .store(client_drop_p0s, Ordering::Relaxed);
+            }
+            Err(err) => global::handle_error(err),
+        }
+    }
+
+    /// Send a payload, then replay any spilled payloads if the agent accepted it.
+    pub(crate) async fn send(self, counts: PayloadCounts, data: Vec<u8>) -> ExportResult {
+        match self.send_with_retry(counts.traces, &data).await {
+            Ok(response) => {
+                self.handle_response(&response);
+                self.record_outcome(counts, SendOutcome::Sent { bytes: data.len() });
+                self.replay_spilled().await;
+                Ok(())
+            }
+            Err(SendError { retryable, error }) => {
+                let outcome = match (retryable, &self.spill) {
+                    (true, Some(spill)) => {
+                        let spill = spill.clone();
+                        match background::blocking_io(move || spill.push(counts, &data)).await {
+                            Ok(evicted) => {
+                                self.record_outcome(evicted, SendOutcome::Dropped);
+                                SendOutcome::Spilled
+                            }
+                            Err(err) => {
+                                global::handle_error(TraceError::from(Error::SpillError(err)));
+                                SendOutcome::Dropped
+                            }
+                        }
+                    }
+                    _ => SendOutcome::Dropped,
+                };
+                self.record_outcome(counts, outcome);
+                Err(error)
+            }
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    fn record_outcome(&self, counts: PayloadCounts, outcome: SendOutcome) {
+        if let Some(telemetry) = &self.telemetry {
+            match outcome {
+                SendOutcome::Sent { bytes } => telemetry.record_sent(counts, bytes),
+                SendOutcome::Spilled => telemetry.record_spilled(counts),
+                SendOutcome::Dropped => telemetry.record_dropped(counts),
+            }
+        }
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    fn record_outcome(&self, _counts: PayloadCounts, _outcome: SendOutcome) {}
+
+    #[cfg(feature = "agent-sampling")]
+    fn handle_response(&self, response: &Response<Bytes>) {
+        if let Some(sampler) = &self.agent_sampler {
+            sampler.update_from_response(response.body());
+        }
+    }
+
+    #[cfg(not(feature = "agent-sampling"))]
+    fn handle_response(&self, _response: &Response<Bytes>) {}
+
+    async fn send_with_retry(
+        &self,
+        trace_count: usize,
+        data: &[u8],
+    ) -> Result<Response<Bytes>, SendError> {
+        let mut attempt = 0;
+        loop {
+            match self.send_once(trace_count, data.to_vec()).await {
+                Err(err) if err.retryable && attempt < self.retry_config.max_retries => {
+                    (self.delay)(self.retry_config.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        trace_count: usize,
+        data: Vec<u8>,
+    ) -> Result<Response<Bytes>, SendError> {
+        let request = self
+            .build_request(trace_count, data)
+            .map_err(|error| SendError {
+                retryable: false,
+                error,
+            })?;
+        let response = self.client.send(request).await;
+        #[cfg(feature = "metrics")]
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.record_request(response.as_ref().ok().map(|response| response.status()));
+        }
+        let response = response.map_err(|err| SendError {
+            retryable: true,
+            error: err.into(),
+        })?;
+        let status = response.status();
+        response.error_for_status().map_err(|err| SendError {
+            retryable: is_retryable(status),
+            error: err.into(),
+        })
+    }
+
+    /// Send the oldest spilled payloads again, at most [`MAX_REPLAYED_PAYLOADS`] of them.
+    async fn replay_spilled(&self) {
+        let spill = match &self.spill {
+            Some(spill) if spill.start_replay() => spill,
+            _ => return,
+        };
+
+        let pending = {
+            let spill = spill.clone();
+            background::blocking_io(move || spill.pending()).await
+        };
+        match pending {
+            Ok(pending) => {
+                for payload in pending.into_iter().take(MAX_REPLAYED_PAYLOADS) {
+                    let data = {
+                        let payload = payload.clone();
+                        background::blocking_io(move || payload.read()).await
+                    };
+                    let (result, bytes) = match data {
+                        Ok(data) => {
+                            let bytes = data.len();
+                            (self.send_once(payload.counts.traces, data).await, bytes)
+                        }
+                        Err(err) => {
+                            global::handle_error(TraceError::from(Error::SpillError(err)));
+                            continue;
+                        }
+                    };
+                    let outcome = match result {
+                        Ok(_) => SendOutcome::Sent { bytes },
+                        // Payloads rejected for good would otherwise be replayed forever.
+                        Err(SendError {
+                            retryable: false, ..
+                        }) => SendOutcome::Dropped,
+                        Err(SendError { error, .. }) => {
+                            global::handle_error(error);
+                            break;
+                        }
+                    };
+                    self.record_outcome(payload.counts, outcome);
+                    if let Err(err) = background::blocking_io(move || payload.remove()).await {
+                        global::handle_error(TraceError::from(Error::SpillError(err)));
+                    }
+                }
+            }
+            Err(err) => global::handle_error(TraceError::from(Error::SpillError(err))),
+        }
+
+        spill.finish_replay();
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let config = RetryConfig::new(10)
+            .with_initial_backoff(Duration::from_millis(100))
+            .with_max_backoff(Duration::from_secs(1));
+
+        assert_eq!(config.backoff(0), Duration::from_millis(100));
+        assert_eq!(config.backoff(1), Duration::from_millis(200));
+        assert_eq!(config.backoff(3), Duration::from_millis(800));
+        assert_eq!(config.backoff(4), Duration::from_secs(1));
+        assert_eq!(config.backoff(40), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_thread_delay_does_not_block() {
+        use std::task::Context;
+
+        let start = std::time::Instant::now();
+        let mut delay = thread_delay()(Duration::from_millis(100));
+        let waker = futures_util::task::noop_waker();
+        assert!(delay
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        futures_executor::block_on(delay);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_retryable_status() {
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+}
@@ -0,0 +1,161 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, HeaderValue, Request, Response, Uri};
+use hyper::Body;
+use opentelemetry_http::{HttpClient, HttpError};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::UnixStream;
+
+/// URL scheme of agent endpoints reached through a Unix domain socket.
+pub(crate) const UNIX_SCHEME: &str = "unix";
+
+/// Socket the agent listens on by default, used by `from_env` when it exists and no endpoint is
+/// configured.
+pub(crate) const DEFAULT_SOCKET_PATH: &str = "/var/run/datadog/apm.socket";
+
+/// Base of the request URLs sent through the socket, where only the path matters.
+pub(crate) const SOCKET_BASE_URL: &str = "http://localhost";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HTTP/1.1 client sending each request on a new connection to a Unix domain socket.
+///
+/// The connection is driven by the Tokio runtime the export runs on, requests fail when there is
+/// none.
+#[derive(Debug, Clone)]
+pub(crate) struct UnixSocketClient {
+    path: PathBuf,
+}
+
+impl UnixSocketClient {
+    pub(crate) fn new(path: impl Into<PathBuf>) -> Self {
+        UnixSocketClient { path: path.into() }
+    }
+
+    async fn send_request(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        let stream = UnixStream::connect(&self.path).await?;
+        let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+        tokio::spawn(connection);
+
+        let (mut parts, body) = request.into_parts();
+        // the request target is the path, the socket stands for the host
+        parts.uri = parts
+            .uri
+            .path_and_query()
+            .map_or(Ok(Uri::from_static("/")), |path| path.as_str().parse())?;
+        parts
+            .headers
+            .entry(header::HOST)
+            .or_insert(HeaderValue::from_static("localhost"));
+
+        let response = sender
+            .send_request(Request::from_parts(parts, Body::from(body)))
+            .await?;
+        let (parts, body) = response.into_parts();
+        let body = hyper::body::to_bytes(body).await?;
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+/// Extract the socket path from a `unix:///path/to/socket` endpoint.
+pub(crate) fn socket_path(endpoint: &str) -> Option<&Path> {
+    endpoint
+        .strip_prefix(UNIX_SCHEME)?
+        .strip_prefix("://")
+        .filter(|path| !path.is_empty())
+        .map(Path::new)
+}
+
+#[async_trait]
+impl HttpClient for UnixSocketClient {
+    async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Bytes>, HttpError> {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Err("Unix domain socket agent endpoints require a Tokio runtime".into());
+        }
+        tokio::time::timeout(TIMEOUT, self.send_request(request)).await?
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_socket_path() {
+        assert_eq!(
+            socket_path("unix:///var/run/datadog/apm.socket"),
+            Some(Path::new("/var/run/datadog/apm.socket"))
+        );
+        assert_eq!(socket_path("unix://"), None);
+        assert_eq!(socket_path("http://localhost:8126"), None);
+    }
+
+    /// Accept a single connection on `path` and answer `{}`, returning the request line, headers
+    /// and body once the whole request is read.
+    pub(crate) fn mock_agent(
+        path: &Path,
+    ) -> std::thread::JoinHandle<(String, Vec<String>, Vec<u8>)> {
+        let listener = UnixListener::bind(path).unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut headers = Vec::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_string();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(": ") {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.parse().unwrap();
+                    }
+                }
+                headers.push(line);
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            (&stream)
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}")
+                .unwrap();
+            (request_line.trim_end().to_string(), headers, body)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_send_over_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("apm.socket");
+        let server = mock_agent(&path);
+
+        let request = Request::post("http://localhost/v0.5/traces")
+            .body(b"payload".to_vec())
+            .unwrap();
+        let response = UnixSocketClient::new(&path).send(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), b"{}");
+
+        let (request_line, headers, body) = server.join().unwrap();
+        assert_eq!(request_line, "POST /v0.5/traces HTTP/1.1");
+        assert!(headers.contains(&"host: localhost".to_string()));
+        assert_eq!(body, b"payload");
+    }
+
+    #[test]
+    fn test_requires_tokio_runtime() {
+        let request = Request::post("http://localhost/v0.5/traces")
+            .body(Vec::new())
+            .unwrap();
+        let result = futures_executor::block_on(
+            UnixSocketClient::new("/nonexistent/apm.socket").send(request),
+        );
+        assert!(result.is_err());
+    }
+}
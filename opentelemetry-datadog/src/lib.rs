@@ -135,14 +135,37 @@
 //! ```
 
 mod exporter;
+mod logs;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "remote-config")]
+mod remote_config;
+#[cfg(feature = "agent-sampling")]
+mod sampler;
 
 pub use exporter::{
-    new_pipeline, ApiVersion, DatadogExporter, DatadogPipelineBuilder, Error, FieldMappingFn,
-    ModelConfig,
+    new_pipeline, ApiVersion, DatadogExporter, DatadogField, DatadogPipelineBuilder, Error,
+    FieldMappingFn, MappingRuleFn, MappingRules, ModelConfig, RetryConfig,
 };
-pub use propagator::{DatadogPropagator, DatadogTraceState, DatadogTraceStateBuilder};
+pub use logs::DatadogLogCorrelation;
+#[cfg(feature = "logs")]
+pub use logs::DatadogLogCorrelationProcessor;
+#[cfg(feature = "metrics")]
+pub use metrics::{DogStatsdExporter, DogStatsdExporterBuilder};
+pub use propagator::{
+    DatadogCompositePropagator, DatadogPropagator, DatadogTraceState, DatadogTraceStateBuilder,
+    PropagationStyle,
+};
+#[cfg(feature = "remote-config")]
+pub use remote_config::DatadogRemoteConfig;
+#[cfg(feature = "agent-sampling")]
+pub use sampler::{DatadogAgentSampler, DatadogRuleSampler};
 
 mod propagator {
+    mod composite;
+
+    pub use composite::{DatadogCompositePropagator, PropagationStyle};
+
     use once_cell::sync::Lazy;
     use opentelemetry::{
         propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
@@ -153,19 +176,39 @@ mod propagator {
     const DATADOG_TRACE_ID_HEADER: &str = "x-datadog-trace-id";
     const DATADOG_PARENT_ID_HEADER: &str = "x-datadog-parent-id";
     const DATADOG_SAMPLING_PRIORITY_HEADER: &str = "x-datadog-sampling-priority";
+    const DATADOG_TAGS_HEADER: &str = "x-datadog-tags";
+    const DATADOG_ORIGIN_HEADER: &str = "x-datadog-origin";
+
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/spancontext.go#L36
+    const DATADOG_TRACE_ID_HIGH_TAG: &str = "_dd.p.tid";
+    pub(crate) const DATADOG_PROPAGATED_TAG_PREFIX: &str = "_dd.p.";
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/option.go#L264
+    const DATADOG_TAGS_MAX_LENGTH: usize = 512;
 
     const TRACE_FLAG_DEFERRED: TraceFlags = TraceFlags::new(0x02);
-    #[cfg(feature = "agent-sampling")]
-    const TRACE_STATE_PRIORITY_SAMPLING: &str = "psr";
+    pub(crate) const TRACE_STATE_PRIORITY_SAMPLING: &str = "psr";
     const TRACE_STATE_MEASURE: &str = "m";
+    const TRACE_STATE_ORIGIN: &str = "o";
+    const TRACE_STATE_PROPAGATED_TAGS: &str = "t";
+    // `<remote parent>:<datadog parent>`, so that only the local root spans report it
+    const TRACE_STATE_DATADOG_PARENT: &str = "p";
+    // https://www.w3.org/TR/trace-context/#value
+    const TRACE_STATE_MAX_VALUE_LENGTH: usize = 256;
     const TRACE_STATE_TRUE_VALUE: &str = "1";
     const TRACE_STATE_FALSE_VALUE: &str = "0";
+    // https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/priority.go
+    #[cfg(feature = "agent-sampling")]
+    const TRACE_STATE_USER_KEEP_VALUE: &str = "2";
+    #[cfg(feature = "agent-sampling")]
+    const TRACE_STATE_USER_REJECT_VALUE: &str = "-1";
 
-    static DATADOG_HEADER_FIELDS: Lazy<[String; 3]> = Lazy::new(|| {
+    static DATADOG_HEADER_FIELDS: Lazy<[String; 5]> = Lazy::new(|| {
         [
             DATADOG_TRACE_ID_HEADER.to_string(),
             DATADOG_PARENT_ID_HEADER.to_string(),
             DATADOG_SAMPLING_PRIORITY_HEADER.to_string(),
+            DATADOG_TAGS_HEADER.to_string(),
+            DATADOG_ORIGIN_HEADER.to_string(),
         ]
     });
 
@@ -173,6 +216,8 @@ mod propagator {
     pub struct DatadogTraceStateBuilder {
         #[cfg(feature = "agent-sampling")]
         priority_sampling: bool,
+        #[cfg(feature = "agent-sampling")]
+        user_priority: bool,
         measuring: bool,
     }
 
@@ -188,11 +233,102 @@ mod propagator {
         value == TRACE_STATE_TRUE_VALUE
     }
 
+    #[cfg(feature = "agent-sampling")]
+    fn priority_to_trace_state_value(keep: bool, user: bool) -> &'static str {
+        match (keep, user) {
+            (true, true) => TRACE_STATE_USER_KEEP_VALUE,
+            (false, true) => TRACE_STATE_USER_REJECT_VALUE,
+            (keep, false) => boolean_to_trace_state_flag(keep),
+        }
+    }
+
+    // Propagated tags are kept in a single trace state entry, encoded the way Datadog encodes
+    // them in the `dd` member of W3C `tracestate` headers: `dm:-1;usvc:my-service`.
+    fn encode_propagated_tags(tags: &[(String, String)]) -> String {
+        let mut encoded = String::new();
+        for (key, value) in tags {
+            if key == DATADOG_TRACE_ID_HIGH_TAG {
+                continue;
+            }
+            let key = match key.strip_prefix(DATADOG_PROPAGATED_TAG_PREFIX) {
+                Some(key) => key,
+                None => continue,
+            };
+            if key.is_empty()
+                || key.contains([':', ';', ',', '=', '~'])
+                || value.contains([';', ',', '~'])
+            {
+                continue;
+            }
+            let entry = format!("{key}:{}", value.replace('=', "~"));
+            let separator = usize::from(!encoded.is_empty());
+            if encoded.len() + separator + entry.len() > TRACE_STATE_MAX_VALUE_LENGTH {
+                continue;
+            }
+            if separator == 1 {
+                encoded.push(';');
+            }
+            encoded.push_str(&entry);
+        }
+        encoded
+    }
+
+    fn decode_propagated_tags(encoded: &str) -> Vec<(String, String)> {
+        encoded_propagated_tags(encoded)
+            .map(|(key, value)| {
+                (
+                    format!("{DATADOG_PROPAGATED_TAG_PREFIX}{key}"),
+                    value.replace('~', "="),
+                )
+            })
+            .collect()
+    }
+
+    fn encoded_propagated_tags(encoded: &str) -> impl Iterator<Item = (&str, &str)> {
+        encoded.split(';').filter_map(|entry| entry.split_once(':'))
+    }
+
+    /// The sampling priority a Datadog sampler or propagator set in the trace state, if any.
+    #[cfg(feature = "agent-sampling")]
+    pub(crate) fn explicit_sampling_priority(trace_state: &TraceState) -> Option<i32> {
+        trace_state
+            .get(TRACE_STATE_PRIORITY_SAMPLING)
+            .map(|priority| match priority {
+                TRACE_STATE_USER_KEEP_VALUE => 2,
+                TRACE_STATE_TRUE_VALUE => 1,
+                TRACE_STATE_USER_REJECT_VALUE => -1,
+                _ => 0,
+            })
+    }
+
+    /// The `_dd.p.*` tags propagated with the trace as they are kept in the trace state, without
+    /// the `_dd.p.` prefix and with `~` in place of `=` in values.
+    pub(crate) fn raw_propagated_tags(
+        trace_state: &TraceState,
+    ) -> impl Iterator<Item = (&str, &str)> {
+        trace_state
+            .get(TRACE_STATE_PROPAGATED_TAGS)
+            .into_iter()
+            .flat_map(encoded_propagated_tags)
+    }
+
     impl DatadogTraceStateBuilder {
         #[cfg(feature = "agent-sampling")]
         pub fn with_priority_sampling(self, enabled: bool) -> Self {
             Self {
                 priority_sampling: enabled,
+                user_priority: false,
+                ..self
+            }
+        }
+
+        /// Keep or reject the trace with the `USER_KEEP` or `USER_REJECT` priority of sampling
+        /// rules, see [`DatadogTraceState::with_user_priority_sampling`].
+        #[cfg(feature = "agent-sampling")]
+        pub fn with_user_priority_sampling(self, keep: bool) -> Self {
+            Self {
+                priority_sampling: keep,
+                user_priority: true,
                 ..self
             }
         }
@@ -218,7 +354,7 @@ mod propagator {
                 ),
                 (
                     TRACE_STATE_PRIORITY_SAMPLING,
-                    boolean_to_trace_state_flag(self.priority_sampling),
+                    priority_to_trace_state_value(self.priority_sampling, self.user_priority),
                 ),
             ];
 
@@ -226,7 +362,16 @@ mod propagator {
         }
     }
 
-    pub trait DatadogTraceState {
+    mod private {
+        pub trait Sealed {}
+
+        impl Sealed for opentelemetry::trace::TraceState {}
+    }
+
+    /// Datadog settings carried by the trace state.
+    ///
+    /// This trait is sealed, it is only implemented for [`TraceState`].
+    pub trait DatadogTraceState: private::Sealed {
         fn with_measuring(&self, enabled: bool) -> TraceState;
 
         fn measuring_enabled(&self) -> bool;
@@ -236,6 +381,32 @@ mod propagator {
 
         #[cfg(feature = "agent-sampling")]
         fn priority_sampling_enabled(&self) -> bool;
+
+        /// Keep or reject the trace on behalf of a sampling rule or rate limit, with the
+        /// `USER_KEEP` (2) or `USER_REJECT` (-1) sampling priority rather than the `AUTO_KEEP` (1)
+        /// or `AUTO_REJECT` (0) one of [`with_priority_sampling`].
+        ///
+        /// [`with_priority_sampling`]: DatadogTraceState::with_priority_sampling
+        #[cfg(feature = "agent-sampling")]
+        fn with_user_priority_sampling(&self, keep: bool) -> TraceState;
+
+        /// The Datadog sampling priority of the trace, from `USER_REJECT` (-1) to `USER_KEEP` (2).
+        #[cfg(feature = "agent-sampling")]
+        fn sampling_priority(&self) -> i32;
+
+        /// Set the origin of the trace, e.g. `synthetics` for traces started by Synthetic tests.
+        fn with_origin(&self, origin: &str) -> TraceState;
+
+        fn origin(&self) -> Option<&str>;
+
+        /// Replace the `_dd.p.*` tags propagated with the trace.
+        ///
+        /// Tags are only kept as long as they fit in a single trace state value, later tags are
+        /// dropped once it is full.
+        fn with_propagated_tags(&self, tags: &[(String, String)]) -> TraceState;
+
+        /// The `_dd.p.*` tags propagated with the trace, keys include the `_dd.p.` prefix.
+        fn propagated_tags(&self) -> Vec<(String, String)>;
     }
 
     impl DatadogTraceState for TraceState {
@@ -261,12 +432,77 @@ mod propagator {
 
         #[cfg(feature = "agent-sampling")]
         fn priority_sampling_enabled(&self) -> bool {
-            self.get(TRACE_STATE_PRIORITY_SAMPLING)
-                .map(trace_flag_to_boolean)
+            self.sampling_priority() > 0
+        }
+
+        #[cfg(feature = "agent-sampling")]
+        fn with_user_priority_sampling(&self, keep: bool) -> TraceState {
+            self.insert(
+                TRACE_STATE_PRIORITY_SAMPLING,
+                priority_to_trace_state_value(keep, true),
+            )
+            .unwrap_or_else(|_err| self.clone())
+        }
+
+        #[cfg(feature = "agent-sampling")]
+        fn sampling_priority(&self) -> i32 {
+            explicit_sampling_priority(self).unwrap_or(0)
+        }
+
+        fn with_origin(&self, origin: &str) -> TraceState {
+            self.insert(TRACE_STATE_ORIGIN, origin)
+                .unwrap_or_else(|_err| self.clone())
+        }
+
+        fn origin(&self) -> Option<&str> {
+            self.get(TRACE_STATE_ORIGIN)
+        }
+
+        fn with_propagated_tags(&self, tags: &[(String, String)]) -> TraceState {
+            let encoded = encode_propagated_tags(tags);
+            if encoded.is_empty() {
+                self.delete(TRACE_STATE_PROPAGATED_TAGS)
+                    .unwrap_or_else(|_err| self.clone())
+            } else {
+                self.insert(TRACE_STATE_PROPAGATED_TAGS, encoded)
+                    .unwrap_or_else(|_err| self.clone())
+            }
+        }
+
+        fn propagated_tags(&self) -> Vec<(String, String)> {
+            self.get(TRACE_STATE_PROPAGATED_TAGS)
+                .map(decode_propagated_tags)
                 .unwrap_or_default()
         }
     }
 
+    /// Record `datadog_parent` as the last Datadog span the trace went through before reaching
+    /// `remote_parent`, the parent it was extracted with.
+    pub(crate) fn with_datadog_parent_id(
+        trace_state: &TraceState,
+        remote_parent: SpanId,
+        datadog_parent: SpanId,
+    ) -> TraceState {
+        trace_state
+            .insert(
+                TRACE_STATE_DATADOG_PARENT,
+                format!("{remote_parent}:{datadog_parent}"),
+            )
+            .unwrap_or_else(|_err| trace_state.clone())
+    }
+
+    /// The last Datadog span the trace went through, if `parent` is the remote parent it was
+    /// extracted with.
+    pub(crate) fn datadog_parent_id(trace_state: &TraceState, parent: SpanId) -> Option<SpanId> {
+        let (remote_parent, datadog_parent) = trace_state
+            .get(TRACE_STATE_DATADOG_PARENT)?
+            .split_once(':')?;
+        if SpanId::from_hex(remote_parent).ok()? != parent {
+            return None;
+        }
+        SpanId::from_hex(datadog_parent).ok()
+    }
+
     enum SamplingPriority {
         UserReject = -1,
         AutoReject = 0,
@@ -302,14 +538,27 @@ mod propagator {
     }
 
     #[cfg(not(feature = "agent-sampling"))]
-    fn create_trace_state_and_flags(trace_flags: TraceFlags) -> (TraceState, TraceFlags) {
+    fn create_trace_state_and_flags(
+        trace_flags: TraceFlags,
+        _user_priority: bool,
+    ) -> (TraceState, TraceFlags) {
         (TraceState::default(), trace_flags)
     }
 
     #[cfg(feature = "agent-sampling")]
-    fn create_trace_state_and_flags(trace_flags: TraceFlags) -> (TraceState, TraceFlags) {
+    fn create_trace_state_and_flags(
+        trace_flags: TraceFlags,
+        user_priority: bool,
+    ) -> (TraceState, TraceFlags) {
         if trace_flags & TRACE_FLAG_DEFERRED == TRACE_FLAG_DEFERRED {
             (TraceState::default(), trace_flags)
+        } else if user_priority {
+            (
+                DatadogTraceStateBuilder::default()
+                    .with_user_priority_sampling(trace_flags.is_sampled())
+                    .build(),
+                TraceFlags::SAMPLED,
+            )
         } else {
             (
                 DatadogTraceStateBuilder::default()
@@ -326,11 +575,16 @@ mod propagator {
             DatadogPropagator::default()
         }
 
-        fn extract_trace_id(&self, trace_id: &str) -> Result<TraceId, ExtractError> {
-            trace_id
-                .parse::<u64>()
-                .map(|id| TraceId::from(id as u128))
-                .map_err(|_| ExtractError::TraceId)
+        fn extract_trace_id(
+            &self,
+            trace_id: &str,
+            tags: Option<&str>,
+        ) -> Result<TraceId, ExtractError> {
+            let low = trace_id.parse::<u64>().map_err(|_| ExtractError::TraceId)?;
+            // The upper 64 bits of 128-bit trace ids are propagated as a tag, a malformed value
+            // only loses them rather than the whole context.
+            let high = tags.and_then(extract_trace_id_high).unwrap_or_default();
+            Ok(TraceId::from(((high as u128) << 64) | low as u128))
         }
 
         fn extract_span_id(&self, span_id: &str) -> Result<SpanId, ExtractError> {
@@ -361,8 +615,10 @@ mod propagator {
             &self,
             extractor: &dyn Extractor,
         ) -> Result<SpanContext, ExtractError> {
-            let trace_id =
-                self.extract_trace_id(extractor.get(DATADOG_TRACE_ID_HEADER).unwrap_or(""))?;
+            let trace_id = self.extract_trace_id(
+                extractor.get(DATADOG_TRACE_ID_HEADER).unwrap_or(""),
+                extractor.get(DATADOG_TAGS_HEADER),
+            )?;
             // If we have a trace_id but can't get the parent span, we default it to invalid instead of completely erroring
             // out so that the rest of the spans aren't completely lost
             let span_id = self
@@ -384,7 +640,22 @@ mod propagator {
                 Err(_) => TRACE_FLAG_DEFERRED,
             };
 
-            let (trace_state, trace_flags) = create_trace_state_and_flags(sampled);
+            let user_priority = matches!(
+                sampling_priority,
+                Ok(SamplingPriority::UserReject) | Ok(SamplingPriority::UserKeep)
+            );
+            let (mut trace_state, trace_flags) =
+                create_trace_state_and_flags(sampled, user_priority);
+            if let Some(origin) = extractor.get(DATADOG_ORIGIN_HEADER) {
+                trace_state = trace_state.with_origin(origin);
+            }
+            let propagated_tags = extractor
+                .get(DATADOG_TAGS_HEADER)
+                .map(extract_propagated_tags)
+                .unwrap_or_default();
+            if !propagated_tags.is_empty() {
+                trace_state = trace_state.with_propagated_tags(&propagated_tags);
+            }
 
             Ok(SpanContext::new(
                 trace_id,
@@ -396,6 +667,32 @@ mod propagator {
         }
     }
 
+    fn extract_propagated_tags(tags: &str) -> Vec<(String, String)> {
+        // Oversized headers are dropped as a whole, like dd-trace libraries do.
+        if tags.len() > DATADOG_TAGS_MAX_LENGTH {
+            return vec![];
+        }
+        tags.split(',')
+            .filter_map(|tag| {
+                let (key, value) = tag.split_once('=')?;
+                let key = key.trim();
+                (key.starts_with(DATADOG_PROPAGATED_TAG_PREFIX) && key != DATADOG_TRACE_ID_HIGH_TAG)
+                    .then(|| (key.to_string(), value.trim().to_string()))
+            })
+            .collect()
+    }
+
+    fn extract_trace_id_high(tags: &str) -> Option<u64> {
+        let value = tags.split(',').find_map(|tag| {
+            let (key, value) = tag.split_once('=')?;
+            (key.trim() == DATADOG_TRACE_ID_HIGH_TAG).then_some(value.trim())
+        })?;
+        if value.len() != 16 {
+            return None;
+        }
+        u64::from_str_radix(value, 16).ok()
+    }
+
     #[cfg(not(feature = "agent-sampling"))]
     fn get_sampling_priority(span_context: &SpanContext) -> SamplingPriority {
         if span_context.is_sampled() {
@@ -407,10 +704,11 @@ mod propagator {
 
     #[cfg(feature = "agent-sampling")]
     fn get_sampling_priority(span_context: &SpanContext) -> SamplingPriority {
-        if span_context.trace_state().priority_sampling_enabled() {
-            SamplingPriority::AutoKeep
-        } else {
-            SamplingPriority::AutoReject
+        match span_context.trace_state().sampling_priority() {
+            2 => SamplingPriority::UserKeep,
+            1 => SamplingPriority::AutoKeep,
+            -1 => SamplingPriority::UserReject,
+            _ => SamplingPriority::AutoReject,
         }
     }
 
@@ -419,10 +717,25 @@ mod propagator {
             let span = cx.span();
             let span_context = span.span_context();
             if span_context.is_valid() {
-                injector.set(
-                    DATADOG_TRACE_ID_HEADER,
-                    (u128::from_be_bytes(span_context.trace_id().to_bytes()) as u64).to_string(),
-                );
+                let trace_id = u128::from_be_bytes(span_context.trace_id().to_bytes());
+                injector.set(DATADOG_TRACE_ID_HEADER, (trace_id as u64).to_string());
+                let trace_state = span_context.trace_state();
+                let trace_id_high = (trace_id >> 64) as u64;
+                let mut tags = Vec::new();
+                if trace_id_high != 0 {
+                    tags.push(format!("{DATADOG_TRACE_ID_HIGH_TAG}={trace_id_high:016x}"));
+                }
+                for (key, value) in trace_state.propagated_tags() {
+                    tags.push(format!("{key}={value}"));
+                }
+                let tags = tags.join(",");
+                // Datadog drops the whole header rather than truncating it.
+                if !tags.is_empty() && tags.len() <= DATADOG_TAGS_MAX_LENGTH {
+                    injector.set(DATADOG_TAGS_HEADER, tags);
+                }
+                if let Some(origin) = trace_state.origin() {
+                    injector.set(DATADOG_ORIGIN_HEADER, origin.to_string());
+                }
                 injector.set(
                     DATADOG_PARENT_ID_HEADER,
                     u64::from_be_bytes(span_context.span_id().to_bytes()).to_string(),
@@ -468,6 +781,8 @@ mod propagator {
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TRACE_FLAG_DEFERRED, true, TraceState::default())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(false).build())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(true).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "-1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_user_priority_sampling(false).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_user_priority_sampling(true).build())),
             ];
             #[cfg(not(feature = "agent-sampling"))]
             return vec![
@@ -492,6 +807,8 @@ mod propagator {
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TRACE_FLAG_DEFERRED, true, TraceState::default())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "0")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(false).build())),
                 (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_priority_sampling(true).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "-1")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_user_priority_sampling(false).build())),
+                (vec![(DATADOG_TRACE_ID_HEADER, "1234"), (DATADOG_PARENT_ID_HEADER, "12"), (DATADOG_SAMPLING_PRIORITY_HEADER, "2")], SpanContext::new(TraceId::from_u128(1234), SpanId::from_u64(12), TraceFlags::SAMPLED, true, DatadogTraceStateBuilder::default().with_user_priority_sampling(true).build())),
             ];
             #[cfg(not(feature = "agent-sampling"))]
             return vec![
@@ -535,6 +852,155 @@ mod propagator {
             assert!(!context.has_active_span())
         }
 
+        #[test]
+        fn test_extract_128_bit_trace_id() {
+            let propagator = DatadogPropagator::default();
+            for (tags, expected) in [
+                (
+                    "_dd.p.tid=640cfd8d00000000",
+                    0x640cfd8d00000000_00000000000004d2,
+                ),
+                (
+                    "_dd.p.dm=-1,_dd.p.tid=640cfd8d00000000",
+                    0x640cfd8d00000000_00000000000004d2,
+                ),
+                ("_dd.p.tid=640cfd8d", 1234),
+                ("_dd.p.tid=garbage0garbage0", 1234),
+            ] {
+                let map: HashMap<String, String> = [
+                    (DATADOG_TRACE_ID_HEADER, "1234"),
+                    (DATADOG_PARENT_ID_HEADER, "12"),
+                    (DATADOG_TAGS_HEADER, tags),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+
+                let context = propagator.extract(&map);
+                assert_eq!(
+                    context.span().span_context().trace_id(),
+                    TraceId::from_u128(expected),
+                    "extracting {tags}"
+                );
+            }
+        }
+
+        #[test]
+        fn test_inject_128_bit_trace_id() {
+            let propagator = DatadogPropagator::default();
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(
+                &Context::current_with_span(TestSpan(SpanContext::new(
+                    TraceId::from_u128(0x640cfd8d00000000_00000000000004d2),
+                    SpanId::from_u64(12),
+                    TraceFlags::SAMPLED,
+                    true,
+                    TraceState::default(),
+                ))),
+                &mut injector,
+            );
+
+            assert_eq!(
+                injector.get(DATADOG_TRACE_ID_HEADER),
+                Some(&"1234".to_string())
+            );
+            assert_eq!(
+                injector.get(DATADOG_TAGS_HEADER),
+                Some(&"_dd.p.tid=640cfd8d00000000".to_string())
+            );
+
+            let context = propagator.extract(&injector);
+            assert_eq!(
+                context.span().span_context().trace_id(),
+                TraceId::from_u128(0x640cfd8d00000000_00000000000004d2)
+            );
+        }
+
+        #[test]
+        fn test_extract_origin_and_propagated_tags() {
+            let map: HashMap<String, String> = [
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (DATADOG_ORIGIN_HEADER, "synthetics"),
+                (
+                    DATADOG_TAGS_HEADER,
+                    "_dd.p.dm=-4,_dd.p.tid=640cfd8d00000000,_dd.p.usr.id=YmF6=,other=value",
+                ),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+            let propagator = DatadogPropagator::default();
+            let context = propagator.extract(&map);
+            let trace_state = context.span().span_context().trace_state().clone();
+
+            assert_eq!(trace_state.origin(), Some("synthetics"));
+            assert_eq!(
+                trace_state.propagated_tags(),
+                vec![
+                    ("_dd.p.dm".to_string(), "-4".to_string()),
+                    ("_dd.p.usr.id".to_string(), "YmF6=".to_string()),
+                ]
+            );
+
+            let mut injector: HashMap<String, String> = HashMap::new();
+            propagator.inject_context(&context, &mut injector);
+            assert_eq!(
+                injector.get(DATADOG_ORIGIN_HEADER),
+                Some(&"synthetics".to_string())
+            );
+            assert_eq!(
+                injector.get(DATADOG_TAGS_HEADER),
+                Some(&"_dd.p.tid=640cfd8d00000000,_dd.p.dm=-4,_dd.p.usr.id=YmF6=".to_string())
+            );
+        }
+
+        #[test]
+        fn test_extract_oversized_tags() {
+            let tags = format!(
+                "_dd.p.dm=-4,_dd.p.big={}",
+                "a".repeat(DATADOG_TAGS_MAX_LENGTH)
+            );
+            let map: HashMap<String, String> = [
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (DATADOG_TAGS_HEADER, tags.as_str()),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+            let context = DatadogPropagator::default().extract(&map);
+            assert!(context
+                .span()
+                .span_context()
+                .trace_state()
+                .propagated_tags()
+                .is_empty());
+        }
+
+        #[test]
+        fn test_propagated_tags_trace_state_limit() {
+            let tags = vec![
+                ("_dd.p.dm".to_string(), "-4".to_string()),
+                (
+                    "_dd.p.big".to_string(),
+                    "a".repeat(TRACE_STATE_MAX_VALUE_LENGTH),
+                ),
+                ("_dd.p.usvc".to_string(), "service".to_string()),
+                ("_dd.p.bad".to_string(), "a;b".to_string()),
+            ];
+            let trace_state = TraceState::default().with_propagated_tags(&tags);
+            assert_eq!(
+                trace_state.propagated_tags(),
+                vec![
+                    ("_dd.p.dm".to_string(), "-4".to_string()),
+                    ("_dd.p.usvc".to_string(), "service".to_string()),
+                ]
+            );
+        }
+
         #[test]
         fn test_inject() {
             let propagator = DatadogPropagator::default();
@@ -0,0 +1,156 @@
+use crate::exporter::model::unified_tags::UnifiedTags;
+use crate::exporter::model::{dd_span_id, dd_trace_id};
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
+use opentelemetry::Context;
+
+const TRACE_ID_FIELD: &str = "dd.trace_id";
+const SPAN_ID_FIELD: &str = "dd.span_id";
+const SERVICE_FIELD: &str = "dd.service";
+const ENV_FIELD: &str = "dd.env";
+const VERSION_FIELD: &str = "dd.version";
+
+/// Produces the fields Datadog uses to correlate logs with traces.
+///
+/// Trace and span ids are converted to the 64 bits decimal ids the exporter sends to Datadog,
+/// and service, env and version default to the unified service tags read from `DD_SERVICE`,
+/// `DD_ENV` and `DD_VERSION`.
+///
+/// See <https://docs.datadoghq.com/tracing/other_telemetry/connect_logs_and_traces/opentelemetry/>
+///
+/// ```
+/// use opentelemetry_datadog::DatadogLogCorrelation;
+///
+/// let correlation = DatadogLogCorrelation::new().with_service("my-service");
+/// for (key, value) in correlation.current_fields() {
+///     println!("{key}={value}");
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct DatadogLogCorrelation {
+    service: Option<String>,
+    env: Option<String>,
+    version: Option<String>,
+}
+
+impl Default for DatadogLogCorrelation {
+    fn default() -> Self {
+        DatadogLogCorrelation::new()
+    }
+}
+
+impl DatadogLogCorrelation {
+    /// Create a correlation helper using the unified service tags from the environment.
+    pub fn new() -> Self {
+        let unified_tags = UnifiedTags::new();
+        DatadogLogCorrelation {
+            service: unified_tags.service.value,
+            env: unified_tags.env.value,
+            version: unified_tags.version.value,
+        }
+    }
+
+    /// Set the service name reported as `dd.service`.
+    pub fn with_service<T: Into<String>>(mut self, service: T) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Set the environment reported as `dd.env`.
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.env = Some(env.into());
+        self
+    }
+
+    /// Set the version reported as `dd.version`.
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Correlation fields for the span active in `cx`.
+    ///
+    /// `dd.trace_id` and `dd.span_id` are only present if `cx` holds a valid span context.
+    pub fn fields(&self, cx: &Context) -> Vec<(&'static str, String)> {
+        let span = cx.span();
+        let span_context = span.span_context();
+        let ids = span_context
+            .is_valid()
+            .then(|| (span_context.trace_id(), span_context.span_id()));
+        self.fields_for(ids)
+    }
+
+    /// Correlation fields for the span active in the current context.
+    pub fn current_fields(&self) -> Vec<(&'static str, String)> {
+        Context::map_current(|cx| self.fields(cx))
+    }
+
+    pub(crate) fn fields_for(&self, ids: Option<(TraceId, SpanId)>) -> Vec<(&'static str, String)> {
+        let mut fields = Vec::with_capacity(5);
+        if let Some((trace_id, span_id)) = ids {
+            fields.push((TRACE_ID_FIELD, dd_trace_id(trace_id).to_string()));
+            fields.push((SPAN_ID_FIELD, dd_span_id(span_id).to_string()));
+        }
+        let tags = [
+            (SERVICE_FIELD, &self.service),
+            (ENV_FIELD, &self.env),
+            (VERSION_FIELD, &self.version),
+        ];
+        for (key, value) in tags {
+            if let Some(value) = value {
+                fields.push((key, value.clone()));
+            }
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, TraceFlags, TraceState};
+
+    fn correlation() -> DatadogLogCorrelation {
+        DatadogLogCorrelation {
+            service: None,
+            env: None,
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_fields() {
+        let correlation = correlation()
+            .with_service("service")
+            .with_env("env")
+            .with_version("1.0.0");
+        let span_context = SpanContext::new(
+            TraceId::from_u128(0x0000_0000_0000_0001_0000_0000_0000_0002),
+            SpanId::from_u64(3),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+        let cx = Context::new().with_remote_span_context(span_context);
+
+        assert_eq!(
+            correlation.fields(&cx),
+            vec![
+                ("dd.trace_id", "2".to_string()),
+                ("dd.span_id", "3".to_string()),
+                ("dd.service", "service".to_string()),
+                ("dd.env", "env".to_string()),
+                ("dd.version", "1.0.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fields_without_span() {
+        let correlation = correlation().with_service("service");
+
+        assert_eq!(
+            correlation.fields(&Context::new()),
+            vec![("dd.service", "service".to_string())]
+        );
+    }
+}
@@ -0,0 +1,7 @@
+mod correlation;
+#[cfg(feature = "logs")]
+mod processor;
+
+pub use correlation::DatadogLogCorrelation;
+#[cfg(feature = "logs")]
+pub use processor::DatadogLogCorrelationProcessor;
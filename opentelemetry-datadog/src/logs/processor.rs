@@ -0,0 +1,154 @@
+use crate::logs::DatadogLogCorrelation;
+#[cfg(feature = "logs_level_enabled")]
+use opentelemetry::logs::Severity;
+use opentelemetry::logs::{AnyValue, LogResult};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::{Context, Key};
+use opentelemetry_sdk::export::logs::LogData;
+use opentelemetry_sdk::logs::LogProcessor;
+
+/// Log processor adding the Datadog correlation fields to every [`LogRecord`] as attributes
+/// before handing it to the wrapped processor.
+///
+/// The trace and span ids come from the record trace context, or from the current context if
+/// the record has none.
+///
+/// ```
+/// use opentelemetry_datadog::{DatadogLogCorrelation, DatadogLogCorrelationProcessor};
+/// use opentelemetry_sdk::logs::{LogProcessor, LoggerProvider};
+///
+/// fn logger_provider<P: LogProcessor + 'static>(processor: P) -> LoggerProvider {
+///     LoggerProvider::builder()
+///         .with_log_processor(DatadogLogCorrelationProcessor::new(
+///             DatadogLogCorrelation::new(),
+///             processor,
+///         ))
+///         .build()
+/// }
+/// ```
+///
+/// [`LogRecord`]: opentelemetry::logs::LogRecord
+#[derive(Debug)]
+pub struct DatadogLogCorrelationProcessor<P> {
+    correlation: DatadogLogCorrelation,
+    inner: P,
+}
+
+impl<P: LogProcessor> DatadogLogCorrelationProcessor<P> {
+    /// Wrap `inner`, adding the fields produced by `correlation` to each record.
+    pub fn new(correlation: DatadogLogCorrelation, inner: P) -> Self {
+        DatadogLogCorrelationProcessor { correlation, inner }
+    }
+}
+
+impl<P: LogProcessor> LogProcessor for DatadogLogCorrelationProcessor<P> {
+    fn emit(&self, mut data: LogData) {
+        let ids = match &data.record.trace_context {
+            Some(trace_context) => Some((trace_context.trace_id, trace_context.span_id)),
+            None => Context::map_current(|cx| {
+                let span = cx.span();
+                let span_context = span.span_context();
+                span_context
+                    .is_valid()
+                    .then(|| (span_context.trace_id(), span_context.span_id()))
+            }),
+        };
+        let fields = self.correlation.fields_for(ids);
+        data.record.attributes.get_or_insert_with(Vec::new).extend(
+            fields
+                .into_iter()
+                .map(|(key, value)| (Key::from_static_str(key), AnyValue::from(value))),
+        );
+        self.inner.emit(data)
+    }
+
+    fn force_flush(&self) -> LogResult<()> {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&mut self) -> LogResult<()> {
+        self.inner.shutdown()
+    }
+
+    #[cfg(feature = "logs_level_enabled")]
+    fn event_enabled(&self, level: Severity, target: &str, name: &str) -> bool {
+        self.inner.event_enabled(level, target, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::logs::{LogRecord, Logger, LoggerProvider as _};
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+    use opentelemetry_sdk::logs::LoggerProvider;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Debug, Default)]
+    struct RecordingProcessor {
+        logs: Arc<Mutex<Vec<LogData>>>,
+    }
+
+    impl LogProcessor for RecordingProcessor {
+        fn emit(&self, data: LogData) {
+            self.logs.lock().unwrap().push(data)
+        }
+
+        fn force_flush(&self) -> LogResult<()> {
+            Ok(())
+        }
+
+        fn shutdown(&mut self) -> LogResult<()> {
+            Ok(())
+        }
+
+        #[cfg(feature = "logs_level_enabled")]
+        fn event_enabled(&self, _level: Severity, _target: &str, _name: &str) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_emit_with_correlation_attributes() {
+        let recorder = RecordingProcessor::default();
+        let provider = LoggerProvider::builder()
+            .with_log_processor(DatadogLogCorrelationProcessor::new(
+                DatadogLogCorrelation::new()
+                    .with_service("service")
+                    .with_env("env")
+                    .with_version("1.0.0"),
+                recorder.clone(),
+            ))
+            .build();
+        let logger = provider.logger("test");
+
+        let span_context = SpanContext::new(
+            TraceId::from_u128(0x0000_0000_0000_0001_0000_0000_0000_0002),
+            SpanId::from_u64(3),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+        let _guard = Context::new()
+            .with_remote_span_context(span_context)
+            .attach();
+        logger.emit(LogRecord::builder().with_body("message").build());
+
+        let logs = recorder.logs.lock().unwrap();
+        assert_eq!(logs.len(), 1);
+        let attributes = logs[0].record.attributes.clone().unwrap_or_default();
+        assert_eq!(
+            attributes,
+            vec![
+                (Key::new("dd.trace_id"), AnyValue::from("2".to_string())),
+                (Key::new("dd.span_id"), AnyValue::from("3".to_string())),
+                (
+                    Key::new("dd.service"),
+                    AnyValue::from("service".to_string())
+                ),
+                (Key::new("dd.env"), AnyValue::from("env".to_string())),
+                (Key::new("dd.version"), AnyValue::from("1.0.0".to_string())),
+            ]
+        );
+    }
+}
@@ -0,0 +1,648 @@
+use super::socket::{self, DogStatsdSocket};
+use crate::exporter::model::unified_tags::UnifiedTags;
+use async_trait::async_trait;
+use opentelemetry::metrics::{MetricsError, Result};
+use opentelemetry_sdk::metrics::data::{
+    ExponentialHistogram, Gauge, Histogram, ResourceMetrics, Sum, Temporality,
+};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{
+    AggregationSelector, DefaultAggregationSelector, TemporalitySelector,
+};
+use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exports metrics to DogStatsD, the StatsD server embedded in the Datadog agent.
+///
+/// Each data point is sent as a DogStatsD line tagged with the unified service tags, the global
+/// tags, the resource attributes and the data point attributes:
+///
+/// |aggregation|DogStatsD metrics|
+/// |-----------|-----------------|
+/// |monotonic delta sum|count|
+/// |other sums, gauge|gauge|
+/// |histogram|`<name>.count` and `<name>.sum` counts, `<name>.min` and `<name>.max` gauges, and `<name>.bucket` counts tagged with their `lower_bound` and `upper_bound`|
+/// |exponential histogram|`<name>.count` and `<name>.sum` counts, `<name>.min` and `<name>.max` gauges|
+///
+/// Counters and histograms are aggregated with delta temporality, so they map to DogStatsD
+/// counts. Lines are packed into datagrams up to the size DogStatsD reads at once. Datagrams are
+/// sent without blocking, those which don't fit in the socket buffer are dropped and reported as
+/// export errors.
+///
+/// ## Example
+///
+/// ```no_run
+/// use opentelemetry_datadog::DogStatsdExporter;
+/// use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+///
+/// # fn main() -> opentelemetry::metrics::Result<()> {
+/// let exporter = DogStatsdExporter::builder()
+///     .with_endpoint("udp://localhost:8125")
+///     .with_service("my_app")
+///     .build()?;
+/// let reader = PeriodicReader::builder(exporter, opentelemetry_sdk::runtime::Tokio).build();
+/// let provider = SdkMeterProvider::builder().with_reader(reader).build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DogStatsdExporter {
+    socket: DogStatsdSocket,
+    tags: Vec<String>,
+    max_packet_size: usize,
+    is_shutdown: AtomicBool,
+}
+
+impl DogStatsdExporter {
+    /// Create a builder configured from the environment, see [`DogStatsdExporterBuilder`].
+    pub fn builder() -> DogStatsdExporterBuilder {
+        DogStatsdExporterBuilder::default()
+    }
+
+    /// Encode the data points as DogStatsD lines.
+    fn encode(&self, metrics: &ResourceMetrics) -> Vec<String> {
+        let mut common_tags = self.tags.clone();
+        common_tags.extend(
+            metrics
+                .resource
+                .iter()
+                .map(|(key, value)| format_tag(key.as_str(), &value.as_str())),
+        );
+
+        let mut lines = Vec::new();
+        for metric in metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| scope.metrics.iter())
+        {
+            let name = sanitize_name(&metric.name);
+            let data = metric.data.as_any();
+            let mut encoder = LineEncoder {
+                lines: &mut lines,
+                common_tags: &common_tags,
+            };
+            if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+                encoder.sum(&name, sum);
+            } else if let Some(sum) = data.downcast_ref::<Sum<i64>>() {
+                encoder.sum(&name, sum);
+            } else if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+                encoder.sum(&name, sum);
+            } else if let Some(gauge) = data.downcast_ref::<Gauge<u64>>() {
+                encoder.gauge(&name, gauge);
+            } else if let Some(gauge) = data.downcast_ref::<Gauge<i64>>() {
+                encoder.gauge(&name, gauge);
+            } else if let Some(gauge) = data.downcast_ref::<Gauge<f64>>() {
+                encoder.gauge(&name, gauge);
+            } else if let Some(histogram) = data.downcast_ref::<Histogram<u64>>() {
+                encoder.histogram(&name, histogram);
+            } else if let Some(histogram) = data.downcast_ref::<Histogram<i64>>() {
+                encoder.histogram(&name, histogram);
+            } else if let Some(histogram) = data.downcast_ref::<Histogram<f64>>() {
+                encoder.histogram(&name, histogram);
+            } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<u64>>() {
+                encoder.exponential_histogram(&name, histogram);
+            } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<i64>>() {
+                encoder.exponential_histogram(&name, histogram);
+            } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<f64>>() {
+                encoder.exponential_histogram(&name, histogram);
+            }
+        }
+        lines
+    }
+}
+
+impl TemporalitySelector for DogStatsdExporter {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        match kind {
+            InstrumentKind::Counter
+            | InstrumentKind::ObservableCounter
+            | InstrumentKind::Histogram => Temporality::Delta,
+            _ => Temporality::Cumulative,
+        }
+    }
+}
+
+impl AggregationSelector for DogStatsdExporter {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl PushMetricsExporter for DogStatsdExporter {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> Result<()> {
+        if self.is_shutdown.load(Ordering::Relaxed) {
+            return Err(MetricsError::Other("exporter is shut down".into()));
+        }
+
+        let mut result = Ok(());
+        for packet in packets(self.encode(metrics), self.max_packet_size) {
+            if let Err(err) = self.socket.send(packet.as_bytes()) {
+                result = Err(MetricsError::Other(format!(
+                    "failed to send DogStatsD packet: {err}"
+                )));
+            }
+        }
+        result
+    }
+
+    async fn force_flush(&self) -> Result<()> {
+        // packets are sent as soon as they are exported
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.is_shutdown.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Builder for [`DogStatsdExporter`].
+///
+/// The DogStatsD endpoint defaults to `DD_DOGSTATSD_URL`, then `DD_AGENT_HOST` and
+/// `DD_DOGSTATSD_PORT`, then the `/var/run/datadog/dsd.socket` socket if it exists, and finally
+/// `localhost:8125`. The unified service tags default to `DD_SERVICE`, `DD_ENV` and `DD_VERSION`.
+#[derive(Debug)]
+pub struct DogStatsdExporterBuilder {
+    endpoint: String,
+    service: Option<String>,
+    env: Option<String>,
+    version: Option<String>,
+    tags: Vec<(String, String)>,
+    max_packet_size: Option<usize>,
+}
+
+impl Default for DogStatsdExporterBuilder {
+    fn default() -> Self {
+        DogStatsdExporterBuilder {
+            endpoint: default_endpoint(),
+            service: None,
+            env: None,
+            version: None,
+            tags: Vec::new(),
+            max_packet_size: None,
+        }
+    }
+}
+
+impl DogStatsdExporterBuilder {
+    /// Set the DogStatsD endpoint, either `udp://host:port`, `host:port` or
+    /// `unix:///path/to/socket`.
+    pub fn with_endpoint<T: Into<String>>(mut self, endpoint: T) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Set the `service` tag of every metric.
+    pub fn with_service<T: Into<String>>(mut self, service: T) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Set the `env` tag of every metric.
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.env = Some(env.into());
+        self
+    }
+
+    /// Set the `version` tag of every metric.
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Add a tag to every metric.
+    pub fn with_tag<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the largest datagram sent to DogStatsD, 1432 bytes over UDP and 8192 bytes over Unix
+    /// sockets by default.
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = Some(max_packet_size);
+        self
+    }
+
+    /// Connect the exporter to DogStatsD. Unix domain sockets are connected once metrics are
+    /// exported, and reconnected after a failure, so DogStatsD doesn't have to be listening yet.
+    pub fn build(self) -> Result<DogStatsdExporter> {
+        let socket = DogStatsdSocket::connect(&self.endpoint)?;
+
+        let mut unified_tags = UnifiedTags::new();
+        if self.service.is_some() {
+            unified_tags.set_service(self.service);
+        }
+        if self.env.is_some() {
+            unified_tags.set_env(self.env);
+        }
+        if self.version.is_some() {
+            unified_tags.set_version(self.version);
+        }
+        let tags = [
+            &unified_tags.service,
+            &unified_tags.env,
+            &unified_tags.version,
+        ]
+        .into_iter()
+        .filter_map(|tag| {
+            tag.value
+                .as_ref()
+                .map(|value| format_tag(tag.get_tag_name(), value))
+        })
+        .chain(self.tags.iter().map(|(key, value)| format_tag(key, value)))
+        .collect();
+
+        Ok(DogStatsdExporter {
+            max_packet_size: self
+                .max_packet_size
+                .unwrap_or_else(|| socket.max_packet_size()),
+            socket,
+            tags,
+            is_shutdown: AtomicBool::new(false),
+        })
+    }
+}
+
+fn default_endpoint() -> String {
+    let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+    if let Some(url) = var("DD_DOGSTATSD_URL") {
+        return url;
+    }
+    let port = var("DD_DOGSTATSD_PORT").unwrap_or_else(|| socket::DEFAULT_DOGSTATSD_PORT.into());
+    if let Some(host) = var("DD_AGENT_HOST") {
+        return if host.contains(':') {
+            format!("udp://[{host}]:{port}")
+        } else {
+            format!("udp://{host}:{port}")
+        };
+    }
+    #[cfg(unix)]
+    if std::path::Path::new(socket::DEFAULT_SOCKET_PATH).exists() {
+        return format!("unix://{}", socket::DEFAULT_SOCKET_PATH);
+    }
+    format!("udp://localhost:{port}")
+}
+
+struct LineEncoder<'a> {
+    lines: &'a mut Vec<String>,
+    common_tags: &'a [String],
+}
+
+impl LineEncoder<'_> {
+    fn sum<T: Display>(&mut self, name: &str, sum: &Sum<T>) {
+        let metric_type = match (sum.is_monotonic, sum.temporality) {
+            (true, Temporality::Delta) => "c",
+            _ => "g",
+        };
+        for point in sum.data_points.iter() {
+            self.line(name, &point.value, metric_type, &point.attributes, None);
+        }
+    }
+
+    fn gauge<T: Display>(&mut self, name: &str, gauge: &Gauge<T>) {
+        for point in gauge.data_points.iter() {
+            self.line(name, &point.value, "g", &point.attributes, None);
+        }
+    }
+
+    fn histogram<T: Display>(&mut self, name: &str, histogram: &Histogram<T>) {
+        let count_type = counter_type(histogram.temporality);
+        for point in histogram.data_points.iter() {
+            let attributes = &point.attributes;
+            self.line(
+                &format!("{name}.count"),
+                &point.count,
+                count_type,
+                attributes,
+                None,
+            );
+            self.line(
+                &format!("{name}.sum"),
+                &point.sum,
+                count_type,
+                attributes,
+                None,
+            );
+            if let Some(min) = &point.min {
+                self.line(&format!("{name}.min"), min, "g", attributes, None);
+            }
+            if let Some(max) = &point.max {
+                self.line(&format!("{name}.max"), max, "g", attributes, None);
+            }
+
+            let bucket_name = format!("{name}.bucket");
+            for (index, count) in point.bucket_counts.iter().enumerate() {
+                let lower_bound = index
+                    .checked_sub(1)
+                    .and_then(|index| point.bounds.get(index))
+                    .map_or_else(|| "-inf".to_string(), f64::to_string);
+                let upper_bound = point
+                    .bounds
+                    .get(index)
+                    .map_or_else(|| "inf".to_string(), f64::to_string);
+                let bounds = [
+                    format!("lower_bound:{lower_bound}"),
+                    format!("upper_bound:{upper_bound}"),
+                ];
+                self.line(&bucket_name, count, count_type, attributes, Some(&bounds));
+            }
+        }
+    }
+
+    fn exponential_histogram<T: Display>(
+        &mut self,
+        name: &str,
+        histogram: &ExponentialHistogram<T>,
+    ) {
+        let count_type = counter_type(histogram.temporality);
+        for point in histogram.data_points.iter() {
+            let attributes = &point.attributes;
+            self.line(
+                &format!("{name}.count"),
+                &point.count,
+                count_type,
+                attributes,
+                None,
+            );
+            self.line(
+                &format!("{name}.sum"),
+                &point.sum,
+                count_type,
+                attributes,
+                None,
+            );
+            if let Some(min) = &point.min {
+                self.line(&format!("{name}.min"), min, "g", attributes, None);
+            }
+            if let Some(max) = &point.max {
+                self.line(&format!("{name}.max"), max, "g", attributes, None);
+            }
+        }
+    }
+
+    /// Append a `name:value|type|#tags` line.
+    fn line(
+        &mut self,
+        name: &str,
+        value: &dyn Display,
+        metric_type: &str,
+        attributes: &opentelemetry_sdk::AttributeSet,
+        extra_tags: Option<&[String]>,
+    ) {
+        let value = value.to_string();
+        // DogStatsD can't represent NaN or infinite values
+        if value
+            .parse::<f64>()
+            .map_or(true, |value| !value.is_finite())
+        {
+            return;
+        }
+
+        let mut line = format!("{name}:{value}|{metric_type}");
+        let tags = self
+            .common_tags
+            .iter()
+            .cloned()
+            .chain(
+                attributes
+                    .iter()
+                    .map(|(key, value)| format_tag(key.as_str(), &value.as_str())),
+            )
+            .chain(extra_tags.into_iter().flatten().cloned());
+        for (index, tag) in tags.enumerate() {
+            line.push_str(if index == 0 { "|#" } else { "," });
+            line.push_str(&tag);
+        }
+        self.lines.push(line);
+    }
+}
+
+fn counter_type(temporality: Temporality) -> &'static str {
+    match temporality {
+        Temporality::Delta => "c",
+        _ => "g",
+    }
+}
+
+/// Pack lines into newline separated packets of at most `max_packet_size` bytes. Lines larger
+/// than that are sent alone.
+fn packets(lines: Vec<String>, max_packet_size: usize) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > max_packet_size {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(&line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Metric names are made of alphanumerics, underscores and periods.
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Tags can't contain the separators of the DogStatsD protocol.
+fn format_tag(key: &str, value: &str) -> String {
+    format!("{key}:{value}")
+        .chars()
+        .map(|c| match c {
+            '|' | ',' | '#' | '\n' | '\r' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::metrics::data::{
+        self, DataPoint, HistogramDataPoint, Metric, ScopeMetrics,
+    };
+    use opentelemetry_sdk::{AttributeSet, Resource};
+    use std::net::UdpSocket;
+    use std::time::{Duration, SystemTime};
+
+    fn metric<T: data::Aggregation>(name: &'static str, data: T) -> Metric {
+        Metric {
+            name: name.into(),
+            description: "".into(),
+            unit: Default::default(),
+            data: Box::new(data),
+        }
+    }
+
+    fn resource_metrics() -> ResourceMetrics {
+        let attributes = AttributeSet::from(&[KeyValue::new("endpoint", "/users")][..]);
+        ResourceMetrics {
+            resource: Resource::new(vec![KeyValue::new("host.name", "test")]),
+            scope_metrics: vec![ScopeMetrics {
+                metrics: vec![
+                    metric(
+                        "http.requests",
+                        Sum {
+                            data_points: vec![DataPoint {
+                                attributes: attributes.clone(),
+                                start_time: None,
+                                time: None,
+                                value: 3u64,
+                                exemplars: vec![],
+                            }],
+                            temporality: Temporality::Delta,
+                            is_monotonic: true,
+                        },
+                    ),
+                    metric(
+                        "queue size",
+                        Sum {
+                            data_points: vec![DataPoint {
+                                attributes: AttributeSet::default(),
+                                start_time: None,
+                                time: None,
+                                value: -2i64,
+                                exemplars: vec![],
+                            }],
+                            temporality: Temporality::Cumulative,
+                            is_monotonic: false,
+                        },
+                    ),
+                    metric(
+                        "latency",
+                        Histogram {
+                            data_points: vec![HistogramDataPoint {
+                                attributes,
+                                start_time: SystemTime::UNIX_EPOCH,
+                                time: SystemTime::UNIX_EPOCH,
+                                count: 3,
+                                bounds: vec![10.0],
+                                bucket_counts: vec![2, 1],
+                                min: Some(1.5),
+                                max: Some(20.0),
+                                sum: 24.5,
+                                exemplars: vec![],
+                            }],
+                            temporality: Temporality::Delta,
+                        },
+                    ),
+                ],
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn exporter(endpoint: String) -> DogStatsdExporter {
+        DogStatsdExporter::builder()
+            .with_endpoint(endpoint)
+            .with_service("service")
+            .with_env("prod")
+            .with_version("1.0")
+            .with_tag("team", "a|b")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_export_over_udp() -> Result<()> {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let exporter = exporter(format!("udp://{}", listener.local_addr().unwrap()));
+
+        futures_executor::block_on(exporter.export(&mut resource_metrics()))?;
+
+        let mut buffer = [0; 2048];
+        let len = listener.recv(&mut buffer).unwrap();
+        let packet = std::str::from_utf8(&buffer[..len]).unwrap();
+        let tags = "service:service,env:prod,version:1.0,team:a_b,host.name:test";
+        assert_eq!(
+            packet.lines().collect::<Vec<_>>(),
+            vec![
+                format!("http.requests:3|c|#{tags},endpoint:/users"),
+                format!("queue_size:-2|g|#{tags}"),
+                format!("latency.count:3|c|#{tags},endpoint:/users"),
+                format!("latency.sum:24.5|c|#{tags},endpoint:/users"),
+                format!("latency.min:1.5|g|#{tags},endpoint:/users"),
+                format!("latency.max:20|g|#{tags},endpoint:/users"),
+                format!(
+                    "latency.bucket:2|c|#{tags},endpoint:/users,lower_bound:-inf,upper_bound:10"
+                ),
+                format!(
+                    "latency.bucket:1|c|#{tags},endpoint:/users,lower_bound:10,upper_bound:inf"
+                ),
+            ]
+        );
+
+        exporter.shutdown()?;
+        assert!(futures_executor::block_on(exporter.export(&mut resource_metrics())).is_err());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_export_over_unix_socket() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dsd.socket");
+        let listener = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        let exporter = exporter(format!("unix://{}", path.display()));
+        assert_eq!(exporter.max_packet_size, 8192);
+
+        futures_executor::block_on(exporter.export(&mut resource_metrics()))?;
+
+        let mut buffer = [0; 8192];
+        let len = listener.recv(&mut buffer).unwrap();
+        let packet = std::str::from_utf8(&buffer[..len]).unwrap();
+        assert_eq!(packet.lines().count(), 8);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_connect_unix_socket_lazily() -> Result<()> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dsd.socket");
+        // DogStatsD isn't listening yet
+        let exporter = exporter(format!("unix://{}", path.display()));
+        assert!(futures_executor::block_on(exporter.export(&mut resource_metrics())).is_err());
+
+        let listener = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        futures_executor::block_on(exporter.export(&mut resource_metrics()))?;
+
+        let mut buffer = [0; 8192];
+        let len = listener.recv(&mut buffer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buffer[..len]).unwrap().lines().count(),
+            8
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_packets() {
+        let lines = vec![
+            "a:1|c".to_string(),
+            "b:1|c".to_string(),
+            "c:1|c".to_string(),
+        ];
+        assert_eq!(packets(lines.clone(), 11), vec!["a:1|c\nb:1|c", "c:1|c"]);
+        assert_eq!(packets(lines, 4), vec!["a:1|c", "b:1|c", "c:1|c"]);
+    }
+}
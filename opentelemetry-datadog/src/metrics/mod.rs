@@ -0,0 +1,4 @@
+mod exporter;
+mod socket;
+
+pub use exporter::{DogStatsdExporter, DogStatsdExporterBuilder};
@@ -0,0 +1,133 @@
+use opentelemetry::metrics::MetricsError;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::sync::{Mutex, PoisonError};
+
+/// Port DogStatsD listens on by default.
+pub(crate) const DEFAULT_DOGSTATSD_PORT: &str = "8125";
+
+/// Socket DogStatsD listens on by default, used when it exists and no endpoint is configured.
+#[cfg(unix)]
+pub(crate) const DEFAULT_SOCKET_PATH: &str = "/var/run/datadog/dsd.socket";
+
+// https://github.com/DataDog/datadog-go/blob/v5.5.0/statsd/options.go#L16
+const MAX_UDP_PACKET_SIZE: usize = 1432;
+#[cfg(unix)]
+const MAX_UDS_PACKET_SIZE: usize = 8192;
+
+/// Non-blocking datagram socket connected to DogStatsD, over UDP or a Unix domain socket.
+///
+/// Sending never blocks: like DogStatsD clients, packets which don't fit in the socket buffer
+/// are dropped.
+#[derive(Debug)]
+pub(crate) enum DogStatsdSocket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+/// Unix datagram socket connected on first use, and again after a failed send, so DogStatsD can
+/// start after the exporter or restart.
+#[cfg(unix)]
+#[derive(Debug)]
+pub(crate) struct UnixSocket {
+    path: PathBuf,
+    socket: Mutex<Option<UnixDatagram>>,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    fn send(&self, packet: &[u8]) -> io::Result<usize> {
+        let mut socket = self.socket.lock().unwrap_or_else(PoisonError::into_inner);
+        let connected = match socket.take() {
+            Some(connected) => connected,
+            None => connect_unix(&self.path)?,
+        };
+        match connected.send(packet) {
+            Err(err) if err.kind() != io::ErrorKind::WouldBlock => Err(err),
+            result => {
+                *socket = Some(connected);
+                result
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &Path) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.set_nonblocking(true)?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+impl DogStatsdSocket {
+    /// Connect to `udp://host:port`, `host:port` or `unix:///path/to/socket` endpoints. Unix
+    /// sockets are only connected once a packet is sent.
+    pub(crate) fn connect(endpoint: &str) -> Result<Self, MetricsError> {
+        let config_error = |err: io::Error| {
+            MetricsError::Config(format!("invalid DogStatsD endpoint {endpoint}: {err}"))
+        };
+
+        if let Some(path) = endpoint.strip_prefix("unix://") {
+            #[cfg(unix)]
+            {
+                if path.is_empty() {
+                    return Err(MetricsError::Config(format!(
+                        "invalid DogStatsD endpoint {endpoint}: missing socket path"
+                    )));
+                }
+                return Ok(DogStatsdSocket::Unix(UnixSocket {
+                    path: PathBuf::from(path),
+                    socket: Mutex::new(None),
+                }));
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = path;
+                return Err(MetricsError::Config(format!(
+                    "unix socket DogStatsD endpoint {endpoint} is not supported on this platform"
+                )));
+            }
+        }
+
+        let address = endpoint.strip_prefix("udp://").unwrap_or(endpoint);
+        let address = address
+            .to_socket_addrs()
+            .map_err(config_error)?
+            .next()
+            .ok_or_else(|| {
+                MetricsError::Config(format!("unresolved DogStatsD endpoint {endpoint}"))
+            })?;
+        let local = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local).map_err(config_error)?;
+        socket.set_nonblocking(true).map_err(config_error)?;
+        socket.connect(address).map_err(config_error)?;
+        Ok(DogStatsdSocket::Udp(socket))
+    }
+
+    /// Largest datagram DogStatsD reads over this socket.
+    pub(crate) fn max_packet_size(&self) -> usize {
+        match self {
+            DogStatsdSocket::Udp(_) => MAX_UDP_PACKET_SIZE,
+            #[cfg(unix)]
+            DogStatsdSocket::Unix(_) => MAX_UDS_PACKET_SIZE,
+        }
+    }
+
+    pub(crate) fn send(&self, packet: &[u8]) -> io::Result<usize> {
+        match self {
+            DogStatsdSocket::Udp(socket) => socket.send(packet),
+            #[cfg(unix)]
+            DogStatsdSocket::Unix(socket) => socket.send(packet),
+        }
+    }
+}
@@ -0,0 +1,856 @@
+use super::{
+    create_trace_state_and_flags, datadog_parent_id, encode_propagated_tags, get_sampling_priority,
+    with_datadog_parent_id, DatadogPropagator, DatadogTraceState, DATADOG_ORIGIN_HEADER,
+    DATADOG_PARENT_ID_HEADER, DATADOG_PROPAGATED_TAG_PREFIX, DATADOG_SAMPLING_PRIORITY_HEADER,
+    DATADOG_TAGS_HEADER, DATADOG_TRACE_ID_HEADER, TRACE_FLAG_DEFERRED, TRACE_STATE_DATADOG_PARENT,
+    TRACE_STATE_MAX_VALUE_LENGTH, TRACE_STATE_MEASURE, TRACE_STATE_ORIGIN,
+    TRACE_STATE_PRIORITY_SAMPLING, TRACE_STATE_PROPAGATED_TAGS,
+};
+use crate::Error;
+use opentelemetry::{
+    global,
+    propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapPropagator},
+    trace::{SpanContext, SpanId, TraceContextExt, TraceError, TraceFlags, TraceId, TraceState},
+    Context,
+};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use std::str::FromStr;
+
+const TRACEPARENT_HEADER: &str = "traceparent";
+const TRACESTATE_HEADER: &str = "tracestate";
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+const B3_SINGLE_HEADER: &str = "b3";
+
+// Member of W3C `tracestate` headers holding the Datadog data of the trace, e.g.
+// `dd=s:1;o:synthetics;p:00f067aa0ba902b7;t.dm:-4`, see
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/textmap.go
+const TRACESTATE_DATADOG_KEY: &str = "dd";
+const TRACESTATE_DATADOG_PROPAGATED_TAG_PREFIX: &str = "t.";
+// https://www.w3.org/TR/trace-context/#tracestate-header-field-values
+const TRACESTATE_MAX_MEMBERS: usize = 32;
+
+/// Header formats the [`DatadogCompositePropagator`] extracts and injects, named as in
+/// `DD_TRACE_PROPAGATION_STYLE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropagationStyle {
+    /// The `x-datadog-*` headers of [`DatadogPropagator`], `datadog`.
+    Datadog,
+    /// The W3C `traceparent` and `tracestate` headers, `tracecontext`.
+    TraceContext,
+    /// The B3 `x-b3-*` headers, `b3multi` or `b3`.
+    B3Multi,
+    /// The B3 `b3` single header, `b3 single header`.
+    B3SingleHeader,
+}
+
+impl FromStr for PropagationStyle {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_lowercase().as_str() {
+            "datadog" => Ok(PropagationStyle::Datadog),
+            "tracecontext" => Ok(PropagationStyle::TraceContext),
+            "b3multi" | "b3" => Ok(PropagationStyle::B3Multi),
+            "b3 single header" => Ok(PropagationStyle::B3SingleHeader),
+            _ => Err(Error::Other(format!("unknown propagation style {name:?}"))),
+        }
+    }
+}
+
+impl PropagationStyle {
+    fn headers(self) -> &'static [&'static str] {
+        match self {
+            PropagationStyle::Datadog => &[
+                DATADOG_TRACE_ID_HEADER,
+                DATADOG_PARENT_ID_HEADER,
+                DATADOG_SAMPLING_PRIORITY_HEADER,
+                DATADOG_TAGS_HEADER,
+                DATADOG_ORIGIN_HEADER,
+            ],
+            PropagationStyle::TraceContext => &[TRACEPARENT_HEADER, TRACESTATE_HEADER],
+            PropagationStyle::B3Multi => &[
+                B3_TRACE_ID_HEADER,
+                B3_SPAN_ID_HEADER,
+                B3_SAMPLED_HEADER,
+                B3_FLAGS_HEADER,
+            ],
+            PropagationStyle::B3SingleHeader => &[B3_SINGLE_HEADER],
+        }
+    }
+
+    fn extract(self, extractor: &dyn Extractor) -> Option<SpanContext> {
+        match self {
+            PropagationStyle::Datadog => DatadogPropagator::new()
+                .extract_span_context(extractor)
+                .ok()
+                .filter(|span_context| span_context.trace_id() != TraceId::INVALID),
+            PropagationStyle::TraceContext => extract_trace_context(extractor),
+            PropagationStyle::B3Multi => extract_b3_multi(extractor),
+            PropagationStyle::B3SingleHeader => extract_b3_single(extractor),
+        }
+    }
+
+    fn inject(self, cx: &Context, span_context: &SpanContext, injector: &mut dyn Injector) {
+        match self {
+            PropagationStyle::Datadog => DatadogPropagator::new().inject_context(cx, injector),
+            PropagationStyle::TraceContext => inject_trace_context(span_context, injector),
+            PropagationStyle::B3Multi => {
+                injector.set(B3_TRACE_ID_HEADER, b3_trace_id(span_context.trace_id()));
+                injector.set(B3_SPAN_ID_HEADER, span_context.span_id().to_string());
+                if let Some(keep) = sampling_decision(span_context) {
+                    injector.set(B3_SAMPLED_HEADER, u8::from(keep).to_string());
+                }
+            }
+            PropagationStyle::B3SingleHeader => {
+                let mut value = format!(
+                    "{}-{}",
+                    b3_trace_id(span_context.trace_id()),
+                    span_context.span_id()
+                );
+                if let Some(keep) = sampling_decision(span_context) {
+                    value.push_str(if keep { "-1" } else { "-0" });
+                }
+                injector.set(B3_SINGLE_HEADER, value);
+            }
+        }
+    }
+}
+
+/// Extracts and injects `SpanContext`s in several header formats, the way dd-trace libraries do
+/// according to `DD_TRACE_PROPAGATION_STYLE_EXTRACT` and `DD_TRACE_PROPAGATION_STYLE_INJECT`.
+///
+/// Styles are extracted in order and the first valid context is kept. When W3C headers of the
+/// same trace follow, their `tracestate` members are added to the context, and if their parent
+/// differs it becomes the parent while the last Datadog span is reported as `_dd.parent_id`,
+/// taken from the `p` entry of the `dd` member or the Datadog headers. This way traces going
+/// through services that only propagate W3C headers are not split.
+///
+/// Contexts are injected in every inject style.
+///
+/// ## Example
+///
+/// ```
+/// use opentelemetry::global;
+/// use opentelemetry_datadog::{DatadogCompositePropagator, PropagationStyle};
+///
+/// global::set_text_map_propagator(
+///     DatadogCompositePropagator::new()
+///         .with_extract_styles([PropagationStyle::Datadog, PropagationStyle::B3Multi]),
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct DatadogCompositePropagator {
+    extract: Vec<PropagationStyle>,
+    inject: Vec<PropagationStyle>,
+    extract_first: bool,
+    fields: Vec<String>,
+}
+
+impl Default for DatadogCompositePropagator {
+    fn default() -> Self {
+        let styles = vec![PropagationStyle::Datadog, PropagationStyle::TraceContext];
+        DatadogCompositePropagator {
+            fields: fields(&styles, &styles),
+            extract: styles.clone(),
+            inject: styles,
+            extract_first: false,
+        }
+    }
+}
+
+impl DatadogCompositePropagator {
+    /// Creates a new `DatadogCompositePropagator` extracting and injecting the `datadog` then
+    /// `tracecontext` styles, the default of dd-trace libraries.
+    pub fn new() -> Self {
+        DatadogCompositePropagator::default()
+    }
+
+    /// Creates a new `DatadogCompositePropagator` configured from the environment:
+    ///
+    /// - `DD_TRACE_PROPAGATION_STYLE_EXTRACT` and `DD_TRACE_PROPAGATION_STYLE_INJECT`, comma
+    ///   separated styles, falling back to `DD_TRACE_PROPAGATION_STYLE` for both. `none` disables
+    ///   propagation.
+    /// - `DD_TRACE_PROPAGATION_EXTRACT_FIRST`, to only extract the first valid context.
+    ///
+    /// Invalid values are reported to the global error handler and ignored.
+    pub fn from_env() -> Self {
+        Self::from_env_vars(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    fn from_env_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut propagator = Self::new();
+        let styles = var("DD_TRACE_PROPAGATION_STYLE");
+        if let Some(styles) = var("DD_TRACE_PROPAGATION_STYLE_EXTRACT").or_else(|| styles.clone()) {
+            propagator = propagator.with_extract_styles(parse_styles(&styles));
+        }
+        if let Some(styles) = var("DD_TRACE_PROPAGATION_STYLE_INJECT").or(styles) {
+            propagator = propagator.with_inject_styles(parse_styles(&styles));
+        }
+        if let Some(extract_first) = var("DD_TRACE_PROPAGATION_EXTRACT_FIRST") {
+            match extract_first.to_lowercase().parse() {
+                Ok(extract_first) => propagator = propagator.with_extract_first(extract_first),
+                Err(_) => global::handle_error(TraceError::Other(
+                    format!(
+                        "invalid DD_TRACE_PROPAGATION_EXTRACT_FIRST {extract_first:?}, expected true or false"
+                    )
+                    .into(),
+                )),
+            }
+        }
+        propagator
+    }
+
+    /// Set the styles contexts are extracted from, in order of precedence.
+    pub fn with_extract_styles<I: IntoIterator<Item = PropagationStyle>>(self, styles: I) -> Self {
+        let extract = styles.into_iter().collect::<Vec<_>>();
+        DatadogCompositePropagator {
+            fields: fields(&extract, &self.inject),
+            extract,
+            ..self
+        }
+    }
+
+    /// Set the styles contexts are injected in.
+    pub fn with_inject_styles<I: IntoIterator<Item = PropagationStyle>>(self, styles: I) -> Self {
+        let inject = styles.into_iter().collect::<Vec<_>>();
+        DatadogCompositePropagator {
+            fields: fields(&self.extract, &inject),
+            inject,
+            ..self
+        }
+    }
+
+    /// Only extract the first valid context, ignoring the W3C headers following it.
+    pub fn with_extract_first(self, extract_first: bool) -> Self {
+        DatadogCompositePropagator {
+            extract_first,
+            ..self
+        }
+    }
+
+    fn extract_span_context(&self, extractor: &dyn Extractor) -> Option<SpanContext> {
+        let mut styles = self.extract.iter();
+        let span_context = styles.find_map(|style| style.extract(extractor))?;
+        if self.extract_first {
+            return Some(span_context);
+        }
+        // Only W3C headers are reconciled with the extracted context, like dd-trace libraries do.
+        let trace_context = styles
+            .any(|style| *style == PropagationStyle::TraceContext)
+            .then(|| extract_trace_context(extractor))
+            .flatten()
+            .filter(|trace_context| trace_context.trace_id() == span_context.trace_id());
+        match trace_context {
+            Some(trace_context) => Some(self.reconcile(extractor, span_context, trace_context)),
+            None => Some(span_context),
+        }
+    }
+
+    fn reconcile(
+        &self,
+        extractor: &dyn Extractor,
+        span_context: SpanContext,
+        trace_context: SpanContext,
+    ) -> SpanContext {
+        let mut trace_state = span_context.trace_state().clone();
+        for (key, value) in vendor_members(trace_context.trace_state())
+            .into_iter()
+            .rev()
+        {
+            if trace_state.get(&key).is_none() {
+                trace_state = trace_state
+                    .insert(key, value)
+                    .unwrap_or_else(|_err| trace_state.clone());
+            }
+        }
+
+        let mut span_id = span_context.span_id();
+        if trace_context.span_id() != span_id {
+            // The W3C parent is more recent, having gone through services not propagating the
+            // Datadog headers.
+            span_id = trace_context.span_id();
+            let datadog_parent = datadog_parent_id(trace_context.trace_state(), span_id)
+                .or_else(|| {
+                    self.extract
+                        .contains(&PropagationStyle::Datadog)
+                        .then(|| PropagationStyle::Datadog.extract(extractor))
+                        .flatten()
+                        .filter(|datadog| datadog.trace_id() == span_context.trace_id())
+                        .map(|datadog| datadog.span_id())
+                })
+                .filter(|parent| *parent != SpanId::INVALID);
+            if let Some(datadog_parent) = datadog_parent {
+                trace_state = with_datadog_parent_id(&trace_state, span_id, datadog_parent);
+            }
+        }
+
+        SpanContext::new(
+            span_context.trace_id(),
+            span_id,
+            span_context.trace_flags(),
+            true,
+            trace_state,
+        )
+    }
+}
+
+impl TextMapPropagator for DatadogCompositePropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            for style in self.inject.iter() {
+                style.inject(cx, span_context, injector);
+            }
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        self.extract_span_context(extractor)
+            .map(|sc| cx.with_remote_span_context(sc))
+            .unwrap_or_else(|| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(self.fields.as_slice())
+    }
+}
+
+fn fields(extract: &[PropagationStyle], inject: &[PropagationStyle]) -> Vec<String> {
+    let mut fields: Vec<String> = Vec::new();
+    for header in extract
+        .iter()
+        .chain(inject)
+        .flat_map(|style| style.headers())
+    {
+        if !fields.iter().any(|field| field == header) {
+            fields.push(header.to_string());
+        }
+    }
+    fields
+}
+
+fn parse_styles(styles: &str) -> Vec<PropagationStyle> {
+    styles
+        .split(',')
+        .filter(|style| !style.trim().is_empty() && !style.trim().eq_ignore_ascii_case("none"))
+        .filter_map(|style| match style.parse() {
+            Ok(style) => Some(style),
+            Err(err) => {
+                global::handle_error(TraceError::from(err));
+                None
+            }
+        })
+        .collect()
+}
+
+/// The sampling decision of the context, `None` if it is deferred.
+fn sampling_decision(span_context: &SpanContext) -> Option<bool> {
+    (span_context.trace_flags() & TRACE_FLAG_DEFERRED != TRACE_FLAG_DEFERRED)
+        .then(|| get_sampling_priority(span_context) as i32 > 0)
+}
+
+/// The members of the trace state set by other vendors, as opposed to the Datadog entries.
+fn vendor_members(trace_state: &TraceState) -> Vec<(String, String)> {
+    let datadog_keys = [
+        TRACESTATE_DATADOG_KEY,
+        TRACE_STATE_PRIORITY_SAMPLING,
+        TRACE_STATE_MEASURE,
+        TRACE_STATE_ORIGIN,
+        TRACE_STATE_PROPAGATED_TAGS,
+        TRACE_STATE_DATADOG_PARENT,
+    ];
+    trace_state
+        .header()
+        .split(',')
+        .filter_map(|member| member.split_once('='))
+        .filter(|(key, _)| !datadog_keys.contains(key))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn extract_trace_context(extractor: &dyn Extractor) -> Option<SpanContext> {
+    let cx = TraceContextPropagator::new().extract(extractor);
+    let span = cx.span();
+    let trace_context = span.span_context();
+    if !trace_context.is_valid() {
+        return None;
+    }
+
+    let datadog = trace_context.trace_state().get(TRACESTATE_DATADOG_KEY);
+    let (mut trace_state, trace_flags) = create_trace_state_and_flags(
+        trace_context.trace_flags(),
+        user_priority(datadog, trace_context.is_sampled()),
+    );
+    for (key, value) in vendor_members(trace_context.trace_state())
+        .into_iter()
+        .rev()
+    {
+        trace_state = trace_state
+            .insert(key, value)
+            .unwrap_or_else(|_err| trace_state.clone());
+    }
+    if let Some(datadog) = datadog {
+        let mut tags = Vec::new();
+        for (key, value) in datadog.split(';').filter_map(|entry| entry.split_once(':')) {
+            let value = value.replace('~', "=");
+            match key {
+                "o" => trace_state = trace_state.with_origin(&value),
+                "p" => {
+                    if let Ok(parent) = SpanId::from_hex(&value) {
+                        trace_state =
+                            with_datadog_parent_id(&trace_state, trace_context.span_id(), parent);
+                    }
+                }
+                _ => {
+                    if let Some(key) = key.strip_prefix(TRACESTATE_DATADOG_PROPAGATED_TAG_PREFIX) {
+                        tags.push((format!("{DATADOG_PROPAGATED_TAG_PREFIX}{key}"), value));
+                    }
+                }
+            }
+        }
+        if !tags.is_empty() {
+            trace_state = trace_state.with_propagated_tags(&tags);
+        }
+    }
+
+    Some(SpanContext::new(
+        trace_context.trace_id(),
+        trace_context.span_id(),
+        trace_flags,
+        true,
+        trace_state,
+    ))
+}
+
+/// Whether the `s` sampling priority of the `dd` tracestate member is a user decision agreeing
+/// with the sampled flag of `traceparent`.
+fn user_priority(datadog: Option<&str>, sampled: bool) -> bool {
+    let priority = datadog
+        .into_iter()
+        .flat_map(|datadog| datadog.split(';'))
+        .find_map(|entry| entry.strip_prefix("s:"));
+    matches!((priority, sampled), (Some("2"), true) | (Some("-1"), false))
+}
+
+fn inject_trace_context(span_context: &SpanContext, injector: &mut dyn Injector) {
+    let decision = sampling_decision(span_context);
+    let sampled = decision.unwrap_or_else(|| span_context.is_sampled());
+    injector.set(
+        TRACEPARENT_HEADER,
+        format!(
+            "00-{}-{}-{:02x}",
+            span_context.trace_id(),
+            span_context.span_id(),
+            u8::from(sampled)
+        ),
+    );
+
+    let trace_state = span_context.trace_state();
+    let mut datadog = Vec::new();
+    if decision.is_some() {
+        datadog.push(format!("s:{}", get_sampling_priority(span_context) as i32));
+    }
+    if let Some(origin) = trace_state.origin() {
+        datadog.push(format!(
+            "o:{}",
+            origin.replace([',', ';', '~'], "_").replace('=', "~")
+        ));
+    }
+    datadog.push(format!("p:{}", span_context.span_id()));
+    let mut datadog = datadog.join(";");
+    let tags = encode_propagated_tags(&trace_state.propagated_tags());
+    for tag in tags.split(';').filter(|tag| !tag.is_empty()) {
+        // tags which don't fit are dropped, the same as in the trace state
+        let entry = format!(";{TRACESTATE_DATADOG_PROPAGATED_TAG_PREFIX}{tag}");
+        if datadog.len() + entry.len() <= TRACE_STATE_MAX_VALUE_LENGTH {
+            datadog.push_str(&entry);
+        }
+    }
+
+    let members = std::iter::once(format!("{TRACESTATE_DATADOG_KEY}={datadog}"))
+        .chain(
+            vendor_members(trace_state)
+                .into_iter()
+                .take(TRACESTATE_MAX_MEMBERS - 1)
+                .map(|(key, value)| format!("{key}={value}")),
+        )
+        .collect::<Vec<_>>();
+    injector.set(TRACESTATE_HEADER, members.join(","));
+}
+
+/// B3 trace ids are 64 bits unless the upper bits are set.
+fn b3_trace_id(trace_id: TraceId) -> String {
+    let trace_id = u128::from_be_bytes(trace_id.to_bytes());
+    if trace_id >> 64 == 0 {
+        format!("{:016x}", trace_id as u64)
+    } else {
+        format!("{trace_id:032x}")
+    }
+}
+
+fn parse_b3_trace_id(trace_id: &str) -> Option<TraceId> {
+    if trace_id.len() != 16 && trace_id.len() != 32 {
+        return None;
+    }
+    TraceId::from_hex(trace_id)
+        .ok()
+        .filter(|trace_id| *trace_id != TraceId::INVALID)
+}
+
+fn parse_b3_span_id(span_id: &str) -> Option<SpanId> {
+    if span_id.len() != 16 {
+        return None;
+    }
+    SpanId::from_hex(span_id)
+        .ok()
+        .filter(|span_id| *span_id != SpanId::INVALID)
+}
+
+fn parse_b3_sampled(sampled: &str) -> Option<bool> {
+    match sampled {
+        "1" | "true" | "d" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn b3_span_context(trace_id: TraceId, span_id: SpanId, sampled: Option<bool>) -> SpanContext {
+    let trace_flags = match sampled {
+        Some(true) => TraceFlags::SAMPLED,
+        Some(false) => TraceFlags::default(),
+        None => TRACE_FLAG_DEFERRED,
+    };
+    let (trace_state, trace_flags) = create_trace_state_and_flags(trace_flags, false);
+    SpanContext::new(trace_id, span_id, trace_flags, true, trace_state)
+}
+
+fn extract_b3_multi(extractor: &dyn Extractor) -> Option<SpanContext> {
+    let trace_id = parse_b3_trace_id(extractor.get(B3_TRACE_ID_HEADER)?)?;
+    let span_id = parse_b3_span_id(extractor.get(B3_SPAN_ID_HEADER)?)?;
+    // the debug flag implies sampling
+    let sampled = match extractor.get(B3_FLAGS_HEADER) {
+        Some("1") => Some(true),
+        _ => extractor.get(B3_SAMPLED_HEADER).and_then(parse_b3_sampled),
+    };
+    Some(b3_span_context(trace_id, span_id, sampled))
+}
+
+fn extract_b3_single(extractor: &dyn Extractor) -> Option<SpanContext> {
+    // `{trace id}-{span id}[-{sampled}[-{parent span id}]]`, a lone sampling decision carries no
+    // context
+    let mut parts = extractor.get(B3_SINGLE_HEADER)?.split('-');
+    let trace_id = parse_b3_trace_id(parts.next()?)?;
+    let span_id = parse_b3_span_id(parts.next()?)?;
+    let sampled = parts.next().and_then(parse_b3_sampled);
+    Some(b3_span_context(trace_id, span_id, sampled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_sdk::testing::trace::TestSpan;
+    use std::collections::HashMap;
+
+    fn headers(headers: &[(&str, &str)]) -> HashMap<String, String> {
+        headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn extract(propagator: &DatadogCompositePropagator, headers: &[(&str, &str)]) -> SpanContext {
+        propagator
+            .extract(&self::headers(headers))
+            .span()
+            .span_context()
+            .clone()
+    }
+
+    const TRACEPARENT: &str = "00-000000000000000000000000000004d2-00000000000000ab-01";
+
+    #[test]
+    fn test_extract_reconciles_w3c_parent() {
+        let propagator = DatadogCompositePropagator::new();
+
+        // the W3C parent went through a service not propagating the Datadog headers
+        let span_context = extract(
+            &propagator,
+            &[
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+                (TRACEPARENT_HEADER, TRACEPARENT),
+                (TRACESTATE_HEADER, "rojo=00f067aa0ba902b7,dd=s:1"),
+            ],
+        );
+        assert_eq!(span_context.trace_id(), TraceId::from_u128(1234));
+        assert_eq!(span_context.span_id(), SpanId::from_u64(0xab));
+        let trace_state = span_context.trace_state();
+        assert_eq!(
+            datadog_parent_id(trace_state, SpanId::from_u64(0xab)),
+            Some(SpanId::from_u64(12))
+        );
+        assert_eq!(datadog_parent_id(trace_state, SpanId::from_u64(12)), None);
+        assert_eq!(trace_state.get("rojo"), Some("00f067aa0ba902b7"));
+
+        // the last Datadog span recorded in the tracestate takes precedence
+        let span_context = extract(
+            &propagator,
+            &[
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (TRACEPARENT_HEADER, TRACEPARENT),
+                (TRACESTATE_HEADER, "dd=s:1;p:00000000000000cd"),
+            ],
+        );
+        assert_eq!(
+            datadog_parent_id(span_context.trace_state(), SpanId::from_u64(0xab)),
+            Some(SpanId::from_u64(0xcd))
+        );
+
+        // contexts of other traces are ignored
+        let span_context = extract(
+            &propagator,
+            &[
+                (DATADOG_TRACE_ID_HEADER, "1"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (TRACEPARENT_HEADER, TRACEPARENT),
+                (TRACESTATE_HEADER, "rojo=00f067aa0ba902b7"),
+            ],
+        );
+        assert_eq!(span_context.trace_id(), TraceId::from_u128(1));
+        assert_eq!(span_context.span_id(), SpanId::from_u64(12));
+        assert_eq!(span_context.trace_state().get("rojo"), None);
+
+        let span_context = extract(
+            &propagator.with_extract_first(true),
+            &[
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (TRACEPARENT_HEADER, TRACEPARENT),
+            ],
+        );
+        assert_eq!(span_context.span_id(), SpanId::from_u64(12));
+    }
+
+    #[test]
+    fn test_extract_in_order() {
+        let propagator = DatadogCompositePropagator::new().with_extract_styles([
+            PropagationStyle::B3SingleHeader,
+            PropagationStyle::B3Multi,
+            PropagationStyle::TraceContext,
+        ]);
+        let datadog = (DATADOG_TRACE_ID_HEADER, "1");
+
+        let span_context = extract(
+            &propagator,
+            &[
+                datadog,
+                (B3_TRACE_ID_HEADER, "00000000000000000000000000000002"),
+                (B3_SPAN_ID_HEADER, "0000000000000003"),
+                (B3_SAMPLED_HEADER, "1"),
+                (TRACEPARENT_HEADER, TRACEPARENT),
+            ],
+        );
+        assert_eq!(span_context.trace_id(), TraceId::from_u128(2));
+        assert_eq!(span_context.span_id(), SpanId::from_u64(3));
+        assert!(span_context.is_sampled());
+
+        let span_context = extract(
+            &propagator,
+            &[
+                datadog,
+                (B3_SINGLE_HEADER, "0000000000000004-0000000000000005-0"),
+            ],
+        );
+        assert_eq!(span_context.trace_id(), TraceId::from_u128(4));
+        assert_eq!(span_context.span_id(), SpanId::from_u64(5));
+        assert_eq!(sampling_decision(&span_context), Some(false));
+
+        // a lone sampling decision or malformed ids are not a context
+        let span_context = extract(
+            &propagator,
+            &[
+                datadog,
+                (B3_SINGLE_HEADER, "0"),
+                (B3_TRACE_ID_HEADER, "2"),
+                (B3_SPAN_ID_HEADER, "0000000000000003"),
+                (TRACEPARENT_HEADER, TRACEPARENT),
+                (TRACESTATE_HEADER, "dd=o:rum;t.dm:-4;t.usr.id:YmF6~"),
+            ],
+        );
+        assert_eq!(span_context.trace_id(), TraceId::from_u128(1234));
+        assert_eq!(span_context.trace_state().origin(), Some("rum"));
+        assert_eq!(
+            span_context.trace_state().propagated_tags(),
+            vec![
+                ("_dd.p.dm".to_string(), "-4".to_string()),
+                ("_dd.p.usr.id".to_string(), "YmF6=".to_string()),
+            ]
+        );
+
+        assert!(!extract(&propagator, &[datadog]).is_valid());
+    }
+
+    #[test]
+    fn test_inject() {
+        let propagator = DatadogCompositePropagator::new().with_inject_styles([
+            PropagationStyle::Datadog,
+            PropagationStyle::TraceContext,
+            PropagationStyle::B3Multi,
+            PropagationStyle::B3SingleHeader,
+        ]);
+        let extracted = extract(
+            &propagator,
+            &[
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+                (DATADOG_ORIGIN_HEADER, "synthetics"),
+                (DATADOG_TAGS_HEADER, "_dd.p.dm=-4"),
+                (TRACEPARENT_HEADER, TRACEPARENT),
+                (TRACESTATE_HEADER, "rojo=00f067aa0ba902b7"),
+            ],
+        );
+
+        let mut injector = HashMap::new();
+        propagator.inject_context(
+            &Context::current_with_span(TestSpan(extracted.clone())),
+            &mut injector,
+        );
+        let header = |name: &str| injector.get(name).map(String::as_str);
+        assert_eq!(header(DATADOG_TRACE_ID_HEADER), Some("1234"));
+        assert_eq!(header(DATADOG_PARENT_ID_HEADER), Some("171"));
+        assert_eq!(header(TRACEPARENT_HEADER), Some(TRACEPARENT));
+        assert_eq!(
+            header(TRACESTATE_HEADER),
+            Some("dd=s:1;o:synthetics;p:00000000000000ab;t.dm:-4,rojo=00f067aa0ba902b7")
+        );
+        assert_eq!(header(B3_TRACE_ID_HEADER), Some("00000000000004d2"));
+        assert_eq!(header(B3_SPAN_ID_HEADER), Some("00000000000000ab"));
+        assert_eq!(header(B3_SAMPLED_HEADER), Some("1"));
+        assert_eq!(
+            header(B3_SINGLE_HEADER),
+            Some("00000000000004d2-00000000000000ab-1")
+        );
+
+        for style in [
+            PropagationStyle::Datadog,
+            PropagationStyle::TraceContext,
+            PropagationStyle::B3Multi,
+            PropagationStyle::B3SingleHeader,
+        ] {
+            let span_context = extract(
+                &DatadogCompositePropagator::new().with_extract_styles([style]),
+                &injector
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect::<Vec<_>>(),
+            );
+            assert_eq!(span_context.trace_id(), extracted.trace_id(), "{style:?}");
+            assert_eq!(span_context.span_id(), extracted.span_id(), "{style:?}");
+            assert_eq!(sampling_decision(&span_context), Some(true), "{style:?}");
+        }
+    }
+
+    #[cfg(feature = "agent-sampling")]
+    #[test]
+    fn test_user_sampling_priority() {
+        let propagator = DatadogCompositePropagator::new()
+            .with_inject_styles([PropagationStyle::Datadog, PropagationStyle::TraceContext]);
+        let extracted = extract(
+            &propagator,
+            &[
+                (DATADOG_TRACE_ID_HEADER, "1234"),
+                (DATADOG_PARENT_ID_HEADER, "12"),
+                (DATADOG_SAMPLING_PRIORITY_HEADER, "2"),
+            ],
+        );
+        assert_eq!(extracted.trace_state().sampling_priority(), 2);
+
+        let mut injector = HashMap::new();
+        propagator.inject_context(
+            &Context::current_with_span(TestSpan(extracted)),
+            &mut injector,
+        );
+        assert_eq!(
+            injector
+                .get(DATADOG_SAMPLING_PRIORITY_HEADER)
+                .map(String::as_str),
+            Some("2")
+        );
+        assert_eq!(
+            injector.get(TRACESTATE_HEADER).map(String::as_str),
+            Some("dd=s:2;p:000000000000000c")
+        );
+
+        let tracestate_only =
+            DatadogCompositePropagator::new().with_extract_styles([PropagationStyle::TraceContext]);
+        let span_context = extract(
+            &tracestate_only,
+            &[
+                (TRACEPARENT_HEADER, TRACEPARENT),
+                (TRACESTATE_HEADER, "dd=s:2"),
+            ],
+        );
+        assert_eq!(span_context.trace_state().sampling_priority(), 2);
+        // a priority disagreeing with the sampled flag is ignored
+        let span_context = extract(
+            &tracestate_only,
+            &[
+                (TRACEPARENT_HEADER, TRACEPARENT),
+                (TRACESTATE_HEADER, "dd=s:-1"),
+            ],
+        );
+        assert_eq!(span_context.trace_state().sampling_priority(), 1);
+    }
+
+    #[test]
+    fn test_fields() {
+        let propagator = DatadogCompositePropagator::new()
+            .with_extract_styles([PropagationStyle::B3SingleHeader])
+            .with_inject_styles([
+                PropagationStyle::TraceContext,
+                PropagationStyle::B3SingleHeader,
+            ]);
+        assert_eq!(
+            propagator.fields().collect::<Vec<_>>(),
+            vec![B3_SINGLE_HEADER, TRACEPARENT_HEADER, TRACESTATE_HEADER]
+        );
+    }
+
+    #[test]
+    fn test_from_env() {
+        let propagator = DatadogCompositePropagator::from_env_vars(|name| {
+            match name {
+                "DD_TRACE_PROPAGATION_STYLE" => Some("tracecontext, B3 single header"),
+                "DD_TRACE_PROPAGATION_STYLE_INJECT" => Some("none"),
+                "DD_TRACE_PROPAGATION_EXTRACT_FIRST" => Some("True"),
+                _ => None,
+            }
+            .map(str::to_string)
+        });
+        assert_eq!(
+            propagator.extract,
+            vec![
+                PropagationStyle::TraceContext,
+                PropagationStyle::B3SingleHeader
+            ]
+        );
+        assert!(propagator.inject.is_empty());
+        assert!(propagator.extract_first);
+
+        let propagator = DatadogCompositePropagator::from_env_vars(|name| {
+            (name == "DD_TRACE_PROPAGATION_STYLE_EXTRACT").then(|| "b3,jaeger".to_string())
+        });
+        assert_eq!(propagator.extract, vec![PropagationStyle::B3Multi]);
+        assert_eq!(
+            propagator.inject,
+            vec![PropagationStyle::Datadog, PropagationStyle::TraceContext]
+        );
+    }
+}
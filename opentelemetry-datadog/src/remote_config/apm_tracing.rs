@@ -0,0 +1,141 @@
+use crate::sampler::rules::SamplingRule;
+use serde_json::Value;
+
+/// Settings of an `APM_TRACING` remote configuration file that apply to this tracer.
+///
+/// See <https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/remote_config.go>
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct ApmTracingConfig {
+    pub(crate) sample_rate: Option<f64>,
+    pub(crate) sampling_rules: Option<Vec<SamplingRule>>,
+    pub(crate) tags: Option<Vec<(String, String)>>,
+}
+
+/// The service and env an `APM_TRACING` file targets, `*` matching any.
+#[derive(Debug, PartialEq)]
+pub(crate) struct ServiceTarget {
+    service: String,
+    env: String,
+}
+
+impl ServiceTarget {
+    /// How specifically the target designates `service` and `env`, `None` if it doesn't match.
+    pub(crate) fn specificity(&self, service: &str, env: &str) -> Option<u8> {
+        let matches = |target: &str, value: &str| match target {
+            "*" => Some(0),
+            _ if target == value => Some(1),
+            _ => None,
+        };
+        Some(matches(&self.service, service)? * 2 + matches(&self.env, env)?)
+    }
+}
+
+impl ApmTracingConfig {
+    /// Parse an `APM_TRACING` file, returning the service and env it targets along with its
+    /// settings.
+    pub(crate) fn parse(raw: &[u8]) -> Result<(ServiceTarget, Self), String> {
+        let file: Value = serde_json::from_slice(raw)
+            .map_err(|err| format!("invalid APM_TRACING file: {err}"))?;
+        let target = file.get("service_target");
+        let target_field = |field: &str| {
+            target
+                .and_then(|target| target.get(field))
+                .and_then(Value::as_str)
+                .unwrap_or("*")
+                .to_string()
+        };
+        let target = ServiceTarget {
+            service: target_field("service"),
+            env: target_field("env"),
+        };
+
+        let lib_config = file
+            .get("lib_config")
+            .ok_or_else(|| "missing lib_config in APM_TRACING file".to_string())?;
+        let field = |name: &str| lib_config.get(name).filter(|value| !value.is_null());
+
+        let sample_rate = match field("tracing_sampling_rate") {
+            Some(rate) => match rate.as_f64() {
+                Some(rate) if (0.0..=1.0).contains(&rate) => Some(rate),
+                _ => return Err(format!("invalid tracing_sampling_rate {rate}")),
+            },
+            None => None,
+        };
+        let sampling_rules = match field("tracing_sampling_rules") {
+            Some(Value::Array(rules)) => Some(
+                rules
+                    .iter()
+                    .map(|rule| {
+                        SamplingRule::from_remote(rule)
+                            .ok_or_else(|| format!("invalid tracing_sampling_rules entry {rule}"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            Some(rules) => return Err(format!("invalid tracing_sampling_rules {rules}")),
+            None => None,
+        };
+        let tags = match field("tracing_tags") {
+            Some(Value::Array(tags)) => Some(
+                tags.iter()
+                    .filter_map(Value::as_str)
+                    .map(|tag| {
+                        let (key, value) = tag.split_once(':').unwrap_or((tag, ""));
+                        (key.to_string(), value.to_string())
+                    })
+                    .collect(),
+            ),
+            Some(tags) => return Err(format!("invalid tracing_tags {tags}")),
+            None => None,
+        };
+
+        Ok((
+            target,
+            ApmTracingConfig {
+                sample_rate,
+                sampling_rules,
+                tags,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let (target, config) = ApmTracingConfig::parse(
+            br#"{
+                "id": "42",
+                "revision": 1,
+                "schema_version": "v1.0.0",
+                "action": "enable",
+                "lib_config": {
+                    "tracing_sampling_rate": 0.5,
+                    "tracing_sampling_rules": [{"service": "web", "sample_rate": 1}],
+                    "tracing_tags": ["team:apm", "standalone"],
+                    "log_injection_enabled": null
+                },
+                "service_target": {"service": "web", "env": "*"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(target.specificity("web", "prod"), Some(2));
+        assert_eq!(target.specificity("api", "prod"), None);
+        assert_eq!(config.sample_rate, Some(0.5));
+        assert_eq!(config.sampling_rules.map(|rules| rules.len()), Some(1));
+        assert_eq!(
+            config.tags,
+            Some(vec![
+                ("team".to_string(), "apm".to_string()),
+                ("standalone".to_string(), String::new()),
+            ])
+        );
+
+        assert!(
+            ApmTracingConfig::parse(br#"{"lib_config": {"tracing_sampling_rate": 2}}"#).is_err()
+        );
+    }
+}
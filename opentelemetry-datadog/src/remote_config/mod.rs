@@ -0,0 +1,743 @@
+mod apm_tracing;
+
+use crate::exporter::model::unified_tags::UnifiedTags;
+use crate::exporter::Error;
+use crate::DatadogAgentSampler;
+use apm_tracing::ApmTracingConfig;
+use http::{Method, Request, StatusCode, Uri};
+use opentelemetry::{global, trace::TraceError};
+use opentelemetry_http::HttpClient;
+use opentelemetry_sdk::trace::{IdGenerator, RandomIdGenerator};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Path of the agent endpoint serving remote configuration
+pub(crate) const CONFIG_PATH: &str = "/v0.7/config";
+
+const PRODUCT_APM_TRACING: &str = "APM_TRACING";
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/internal/remoteconfig/remoteconfig.go#L84
+const CAPABILITY_APM_TRACING_SAMPLE_RATE: u32 = 12;
+const CAPABILITY_APM_TRACING_CUSTOM_TAGS: u32 = 15;
+const CAPABILITY_APM_TRACING_SAMPLE_RULES: u32 = 29;
+
+// https://github.com/DataDog/datadog-agent/blob/7.52.0/pkg/proto/datadog/remoteconfig/remoteconfig.proto#L166
+const APPLY_STATE_ACKNOWLEDGED: u64 = 2;
+const APPLY_STATE_ERROR: u64 = 3;
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Applies the `APM_TRACING` settings of Datadog remote configuration at runtime.
+///
+/// Registered with [`DatadogPipelineBuilder::with_remote_config`], the exporter polls the agent's
+/// `/v0.7/config` endpoint once per poll interval and reports the state of the configurations it
+/// applied in the next poll. When installed with [`DatadogPipelineBuilder::install_batch`], polls
+/// run from a task of the runtime, otherwise they run along with exports, at most once per poll
+/// interval. Only agent endpoints are polled, not the Datadog intake of agentless mode.
+///
+/// Configuration files are only applied once their length and SHA-256 hash match the signed
+/// targets metadata of the response.
+///
+/// The settings of the configuration targeting the service and env of the exporter are applied:
+/// - the sample rate and sampling rules are applied to the sampler set with [`with_sampler`],
+///   where they take precedence over the agent rates,
+/// - the tags replace the tags set with `DD_TAGS` or [`DatadogPipelineBuilder::with_tag`] in the
+///   exported spans.
+///
+/// Removing the configuration reverts to the local settings.
+///
+/// ## Example
+///
+/// ```no_run
+/// use opentelemetry_datadog::{new_pipeline, DatadogAgentSampler, DatadogRemoteConfig};
+/// use opentelemetry_sdk::trace;
+///
+/// # fn main() -> Result<(), opentelemetry::trace::TraceError> {
+/// let sampler = DatadogAgentSampler::default();
+/// let tracer = new_pipeline()
+///     .with_service_name("my_app")
+///     .with_agent_sampler(sampler.clone())
+///     .with_remote_config(DatadogRemoteConfig::new().with_sampler(sampler.clone()))
+///     .with_trace_config(trace::config().with_sampler(sampler))
+///     .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`DatadogPipelineBuilder::with_remote_config`]: crate::DatadogPipelineBuilder::with_remote_config
+/// [`DatadogPipelineBuilder::with_tag`]: crate::DatadogPipelineBuilder::with_tag
+/// [`DatadogPipelineBuilder::install_batch`]: crate::DatadogPipelineBuilder::install_batch
+/// [`with_sampler`]: DatadogRemoteConfig::with_sampler
+#[derive(Clone, Debug)]
+pub struct DatadogRemoteConfig {
+    poll_interval: Duration,
+    sampler: Option<DatadogAgentSampler>,
+    client_id: String,
+    state: Arc<Mutex<ClientState>>,
+}
+
+impl Default for DatadogRemoteConfig {
+    fn default() -> Self {
+        DatadogRemoteConfig::new()
+    }
+}
+
+/// A configuration file applied by the client.
+#[derive(Debug)]
+struct ConfigFile {
+    product: String,
+    id: String,
+    version: u64,
+    length: u64,
+    hashes: Map<String, Value>,
+    raw: Vec<u8>,
+    apply_error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ClientState {
+    targets_version: u64,
+    backend_client_state: String,
+    files: HashMap<String, ConfigFile>,
+    error: Option<String>,
+    last_poll: Option<Instant>,
+    tags: Option<Vec<(String, String)>>,
+}
+
+impl DatadogRemoteConfig {
+    /// Create a remote configuration client polling every
+    /// `DD_REMOTE_CONFIG_POLL_INTERVAL_SECONDS`, 5 seconds by default.
+    pub fn new() -> Self {
+        let poll_interval = std::env::var("DD_REMOTE_CONFIG_POLL_INTERVAL_SECONDS")
+            .ok()
+            .and_then(|interval| interval.parse::<f64>().ok())
+            .filter(|interval| interval.is_finite() && *interval >= 0.0)
+            .map_or(DEFAULT_POLL_INTERVAL, Duration::from_secs_f64);
+        DatadogRemoteConfig {
+            poll_interval,
+            sampler: None,
+            client_id: new_uuid(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Set the interval between two polls of the agent, at least 100 milliseconds when polling
+    /// from a runtime task.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Apply the remote sample rate and sampling rules to `sampler`.
+    pub fn with_sampler(mut self, sampler: DatadogAgentSampler) -> Self {
+        self.sampler = Some(sampler);
+        self
+    }
+
+    /// The tags set through remote configuration, if any.
+    pub(crate) fn tags(&self) -> Option<Vec<(String, String)>> {
+        self.state.lock().ok()?.tags.clone()
+    }
+}
+
+/// Random version 4 UUID.
+fn new_uuid() -> String {
+    let bytes = RandomIdGenerator::default().new_trace_id().to_bytes();
+    let id = (u128::from_be_bytes(bytes) & !(0xf000 << 64) & !(0xc << 60))
+        | (0x4000 << 64)
+        | (0x8 << 60);
+    let id = format!("{id:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..]
+    )
+}
+
+/// Capabilities of the client, as a big-endian bit set.
+fn capabilities() -> String {
+    let capabilities: u64 = [
+        CAPABILITY_APM_TRACING_SAMPLE_RATE,
+        CAPABILITY_APM_TRACING_CUSTOM_TAGS,
+        CAPABILITY_APM_TRACING_SAMPLE_RULES,
+    ]
+    .iter()
+    .fold(0, |capabilities, capability| capabilities | 1 << capability);
+    let bytes = capabilities.to_be_bytes();
+    let first = bytes.iter().position(|byte| *byte != 0).unwrap_or(0);
+    base64::encode(&bytes[first..])
+}
+
+/// Polls the agent on behalf of a [`DatadogRemoteConfig`].
+#[derive(Clone, Debug)]
+pub(crate) struct RemoteConfigClient {
+    config: DatadogRemoteConfig,
+    client: Arc<dyn HttpClient>,
+    url: Uri,
+    service: String,
+    env: String,
+    client_tracer: Value,
+}
+
+impl RemoteConfigClient {
+    pub(crate) fn new(
+        config: DatadogRemoteConfig,
+        client: Arc<dyn HttpClient>,
+        url: Uri,
+        service: String,
+        unified_tags: &UnifiedTags,
+    ) -> Self {
+        let env = unified_tags.env.value.clone().unwrap_or_default();
+        let client_tracer = json!({
+            "runtime_id": new_uuid(),
+            "language": "rust",
+            "tracer_version": env!("CARGO_PKG_VERSION"),
+            "service": service,
+            "env": env,
+            "app_version": unified_tags.version.value.clone().unwrap_or_default(),
+            "tags": unified_tags
+                .tags
+                .iter()
+                .map(|(key, value)| format!("{key}:{value}"))
+                .collect::<Vec<_>>(),
+        });
+        RemoteConfigClient {
+            config,
+            client,
+            url,
+            service,
+            env,
+            client_tracer,
+        }
+    }
+
+    pub(crate) fn tags(&self) -> Option<Vec<(String, String)>> {
+        self.config.tags()
+    }
+
+    /// Interval of the polls run from a runtime task.
+    pub(crate) fn poll_interval(&self) -> Duration {
+        self.config.poll_interval.max(MIN_POLL_INTERVAL)
+    }
+
+    /// Whether the poll interval elapsed since the last poll, in which case the next poll is
+    /// considered started.
+    pub(crate) fn poll_due(&self) -> bool {
+        let mut state = match self.config.state.lock() {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+        let now = Instant::now();
+        match state.last_poll {
+            Some(last_poll) if now.duration_since(last_poll) < self.config.poll_interval => false,
+            _ => {
+                state.last_poll = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Fetch the latest configuration from the agent and apply it.
+    pub(crate) async fn poll(self) {
+        if let Err(err) = self.try_poll().await {
+            global::handle_error(err);
+        }
+    }
+
+    async fn try_poll(&self) -> Result<(), TraceError> {
+        let body = self.request_body()?;
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .map_err::<Error, _>(Into::into)?;
+        let response = self.client.send(request).await?;
+        match response.status() {
+            // remote configuration is disabled on the agent
+            StatusCode::NOT_FOUND => Ok(()),
+            status if status.is_success() => self.apply_response(response.body()),
+            status => Err(Error::Other(format!(
+                "remote configuration request failed with status {status}"
+            ))
+            .into()),
+        }
+    }
+
+    fn request_body(&self) -> Result<Vec<u8>, TraceError> {
+        let state = self
+            .config
+            .state
+            .lock()
+            .map_err(|_| Error::Other("remote configuration state poisoned".into()))?;
+        let mut config_states = Vec::new();
+        let mut cached_target_files = Vec::new();
+        for (path, file) in &state.files {
+            config_states.push(json!({
+                "id": file.id,
+                "version": file.version,
+                "product": file.product,
+                "apply_state": if file.apply_error.is_some() {
+                    APPLY_STATE_ERROR
+                } else {
+                    APPLY_STATE_ACKNOWLEDGED
+                },
+                "apply_error": file.apply_error.clone().unwrap_or_default(),
+            }));
+            cached_target_files.push(json!({
+                "path": path,
+                "length": file.length,
+                "hashes": file
+                    .hashes
+                    .iter()
+                    .map(|(algorithm, hash)| json!({"algorithm": algorithm, "hash": hash}))
+                    .collect::<Vec<_>>(),
+            }));
+        }
+        let body = json!({
+            "client": {
+                "state": {
+                    "root_version": 1,
+                    "targets_version": state.targets_version,
+                    "config_states": config_states,
+                    "has_error": state.error.is_some(),
+                    "error": state.error.clone().unwrap_or_default(),
+                    "backend_client_state": state.backend_client_state,
+                },
+                "id": self.config.client_id,
+                "products": [PRODUCT_APM_TRACING],
+                "is_tracer": true,
+                "client_tracer": self.client_tracer,
+                "capabilities": capabilities(),
+            },
+            "cached_target_files": cached_target_files,
+        });
+        Ok(serde_json::to_vec(&body).map_err(|err| Error::Other(err.to_string()))?)
+    }
+
+    fn apply_response(&self, body: &[u8]) -> Result<(), TraceError> {
+        let mut state = self
+            .config
+            .state
+            .lock()
+            .map_err(|_| Error::Other("remote configuration state poisoned".into()))?;
+        let result = self.update_files(&mut state, body);
+        state.error = result.as_ref().err().cloned();
+        if result.is_ok() {
+            self.apply_files(&mut state);
+        }
+        result.map_err(|err| Error::Other(err).into())
+    }
+
+    /// Replace the applied files by the ones targeting this client in the response.
+    fn update_files(&self, state: &mut ClientState, body: &[u8]) -> Result<(), String> {
+        let response: Value = serde_json::from_slice(body)
+            .map_err(|err| format!("invalid remote configuration response: {err}"))?;
+        let targets = match response.get("targets").and_then(Value::as_str) {
+            Some(targets) if !targets.is_empty() => targets,
+            // nothing changed since the last poll
+            _ => return Ok(()),
+        };
+        let targets: Value = base64::decode(targets)
+            .ok()
+            .and_then(|targets| serde_json::from_slice(&targets).ok())
+            .ok_or_else(|| "invalid remote configuration targets".to_string())?;
+        let signed = &targets["signed"];
+
+        let mut target_files = HashMap::new();
+        if let Some(files) = response.get("target_files").and_then(Value::as_array) {
+            for file in files {
+                let path = file["path"].as_str().unwrap_or_default();
+                let raw = base64::decode(file["raw"].as_str().unwrap_or_default())
+                    .map_err(|err| format!("invalid remote configuration file {path}: {err}"))?;
+                target_files.insert(path.to_string(), raw);
+            }
+        }
+
+        let mut files = HashMap::new();
+        let client_configs = response.get("client_configs").and_then(Value::as_array);
+        for path in client_configs
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            let (product, id) = parse_config_path(path)
+                .ok_or_else(|| format!("invalid remote configuration path {path}"))?;
+            let meta = &signed["targets"][path];
+            let hashes = meta["hashes"].as_object().cloned().unwrap_or_default();
+            let raw = match target_files.remove(path) {
+                Some(raw) => {
+                    verify_file(path, &raw, meta)?;
+                    raw
+                }
+                None => match state.files.get(path) {
+                    Some(file) if file.hashes == hashes => file.raw.clone(),
+                    _ => return Err(format!("missing remote configuration file {path}")),
+                },
+            };
+            files.insert(
+                path.to_string(),
+                ConfigFile {
+                    product: product.to_string(),
+                    id: id.to_string(),
+                    version: meta["custom"]["v"].as_u64().unwrap_or_default(),
+                    length: meta["length"].as_u64().unwrap_or(raw.len() as u64),
+                    hashes,
+                    raw,
+                    apply_error: None,
+                },
+            );
+        }
+
+        state.files = files;
+        state.targets_version = signed["version"].as_u64().unwrap_or_default();
+        state.backend_client_state = signed["custom"]["opaque_backend_state"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        Ok(())
+    }
+
+    /// Apply the `APM_TRACING` file the most specific to the service and env of the exporter.
+    fn apply_files(&self, state: &mut ClientState) {
+        let mut selected: Option<(u8, ApmTracingConfig)> = None;
+        for file in state.files.values_mut() {
+            if file.product != PRODUCT_APM_TRACING {
+                continue;
+            }
+            match ApmTracingConfig::parse(&file.raw) {
+                Ok((target, config)) => {
+                    file.apply_error = None;
+                    if let Some(specificity) = target.specificity(&self.service, &self.env) {
+                        if selected
+                            .as_ref()
+                            .map_or(true, |(best, _)| specificity > *best)
+                        {
+                            selected = Some((specificity, config));
+                        }
+                    }
+                }
+                Err(err) => file.apply_error = Some(err),
+            }
+        }
+        let config = selected.map(|(_, config)| config).unwrap_or_default();
+
+        if let Some(sampler) = &self.config.sampler {
+            sampler.set_remote_sampling(
+                config.sample_rate,
+                config.sampling_rules.unwrap_or_default(),
+            );
+        }
+        state.tags = config.tags;
+    }
+}
+
+/// Check the contents of the file at `path` against the length and SHA-256 hash of its targets
+/// metadata.
+fn verify_file(path: &str, raw: &[u8], meta: &Value) -> Result<(), String> {
+    if let Some(length) = meta["length"].as_u64() {
+        if length != raw.len() as u64 {
+            return Err(format!(
+                "remote configuration file {path} has length {}, expected {length}",
+                raw.len()
+            ));
+        }
+    }
+    let expected = meta["hashes"]["sha256"]
+        .as_str()
+        .ok_or_else(|| format!("missing sha256 hash of remote configuration file {path}"))?;
+    let hash = format!("{:x}", Sha256::digest(raw));
+    if !hash.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "remote configuration file {path} has sha256 hash {hash}, expected {expected}"
+        ));
+    }
+    Ok(())
+}
+
+/// Split `datadog/<org_id>/<product>/<config_id>/<name>` and `employee/<product>/<config_id>/<name>`
+/// paths into their product and config id.
+fn parse_config_path(path: &str) -> Option<(&str, &str)> {
+    let parts: Vec<&str> = path.split('/').collect();
+    match parts.as_slice() {
+        ["datadog", _, product, id, _] | ["employee", product, id, _] => Some((product, id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::DatadogTraceState;
+    use bytes::Bytes;
+    use opentelemetry::trace::{SpanKind, TraceId};
+    use opentelemetry_sdk::trace::ShouldSample;
+    use std::collections::VecDeque;
+
+    /// Agent answering remote configuration requests with the queued responses, recording the
+    /// requests it receives.
+    #[derive(Debug, Default)]
+    struct MockAgent {
+        responses: Mutex<VecDeque<Value>>,
+        requests: Mutex<Vec<Value>>,
+    }
+
+    #[async_trait::async_trait]
+    impl HttpClient for MockAgent {
+        async fn send(
+            &self,
+            request: Request<Vec<u8>>,
+        ) -> Result<http::Response<Bytes>, opentelemetry_http::HttpError> {
+            assert_eq!(request.uri().path(), CONFIG_PATH);
+            self.requests
+                .lock()
+                .unwrap()
+                .push(serde_json::from_slice(request.body())?);
+            let response = self.responses.lock().unwrap().pop_front();
+            Ok(http::Response::new(
+                serde_json::to_vec(&response.unwrap_or_else(|| json!({})))?.into(),
+            ))
+        }
+    }
+
+    /// Agent response sending `files`, as paths and contents, to the client.
+    pub(crate) fn agent_response(version: u64, files: &[(&str, Value)]) -> Value {
+        let targets = files
+            .iter()
+            .map(|(path, file)| {
+                let raw = serde_json::to_vec(file).unwrap();
+                (
+                    path.to_string(),
+                    json!({
+                        "custom": {"v": version},
+                        "hashes": {"sha256": format!("{:x}", Sha256::digest(&raw))},
+                        "length": raw.len(),
+                    }),
+                )
+            })
+            .collect::<Map<_, _>>();
+        let targets = json!({
+            "signed": {
+                "_type": "targets",
+                "custom": {"opaque_backend_state": format!("state-{version}")},
+                "targets": targets,
+                "version": version,
+            }
+        });
+        json!({
+            "targets": base64::encode(serde_json::to_vec(&targets).unwrap()),
+            "target_files": files
+                .iter()
+                .map(|(path, file)| json!({
+                    "path": path,
+                    "raw": base64::encode(serde_json::to_vec(file).unwrap()),
+                }))
+                .collect::<Vec<_>>(),
+            "client_configs": files.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+        })
+    }
+
+    pub(crate) fn apm_tracing(service: &str, lib_config: Value) -> Value {
+        json!({
+            "id": "1",
+            "revision": 1,
+            "schema_version": "v1.0.0",
+            "action": "enable",
+            "lib_config": lib_config,
+            "service_target": {"service": service, "env": "*"},
+        })
+    }
+
+    fn client(agent: &Arc<MockAgent>, config: DatadogRemoteConfig) -> RemoteConfigClient {
+        let mut unified_tags = UnifiedTags::new();
+        unified_tags.set_env(Some("prod".to_string()));
+        RemoteConfigClient::new(
+            config,
+            agent.clone(),
+            Uri::from_static("http://localhost:8126/v0.7/config"),
+            "web".to_string(),
+            &unified_tags,
+        )
+    }
+
+    #[test]
+    fn test_poll_applies_apm_tracing() {
+        let path = "datadog/2/APM_TRACING/web-config/config";
+        let agent = Arc::new(MockAgent::default());
+        agent.responses.lock().unwrap().extend([
+            agent_response(
+                3,
+                &[
+                    (
+                        path,
+                        apm_tracing(
+                            "web",
+                            json!({
+                                "tracing_sampling_rate": 0,
+                                "tracing_tags": ["team:apm"],
+                            }),
+                        ),
+                    ),
+                    (
+                        "datadog/2/APM_TRACING/other-config/config",
+                        apm_tracing("other", json!({"tracing_tags": ["team:other"]})),
+                    ),
+                ],
+            ),
+            json!({}),
+            agent_response(4, &[]),
+        ]);
+        let sampler = DatadogAgentSampler::new();
+        let client = client(
+            &agent,
+            DatadogRemoteConfig::new().with_sampler(sampler.clone()),
+        );
+        let keep = || {
+            sampler
+                .should_sample(
+                    None,
+                    TraceId::from_u128(1),
+                    "span",
+                    &SpanKind::Internal,
+                    &[],
+                    &[],
+                )
+                .trace_state
+                .priority_sampling_enabled()
+        };
+
+        futures_executor::block_on(client.clone().poll());
+        assert_eq!(
+            client.tags(),
+            Some(vec![("team".to_string(), "apm".to_string())])
+        );
+        assert!(!keep());
+
+        // nothing changed, the state of the applied files is reported
+        futures_executor::block_on(client.clone().poll());
+        assert_eq!(client.tags().map(|tags| tags.len()), Some(1));
+
+        // configuration removed
+        futures_executor::block_on(client.clone().poll());
+        assert_eq!(client.tags(), None);
+        assert!(keep());
+
+        let requests = agent.requests.lock().unwrap();
+        let first = &requests[0]["client"];
+        assert_eq!(first["products"], json!(["APM_TRACING"]));
+        assert_eq!(first["client_tracer"]["service"], "web");
+        assert_eq!(first["client_tracer"]["env"], "prod");
+        assert_eq!(first["client_tracer"]["language"], "rust");
+        assert_eq!(first["state"]["targets_version"], 0);
+        assert_eq!(first["capabilities"], "IACQAA==");
+
+        let second = &requests[1];
+        assert_eq!(second["client"]["state"]["targets_version"], 3);
+        assert_eq!(second["client"]["state"]["backend_client_state"], "state-3");
+        let mut config_states = second["client"]["state"]["config_states"]
+            .as_array()
+            .unwrap()
+            .clone();
+        config_states.sort_by_key(|state| state["id"].to_string());
+        assert_eq!(config_states[1]["id"], "web-config");
+        assert_eq!(config_states[1]["version"], 3);
+        assert_eq!(config_states[1]["apply_state"], APPLY_STATE_ACKNOWLEDGED);
+        assert_eq!(
+            second["cached_target_files"].as_array().map(Vec::len),
+            Some(2)
+        );
+        assert_eq!(requests[2]["client"]["state"]["targets_version"], 3);
+    }
+
+    #[test]
+    fn test_poll_reports_apply_errors() {
+        let path = "datadog/2/APM_TRACING/invalid/config";
+        let agent = Arc::new(MockAgent::default());
+        agent.responses.lock().unwrap().push_back(agent_response(
+            1,
+            &[(
+                path,
+                apm_tracing("web", json!({"tracing_sampling_rate": "all"})),
+            )],
+        ));
+        let client = client(&agent, DatadogRemoteConfig::new());
+
+        futures_executor::block_on(client.clone().poll());
+        futures_executor::block_on(client.clone().poll());
+
+        let requests = agent.requests.lock().unwrap();
+        let config_state = &requests[1]["client"]["state"]["config_states"][0];
+        assert_eq!(config_state["id"], "invalid");
+        assert_eq!(config_state["apply_state"], APPLY_STATE_ERROR);
+        assert!(config_state["apply_error"]
+            .as_str()
+            .unwrap()
+            .contains("tracing_sampling_rate"));
+    }
+
+    #[test]
+    fn test_poll_rejects_tampered_files() {
+        let path = "datadog/2/APM_TRACING/web-config/config";
+        let mut response = agent_response(
+            1,
+            &[(
+                path,
+                apm_tracing("web", json!({"tracing_tags": ["team:apm"]})),
+            )],
+        );
+        let tampered = apm_tracing("web", json!({"tracing_tags": ["team:xyz"]}));
+        response["target_files"][0]["raw"] =
+            base64::encode(serde_json::to_vec(&tampered).unwrap()).into();
+        let agent = Arc::new(MockAgent::default());
+        agent.responses.lock().unwrap().push_back(response);
+        let client = client(&agent, DatadogRemoteConfig::new());
+
+        futures_executor::block_on(client.clone().poll());
+        futures_executor::block_on(client.clone().poll());
+        assert_eq!(client.tags(), None);
+
+        let requests = agent.requests.lock().unwrap();
+        let state = &requests[1]["client"]["state"];
+        assert_eq!(state["targets_version"], 0);
+        assert_eq!(state["has_error"], true);
+        assert!(state["error"].as_str().unwrap().contains("sha256"));
+    }
+
+    #[test]
+    fn test_poll_due() {
+        let agent = Arc::new(MockAgent::default());
+        let client = client(
+            &agent,
+            DatadogRemoteConfig::new().with_poll_interval(Duration::from_secs(60)),
+        );
+        assert!(client.poll_due());
+        assert!(!client.poll_due());
+
+        let client = self::client(
+            &agent,
+            DatadogRemoteConfig::new().with_poll_interval(Duration::ZERO),
+        );
+        assert!(client.poll_due());
+        assert!(client.poll_due());
+    }
+
+    #[test]
+    fn test_parse_config_path() {
+        assert_eq!(
+            parse_config_path("datadog/2/APM_TRACING/abc/config"),
+            Some(("APM_TRACING", "abc"))
+        );
+        assert_eq!(
+            parse_config_path("employee/APM_TRACING/abc/config"),
+            Some(("APM_TRACING", "abc"))
+        );
+        assert_eq!(parse_config_path("datadog/2/APM_TRACING"), None);
+    }
+}
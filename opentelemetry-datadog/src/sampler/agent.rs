@@ -0,0 +1,342 @@
+use crate::propagator::{
+    DatadogTraceState, DatadogTraceStateBuilder, TRACE_STATE_PRIORITY_SAMPLING,
+};
+#[cfg(feature = "remote-config")]
+use crate::sampler::rules::{RuleProvenance, SampledSpan, SamplingRule};
+use opentelemetry::{
+    trace::{
+        Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId, TraceState,
+    },
+    Context, KeyValue,
+};
+use opentelemetry_sdk::trace::ShouldSample;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/sampler.go#L94
+const KNUTH_FACTOR: u64 = 1_111_111_111_111_111_111;
+
+// Key of the rate the agent applies to services it hasn't seen yet.
+const DEFAULT_RATE_KEY: &str = "service:,env:";
+
+#[derive(Debug, Default)]
+struct AgentRates {
+    #[cfg(feature = "remote-config")]
+    service: String,
+    service_key: String,
+    rates: HashMap<String, f64>,
+    #[cfg(feature = "remote-config")]
+    remote: RemoteSampling,
+}
+
+/// Sampling settings received through remote configuration, taking precedence over the agent
+/// rates.
+#[cfg(feature = "remote-config")]
+#[derive(Debug, Default)]
+struct RemoteSampling {
+    sample_rate: Option<f64>,
+    rules: Vec<SamplingRule>,
+}
+
+/// Priority sampler driven by the sampling rates the Datadog agent returns to the exporter.
+///
+/// The agent answers every trace payload with the rate each `service`/`env` pair should be
+/// sampled at to stay within its target traces per second. The exporter feeds those rates to the
+/// sampler registered with [`DatadogPipelineBuilder::with_agent_sampler`], which uses them to set
+/// the sampling priority of new traces. Until the agent answers, every trace is kept.
+///
+/// All spans are recorded and sent to the agent, so it can still compute trace metrics from
+/// them. Spans with a parent inherit the sampling priority of that parent.
+///
+/// With the `remote-config` feature, the sample rate and sampling rules set through Datadog
+/// remote configuration take precedence over the agent rates, see `DatadogRemoteConfig`. Their
+/// decisions get the `USER_KEEP` (2) or `USER_REJECT` (-1) sampling priority, the ones taken at
+/// the agent rates the `AUTO_KEEP` (1) or `AUTO_REJECT` (0) one.
+///
+/// ## Example
+///
+/// ```no_run
+/// use opentelemetry_datadog::{new_pipeline, DatadogAgentSampler};
+/// use opentelemetry_sdk::trace;
+///
+/// # fn main() -> Result<(), opentelemetry::trace::TraceError> {
+/// let sampler = DatadogAgentSampler::default();
+/// let tracer = new_pipeline()
+///     .with_service_name("my_app")
+///     .with_agent_sampler(sampler.clone())
+///     .with_trace_config(trace::config().with_sampler(sampler))
+///     .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`DatadogPipelineBuilder::with_agent_sampler`]: crate::DatadogPipelineBuilder::with_agent_sampler
+#[derive(Clone, Debug, Default)]
+pub struct DatadogAgentSampler {
+    rates: Arc<RwLock<AgentRates>>,
+}
+
+impl DatadogAgentSampler {
+    /// Creates a new `DatadogAgentSampler` keeping every trace until rates are received.
+    pub fn new() -> Self {
+        DatadogAgentSampler::default()
+    }
+
+    pub(crate) fn set_service(&self, service: &str, env: Option<&str>) {
+        if let Ok(mut rates) = self.rates.write() {
+            #[cfg(feature = "remote-config")]
+            {
+                rates.service = service.to_string();
+            }
+            rates.service_key = format!("service:{},env:{}", service, env.unwrap_or_default());
+        }
+    }
+
+    /// Update the rates from the body of an agent response to a trace payload.
+    pub(crate) fn update_from_response(&self, body: &[u8]) {
+        let rates = serde_json::from_slice::<serde_json::Value>(body)
+            .ok()
+            .and_then(|response| {
+                response.get("rate_by_service")?.as_object().map(|rates| {
+                    rates
+                        .iter()
+                        .filter_map(|(key, rate)| Some((key.clone(), rate.as_f64()?)))
+                        .collect::<HashMap<_, _>>()
+                })
+            });
+
+        if let (Some(new_rates), Ok(mut rates)) = (rates, self.rates.write()) {
+            rates.rates = new_rates;
+        }
+    }
+
+    /// Replace the sample rate and sampling rules received through remote configuration.
+    #[cfg(feature = "remote-config")]
+    pub(crate) fn set_remote_sampling(&self, sample_rate: Option<f64>, rules: Vec<SamplingRule>) {
+        if let Ok(mut rates) = self.rates.write() {
+            rates.remote = RemoteSampling { sample_rate, rules };
+        }
+    }
+
+    /// The remote sampling rule or sample rate applying to `span`, along with its provenance.
+    #[cfg(feature = "remote-config")]
+    pub(crate) fn remote_rate(&self, span: &SampledSpan<'_>) -> Option<(f64, RuleProvenance)> {
+        let rates = self.rates.read().ok()?;
+        match rates.remote.rules.iter().find(|rule| rule.matches(span)) {
+            Some(rule) => Some((rule.sample_rate, rule.provenance)),
+            None => rates
+                .remote
+                .sample_rate
+                .map(|rate| (rate, RuleProvenance::Customer)),
+        }
+    }
+
+    /// The rate the agent asked to sample the service at, `1.0` until it answered.
+    pub(crate) fn agent_rate(&self) -> f64 {
+        self.rates
+            .read()
+            .ok()
+            .and_then(|rates| {
+                rates
+                    .rates
+                    .get(&rates.service_key)
+                    .or_else(|| rates.rates.get(DEFAULT_RATE_KEY))
+                    .copied()
+            })
+            .unwrap_or(1.0)
+    }
+
+    /// The rate to sample a new trace at, along with whether it is set by a remote rule or
+    /// sample rate rather than the agent.
+    #[cfg_attr(not(feature = "remote-config"), allow(unused_variables))]
+    fn rate(&self, name: &str, attributes: &[KeyValue]) -> (f64, bool) {
+        #[cfg(feature = "remote-config")]
+        {
+            let service = self
+                .rates
+                .read()
+                .map(|rates| rates.service.clone())
+                .unwrap_or_default();
+            if let Some((rate, _)) = self.remote_rate(&SampledSpan::new(&service, name, attributes))
+            {
+                return (rate, true);
+            }
+        }
+        (self.agent_rate(), false)
+    }
+}
+
+/// Deterministic sampling on the lower 64 bits of the trace id, the same way dd-trace libraries
+/// do it so that every service of a trace takes the same decision for the same rate.
+pub(crate) fn sampled_by_rate(trace_id: TraceId, rate: f64) -> bool {
+    if rate >= 1.0 {
+        return true;
+    }
+    if rate <= 0.0 {
+        return false;
+    }
+    let id = u128::from_be_bytes(trace_id.to_bytes()) as u64;
+    id.wrapping_mul(KNUTH_FACTOR) < (rate * u64::MAX as f64) as u64
+}
+
+/// The trace state of the parent span if it carries a sampling decision, which children inherit.
+pub(super) fn inherited_trace_state(parent_context: Option<&Context>) -> Option<TraceState> {
+    parent_context
+        .filter(|cx| cx.has_active_span())
+        .map(|cx| cx.span().span_context().trace_state().clone())
+        .filter(|trace_state| trace_state.get(TRACE_STATE_PRIORITY_SAMPLING).is_some())
+}
+
+/// The trace state of a span taking the sampling decision `keep`, on top of the trace state of
+/// its parent span if any. Decisions of sampling rules and rate limits are `user` ones, sent with
+/// the `USER_KEEP` and `USER_REJECT` priorities.
+pub(super) fn decided_trace_state(
+    parent_context: Option<&Context>,
+    keep: bool,
+    user: bool,
+) -> TraceState {
+    let trace_state = match parent_context {
+        Some(cx) if cx.has_active_span() => cx.span().span_context().trace_state().clone(),
+        _ => DatadogTraceStateBuilder::default().build(),
+    };
+    if user {
+        trace_state.with_user_priority_sampling(keep)
+    } else {
+        trace_state.with_priority_sampling(keep)
+    }
+}
+
+impl ShouldSample for DatadogAgentSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        _span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        let trace_state = inherited_trace_state(parent_context).unwrap_or_else(|| {
+            let (rate, user) = self.rate(name, attributes);
+            decided_trace_state(parent_context, sampled_by_rate(trace_id, rate), user)
+        });
+
+        SamplingResult {
+            // send all spans to datadog-agent, the priority tells it which traces to keep
+            decision: SamplingDecision::RecordAndSample,
+            attributes: vec![],
+            trace_state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags};
+    use opentelemetry_sdk::testing::trace::TestSpan;
+
+    fn sample(sampler: &DatadogAgentSampler, parent: Option<&Context>, trace_id: u128) -> bool {
+        sampler
+            .should_sample(
+                parent,
+                TraceId::from_u128(trace_id),
+                "span",
+                &SpanKind::Internal,
+                &[],
+                &[],
+            )
+            .trace_state
+            .priority_sampling_enabled()
+    }
+
+    #[test]
+    fn test_keeps_everything_without_rates() {
+        let sampler = DatadogAgentSampler::new();
+        assert!((0..100).all(|id| sample(&sampler, None, id)));
+    }
+
+    #[test]
+    fn test_uses_service_rate() {
+        let sampler = DatadogAgentSampler::new();
+        sampler.set_service("my-service", Some("prod"));
+        sampler.update_from_response(
+            br#"{"rate_by_service":{"service:,env:":1,"service:my-service,env:prod":0}}"#,
+        );
+        assert!((0..100).all(|id| !sample(&sampler, None, id)));
+
+        sampler.set_service("other-service", Some("prod"));
+        assert!((0..100).all(|id| sample(&sampler, None, id)));
+    }
+
+    #[test]
+    fn test_ignores_invalid_response() {
+        let sampler = DatadogAgentSampler::new();
+        sampler.update_from_response(br#"{"rate_by_service":{"service:,env:":0}}"#);
+        sampler.update_from_response(b"OK");
+        assert!(!sample(&sampler, None, 1));
+    }
+
+    #[test]
+    fn test_partial_rate() {
+        let sampler = DatadogAgentSampler::new();
+        sampler.update_from_response(br#"{"rate_by_service":{"service:,env:":0.5}}"#);
+        let kept = (0..10_000u128)
+            .map(|id| id * 7919 + 1)
+            .filter(|id| sample(&sampler, None, *id))
+            .count();
+        assert!((4_000..6_000).contains(&kept), "kept {kept} traces");
+    }
+
+    #[cfg(feature = "remote-config")]
+    #[test]
+    fn test_remote_sampling_overrides_agent_rates() {
+        let sampler = DatadogAgentSampler::new();
+        sampler.set_service("my-service", None);
+        sampler.update_from_response(br#"{"rate_by_service":{"service:,env:":1}}"#);
+        sampler.set_remote_sampling(
+            Some(0.0),
+            vec![SamplingRule::from_remote(&serde_json::json!({
+                "service": "my-*",
+                "name": "kept",
+                "sample_rate": 1,
+            }))
+            .unwrap()],
+        );
+        let sample_named = |name: &str| {
+            sampler
+                .should_sample(
+                    None,
+                    TraceId::from_u128(1),
+                    name,
+                    &SpanKind::Internal,
+                    &[],
+                    &[],
+                )
+                .trace_state
+                .sampling_priority()
+        };
+        // rules take user decisions
+        assert_eq!(sample_named("kept"), 2);
+        assert_eq!(sample_named("dropped"), -1);
+
+        sampler.set_remote_sampling(None, Vec::new());
+        assert_eq!(sample_named("dropped"), 1);
+    }
+
+    #[test]
+    fn test_inherits_parent_decision() {
+        let sampler = DatadogAgentSampler::new();
+        sampler.update_from_response(br#"{"rate_by_service":{"service:,env:":0}}"#);
+        let parent = Context::new().with_span(TestSpan(SpanContext::new(
+            TraceId::from_u128(1),
+            SpanId::from_u64(1),
+            TraceFlags::SAMPLED,
+            true,
+            DatadogTraceStateBuilder::default()
+                .with_priority_sampling(true)
+                .build(),
+        )));
+        assert!(sample(&sampler, Some(&parent), 1));
+    }
+}
@@ -0,0 +1,6 @@
+mod agent;
+mod rule_based;
+pub(crate) mod rules;
+
+pub use agent::DatadogAgentSampler;
+pub use rule_based::DatadogRuleSampler;
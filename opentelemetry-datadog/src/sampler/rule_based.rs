@@ -0,0 +1,529 @@
+use crate::exporter::model::unified_tags::UnifiedTags;
+use crate::exporter::model::{DD_LIMIT_PSR_KEY, DD_RULE_PSR_KEY};
+use crate::exporter::Error;
+use crate::propagator::DatadogTraceState;
+use crate::sampler::agent::{decided_trace_state, inherited_trace_state, sampled_by_rate};
+use crate::sampler::rules::{RuleProvenance, SampledSpan, SamplingRule};
+use crate::DatadogAgentSampler;
+use opentelemetry::{
+    global,
+    trace::{Link, SamplingDecision, SamplingResult, SpanKind, TraceError, TraceId},
+    Context, KeyValue,
+};
+use opentelemetry_sdk::trace::ShouldSample;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/tracer/option.go#L62
+const DEFAULT_RATE_LIMIT: f64 = 100.0;
+
+const DECISION_MAKER_TAG: &str = "_dd.p.dm";
+const DECISION_MAKER_DEFAULT: &str = "-0";
+const DECISION_MAKER_AGENT_RATE: &str = "-1";
+
+/// Priority sampler applying sampling rules, matching the `DD_TRACE_SAMPLING_RULES` behavior of
+/// dd-trace libraries.
+///
+/// The sample rate of a new trace is, in order of precedence:
+/// - the rate of the first remote configuration rule matching the root span, or the remote sample
+///   rate, when the sampler set with [`with_agent_sampler`] receives remote configuration,
+/// - the rate of the first rule matching the root span,
+/// - the default sample rate, see [`with_sample_rate`],
+/// - the rate returned by the agent to the sampler set with [`with_agent_sampler`], if any,
+///   otherwise every trace is kept.
+///
+/// Rules match the glob patterns of their `service`, `name`, `resource` and `tags` against the
+/// service of the sampler, the span name, its `resource.name` attribute (or name) and its
+/// attributes. Traces kept by a rule or the default sample rate are then subject to a rate limit,
+/// `100` traces per second by default.
+///
+/// Traces sampled by a rule, the default sample rate or the rate limit get the `USER_KEEP` (2) or
+/// `USER_REJECT` (-1) sampling priority, the ones sampled at the agent rate the `AUTO_KEEP` (1)
+/// or `AUTO_REJECT` (0) one.
+///
+/// The root span records the rate of the matched rule as `_dd.rule_psr` and the effective rate of
+/// the limiter as `_dd.limit_psr`, and the sampling mechanism is propagated as the `_dd.p.dm` tag
+/// of the trace state. As with [`DatadogAgentSampler`], all spans are recorded and spans with a
+/// parent inherit its sampling priority.
+///
+/// ## Example
+///
+/// ```no_run
+/// use opentelemetry_datadog::{new_pipeline, DatadogRuleSampler};
+/// use opentelemetry_sdk::trace;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let sampler = DatadogRuleSampler::new()
+///     .with_service("my_app")
+///     .with_rules(r#"[{"name": "http.request", "resource": "GET /health*", "sample_rate": 0}]"#)?
+///     .with_sample_rate(0.5);
+/// let tracer = new_pipeline()
+///     .with_service_name("my_app")
+///     .with_trace_config(trace::config().with_sampler(sampler))
+///     .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`with_agent_sampler`]: DatadogRuleSampler::with_agent_sampler
+/// [`with_sample_rate`]: DatadogRuleSampler::with_sample_rate
+#[derive(Clone, Debug)]
+pub struct DatadogRuleSampler {
+    service: String,
+    rules: Vec<SamplingRule>,
+    sample_rate: Option<f64>,
+    limiter: Arc<RateLimiter>,
+    agent_sampler: Option<DatadogAgentSampler>,
+}
+
+impl Default for DatadogRuleSampler {
+    fn default() -> Self {
+        DatadogRuleSampler::new()
+    }
+}
+
+impl DatadogRuleSampler {
+    /// Create a sampler without rules for the service set in `DD_SERVICE`, keeping every trace.
+    pub fn new() -> Self {
+        DatadogRuleSampler {
+            service: UnifiedTags::new().service().unwrap_or_default(),
+            rules: Vec::new(),
+            sample_rate: None,
+            limiter: Arc::new(RateLimiter::new(DEFAULT_RATE_LIMIT)),
+            agent_sampler: None,
+        }
+    }
+
+    /// Create a sampler configured from the environment variables used by dd-trace libraries:
+    /// - `DD_TRACE_SAMPLING_RULES`, the rules as a JSON array,
+    /// - `DD_TRACE_SAMPLE_RATE`, the default sample rate,
+    /// - `DD_TRACE_RATE_LIMIT`, the maximum number of traces kept per second by rules.
+    ///
+    /// Invalid values are reported to the global error handler and ignored.
+    pub fn from_env() -> Self {
+        Self::from_env_vars(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+    }
+
+    pub(crate) fn from_env_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let mut sampler = Self::new();
+        if let Some(rules) = var("DD_TRACE_SAMPLING_RULES") {
+            match parse_rules(&rules) {
+                Ok(rules) => sampler.rules = rules,
+                Err(err) => global::handle_error(TraceError::from(err)),
+            }
+        }
+        if let Some(rate) = var("DD_TRACE_SAMPLE_RATE") {
+            match rate.parse::<f64>() {
+                Ok(rate) if (0.0..=1.0).contains(&rate) => sampler.sample_rate = Some(rate),
+                _ => global::handle_error(TraceError::Other(
+                    format!(
+                        "invalid DD_TRACE_SAMPLE_RATE {rate:?}, expected a number between 0 and 1"
+                    )
+                    .into(),
+                )),
+            }
+        }
+        if let Some(limit) = var("DD_TRACE_RATE_LIMIT") {
+            match limit.parse::<f64>() {
+                Ok(limit) if limit >= 0.0 => sampler = sampler.with_rate_limit(limit),
+                _ => global::handle_error(TraceError::Other(
+                    format!("invalid DD_TRACE_RATE_LIMIT {limit:?}, expected a positive number")
+                        .into(),
+                )),
+            }
+        }
+        sampler
+    }
+
+    /// Set the service matched by the `service` pattern of rules, `DD_SERVICE` by default.
+    pub fn with_service<T: Into<String>>(mut self, service: T) -> Self {
+        self.service = service.into();
+        self
+    }
+
+    /// Replace the rules with the ones of a JSON array in the `DD_TRACE_SAMPLING_RULES` format,
+    /// e.g. `[{"service": "web", "name": "http.*", "tags": {"http.method": "GET"}, "sample_rate": 0.1}]`.
+    ///
+    /// Every pattern is optional, and `sample_rate` defaults to `1.0`.
+    pub fn with_rules(mut self, rules: &str) -> Result<Self, Error> {
+        self.rules = parse_rules(rules)?;
+        Ok(self)
+    }
+
+    /// Set the sample rate of traces matching no rule.
+    pub fn with_sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = Some(sample_rate.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Set the maximum number of traces kept per second by rules and the default sample rate.
+    pub fn with_rate_limit(mut self, max_per_second: f64) -> Self {
+        self.limiter = Arc::new(RateLimiter::new(max_per_second));
+        self
+    }
+
+    /// Sample traces matching no rule at the rates the agent returns to `sampler`, and apply the
+    /// rules `sampler` receives through remote configuration.
+    ///
+    /// The sampler still has to be registered with
+    /// [`DatadogPipelineBuilder::with_agent_sampler`].
+    ///
+    /// [`DatadogPipelineBuilder::with_agent_sampler`]: crate::DatadogPipelineBuilder::with_agent_sampler
+    pub fn with_agent_sampler(mut self, sampler: DatadogAgentSampler) -> Self {
+        self.agent_sampler = Some(sampler);
+        self
+    }
+
+    /// The rule or sample rate applying to `span`, along with its provenance.
+    fn rule_rate(&self, span: &SampledSpan<'_>) -> Option<(f64, RuleProvenance)> {
+        #[cfg(feature = "remote-config")]
+        if let Some(rate) = self
+            .agent_sampler
+            .as_ref()
+            .and_then(|sampler| sampler.remote_rate(span))
+        {
+            return Some(rate);
+        }
+        match self.rules.iter().find(|rule| rule.matches(span)) {
+            Some(rule) => Some((rule.sample_rate, rule.provenance)),
+            None => self.sample_rate.map(|rate| (rate, RuleProvenance::Local)),
+        }
+    }
+}
+
+fn parse_rules(rules: &str) -> Result<Vec<SamplingRule>, Error> {
+    let invalid = |err: String| Error::Other(format!("invalid sampling rules: {err}"));
+    match serde_json::from_str(rules).map_err(|err| invalid(err.to_string()))? {
+        serde_json::Value::Array(rules) => rules
+            .iter()
+            .map(|rule| SamplingRule::from_local(rule).map_err(invalid))
+            .collect(),
+        _ => Err(invalid("expected a JSON array".to_string())),
+    }
+}
+
+impl ShouldSample for DatadogRuleSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        trace_id: TraceId,
+        name: &str,
+        _span_kind: &SpanKind,
+        attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        if let Some(trace_state) = inherited_trace_state(parent_context) {
+            return SamplingResult {
+                decision: SamplingDecision::RecordAndSample,
+                attributes: vec![],
+                trace_state,
+            };
+        }
+
+        let mut sampling_attributes = Vec::new();
+        let span = SampledSpan::new(&self.service, name, attributes);
+        let (keep, user, decision_maker) = match self.rule_rate(&span) {
+            Some((rate, provenance)) => {
+                sampling_attributes.push(KeyValue::new(DD_RULE_PSR_KEY, rate));
+                let mut keep = sampled_by_rate(trace_id, rate);
+                if keep {
+                    let (allowed, effective_rate) = self.limiter.allow(Instant::now());
+                    sampling_attributes.push(KeyValue::new(DD_LIMIT_PSR_KEY, effective_rate));
+                    keep = allowed;
+                }
+                (keep, true, provenance.decision_maker())
+            }
+            None => match &self.agent_sampler {
+                Some(sampler) => (
+                    sampled_by_rate(trace_id, sampler.agent_rate()),
+                    false,
+                    DECISION_MAKER_AGENT_RATE,
+                ),
+                None => (true, false, DECISION_MAKER_DEFAULT),
+            },
+        };
+
+        let mut trace_state = decided_trace_state(parent_context, keep, user);
+        if keep {
+            let mut tags = trace_state.propagated_tags();
+            tags.retain(|(key, _)| key != DECISION_MAKER_TAG);
+            tags.push((DECISION_MAKER_TAG.to_string(), decision_maker.to_string()));
+            trace_state = trace_state.with_propagated_tags(&tags);
+        }
+
+        SamplingResult {
+            // send all spans to datadog-agent, the priority tells it which traces to keep
+            decision: SamplingDecision::RecordAndSample,
+            attributes: sampling_attributes,
+            trace_state,
+        }
+    }
+}
+
+/// Token bucket allowing `max_per_second` traces per second, in bursts of up to as many.
+#[derive(Debug)]
+struct RateLimiter {
+    max_per_second: f64,
+    state: Mutex<LimiterState>,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    tokens: f64,
+    last_refill: Instant,
+    window_start: Instant,
+    seen: u64,
+    allowed: u64,
+    previous_rate: Option<f64>,
+}
+
+impl RateLimiter {
+    fn new(max_per_second: f64) -> Self {
+        let now = Instant::now();
+        RateLimiter {
+            max_per_second,
+            state: Mutex::new(LimiterState {
+                tokens: max_per_second,
+                last_refill: now,
+                window_start: now,
+                seen: 0,
+                allowed: 0,
+                previous_rate: None,
+            }),
+        }
+    }
+
+    /// Whether a trace is allowed at `now`, along with the ratio of traces the limiter allowed,
+    /// averaged over the current and previous second like dd-trace does.
+    fn allow(&self, now: Instant) -> (bool, f64) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return (true, 1.0),
+        };
+
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        state.tokens =
+            (state.tokens + elapsed.as_secs_f64() * self.max_per_second).min(self.max_per_second);
+        state.last_refill = now;
+
+        if now.saturating_duration_since(state.window_start) >= Duration::from_secs(1) {
+            state.previous_rate = Some(window_rate(state.allowed, state.seen));
+            state.window_start = now;
+            state.seen = 0;
+            state.allowed = 0;
+        }
+
+        let allowed = state.tokens >= 1.0;
+        state.seen += 1;
+        if allowed {
+            state.tokens -= 1.0;
+            state.allowed += 1;
+        }
+
+        let rate = window_rate(state.allowed, state.seen);
+        let effective_rate = match state.previous_rate {
+            Some(previous_rate) => (rate + previous_rate) / 2.0,
+            None => rate,
+        };
+        (allowed, effective_rate)
+    }
+}
+
+fn window_rate(allowed: u64, seen: u64) -> f64 {
+    if seen == 0 {
+        1.0
+    } else {
+        allowed as f64 / seen as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags};
+    use opentelemetry_sdk::testing::trace::TestSpan;
+
+    fn sample(
+        sampler: &DatadogRuleSampler,
+        name: &str,
+        attributes: &[KeyValue],
+        trace_id: u128,
+    ) -> SamplingResult {
+        sampler.should_sample(
+            None,
+            TraceId::from_u128(trace_id),
+            name,
+            &SpanKind::Server,
+            attributes,
+            &[],
+        )
+    }
+
+    fn attribute(result: &SamplingResult, key: &str) -> Option<f64> {
+        result
+            .attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .and_then(|kv| match kv.value {
+                opentelemetry::Value::F64(value) => Some(value),
+                _ => None,
+            })
+    }
+
+    fn decision_maker(result: &SamplingResult) -> Option<String> {
+        result
+            .trace_state
+            .propagated_tags()
+            .into_iter()
+            .find(|(key, _)| key == DECISION_MAKER_TAG)
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn test_rules() -> Result<(), Box<dyn std::error::Error>> {
+        let sampler = DatadogRuleSampler::new()
+            .with_service("web")
+            .with_rules(
+                r#"[
+                    {"service": "api", "sample_rate": 1},
+                    {"name": "http.*", "resource": "GET /health*", "sample_rate": 0},
+                    {"tags": {"http.route": "/users/*"}, "sample_rate": 0.5}
+                ]"#,
+            )?
+            .with_sample_rate(1.0);
+
+        let health = sample(
+            &sampler,
+            "http.request",
+            &[KeyValue::new("resource.name", "GET /healthz")],
+            1,
+        );
+        assert!(!health.trace_state.priority_sampling_enabled());
+        assert_eq!(health.trace_state.sampling_priority(), -1);
+        assert_eq!(attribute(&health, DD_RULE_PSR_KEY), Some(0.0));
+        assert_eq!(attribute(&health, DD_LIMIT_PSR_KEY), None);
+        assert_eq!(decision_maker(&health), None);
+
+        let users = sample(
+            &sampler,
+            "http.request",
+            &[KeyValue::new("http.route", "/users/42")],
+            1,
+        );
+        assert_eq!(attribute(&users, DD_RULE_PSR_KEY), Some(0.5));
+
+        let other = sample(&sampler, "grpc.request", &[], 1);
+        assert!(other.trace_state.priority_sampling_enabled());
+        assert_eq!(other.trace_state.sampling_priority(), 2);
+        assert_eq!(attribute(&other, DD_RULE_PSR_KEY), Some(1.0));
+        assert_eq!(attribute(&other, DD_LIMIT_PSR_KEY), Some(1.0));
+        assert_eq!(decision_maker(&other).as_deref(), Some("-3"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_rate() -> Result<(), Box<dyn std::error::Error>> {
+        let sampler = DatadogRuleSampler::new()
+            .with_rules(r#"[{"sample_rate": 0.5}]"#)?
+            .with_rate_limit(f64::MAX);
+        let kept = (0..10_000u128)
+            .map(|id| id * 7919 + 1)
+            .filter(|id| {
+                sample(&sampler, "span", &[], *id)
+                    .trace_state
+                    .priority_sampling_enabled()
+            })
+            .count();
+        assert!((4_000..6_000).contains(&kept), "kept {kept} traces");
+        Ok(())
+    }
+
+    #[test]
+    fn test_falls_back_to_agent_rates() {
+        let agent_sampler = DatadogAgentSampler::new();
+        agent_sampler.update_from_response(br#"{"rate_by_service":{"service:,env:":0}}"#);
+        let sampler = DatadogRuleSampler::new().with_agent_sampler(agent_sampler);
+
+        let result = sample(&sampler, "span", &[], 1);
+        assert_eq!(result.trace_state.sampling_priority(), 0);
+        assert!(result.attributes.is_empty());
+
+        let sampler = DatadogRuleSampler::new();
+        let result = sample(&sampler, "span", &[], 1);
+        assert_eq!(result.trace_state.sampling_priority(), 1);
+        assert_eq!(decision_maker(&result).as_deref(), Some("-0"));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let sampler = DatadogRuleSampler::new()
+            .with_sample_rate(1.0)
+            .with_rate_limit(2.0);
+        let results: Vec<_> = (1..=3)
+            .map(|id| sample(&sampler, "span", &[], id))
+            .collect();
+        assert!(results[0].trace_state.priority_sampling_enabled());
+        assert!(results[1].trace_state.priority_sampling_enabled());
+        assert_eq!(results[2].trace_state.sampling_priority(), -1);
+        assert_eq!(attribute(&results[2], DD_RULE_PSR_KEY), Some(1.0));
+        assert_eq!(attribute(&results[2], DD_LIMIT_PSR_KEY), Some(2.0 / 3.0));
+    }
+
+    #[test]
+    fn test_rate_limiter_refill() {
+        let limiter = RateLimiter::new(1.0);
+        let start = Instant::now();
+        assert_eq!(limiter.allow(start), (true, 1.0));
+        assert_eq!(limiter.allow(start), (false, 0.5));
+        let (allowed, rate) = limiter.allow(start + Duration::from_secs(1));
+        assert!(allowed);
+        assert_eq!(rate, (1.0 + 0.5) / 2.0);
+    }
+
+    #[test]
+    fn test_inherits_parent_decision() {
+        let sampler = DatadogRuleSampler::new().with_sample_rate(0.0);
+        let parent = Context::new().with_span(TestSpan(SpanContext::new(
+            TraceId::from_u128(1),
+            SpanId::from_u64(1),
+            TraceFlags::SAMPLED,
+            true,
+            crate::DatadogTraceStateBuilder::default()
+                .with_priority_sampling(true)
+                .build(),
+        )));
+        let result = sampler.should_sample(
+            Some(&parent),
+            TraceId::from_u128(1),
+            "span",
+            &SpanKind::Internal,
+            &[],
+            &[],
+        );
+        assert!(result.trace_state.priority_sampling_enabled());
+        assert!(result.attributes.is_empty());
+    }
+
+    #[test]
+    fn test_from_env() {
+        let vars = |name: &str| {
+            match name {
+                "DD_TRACE_SAMPLING_RULES" => Some(r#"[{"service": "web", "sample_rate": 0.1}]"#),
+                "DD_TRACE_SAMPLE_RATE" => Some("0.2"),
+                "DD_TRACE_RATE_LIMIT" => Some("10"),
+                _ => None,
+            }
+            .map(str::to_string)
+        };
+        let sampler = DatadogRuleSampler::from_env_vars(vars);
+        assert_eq!(sampler.rules.len(), 1);
+        assert_eq!(sampler.sample_rate, Some(0.2));
+        assert_eq!(sampler.limiter.max_per_second, 10.0);
+
+        let sampler = DatadogRuleSampler::from_env_vars(|name| {
+            (name == "DD_TRACE_SAMPLING_RULES").then(|| "{".to_string())
+        });
+        assert!(sampler.rules.is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use opentelemetry::KeyValue;
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// Glob pattern of sampling rules, `*` matches any sequence of characters and `?` any single
+/// character. Matching is case insensitive, as in dd-trace libraries.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct GlobPattern {
+    pattern: Vec<char>,
+}
+
+impl GlobPattern {
+    pub(crate) fn new(pattern: &str) -> Self {
+        GlobPattern {
+            pattern: pattern.to_lowercase().chars().collect(),
+        }
+    }
+
+    fn matches_any(&self) -> bool {
+        !self.pattern.is_empty() && self.pattern.iter().all(|c| *c == '*')
+    }
+
+    pub(crate) fn matches(&self, value: &str) -> bool {
+        if self.matches_any() {
+            return true;
+        }
+        let value: Vec<char> = value.to_lowercase().chars().collect();
+        let (mut p, mut v) = (0, 0);
+        // position of the last `*` and of the value character it currently stands for
+        let mut backtrack = None;
+        while v < value.len() {
+            match self.pattern.get(p) {
+                Some('*') => {
+                    backtrack = Some((p, v));
+                    p += 1;
+                }
+                Some(c) if *c == '?' || *c == value[v] => {
+                    p += 1;
+                    v += 1;
+                }
+                _ => match backtrack {
+                    Some((star, matched)) => {
+                        p = star + 1;
+                        v = matched + 1;
+                        backtrack = Some((star, matched + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.pattern[p..].iter().all(|c| *c == '*')
+    }
+}
+
+// Attribute holding the Datadog resource of spans, matched by the resource of sampling rules.
+const RESOURCE_NAME_KEY: &str = "resource.name";
+
+/// Where a sampling rule comes from, reported as the sampling decision maker.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RuleProvenance {
+    /// Configured in the service itself.
+    Local,
+    /// Set by users through remote configuration.
+    #[cfg(feature = "remote-config")]
+    Customer,
+    /// Computed by Datadog and sent through remote configuration.
+    #[cfg(feature = "remote-config")]
+    Dynamic,
+}
+
+impl RuleProvenance {
+    /// The `_dd.p.dm` sampling mechanism of decisions taken by rules of this provenance.
+    ///
+    /// See <https://github.com/DataDog/dd-trace-go/blob/v1.62.0/ddtrace/ext/priority.go>
+    pub(crate) fn decision_maker(self) -> &'static str {
+        match self {
+            RuleProvenance::Local => "-3",
+            #[cfg(feature = "remote-config")]
+            RuleProvenance::Customer => "-11",
+            #[cfg(feature = "remote-config")]
+            RuleProvenance::Dynamic => "-12",
+        }
+    }
+}
+
+/// The span properties sampling rules match on.
+pub(crate) struct SampledSpan<'a> {
+    pub(crate) service: &'a str,
+    pub(crate) name: &'a str,
+    pub(crate) resource: Cow<'a, str>,
+    pub(crate) attributes: &'a [KeyValue],
+}
+
+impl<'a> SampledSpan<'a> {
+    /// The resource of the span is its `resource.name` attribute if set, its name otherwise.
+    pub(crate) fn new(service: &'a str, name: &'a str, attributes: &'a [KeyValue]) -> Self {
+        let resource = attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == RESOURCE_NAME_KEY)
+            .map_or(Cow::Borrowed(name), |attribute| attribute.value.as_str());
+        SampledSpan {
+            service,
+            name,
+            resource,
+            attributes,
+        }
+    }
+}
+
+/// Sample rate applying to the spans matching all the patterns of the rule.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct SamplingRule {
+    service: Option<GlobPattern>,
+    name: Option<GlobPattern>,
+    resource: Option<GlobPattern>,
+    tags: Vec<(String, GlobPattern)>,
+    pub(crate) sample_rate: f64,
+    pub(crate) provenance: RuleProvenance,
+}
+
+impl SamplingRule {
+    /// Parse a rule in the `DD_TRACE_SAMPLING_RULES` format:
+    /// `{"service": "web", "resource": "GET /*", "tags": {"k": "v"}, "sample_rate": 0.5}`
+    ///
+    /// The sample rate defaults to `1.0`.
+    pub(crate) fn from_local(rule: &Value) -> Result<Self, String> {
+        let rule = rule
+            .as_object()
+            .ok_or_else(|| format!("sampling rule {rule} is not an object"))?;
+        let pattern = |field: &str| match rule.get(field) {
+            Some(Value::String(pattern)) => Ok(Some(GlobPattern::new(pattern))),
+            None | Some(Value::Null) => Ok(None),
+            Some(value) => Err(format!("invalid {field} pattern {value}")),
+        };
+        let tags = match rule.get("tags") {
+            Some(Value::Object(tags)) => tags
+                .iter()
+                .map(|(key, value)| match value {
+                    Value::String(pattern) => Ok((key.clone(), GlobPattern::new(pattern))),
+                    _ => Err(format!("invalid {key} tag pattern {value}")),
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None | Some(Value::Null) => Vec::new(),
+            Some(tags) => return Err(format!("invalid tags {tags}")),
+        };
+        let sample_rate = match rule.get("sample_rate") {
+            None | Some(Value::Null) => 1.0,
+            Some(rate) => rate
+                .as_f64()
+                .filter(|rate| (0.0..=1.0).contains(rate))
+                .ok_or_else(|| format!("invalid sample_rate {rate}"))?,
+        };
+        Ok(SamplingRule {
+            service: pattern("service")?,
+            name: pattern("name")?,
+            resource: pattern("resource")?,
+            tags,
+            sample_rate,
+            provenance: RuleProvenance::Local,
+        })
+    }
+
+    /// Parse a rule in the remote configuration format:
+    /// `{"service": "web", "resource": "GET /*", "tags": [{"key": "k", "value_glob": "v"}],
+    /// "sample_rate": 0.5, "provenance": "customer"}`
+    #[cfg(feature = "remote-config")]
+    pub(crate) fn from_remote(rule: &Value) -> Option<Self> {
+        let pattern = |field: &str| rule.get(field)?.as_str().map(GlobPattern::new);
+        let tags = match rule.get("tags") {
+            Some(Value::Array(tags)) => tags
+                .iter()
+                .map(|tag| {
+                    Some((
+                        tag.get("key")?.as_str()?.to_string(),
+                        GlobPattern::new(tag.get("value_glob")?.as_str()?),
+                    ))
+                })
+                .collect::<Option<Vec<_>>>()?,
+            None | Some(Value::Null) => Vec::new(),
+            Some(_) => return None,
+        };
+        let provenance = match rule.get("provenance").and_then(Value::as_str) {
+            Some("dynamic") => RuleProvenance::Dynamic,
+            _ => RuleProvenance::Customer,
+        };
+        Some(SamplingRule {
+            service: pattern("service"),
+            name: pattern("name"),
+            resource: pattern("resource"),
+            tags,
+            sample_rate: rule.get("sample_rate")?.as_f64()?.clamp(0.0, 1.0),
+            provenance,
+        })
+    }
+
+    pub(crate) fn matches(&self, span: &SampledSpan<'_>) -> bool {
+        let matches = |pattern: &Option<GlobPattern>, value: &str| {
+            pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.matches(value))
+        };
+        matches(&self.service, span.service)
+            && matches(&self.name, span.name)
+            && matches(&self.resource, &span.resource)
+            && self.tags.iter().all(|(key, pattern)| {
+                span.attributes
+                    .iter()
+                    .find(|attribute| attribute.key.as_str() == key)
+                    .map_or(false, |attribute| {
+                        pattern.matches(&attribute.value.as_str())
+                    })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_pattern() {
+        let cases = [
+            ("*", "anything", true),
+            ("*", "", true),
+            ("web", "web", true),
+            ("web", "WEB", true),
+            ("web", "web-api", false),
+            ("web-*", "web-api", true),
+            ("web-*", "web-", true),
+            ("w?b", "web", true),
+            ("w?b", "wb", false),
+            ("*-api-*", "web-api-prod", true),
+            ("*-api-*", "web-api", false),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYbZ", false),
+            ("", "", true),
+            ("", "web", false),
+        ];
+        for (pattern, value, expected) in cases {
+            assert_eq!(
+                GlobPattern::new(pattern).matches(value),
+                expected,
+                "{pattern} on {value}"
+            );
+        }
+    }
+
+    #[cfg(feature = "remote-config")]
+    #[test]
+    fn test_remote_rule() {
+        let rule = SamplingRule::from_remote(&serde_json::json!({
+            "service": "web-*",
+            "resource": "GET /users/*",
+            "tags": [{"key": "http.route", "value_glob": "/users/?"}],
+            "sample_rate": 0.25,
+            "provenance": "dynamic",
+        }))
+        .unwrap();
+        assert_eq!(rule.sample_rate, 0.25);
+        assert_eq!(rule.provenance, RuleProvenance::Dynamic);
+
+        let attributes = [
+            KeyValue::new("http.route", "/users/1"),
+            KeyValue::new("resource.name", "GET /users/1"),
+        ];
+        assert!(rule.matches(&SampledSpan::new("web-api", "http.request", &attributes)));
+        assert!(!rule.matches(&SampledSpan::new(
+            "web-api",
+            "http.request",
+            &attributes[..1]
+        )));
+
+        assert!(SamplingRule::from_remote(&serde_json::json!({"service": "web"})).is_none());
+    }
+
+    #[test]
+    fn test_local_rule() {
+        let rule = SamplingRule::from_local(&serde_json::json!({
+            "name": "http.*",
+            "tags": {"http.method": "GET"},
+        }))
+        .unwrap();
+        assert_eq!(rule.sample_rate, 1.0);
+        assert_eq!(rule.provenance, RuleProvenance::Local);
+
+        let attributes = [KeyValue::new("http.method", "get")];
+        assert!(rule.matches(&SampledSpan::new("web", "http.request", &attributes)));
+        assert!(!rule.matches(&SampledSpan::new("web", "grpc.request", &attributes)));
+
+        for invalid in [
+            serde_json::json!({"sample_rate": 1.5}),
+            serde_json::json!({"service": 1}),
+            serde_json::json!({"tags": ["http.method:GET"]}),
+            serde_json::json!("web"),
+        ] {
+            assert!(SamplingRule::from_local(&invalid).is_err(), "{invalid}");
+        }
+    }
+}
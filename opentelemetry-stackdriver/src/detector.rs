@@ -0,0 +1,568 @@
+//! Detect the resource of applications running on Google Cloud.
+//!
+//! The platform is told apart by the environment variables set by Cloud Run, Cloud Functions
+//! and Kubernetes, details such as the project, zone or cluster being read from the
+//! [metadata server](https://cloud.google.com/compute/docs/metadata/overview).
+
+use std::time::Duration;
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client};
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::Resource;
+use opentelemetry_semantic_conventions::resource as semconv;
+
+use crate::MonitoredResource;
+
+const METADATA_HOST: &str = "metadata.google.internal";
+const METADATA_FLAVOR: &str = "Metadata-Flavor";
+const NAMESPACE_FILE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// Detects the Google Cloud platform the application runs on.
+///
+/// Detection follows, in order:
+///
+/// - Cloud Functions, when `FUNCTION_TARGET` is set;
+/// - Cloud Run, when `K_SERVICE` is set;
+/// - Google Kubernetes Engine, when `KUBERNETES_SERVICE_HOST` is set. The namespace, pod and
+///   container names are read from the `NAMESPACE`, `POD_NAME` (or `HOSTNAME`) and
+///   `CONTAINER_NAME` variables, which should be set through the downward API;
+/// - Compute Engine, when the metadata server answers with an instance id.
+///
+/// The project id is read from the metadata server, or from `GOOGLE_CLOUD_PROJECT` if it can't
+/// be reached.
+///
+/// When none of these platforms is detected, the resource is the `Global` monitored resource if
+/// the metadata server answers, e.g. on App Engine. Otherwise the application doesn't run on
+/// Google Cloud and `None` is returned, even if `GOOGLE_CLOUD_PROJECT` is set.
+///
+/// ```no_run
+/// use opentelemetry_stackdriver::detector::GcpDetector;
+/// use opentelemetry_stackdriver::LogContext;
+///
+/// # async fn run() {
+/// if let Some(detected) = GcpDetector::default().detect().await {
+///     let log_context = LogContext {
+///         log_id: "my-service".to_owned(),
+///         resource: detected.monitored_resource,
+///     };
+///     let config = opentelemetry_sdk::trace::config().with_resource(detected.resource);
+/// }
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct GcpDetector {
+    metadata_endpoint: String,
+    timeout: Duration,
+}
+
+impl Default for GcpDetector {
+    fn default() -> Self {
+        let host = std::env::var("GCE_METADATA_HOST").unwrap_or_else(|_| METADATA_HOST.to_owned());
+        Self {
+            metadata_endpoint: format!("http://{host}"),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+impl GcpDetector {
+    /// Set the URL of the metadata server.
+    ///
+    /// If not set, defaults to `http://metadata.google.internal`, or the host set by the
+    /// `GCE_METADATA_HOST` environment variable.
+    pub fn metadata_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.metadata_endpoint = endpoint.into();
+        self
+    }
+
+    /// Set the timeout of each request to the metadata server.
+    ///
+    /// If not set, defaults to 1 second.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Detect the resource of the application, or `None` when not running on Google Cloud.
+    pub async fn detect(&self) -> Option<GcpResource> {
+        self.detect_with(|name| std::env::var(name).ok()).await
+    }
+
+    async fn detect_with(&self, env: impl Fn(&str) -> Option<String>) -> Option<GcpResource> {
+        let metadata = Metadata {
+            client: Client::new(),
+            endpoint: &self.metadata_endpoint,
+            timeout: self.timeout,
+        };
+        let metadata_project_id = metadata.get("project/project-id").await;
+        let on_metadata_server = metadata_project_id.is_some();
+        let project_id = match metadata_project_id {
+            Some(project_id) => project_id,
+            None => env("GOOGLE_CLOUD_PROJECT")?,
+        };
+
+        let mut attributes = vec![
+            KeyValue::new(semconv::CLOUD_PROVIDER, "gcp"),
+            KeyValue::new(semconv::CLOUD_ACCOUNT_ID, project_id.clone()),
+        ];
+        let mut attribute = |key: &'static str, value: &Option<String>| {
+            if let Some(value) = value {
+                attributes.push(KeyValue::new(key, value.clone()));
+            }
+        };
+
+        let monitored_resource = if let Some(target) = env("FUNCTION_TARGET") {
+            let function_name = env("K_SERVICE").or_else(|| env("FUNCTION_NAME"));
+            let region = metadata.get("instance/region").await.map(last_segment);
+            attribute(semconv::CLOUD_PLATFORM, &Some("gcp_cloud_functions".into()));
+            attribute(semconv::CLOUD_REGION, &region);
+            attribute(semconv::FAAS_NAME, &function_name.clone().or(Some(target)));
+            attribute(semconv::FAAS_VERSION, &env("K_REVISION"));
+            attribute(semconv::FAAS_INSTANCE, &metadata.get("instance/id").await);
+
+            MonitoredResource::CloudFunction {
+                project_id,
+                region,
+                function_name,
+            }
+        } else if let Some(service_name) = env("K_SERVICE") {
+            let revision_name = env("K_REVISION");
+            let location = metadata.get("instance/region").await.map(last_segment);
+            attribute(semconv::CLOUD_PLATFORM, &Some("gcp_cloud_run".into()));
+            attribute(semconv::CLOUD_REGION, &location);
+            attribute(semconv::FAAS_NAME, &Some(service_name.clone()));
+            attribute(semconv::FAAS_VERSION, &revision_name);
+            attribute(semconv::FAAS_INSTANCE, &metadata.get("instance/id").await);
+
+            MonitoredResource::CloudRunRevision {
+                project_id,
+                service_name: Some(service_name),
+                revision_name,
+                location,
+                configuration_name: env("K_CONFIGURATION"),
+            }
+        } else if env("KUBERNETES_SERVICE_HOST").is_some() {
+            let location = metadata.get("instance/attributes/cluster-location").await;
+            let cluster_name = metadata.get("instance/attributes/cluster-name").await;
+            let namespace_name = match env("NAMESPACE") {
+                Some(namespace) => Some(namespace),
+                None => tokio::fs::read_to_string(NAMESPACE_FILE)
+                    .await
+                    .ok()
+                    .map(|namespace| namespace.trim().to_owned()),
+            };
+            let pod_name = env("POD_NAME").or_else(|| env("HOSTNAME"));
+            let container_name = env("CONTAINER_NAME");
+            attribute(
+                semconv::CLOUD_PLATFORM,
+                &Some("gcp_kubernetes_engine".into()),
+            );
+            match &location {
+                Some(zone) if is_zone(zone) => {
+                    attribute(semconv::CLOUD_AVAILABILITY_ZONE, &location);
+                    attribute(semconv::CLOUD_REGION, &Some(region_of(zone).to_owned()));
+                }
+                _ => attribute(semconv::CLOUD_REGION, &location),
+            }
+            attribute(semconv::K8S_CLUSTER_NAME, &cluster_name);
+            attribute(semconv::K8S_NAMESPACE_NAME, &namespace_name);
+            attribute(semconv::K8S_POD_NAME, &pod_name);
+            attribute(semconv::K8S_CONTAINER_NAME, &container_name);
+            attribute(semconv::HOST_ID, &metadata.get("instance/id").await);
+
+            MonitoredResource::K8sContainer {
+                project_id,
+                location,
+                cluster_name,
+                namespace_name,
+                pod_name,
+                container_name,
+            }
+        } else if let Some(instance_id) = metadata.get("instance/id").await {
+            let zone = metadata.get("instance/zone").await.map(last_segment);
+            attribute(semconv::CLOUD_PLATFORM, &Some("gcp_compute_engine".into()));
+            attribute(semconv::CLOUD_AVAILABILITY_ZONE, &zone);
+            attribute(
+                semconv::CLOUD_REGION,
+                &zone.as_deref().map(|zone| region_of(zone).to_owned()),
+            );
+            attribute(semconv::HOST_ID, &Some(instance_id.clone()));
+            attribute(semconv::HOST_NAME, &metadata.get("instance/name").await);
+            attribute(
+                semconv::HOST_TYPE,
+                &metadata
+                    .get("instance/machine-type")
+                    .await
+                    .map(last_segment),
+            );
+
+            MonitoredResource::GceInstance {
+                project_id,
+                instance_id: Some(instance_id),
+                zone,
+            }
+        } else if on_metadata_server {
+            MonitoredResource::Global { project_id }
+        } else {
+            return None;
+        };
+
+        Some(GcpResource {
+            resource: Resource::new(attributes),
+            monitored_resource,
+        })
+    }
+}
+
+/// The resource of an application running on Google Cloud.
+#[derive(Clone, Debug)]
+pub struct GcpResource {
+    /// The `cloud.*`, `faas.*`, `host.*` and `k8s.*` attributes of the platform.
+    pub resource: Resource,
+    /// The resource of the log entries, spans and metrics written by the application.
+    pub monitored_resource: MonitoredResource,
+}
+
+struct Metadata<'a> {
+    client: Client<HttpConnector, Body>,
+    endpoint: &'a str,
+    timeout: Duration,
+}
+
+impl Metadata<'_> {
+    /// Read a value of the metadata server, `None` if it can't be reached or the value isn't
+    /// set.
+    async fn get(&self, path: &str) -> Option<String> {
+        let request = http::Request::get(format!("{}/computeMetadata/v1/{path}", self.endpoint))
+            .header(METADATA_FLAVOR, "Google")
+            .body(Body::empty())
+            .ok()?;
+        let response = tokio::time::timeout(self.timeout, self.client.request(request))
+            .await
+            .ok()?
+            .ok()?;
+        // anything else than the metadata server, e.g. a captive portal, is ignored
+        if !response.status().is_success()
+            || response.headers().get(METADATA_FLAVOR) != Some(&"Google".parse().ok()?)
+        {
+            return None;
+        }
+
+        let body = tokio::time::timeout(self.timeout, hyper::body::to_bytes(response.into_body()))
+            .await
+            .ok()?
+            .ok()?;
+        let value = String::from_utf8(body.to_vec()).ok()?;
+        let value = value.trim();
+        (!value.is_empty()).then(|| value.to_owned())
+    }
+}
+
+/// Zones and regions are returned as `projects/<number>/zones/<zone>`.
+fn last_segment(value: String) -> String {
+    match value.rsplit_once('/') {
+        Some((_, last)) => last.to_owned(),
+        None => value,
+    }
+}
+
+/// Zones, e.g. `us-central1-a`, have one more part than regions, e.g. `us-central1`.
+fn is_zone(location: &str) -> bool {
+    location.matches('-').count() >= 2
+}
+
+fn region_of(zone: &str) -> &str {
+    zone.rsplit_once('-').map_or(zone, |(region, _)| region)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Request, Response, Server, StatusCode};
+    use opentelemetry::Key;
+
+    use super::*;
+
+    /// Serve the given metadata values, keyed by their path.
+    fn serve(values: &[(&str, &str)]) -> SocketAddr {
+        let values = values
+            .iter()
+            .map(|(path, value)| (format!("/computeMetadata/v1/{path}"), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        let make_service = make_service_fn(move |_| {
+            let values = values.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let response = match (
+                        values.get(request.uri().path()),
+                        request.headers().get(METADATA_FLAVOR),
+                    ) {
+                        (Some(value), Some(flavor)) if flavor == "Google" => Response::builder()
+                            .header(METADATA_FLAVOR, "Google")
+                            .body(Body::from(value.clone())),
+                        _ => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .header(METADATA_FLAVOR, "Google")
+                            .body(Body::empty()),
+                    };
+                    async move { response }
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn detect(addr: SocketAddr, env: &[(&str, &str)]) -> Option<GcpResource> {
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>();
+        GcpDetector::default()
+            .metadata_endpoint(format!("http://{addr}"))
+            .detect_with(|name| env.get(name).cloned())
+            .await
+    }
+
+    fn attribute(resource: &Resource, key: &'static str) -> Option<String> {
+        resource
+            .get(Key::from_static_str(key))
+            .map(|v| v.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_gce_instance() {
+        let addr = serve(&[
+            ("project/project-id", "my-project"),
+            ("instance/id", "1234"),
+            ("instance/name", "my-instance"),
+            ("instance/zone", "projects/42/zones/europe-west1-b"),
+            ("instance/machine-type", "projects/42/machineTypes/e2-small"),
+        ]);
+
+        let detected = detect(addr, &[]).await.unwrap();
+
+        assert_eq!(
+            detected.monitored_resource,
+            MonitoredResource::GceInstance {
+                project_id: "my-project".to_owned(),
+                instance_id: Some("1234".to_owned()),
+                zone: Some("europe-west1-b".to_owned()),
+            }
+        );
+        let resource = detected.resource;
+        assert_eq!(
+            attribute(&resource, semconv::CLOUD_PLATFORM).as_deref(),
+            Some("gcp_compute_engine")
+        );
+        assert_eq!(
+            attribute(&resource, semconv::CLOUD_ACCOUNT_ID).as_deref(),
+            Some("my-project")
+        );
+        assert_eq!(
+            attribute(&resource, semconv::CLOUD_REGION).as_deref(),
+            Some("europe-west1")
+        );
+        assert_eq!(
+            attribute(&resource, semconv::HOST_TYPE).as_deref(),
+            Some("e2-small")
+        );
+        assert_eq!(
+            attribute(&resource, semconv::HOST_NAME).as_deref(),
+            Some("my-instance")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_gke_container() {
+        let addr = serve(&[
+            ("project/project-id", "my-project"),
+            ("instance/id", "1234"),
+            ("instance/attributes/cluster-name", "my-cluster"),
+            ("instance/attributes/cluster-location", "us-central1"),
+        ]);
+
+        let detected = detect(
+            addr,
+            &[
+                ("KUBERNETES_SERVICE_HOST", "10.0.0.1"),
+                ("NAMESPACE", "default"),
+                ("HOSTNAME", "my-pod-abcde"),
+                ("CONTAINER_NAME", "app"),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            detected.monitored_resource,
+            MonitoredResource::K8sContainer {
+                project_id: "my-project".to_owned(),
+                location: Some("us-central1".to_owned()),
+                cluster_name: Some("my-cluster".to_owned()),
+                namespace_name: Some("default".to_owned()),
+                pod_name: Some("my-pod-abcde".to_owned()),
+                container_name: Some("app".to_owned()),
+            }
+        );
+        let resource = detected.resource;
+        assert_eq!(
+            attribute(&resource, semconv::CLOUD_PLATFORM).as_deref(),
+            Some("gcp_kubernetes_engine")
+        );
+        // regional clusters have no zone
+        assert_eq!(
+            attribute(&resource, semconv::CLOUD_REGION).as_deref(),
+            Some("us-central1")
+        );
+        assert_eq!(attribute(&resource, semconv::CLOUD_AVAILABILITY_ZONE), None);
+        assert_eq!(
+            attribute(&resource, semconv::K8S_POD_NAME).as_deref(),
+            Some("my-pod-abcde")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloud_run_revision() {
+        let addr = serve(&[
+            ("project/project-id", "my-project"),
+            ("instance/id", "00abcdef"),
+            ("instance/region", "projects/42/regions/us-east1"),
+        ]);
+
+        let detected = detect(
+            addr,
+            &[
+                ("K_SERVICE", "my-service"),
+                ("K_REVISION", "my-service-00001-abc"),
+                ("K_CONFIGURATION", "my-service"),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            detected.monitored_resource,
+            MonitoredResource::CloudRunRevision {
+                project_id: "my-project".to_owned(),
+                service_name: Some("my-service".to_owned()),
+                revision_name: Some("my-service-00001-abc".to_owned()),
+                location: Some("us-east1".to_owned()),
+                configuration_name: Some("my-service".to_owned()),
+            }
+        );
+        let resource = detected.resource;
+        assert_eq!(
+            attribute(&resource, semconv::CLOUD_PLATFORM).as_deref(),
+            Some("gcp_cloud_run")
+        );
+        assert_eq!(
+            attribute(&resource, semconv::FAAS_INSTANCE).as_deref(),
+            Some("00abcdef")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cloud_function() {
+        let addr = serve(&[
+            ("project/project-id", "my-project"),
+            ("instance/region", "projects/42/regions/us-east1"),
+        ]);
+
+        // 2nd gen functions run on Cloud Run, and set its variables too
+        let detected = detect(
+            addr,
+            &[
+                ("FUNCTION_TARGET", "handler"),
+                ("K_SERVICE", "my-function"),
+                ("K_REVISION", "my-function-00001-abc"),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            detected.monitored_resource,
+            MonitoredResource::CloudFunction {
+                project_id: "my-project".to_owned(),
+                region: Some("us-east1".to_owned()),
+                function_name: Some("my-function".to_owned()),
+            }
+        );
+        assert_eq!(
+            attribute(&detected.resource, semconv::CLOUD_PLATFORM).as_deref(),
+            Some("gcp_cloud_functions")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_outside_google_cloud() {
+        // nothing listens on the port anymore
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        assert!(detect(addr, &[]).await.is_none());
+        // a configured project doesn't mean running on Google Cloud
+        assert!(detect(addr, &[("GOOGLE_CLOUD_PROJECT", "my-project")])
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_project_from_environment() {
+        // nothing listens on the port anymore
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // the platform is still told by its environment variables
+        let detected = detect(
+            addr,
+            &[
+                ("GOOGLE_CLOUD_PROJECT", "my-project"),
+                ("K_SERVICE", "my-service"),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            detected.monitored_resource,
+            MonitoredResource::CloudRunRevision {
+                project_id: "my-project".to_owned(),
+                service_name: Some("my-service".to_owned()),
+                revision_name: None,
+                location: None,
+                configuration_name: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_global_on_other_platforms() {
+        // e.g. App Engine, which has a metadata server but no instance id
+        let addr = serve(&[("project/project-id", "my-project")]);
+
+        let detected = detect(addr, &[]).await.unwrap();
+        assert_eq!(
+            detected.monitored_resource,
+            MonitoredResource::Global {
+                project_id: "my-project".to_owned()
+            }
+        );
+        assert_eq!(
+            attribute(&detected.resource, semconv::CLOUD_ACCOUNT_ID).as_deref(),
+            Some("my-project")
+        );
+    }
+}
@@ -26,8 +26,8 @@ use futures_core::future::BoxFuture;
 use futures_util::stream::StreamExt;
 use opentelemetry::{
     global::handle_error,
-    trace::{SpanId, TraceError},
-    Key, KeyValue, Value,
+    trace::{SpanId, TraceError, TraceId},
+    Array, Key, KeyValue, Value,
 };
 use opentelemetry_sdk::{
     export::{
@@ -38,6 +38,10 @@ use opentelemetry_sdk::{
 };
 use opentelemetry_semantic_conventions as semconv;
 use thiserror::Error;
+use tokio::sync::{
+    mpsc::{self, error::TrySendError},
+    oneshot,
+};
 #[cfg(any(feature = "yup-authorizer", feature = "gcp-authorizer"))]
 use tonic::metadata::MetadataValue;
 use tonic::{
@@ -50,9 +54,17 @@ use yup_oauth2::authenticator::Authenticator;
 #[allow(clippy::derive_partial_eq_without_eq)] // tonic doesn't derive Eq for generated types
 pub mod proto;
 
+pub mod detector;
+
 #[cfg(feature = "propagator")]
 pub mod google_trace_context_propagator;
 
+#[cfg(feature = "logs")]
+pub mod logs;
+
+#[cfg(feature = "metrics")]
+pub mod metrics;
+
 const HTTP_HOST: &str = "http.host";
 const HTTP_PATH: &str = "http.path";
 const HTTP_USER_AGENT: &str = "http.user_agent";
@@ -61,11 +73,12 @@ const GCP_HTTP_PATH: &str = "/http/path";
 
 use proto::devtools::cloudtrace::v2::span::time_event::Annotation;
 use proto::devtools::cloudtrace::v2::span::{
-    Attributes, Link, Links, SpanKind, TimeEvent, TimeEvents,
+    link, Attributes, Link, Links, SpanKind, TimeEvent, TimeEvents,
 };
+use proto::devtools::cloudtrace::v2::stack_trace::{StackFrame, StackFrames};
 use proto::devtools::cloudtrace::v2::trace_service_client::TraceServiceClient;
 use proto::devtools::cloudtrace::v2::{
-    AttributeValue, BatchWriteSpansRequest, Span, TruncatableString,
+    AttributeValue, BatchWriteSpansRequest, Span, StackTrace, TruncatableString,
 };
 use proto::logging::v2::{
     log_entry::Payload, logging_service_v2_client::LoggingServiceV2Client, LogEntry,
@@ -75,15 +88,29 @@ use proto::rpc::Status;
 
 /// Exports opentelemetry tracing spans to Google StackDriver.
 ///
-/// As of the time of this writing, the opentelemetry crate exposes no link information
-/// so this struct does not send link information.
+/// Batches are queued for the driver future returned by [`Builder::build`], and the export
+/// completes once the driver sent them, with the error of the Cloud Trace or Cloud Logging
+/// request if it failed. Requests failing with a retryable status are retried, see
+/// [`Builder::max_retries`].
+///
+/// OpenTelemetry doesn't record how a linked span relates to the exported one, so links are sent
+/// as `PARENT_LINKED_SPAN` when they point to the parent of the span, `CHILD_LINKED_SPAN` when
+/// they point to one of its children exported in the same batch, and with an unspecified type
+/// otherwise. The child span count is the number of children exported in the same batch as
+/// their parent, unset without any. The SDK doesn't tell whether the parent of a span is remote,
+/// so the parent of `Server` and `Consumer` spans is assumed to run in another process, and the
+/// parent of other spans in the same one.
 #[derive(Clone)]
 pub struct StackDriverExporter {
-    tx: futures_channel::mpsc::Sender<Vec<SpanData>>,
+    tx: mpsc::Sender<QueuedBatch>,
     pending_count: Arc<AtomicUsize>,
     maximum_shutdown_duration: Duration,
+    drop_policy: DropPolicy,
 }
 
+/// A batch waiting to be sent by the driver, along with the sender of its export result.
+type QueuedBatch = (Vec<SpanData>, oneshot::Sender<ExportResult>);
+
 impl StackDriverExporter {
     pub fn builder() -> Builder {
         Builder::default()
@@ -96,15 +123,37 @@ impl StackDriverExporter {
 
 impl SpanExporter for StackDriverExporter {
     fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
-        match self.tx.try_send(batch) {
-            Err(e) => Box::pin(std::future::ready(Err(TraceError::Other(Box::new(
-                e.into_send_error(),
-            ))))),
-            Ok(()) => {
-                self.pending_count.fetch_add(1, Ordering::Relaxed);
-                Box::pin(std::future::ready(Ok(())))
+        let (result_tx, result_rx) = oneshot::channel();
+        // count the batch before the driver may pick it up
+        self.pending_count.fetch_add(1, Ordering::Relaxed);
+        let queued = match self.tx.try_send((batch, result_tx)) {
+            Ok(()) => None,
+            Err(TrySendError::Full(batch)) if self.drop_policy == DropPolicy::Wait => {
+                Some((self.tx.clone(), batch))
             }
-        }
+            Err(e) => {
+                self.pending_count.fetch_sub(1, Ordering::Relaxed);
+                let err = match e {
+                    TrySendError::Full(_) => Error::QueueFull,
+                    TrySendError::Closed(_) => driver_stopped(),
+                };
+                return Box::pin(std::future::ready(Err(err.into())));
+            }
+        };
+
+        let pending_count = self.pending_count.clone();
+        Box::pin(async move {
+            if let Some((tx, batch)) = queued {
+                if tx.send(batch).await.is_err() {
+                    pending_count.fetch_sub(1, Ordering::Relaxed);
+                    return Err(driver_stopped().into());
+                }
+            }
+
+            result_rx
+                .await
+                .unwrap_or_else(|_| Err(driver_stopped().into()))
+        })
     }
 
     fn shutdown(&mut self) {
@@ -117,6 +166,10 @@ impl SpanExporter for StackDriverExporter {
     }
 }
 
+fn driver_stopped() -> Error {
+    Error::Other("the exporter driver future was dropped".into())
+}
+
 impl fmt::Debug for StackDriverExporter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         #[allow(clippy::unneeded_field_pattern)]
@@ -124,21 +177,43 @@ impl fmt::Debug for StackDriverExporter {
             tx: _,
             pending_count,
             maximum_shutdown_duration,
+            drop_policy,
         } = self;
         f.debug_struct("StackDriverExporter")
             .field("tx", &"(elided)")
             .field("pending_count", pending_count)
             .field("maximum_shutdown_duration", maximum_shutdown_duration)
+            .field("drop_policy", drop_policy)
             .finish()
     }
 }
 
+/// What [`StackDriverExporter`] does with a batch exported while its queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the batch, failing its export with [`Error::QueueFull`].
+    #[default]
+    DropNewest,
+    /// Wait for the driver to make room for the batch in the queue.
+    Wait,
+}
+
 /// Helper type to build a `StackDriverExporter`.
 #[derive(Clone, Default)]
 pub struct Builder {
     maximum_shutdown_duration: Option<Duration>,
     num_concurrent_requests: Option<usize>,
     log_context: Option<LogContext>,
+    trace_endpoint: Option<String>,
+    log_endpoint: Option<String>,
+    insecure: bool,
+    trace_channel: Option<Channel>,
+    log_channel: Option<Channel>,
+    queue_size: Option<usize>,
+    drop_policy: DropPolicy,
+    max_retries: Option<u32>,
+    initial_backoff: Option<Duration>,
+    max_backoff: Option<Duration>,
 }
 
 impl Builder {
@@ -164,6 +239,86 @@ impl Builder {
         self
     }
 
+    /// Set the endpoint of the Cloud Trace API, e.g. a Private Service Connect endpoint or an
+    /// emulator.
+    ///
+    /// If not set, defaults to `https://cloudtrace.googleapis.com:443`.
+    pub fn trace_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.trace_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Set the endpoint of the Cloud Logging API, used when a `log_context` is set.
+    ///
+    /// If not set, defaults to `https://logging.googleapis.com:443`.
+    pub fn log_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.log_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Connect to the endpoints without TLS, e.g. to reach a local emulator.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Send spans over the given `channel` instead of connecting to the trace endpoint.
+    pub fn trace_channel(mut self, channel: Channel) -> Self {
+        self.trace_channel = Some(channel);
+        self
+    }
+
+    /// Send log entries over the given `channel` instead of connecting to the log endpoint.
+    pub fn log_channel(mut self, channel: Channel) -> Self {
+        self.log_channel = Some(channel);
+        self
+    }
+
+    /// Set the number of batches waiting to be sent by the driver.
+    ///
+    /// If not set, defaults to 64.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        self.queue_size = Some(queue_size);
+        self
+    }
+
+    /// Set what happens to batches exported while the queue is full.
+    ///
+    /// If not set, defaults to [`DropPolicy::DropNewest`].
+    pub fn drop_policy(mut self, drop_policy: DropPolicy) -> Self {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Set the number of times requests failing with an `UNAVAILABLE`, `DEADLINE_EXCEEDED` or
+    /// `RESOURCE_EXHAUSTED` status are retried, `0` disabling retries.
+    ///
+    /// If not set, defaults to 3.
+    ///
+    /// The backoff between retries is awaited with `tokio::time::sleep`, so the driver future
+    /// returned by [`Builder::build`] must run on a Tokio runtime with the time driver enabled, as
+    /// tonic's transport already requires.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = Some(max_retries);
+        self
+    }
+
+    /// Set the backoff before the first retry, doubling with each following retry.
+    ///
+    /// If not set, defaults to 100 milliseconds.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = Some(backoff);
+        self
+    }
+
+    /// Set the upper bound of the backoff between retries.
+    ///
+    /// If not set, defaults to 5 seconds.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = Some(backoff);
+        self
+    }
+
     pub async fn build<A: Authorizer>(
         self,
         authenticator: A,
@@ -175,26 +330,37 @@ impl Builder {
             maximum_shutdown_duration,
             num_concurrent_requests,
             log_context,
+            trace_endpoint,
+            log_endpoint,
+            insecure,
+            trace_channel,
+            log_channel,
+            queue_size,
+            drop_policy,
+            max_retries,
+            initial_backoff,
+            max_backoff,
         } = self;
-        let uri = http::uri::Uri::from_static("https://cloudtrace.googleapis.com:443");
 
-        let trace_channel = Channel::builder(uri)
-            .tls_config(ClientTlsConfig::new())
-            .map_err(|e| Error::Transport(e.into()))?
-            .connect()
-            .await
-            .map_err(|e| Error::Transport(e.into()))?;
+        let trace_channel = match trace_channel {
+            Some(channel) => channel,
+            None => {
+                connect(
+                    trace_endpoint.as_deref().unwrap_or(TRACE_ENDPOINT),
+                    insecure,
+                )
+                .await?
+            }
+        };
 
         let log_client = match log_context {
             Some(log_context) => {
-                let log_channel = Channel::builder(http::uri::Uri::from_static(
-                    "https://logging.googleapis.com:443",
-                ))
-                .tls_config(ClientTlsConfig::new())
-                .map_err(|e| Error::Transport(e.into()))?
-                .connect()
-                .await
-                .map_err(|e| Error::Transport(e.into()))?;
+                let log_channel = match log_channel {
+                    Some(channel) => channel,
+                    None => {
+                        connect(log_endpoint.as_deref().unwrap_or(LOG_ENDPOINT), insecure).await?
+                    }
+                };
 
                 Some(LogClient {
                     client: LoggingServiceV2Client::new(log_channel),
@@ -204,33 +370,44 @@ impl Builder {
             None => None,
         };
 
-        let (tx, rx) = futures_channel::mpsc::channel(64);
+        let (tx, rx) = mpsc::channel(queue_size.unwrap_or(64));
         let pending_count = Arc::new(AtomicUsize::new(0));
         let scopes = Arc::new(match log_client {
             Some(_) => vec![TRACE_APPEND, LOGGING_WRITE],
             None => vec![TRACE_APPEND],
         });
+        let retry = RetryPolicy {
+            max_retries: max_retries.unwrap_or(3),
+            initial_backoff: initial_backoff.unwrap_or_else(|| Duration::from_millis(100)),
+            max_backoff: max_backoff.unwrap_or_else(|| Duration::from_secs(5)),
+        };
 
         let count_clone = pending_count.clone();
         let future = async move {
             let trace_client = TraceServiceClient::new(trace_channel);
             let authorizer = &authenticator;
             let log_client = log_client.clone();
-            rx.for_each_concurrent(num_concurrent_requests, move |batch| {
-                let trace_client = trace_client.clone();
-                let log_client = log_client.clone();
-                let pending_count = count_clone.clone();
-                let scopes = scopes.clone();
-                ExporterContext {
-                    trace_client,
-                    log_client,
-                    authorizer,
-                    pending_count,
-                    scopes,
-                }
-                .export(batch)
-            })
-            .await
+            let batches = futures_util::stream::unfold(rx, |mut rx| async move {
+                rx.recv().await.map(|queued: QueuedBatch| (queued, rx))
+            });
+            batches
+                .for_each_concurrent(num_concurrent_requests, move |(batch, result_tx)| {
+                    let context = ExporterContext {
+                        trace_client: trace_client.clone(),
+                        log_client: log_client.clone(),
+                        authorizer,
+                        pending_count: count_clone.clone(),
+                        scopes: scopes.clone(),
+                        retry,
+                    };
+                    async move {
+                        // nobody waits for the result anymore, e.g. the export timed out
+                        if let Err(Err(e)) = result_tx.send(context.export(batch).await) {
+                            handle_error(e);
+                        }
+                    }
+                })
+                .await
         };
 
         let exporter = StackDriverExporter {
@@ -238,32 +415,57 @@ impl Builder {
             pending_count,
             maximum_shutdown_duration: maximum_shutdown_duration
                 .unwrap_or_else(|| Duration::from_secs(5)),
+            drop_policy,
         };
 
         Ok((exporter, future))
     }
 }
 
+async fn connect(endpoint: &str, insecure: bool) -> Result<Channel, Error> {
+    let mut endpoint =
+        Channel::from_shared(endpoint.to_owned()).map_err(|e| Error::Transport(e.into()))?;
+    if !insecure {
+        endpoint = endpoint
+            .tls_config(ClientTlsConfig::new())
+            .map_err(|e| Error::Transport(e.into()))?;
+    }
+
+    endpoint
+        .connect()
+        .await
+        .map_err(|e| Error::Transport(e.into()))
+}
+
 struct ExporterContext<'a, A> {
     trace_client: TraceServiceClient<Channel>,
     log_client: Option<LogClient>,
     authorizer: &'a A,
     pending_count: Arc<AtomicUsize>,
     scopes: Arc<Vec<&'static str>>,
+    retry: RetryPolicy,
 }
 
 impl<A: Authorizer> ExporterContext<'_, A>
 where
     Error: From<A::Error>,
 {
-    async fn export(mut self, batch: Vec<SpanData>) {
+    async fn export(self, batch: Vec<SpanData>) -> ExportResult {
         use proto::devtools::cloudtrace::v2::span::time_event::Value;
 
         let mut entries = Vec::new();
         let mut spans = Vec::with_capacity(batch.len());
+        let children = children(&batch);
         for span in batch {
+            let span_children =
+                children.get(&(span.span_context.trace_id(), span.span_context.span_id()));
+            let same_process_as_parent_span = same_process_as_parent_span(&span);
+            let child_span_count = span_children.map(|children| children.len() as i32);
+            let links = transform_links(&span, span_children.map_or(&[][..], Vec::as_slice));
             let trace_id = hex::encode(span.span_context.trace_id().to_bytes());
             let span_id = hex::encode(span.span_context.span_id().to_bytes());
+            let stack_trace = exception_stack_trace(&span.events);
+            let dropped_annotations_count = span.events.dropped_count as i32;
             let time_event = match &self.log_client {
                 None => span
                     .events
@@ -272,7 +474,10 @@ where
                         time: Some(event.timestamp.into()),
                         value: Some(Value::Annotation(Annotation {
                             description: Some(to_truncate(event.name.into_owned())),
-                            ..Default::default()
+                            attributes: Some(to_attributes(
+                                event.attributes.into_iter().map(|kv| (kv.key, kv.value)),
+                                event.dropped_attributes_count,
+                            )),
                         })),
                     })
                     .collect(),
@@ -339,53 +544,144 @@ where
                 },
                 start_time: Some(span.start_time.into()),
                 end_time: Some(span.end_time.into()),
-                attributes: Some((span.attributes, span.resource.as_ref()).into()),
+                attributes: Some({
+                    let mut attributes: Attributes =
+                        (span.attributes, span.resource.as_ref()).into();
+                    attributes.dropped_attributes_count += span.dropped_attributes_count as i32;
+                    attributes
+                }),
+                stack_trace,
                 time_events: Some(TimeEvents {
                     time_event,
+                    dropped_annotations_count,
                     ..Default::default()
                 }),
-                links: transform_links(&span.links),
+                links,
                 status: status(span.status),
+                same_process_as_parent_span,
+                child_span_count,
                 span_kind: SpanKind::from(span.span_kind) as i32,
-                ..Default::default()
             });
         }
 
-        let mut req = Request::new(BatchWriteSpansRequest {
-            name: format!("projects/{}", self.authorizer.project_id()),
-            spans,
-        });
-
+        let authorizer = self.authorizer;
+        let scopes = &self.scopes[..];
         self.pending_count.fetch_sub(1, Ordering::Relaxed);
-        if let Err(e) = self.authorizer.authorize(&mut req, &self.scopes).await {
-            handle_error(TraceError::from(Error::Authorizer(e.into())));
-        } else if let Err(e) = self.trace_client.batch_write_spans(req).await {
-            handle_error(TraceError::from(Error::Transport(e.into())));
-        }
+        let trace_result = self
+            .retry
+            .send(
+                BatchWriteSpansRequest {
+                    name: format!("projects/{}", authorizer.project_id()),
+                    spans,
+                },
+                |mut req| {
+                    let mut client = self.trace_client.clone();
+                    async move {
+                        authorizer
+                            .authorize(&mut req, scopes)
+                            .await
+                            .map_err(|e| Error::Authorizer(e.into()))?;
+                        client
+                            .batch_write_spans(req)
+                            .await
+                            .map_err(|e| Error::Transport(e.into()))?;
+                        Ok(())
+                    }
+                },
+            )
+            .await;
 
-        let client = match &mut self.log_client {
+        let client = match &self.log_client {
             Some(client) => client,
-            None => return,
+            None => return trace_result.map_err(TraceError::from),
         };
 
-        let mut req = Request::new(WriteLogEntriesRequest {
-            log_name: format!(
-                "projects/{}/logs/{}",
-                self.authorizer.project_id(),
-                client.context.log_id,
-            ),
-            entries,
-            dry_run: false,
-            labels: HashMap::default(),
-            partial_success: true,
-            resource: None,
-        });
+        let log_result = self
+            .retry
+            .send(
+                WriteLogEntriesRequest {
+                    log_name: format!(
+                        "projects/{}/logs/{}",
+                        authorizer.project_id(),
+                        client.context.log_id,
+                    ),
+                    entries,
+                    dry_run: false,
+                    labels: HashMap::default(),
+                    partial_success: true,
+                    resource: None,
+                },
+                |mut req| {
+                    let mut client = client.client.clone();
+                    async move {
+                        authorizer
+                            .authorize(&mut req, scopes)
+                            .await
+                            .map_err(|e| Error::Authorizer(e.into()))?;
+                        client
+                            .write_log_entries(req)
+                            .await
+                            .map_err(|e| Error::Transport(e.into()))?;
+                        Ok(())
+                    }
+                },
+            )
+            .await;
+
+        trace_result.and(log_result).map_err(TraceError::from)
+    }
+}
+
+/// Retries requests failing with a retryable status, with an exponential backoff.
+#[derive(Clone, Copy, Debug)]
+struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
 
-        if let Err(e) = self.authorizer.authorize(&mut req, &self.scopes).await {
-            handle_error(TraceError::from(Error::from(e)));
-        } else if let Err(e) = client.client.write_log_entries(req).await {
-            handle_error(TraceError::from(Error::Transport(e.into())));
+impl RetryPolicy {
+    /// Send `message` with `send`, again as long as it fails with a retryable status and
+    /// retries are left.
+    ///
+    /// The message is only cloned for attempts which may be followed by another one, the last
+    /// attempt takes it.
+    async fn send<T, F, Fut>(&self, message: T, mut send: F) -> Result<(), Error>
+    where
+        T: Clone,
+        F: FnMut(Request<T>) -> Fut,
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        let mut attempt = 0;
+        while attempt < self.max_retries {
+            match send(Request::new(message.clone())).await {
+                Err(e) if is_retryable(&e) => {
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
         }
+        send(Request::new(message)).await
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+fn is_retryable(err: &Error) -> bool {
+    match err {
+        Error::Transport(e) => e.downcast_ref::<tonic::Status>().map_or(false, |status| {
+            matches!(
+                status.code(),
+                Code::Unavailable | Code::DeadlineExceeded | Code::ResourceExhausted
+            )
+        }),
+        _ => false,
     }
 }
 
@@ -533,7 +829,9 @@ impl From<Value> for AttributeValue {
             Value::F64(v) => attribute_value::Value::StringValue(to_truncate(v.to_string())),
             Value::I64(v) => attribute_value::Value::IntValue(v),
             Value::String(v) => attribute_value::Value::StringValue(to_truncate(v.to_string())),
-            Value::Array(_) => attribute_value::Value::StringValue(to_truncate(v.to_string())),
+            Value::Array(array) => {
+                attribute_value::Value::StringValue(to_truncate(array_to_json(&array)))
+            }
         };
         AttributeValue {
             value: Some(new_value),
@@ -541,6 +839,49 @@ impl From<Value> for AttributeValue {
     }
 }
 
+/// Cloud Trace has no array values, so arrays are sent as JSON.
+fn array_to_json(array: &Array) -> String {
+    fn write_values<T>(
+        out: &mut String,
+        values: &[T],
+        mut write_value: impl FnMut(&mut String, &T),
+    ) {
+        out.push('[');
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_value(out, value);
+        }
+        out.push(']');
+    }
+
+    let mut out = String::new();
+    match array {
+        Array::Bool(values) => {
+            write_values(&mut out, values, |out, v| out.push_str(&v.to_string()))
+        }
+        Array::I64(values) => write_values(&mut out, values, |out, v| out.push_str(&v.to_string())),
+        Array::F64(values) => write_values(&mut out, values, |out, v| out.push_str(&v.to_string())),
+        Array::String(values) => write_values(&mut out, values, |out, v| {
+            out.push('"');
+            for c in v.as_str().chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }),
+    }
+    out
+}
+
 fn to_truncate(s: String) -> TruncatableString {
     TruncatableString {
         value: s,
@@ -549,11 +890,14 @@ fn to_truncate(s: String) -> TruncatableString {
 }
 
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("authorizer error: {0}")]
     Authorizer(#[source] Box<dyn std::error::Error + Send + Sync>),
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("export queue is full")]
+    QueueFull,
     #[error("{0}")]
     Other(#[from] Box<dyn std::error::Error + Send + Sync>),
     #[error("tonic error: {0}")]
@@ -594,8 +938,17 @@ pub struct LogContext {
 
 impl From<LogContext> for InternalLogContext {
     fn from(cx: LogContext) -> Self {
+        Self {
+            log_id: cx.log_id,
+            resource: cx.resource.into(),
+        }
+    }
+}
+
+impl From<MonitoredResource> for proto::api::MonitoredResource {
+    fn from(resource: MonitoredResource) -> Self {
         let mut labels = HashMap::default();
-        let resource = match cx.resource {
+        match resource {
             MonitoredResource::CloudRunRevision {
                 project_id,
                 service_name,
@@ -677,11 +1030,72 @@ impl From<LogContext> for InternalLogContext {
                     labels,
                 }
             }
-        };
+            MonitoredResource::GceInstance {
+                project_id,
+                instance_id,
+                zone,
+            } => {
+                labels.insert("project_id".to_owned(), project_id);
+                if let Some(instance_id) = instance_id {
+                    labels.insert("instance_id".to_owned(), instance_id);
+                }
+                if let Some(zone) = zone {
+                    labels.insert("zone".to_owned(), zone);
+                }
 
-        Self {
-            log_id: cx.log_id,
-            resource,
+                proto::api::MonitoredResource {
+                    r#type: "gce_instance".to_owned(),
+                    labels,
+                }
+            }
+            MonitoredResource::K8sContainer {
+                project_id,
+                location,
+                cluster_name,
+                namespace_name,
+                pod_name,
+                container_name,
+            } => {
+                labels.insert("project_id".to_owned(), project_id);
+                if let Some(location) = location {
+                    labels.insert("location".to_owned(), location);
+                }
+                if let Some(cluster_name) = cluster_name {
+                    labels.insert("cluster_name".to_owned(), cluster_name);
+                }
+                if let Some(namespace_name) = namespace_name {
+                    labels.insert("namespace_name".to_owned(), namespace_name);
+                }
+                if let Some(pod_name) = pod_name {
+                    labels.insert("pod_name".to_owned(), pod_name);
+                }
+                if let Some(container_name) = container_name {
+                    labels.insert("container_name".to_owned(), container_name);
+                }
+
+                proto::api::MonitoredResource {
+                    r#type: "k8s_container".to_owned(),
+                    labels,
+                }
+            }
+            MonitoredResource::CloudFunction {
+                project_id,
+                region,
+                function_name,
+            } => {
+                labels.insert("project_id".to_owned(), project_id);
+                if let Some(region) = region {
+                    labels.insert("region".to_owned(), region);
+                }
+                if let Some(function_name) = function_name {
+                    labels.insert("function_name".to_owned(), function_name);
+                }
+
+                proto::api::MonitoredResource {
+                    r#type: "cloud_function".to_owned(),
+                    labels,
+                }
+            }
         }
     }
 }
@@ -690,7 +1104,9 @@ impl From<LogContext> for InternalLogContext {
 ///
 /// Possible values are listed in the [API documentation](https://cloud.google.com/logging/docs/api/v2/resource-list).
 /// Please submit an issue or pull request if you want to use a resource type not listed here.
-#[derive(Clone)]
+///
+/// [`detector::GcpDetector`] detects the resource of applications running on Google Cloud.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MonitoredResource {
     Global {
         project_id: String,
@@ -715,77 +1131,267 @@ pub enum MonitoredResource {
         location: Option<String>,
         configuration_name: Option<String>,
     },
+    GceInstance {
+        project_id: String,
+        instance_id: Option<String>,
+        zone: Option<String>,
+    },
+    K8sContainer {
+        project_id: String,
+        location: Option<String>,
+        cluster_name: Option<String>,
+        namespace_name: Option<String>,
+        pod_name: Option<String>,
+        container_name: Option<String>,
+    },
+    CloudFunction {
+        project_id: String,
+        region: Option<String>,
+        function_name: Option<String>,
+    },
 }
 
 impl From<(Vec<KeyValue>, &Resource)> for Attributes {
-    /// Combines `EvictedHashMap` and `Resource` attributes into a maximum of 32.
+    /// Combines span and `Resource` attributes into a maximum of 32.
     ///
-    /// The `Resource` takes precedence over the `EvictedHashMap` attributes.
+    /// When there are more, attributes mapped to Cloud Trace labels are kept first, then other
+    /// semantic convention attributes, then the remaining ones. Within each group, `Resource`
+    /// attributes sorted by key come before span attributes in the order they were recorded.
     fn from((attributes, resource): (Vec<KeyValue>, &Resource)) -> Self {
-        let mut dropped_attributes_count: i32 = 0;
-        let num_resource_attributes = resource.len();
-        let num_attributes = attributes.len();
-
-        let attributes_as_key_value_tuples: Vec<(Key, Value)> = attributes
-            .into_iter()
-            .map(|kv| (kv.key, kv.value))
-            .collect();
-
-        let attribute_map = resource
-            .into_iter()
+        let mut resource_attributes = resource
+            .iter()
             .map(|(k, v)| (k.clone(), v.clone()))
-            .chain(attributes_as_key_value_tuples)
-            .flat_map(|(k, v)| {
-                let key = k.as_str();
-                if key.len() > 128 {
-                    dropped_attributes_count += 1;
-                    return None;
-                }
+            .collect::<Vec<_>>();
+        resource_attributes.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+        to_attributes(
+            resource_attributes
+                .into_iter()
+                .chain(attributes.into_iter().map(|kv| (kv.key, kv.value))),
+            0,
+        )
+    }
+}
 
-                if k.as_str() == semconv::resource::SERVICE_NAME {
-                    return Some((GCP_SERVICE_NAME.to_owned(), v.into()));
-                } else if key == HTTP_PATH {
-                    return Some((GCP_HTTP_PATH.to_owned(), v.into()));
-                }
+/// Convert attributes, keeping at most 32 of them as described on the `From` implementation for
+/// `Attributes`, with `dropped_attributes_count` already dropped by the SDK.
+fn to_attributes(
+    attributes: impl Iterator<Item = (Key, Value)>,
+    dropped_attributes_count: u32,
+) -> Attributes {
+    let mut dropped_attributes_count = dropped_attributes_count as i32;
+    let mut attributes = attributes
+        .filter_map(|(k, v)| {
+            let key = k.as_str();
+            if key.len() > 128 {
+                dropped_attributes_count += 1;
+                return None;
+            }
 
-                for (otel_key, gcp_key) in KEY_MAP {
-                    if otel_key == k.as_str() {
-                        return Some((gcp_key.to_owned(), v.into()));
-                    }
-                }
+            let (priority, key) = match gcp_key(key) {
+                Some(gcp_key) => (0, gcp_key.to_owned()),
+                None if is_semantic_convention(key) => (1, key.to_owned()),
+                None => (2, key.to_owned()),
+            };
+            Some((priority, key, AttributeValue::from(v)))
+        })
+        .collect::<Vec<_>>();
 
-                Some((key.to_owned(), v.into()))
-            })
+    // the sort is stable, keeping the order within each priority
+    attributes.sort_by_key(|(priority, _, _)| *priority);
+    dropped_attributes_count += attributes.len().saturating_sub(MAX_ATTRIBUTES_PER_SPAN) as i32;
+
+    Attributes {
+        attribute_map: attributes
+            .into_iter()
             .take(MAX_ATTRIBUTES_PER_SPAN)
-            .collect();
+            .map(|(_, key, value)| (key, value))
+            .collect(),
+        dropped_attributes_count,
+    }
+}
 
-        Attributes {
-            attribute_map,
-            dropped_attributes_count: dropped_attributes_count
-                + (num_resource_attributes + num_attributes).saturating_sub(MAX_ATTRIBUTES_PER_SPAN)
-                    as i32,
-        }
+fn gcp_key(key: &str) -> Option<&'static str> {
+    if key == semconv::resource::SERVICE_NAME {
+        return Some(GCP_SERVICE_NAME);
     }
+
+    KEY_MAP
+        .iter()
+        .find(|(otel_key, _)| *otel_key == key)
+        .map(|(_, gcp_key)| *gcp_key)
+}
+
+fn is_semantic_convention(key: &str) -> bool {
+    SEMCONV_NAMESPACES
+        .iter()
+        .any(|namespace| key.starts_with(namespace))
 }
 
-fn transform_links(links: &opentelemetry_sdk::trace::SpanLinks) -> Option<Links> {
-    if links.is_empty() {
+/// Links of the span, typed after the relationship of the linked span with the span: its parent,
+/// one of its `children` in the batch, or unknown.
+fn transform_links(span: &SpanData, children: &[SpanId]) -> Option<Links> {
+    if span.links.is_empty() {
         return None;
     }
 
+    let trace_id = span.span_context.trace_id();
     Some(Links {
-        dropped_links_count: links.dropped_count as i32,
-        link: links
+        dropped_links_count: span.links.dropped_count as i32,
+        link: span
+            .links
             .iter()
-            .map(|link| Link {
-                trace_id: hex::encode(link.span_context.trace_id().to_bytes()),
-                span_id: hex::encode(link.span_context.span_id().to_bytes()),
-                ..Default::default()
+            .map(|link| {
+                let linked = &link.span_context;
+                let r#type = if linked.trace_id() != trace_id {
+                    link::Type::Unspecified
+                } else if linked.span_id() == span.parent_span_id {
+                    link::Type::ParentLinkedSpan
+                } else if children.contains(&linked.span_id()) {
+                    link::Type::ChildLinkedSpan
+                } else {
+                    link::Type::Unspecified
+                };
+                Link {
+                    trace_id: hex::encode(linked.trace_id().to_bytes()),
+                    span_id: hex::encode(linked.span_id().to_bytes()),
+                    r#type: r#type as i32,
+                    attributes: Some(to_attributes(
+                        link.attributes
+                            .iter()
+                            .map(|kv| (kv.key.clone(), kv.value.clone())),
+                        link.dropped_attributes_count,
+                    )),
+                }
             })
             .collect(),
     })
 }
 
+/// The children of the spans of the batch exported along with them.
+fn children(batch: &[SpanData]) -> HashMap<(TraceId, SpanId), Vec<SpanId>> {
+    let mut children = HashMap::<_, Vec<_>>::new();
+    for span in batch {
+        if span.parent_span_id != SpanId::INVALID {
+            children
+                .entry((span.span_context.trace_id(), span.parent_span_id))
+                .or_default()
+                .push(span.span_context.span_id());
+        }
+    }
+    children
+}
+
+/// The parent of server and consumer spans usually runs in the client or producer process,
+/// while other spans are started within their parent.
+fn same_process_as_parent_span(span: &SpanData) -> Option<bool> {
+    if span.parent_span_id == SpanId::INVALID {
+        return None;
+    }
+
+    Some(!matches!(
+        span.span_kind,
+        opentelemetry::trace::SpanKind::Server | opentelemetry::trace::SpanKind::Consumer
+    ))
+}
+
+/// Parse the `exception.stacktrace` of the last `exception` event.
+fn exception_stack_trace(events: &opentelemetry_sdk::trace::SpanEvents) -> Option<StackTrace> {
+    let stacktrace = events
+        .iter()
+        .rev()
+        .filter(|event| event.name == "exception")
+        .find_map(|event| {
+            event
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == semconv::trace::EXCEPTION_STACKTRACE)
+        })?
+        .value
+        .as_str();
+
+    let frames = parse_stack_frames(&stacktrace);
+    if frames.is_empty() {
+        return None;
+    }
+
+    Some(StackTrace {
+        stack_frames: Some(StackFrames {
+            dropped_frames_count: frames.len().saturating_sub(MAX_STACK_FRAMES) as i32,
+            frame: frames.into_iter().take(MAX_STACK_FRAMES).collect(),
+        }),
+        stack_trace_hash_id: 0,
+    })
+}
+
+/// Parse the frames of Rust backtraces, where locations follow numbered function names as
+/// `at file:line:column`, and of traces listing a frame per line, such as Java ones.
+fn parse_stack_frames(stacktrace: &str) -> Vec<StackFrame> {
+    let mut frames = Vec::new();
+    // whether the last frame is a numbered one waiting for its location
+    let mut pending_location = false;
+    for line in stacktrace.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(rest) = line.strip_prefix("at ") {
+            if pending_location {
+                if let Some(frame) = frames.last_mut() {
+                    set_location(frame, rest);
+                }
+                pending_location = false;
+                continue;
+            }
+
+            // e.g. `at com.example.Main.run(Main.java:10)`
+            let mut frame = StackFrame::default();
+            let function = match rest.split_once('(') {
+                Some((function, location)) => {
+                    set_location(&mut frame, location.trim_end_matches(')'));
+                    function
+                }
+                None => rest,
+            };
+            frame.function_name = Some(to_truncate(function.to_owned()));
+            frames.push(frame);
+            continue;
+        }
+
+        let (function, numbered) = match line.split_once(": ") {
+            Some((index, function)) if index.chars().all(|c| c.is_ascii_digit()) => {
+                (function, true)
+            }
+            _ => (line, false),
+        };
+        frames.push(StackFrame {
+            function_name: Some(to_truncate(function.to_owned())),
+            ..Default::default()
+        });
+        pending_location = numbered;
+    }
+
+    frames
+}
+
+/// Set the location of `frame` from `file:line:column` or `file:line`.
+fn set_location(frame: &mut StackFrame, location: &str) {
+    let mut file = location;
+    let mut numbers = Vec::with_capacity(2);
+    while numbers.len() < 2 {
+        match file
+            .rsplit_once(':')
+            .map(|(rest, n)| (rest, n.parse::<i64>()))
+        {
+            Some((rest, Ok(n))) => {
+                numbers.insert(0, n);
+                file = rest;
+            }
+            _ => break,
+        }
+    }
+
+    frame.file_name = Some(to_truncate(file.to_owned()));
+    frame.line_number = numbers.first().copied().unwrap_or_default();
+    frame.column_number = numbers.get(1).copied().unwrap_or_default();
+}
+
 // Map conventional OpenTelemetry keys to their GCP counterparts.
 const KEY_MAP: [(&str, &str); 8] = [
     (HTTP_HOST, "/http/host"),
@@ -825,6 +1431,38 @@ fn status(value: opentelemetry::trace::Status) -> Option<Status> {
         }),
     }
 }
+// Namespaces of the semantic convention attributes, kept before others when truncating.
+const SEMCONV_NAMESPACES: [&str; 25] = [
+    "client.",
+    "cloud.",
+    "code.",
+    "container.",
+    "db.",
+    "deployment.",
+    "enduser.",
+    "exception.",
+    "faas.",
+    "host.",
+    "http.",
+    "k8s.",
+    "messaging.",
+    "net.",
+    "network.",
+    "os.",
+    "peer.",
+    "process.",
+    "rpc.",
+    "server.",
+    "service.",
+    "telemetry.",
+    "thread.",
+    "url.",
+    "user_agent.",
+];
+/// Cloud Trace keeps at most 128 frames per stack trace.
+const MAX_STACK_FRAMES: usize = 128;
+const TRACE_ENDPOINT: &str = "https://cloudtrace.googleapis.com:443";
+const LOG_ENDPOINT: &str = "https://logging.googleapis.com:443";
 const TRACE_APPEND: &str = "https://www.googleapis.com/auth/trace.append";
 const LOGGING_WRITE: &str = "https://www.googleapis.com/auth/logging.write";
 const GCP_SERVICE_NAME: &str = "g.co/gae/app/module";
@@ -833,8 +1471,11 @@ const MAX_ATTRIBUTES_PER_SPAN: usize = 32;
 #[cfg(test)]
 mod tests {
     use super::*;
-    use opentelemetry::{KeyValue, Value};
+    use opentelemetry::trace::{SpanContext, TraceFlags, TraceId, TraceState};
+    use opentelemetry::{KeyValue, StringValue, Value};
     use opentelemetry_semantic_conventions as semcov;
+    use std::borrow::Cow;
+    use std::time::SystemTime;
 
     #[test]
     fn test_attributes_mapping() {
@@ -994,4 +1635,289 @@ mod tests {
         assert_eq!(actual.attribute_map.len(), 1);
         assert_eq!(actual.dropped_attributes_count, 1);
     }
+
+    #[test]
+    fn test_truncation_keeps_semantic_conventions_first() {
+        let resources = Resource::new([
+            KeyValue::new("b.resource", "b"),
+            KeyValue::new(semcov::resource::SERVICE_NAME, "Test Service Name"),
+            KeyValue::new("a.resource", "a"),
+        ]);
+        let mut attributes = (0..40)
+            .map(|i| KeyValue::new(format!("key{}", i), i))
+            .collect::<Vec<_>>();
+        attributes.push(KeyValue::new(semcov::trace::HTTP_METHOD, "GET"));
+        attributes.push(KeyValue::new(semcov::trace::DB_SYSTEM, "postgresql"));
+
+        let actual: Attributes = (attributes, &resources).into();
+
+        assert_eq!(actual.attribute_map.len(), 32);
+        assert_eq!(actual.dropped_attributes_count, 13);
+        for key in [
+            "/http/method",
+            "g.co/gae/app/module",
+            "db.system",
+            "a.resource",
+            "b.resource",
+        ] {
+            assert!(actual.attribute_map.contains_key(key), "{key} is missing");
+        }
+        // then span attributes in the order they were recorded
+        assert!(actual.attribute_map.contains_key("key26"));
+        assert!(!actual.attribute_map.contains_key("key27"));
+    }
+
+    #[test]
+    fn test_array_attribute_values() {
+        use proto::devtools::cloudtrace::v2::attribute_value;
+
+        let string_value = |value: Value| match AttributeValue::from(value).value {
+            Some(attribute_value::Value::StringValue(s)) => s.value,
+            value => panic!("unexpected value {:?}", value),
+        };
+
+        assert_eq!(
+            string_value(Value::Array(
+                vec![StringValue::from("a \"quoted\" value"), "b\\c".into()].into()
+            )),
+            r#"["a \"quoted\" value","b\\c"]"#
+        );
+        assert_eq!(string_value(Value::Array(vec![1, 2].into())), "[1,2]");
+        assert_eq!(
+            string_value(Value::Array(vec![true, false].into())),
+            "[true,false]"
+        );
+    }
+
+    fn span(
+        span_id: u64,
+        parent_span_id: u64,
+        span_kind: opentelemetry::trace::SpanKind,
+    ) -> SpanData {
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from_u128(1),
+                SpanId::from_u64(span_id),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::default(),
+            ),
+            parent_span_id: SpanId::from_u64(parent_span_id),
+            span_kind,
+            name: "span".into(),
+            start_time: SystemTime::now(),
+            end_time: SystemTime::now(),
+            attributes: Vec::new(),
+            dropped_attributes_count: 0,
+            events: Default::default(),
+            links: Default::default(),
+            status: opentelemetry::trace::Status::Unset,
+            resource: Cow::Owned(Resource::empty()),
+            instrumentation_lib: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_links() {
+        let link = |trace_id: u128, span_id: u64| {
+            opentelemetry::trace::Link::new(
+                SpanContext::new(
+                    TraceId::from_u128(trace_id),
+                    SpanId::from_u64(span_id),
+                    TraceFlags::SAMPLED,
+                    true,
+                    TraceState::default(),
+                ),
+                vec![KeyValue::new("messaging.operation", "publish")],
+            )
+        };
+        let mut span = span(2, 1, opentelemetry::trace::SpanKind::Consumer);
+        span.links.dropped_count = 1;
+        span.links.links = vec![link(1, 1), link(1, 3), link(2, 3), link(1, 4)];
+
+        let links = transform_links(&span, &[SpanId::from_u64(3)]).unwrap();
+
+        assert_eq!(links.dropped_links_count, 1);
+        assert_eq!(
+            links.link[0],
+            Link {
+                trace_id: "00000000000000000000000000000001".to_owned(),
+                span_id: "0000000000000001".to_owned(),
+                r#type: link::Type::ParentLinkedSpan as i32,
+                attributes: Some(Attributes {
+                    attribute_map: HashMap::from([(
+                        "messaging.operation".to_owned(),
+                        AttributeValue::from(Value::from("publish")),
+                    )]),
+                    dropped_attributes_count: 0,
+                }),
+            }
+        );
+        // children are only known in the same trace, other links are left unspecified
+        assert_eq!(
+            links
+                .link
+                .iter()
+                .map(|link| link.r#type)
+                .collect::<Vec<_>>(),
+            vec![
+                link::Type::ParentLinkedSpan as i32,
+                link::Type::ChildLinkedSpan as i32,
+                link::Type::Unspecified as i32,
+                link::Type::Unspecified as i32,
+            ]
+        );
+    }
+
+    /// Counts its clones.
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Clone for Counted {
+        fn clone(&self) -> Self {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Counted(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_clones_only_when_retrying() {
+        let retry = |max_retries| RetryPolicy {
+            max_retries,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+        let send = |failures: usize, attempts: Arc<AtomicUsize>| {
+            move |_: Request<Counted>| {
+                let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    match attempt < failures {
+                        true => Err(Error::Transport(
+                            tonic::Status::unavailable("unavailable").into(),
+                        )),
+                        false => Ok(()),
+                    }
+                }
+            }
+        };
+
+        for (max_retries, failures, attempts, clones) in [
+            // without retries, the message is never cloned
+            (0, 0, 1, 0),
+            (0, 1, 1, 0),
+            // a clone per attempt which could be retried
+            (2, 0, 1, 1),
+            (2, 1, 2, 2),
+            // the last attempt takes the message
+            (2, 5, 3, 2),
+        ] {
+            let clones_count = Arc::new(AtomicUsize::new(0));
+            let attempts_count = Arc::new(AtomicUsize::new(0));
+            let result = retry(max_retries)
+                .send(
+                    Counted(clones_count.clone()),
+                    send(failures, attempts_count.clone()),
+                )
+                .await;
+            assert_eq!(result.is_ok(), failures < attempts);
+            assert_eq!(attempts_count.load(Ordering::Relaxed), attempts);
+            assert_eq!(clones_count.load(Ordering::Relaxed), clones);
+        }
+    }
+
+    #[test]
+    fn test_children_and_parent_process() {
+        use opentelemetry::trace::SpanKind as Kind;
+
+        let batch = [
+            span(3, 2, Kind::Client),
+            span(4, 2, Kind::Internal),
+            span(2, 1, Kind::Server),
+            span(1, 0, Kind::Server),
+        ];
+        let children = children(&batch);
+        let child_count = |span_id| {
+            children
+                .get(&(TraceId::from_u128(1), SpanId::from_u64(span_id)))
+                .map(Vec::len)
+        };
+        assert_eq!(child_count(2), Some(2));
+        assert_eq!(child_count(1), Some(1));
+        assert_eq!(child_count(3), None);
+
+        let same_process = batch
+            .iter()
+            .map(same_process_as_parent_span)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            same_process,
+            vec![Some(true), Some(true), Some(false), None]
+        );
+    }
+
+    #[test]
+    fn test_rust_backtrace() {
+        let frames = parse_stack_frames(
+            "   0: my_crate::handler
+             at ./src/handler.rs:42:9
+   1: my_crate::main
+             at ./src/main.rs:10:5
+   2: std::rt::lang_start",
+        );
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames[0],
+            StackFrame {
+                function_name: Some(to_truncate("my_crate::handler".to_owned())),
+                file_name: Some(to_truncate("./src/handler.rs".to_owned())),
+                line_number: 42,
+                column_number: 9,
+                ..Default::default()
+            }
+        );
+        assert_eq!(frames[1].line_number, 10);
+        assert_eq!(frames[2].file_name, None);
+    }
+
+    #[test]
+    fn test_java_stack_trace() {
+        let frames = parse_stack_frames(
+            "java.lang.IllegalStateException: boom
+\tat com.example.Handler.handle(Handler.java:42)
+\tat com.example.Main.main(Main.java:10)",
+        );
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames[1],
+            StackFrame {
+                function_name: Some(to_truncate("com.example.Handler.handle".to_owned())),
+                file_name: Some(to_truncate("Handler.java".to_owned())),
+                line_number: 42,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_exception_stack_trace() {
+        let mut events = opentelemetry_sdk::trace::SpanEvents::default();
+        events.events.push(opentelemetry::trace::Event::new(
+            "exception",
+            SystemTime::now(),
+            vec![KeyValue::new(
+                semcov::trace::EXCEPTION_STACKTRACE,
+                "   0: my_crate::main\n             at ./src/main.rs:10:5",
+            )],
+            0,
+        ));
+
+        let stack_trace = exception_stack_trace(&events).unwrap();
+        let frames = stack_trace.stack_frames.unwrap();
+        assert_eq!(frames.frame.len(), 1);
+        assert_eq!(frames.dropped_frames_count, 0);
+
+        events.events.clear();
+        assert_eq!(exception_stack_trace(&events), None);
+    }
 }
@@ -0,0 +1,470 @@
+//! Export log records of the OpenTelemetry logs SDK to
+//! [Google Cloud Logging](https://cloud.google.com/logging).
+//!
+//! Each record becomes a `LogEntry` of the configured [`LogContext`], correlated with the trace
+//! and span it was emitted in, so logs bridged by appenders such as
+//! `opentelemetry-appender-tracing` show up next to the exported traces.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use async_trait::async_trait;
+use opentelemetry::logs::{AnyValue, LogRecord, LogResult, Severity};
+use opentelemetry::Key;
+use opentelemetry_sdk::export::logs::{LogData, LogExporter};
+use opentelemetry_semantic_conventions as semconv;
+use tonic::transport::Channel;
+use tonic::Request;
+
+use crate::proto::logging::r#type::LogSeverity;
+use crate::proto::logging::v2::{
+    log_entry::Payload, logging_service_v2_client::LoggingServiceV2Client, LogEntry,
+    LogEntrySourceLocation, WriteLogEntriesRequest,
+};
+use crate::{
+    connect, Authorizer, Error, InternalLogContext, LogContext, LOGGING_WRITE, LOG_ENDPOINT,
+};
+
+/// Exports log records to Google Cloud Logging.
+///
+/// Records are mapped to `LogEntry`s as follows:
+///
+/// - the severity number, or the severity text if there is none, sets the entry severity;
+/// - string bodies are sent as `textPayload`, map bodies as `jsonPayload`, and other bodies as
+///   the `message` field of a `jsonPayload`;
+/// - the `code.filepath`, `code.lineno` and `code.function` (or `code.namespace`) attributes
+///   set the source location, and the other attributes are added as labels;
+/// - the trace context sets the `trace`, `spanId` and `traceSampled` fields.
+///
+/// ```no_run
+/// use opentelemetry_sdk::logs::LoggerProvider;
+/// use opentelemetry_sdk::runtime;
+/// use opentelemetry_stackdriver::logs::CloudLoggingExporter;
+/// use opentelemetry_stackdriver::{GcpAuthorizer, LogContext, MonitoredResource};
+///
+/// # async fn run() -> Result<(), opentelemetry_stackdriver::Error> {
+/// let authorizer = GcpAuthorizer::new().await?;
+/// let log_context = LogContext {
+///     log_id: "my-service".to_owned(),
+///     resource: MonitoredResource::Global {
+///         project_id: "my-project".to_owned(),
+///     },
+/// };
+/// let exporter = CloudLoggingExporter::builder(log_context)
+///     .build(authorizer)
+///     .await?;
+/// let provider = LoggerProvider::builder()
+///     .with_batch_exporter(exporter, runtime::Tokio)
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+pub struct CloudLoggingExporter<A> {
+    client: LoggingServiceV2Client<Channel>,
+    authorizer: A,
+    context: InternalLogContext,
+}
+
+impl CloudLoggingExporter<()> {
+    pub fn builder(log_context: LogContext) -> CloudLoggingExporterBuilder {
+        CloudLoggingExporterBuilder {
+            log_context,
+            endpoint: None,
+            insecure: false,
+            channel: None,
+        }
+    }
+}
+
+impl<A> fmt::Debug for CloudLoggingExporter<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CloudLoggingExporter")
+            .field("log_id", &self.context.log_id)
+            .field("resource", &self.context.resource)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl<A: Authorizer> LogExporter for CloudLoggingExporter<A> {
+    async fn export(&mut self, batch: Vec<LogData>) -> LogResult<()> {
+        let project_id = self.authorizer.project_id();
+        let log_name = format!("projects/{project_id}/logs/{}", self.context.log_id);
+        let entries = batch
+            .into_iter()
+            .map(|data| log_entry(data.record, project_id))
+            .collect();
+
+        let mut req = Request::new(WriteLogEntriesRequest {
+            log_name,
+            resource: Some(self.context.resource.clone()),
+            labels: HashMap::default(),
+            entries,
+            partial_success: true,
+            dry_run: false,
+        });
+
+        self.authorizer
+            .authorize(&mut req, &[LOGGING_WRITE])
+            .await
+            .map_err(|e| Error::Authorizer(e.into()))?;
+        self.client
+            .write_log_entries(req)
+            .await
+            .map_err(|e| Error::Transport(e.into()))?;
+        Ok(())
+    }
+}
+
+/// Helper type to build a `CloudLoggingExporter`.
+#[derive(Clone)]
+pub struct CloudLoggingExporterBuilder {
+    log_context: LogContext,
+    endpoint: Option<String>,
+    insecure: bool,
+    channel: Option<Channel>,
+}
+
+impl CloudLoggingExporterBuilder {
+    /// Set the endpoint of the Cloud Logging API.
+    ///
+    /// If not set, defaults to `https://logging.googleapis.com:443`.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Connect to the endpoint without TLS, e.g. to reach a local emulator.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Send log entries over the given `channel` instead of connecting to the endpoint.
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub async fn build<A: Authorizer>(
+        self,
+        authorizer: A,
+    ) -> Result<CloudLoggingExporter<A>, Error> {
+        let channel = match self.channel {
+            Some(channel) => channel,
+            None => {
+                connect(
+                    self.endpoint.as_deref().unwrap_or(LOG_ENDPOINT),
+                    self.insecure,
+                )
+                .await?
+            }
+        };
+
+        Ok(CloudLoggingExporter {
+            client: LoggingServiceV2Client::new(channel),
+            authorizer,
+            context: self.log_context.into(),
+        })
+    }
+}
+
+fn log_entry(record: LogRecord, project_id: &str) -> LogEntry {
+    let mut labels = HashMap::default();
+    let mut source_location = LogEntrySourceLocation::default();
+    let mut namespace = None;
+    for (key, value) in record.attributes.into_iter().flatten() {
+        match (key.as_str(), value) {
+            (semconv::trace::CODE_FILEPATH, AnyValue::String(file)) => {
+                source_location.file = file.into()
+            }
+            (semconv::trace::CODE_LINENO, AnyValue::Int(line)) => source_location.line = line,
+            (semconv::trace::CODE_FUNCTION, AnyValue::String(function)) => {
+                source_location.function = function.into()
+            }
+            (semconv::trace::CODE_NAMESPACE, AnyValue::String(ns)) => namespace = Some(ns),
+            (_, value) => {
+                labels.insert(key.to_string(), label_value(&value));
+            }
+        }
+    }
+    if source_location.function.is_empty() {
+        if let Some(namespace) = namespace {
+            source_location.function = namespace.into();
+        }
+    }
+
+    let (trace, span_id, trace_sampled) = match record.trace_context {
+        Some(cx) => (
+            format!(
+                "projects/{project_id}/traces/{}",
+                hex::encode(cx.trace_id.to_bytes())
+            ),
+            hex::encode(cx.span_id.to_bytes()),
+            cx.trace_flags.map_or(false, |flags| flags.is_sampled()),
+        ),
+        None => (String::new(), String::new(), false),
+    };
+
+    let severity = match (record.severity_number, record.severity_text) {
+        (Some(number), _) => severity(number),
+        (None, Some(text)) => severity_from_text(&text),
+        (None, None) => LogSeverity::Default,
+    };
+
+    LogEntry {
+        timestamp: Some(record.timestamp.unwrap_or(record.observed_timestamp).into()),
+        severity: severity as i32,
+        labels,
+        trace,
+        span_id,
+        trace_sampled,
+        source_location: (source_location != LogEntrySourceLocation::default())
+            .then_some(source_location),
+        payload: record.body.map(payload),
+        ..Default::default()
+    }
+}
+
+/// As defined in https://opentelemetry.io/docs/specs/otel/logs/data-model-appendix/#appendix-b-severitynumber-example-mappings.
+fn severity(severity: Severity) -> LogSeverity {
+    match severity as i32 {
+        1..=8 => LogSeverity::Debug,
+        9..=12 => LogSeverity::Info,
+        13..=16 => LogSeverity::Warning,
+        17..=20 => LogSeverity::Error,
+        21..=24 => LogSeverity::Critical,
+        _ => LogSeverity::Default,
+    }
+}
+
+fn severity_from_text(text: &str) -> LogSeverity {
+    match text.to_ascii_uppercase().as_str() {
+        "TRACE" | "DEBUG" => LogSeverity::Debug,
+        "INFO" => LogSeverity::Info,
+        "NOTICE" => LogSeverity::Notice,
+        "WARN" | "WARNING" => LogSeverity::Warning,
+        "ERROR" => LogSeverity::Error,
+        "CRITICAL" | "FATAL" => LogSeverity::Critical,
+        "ALERT" => LogSeverity::Alert,
+        "EMERGENCY" => LogSeverity::Emergency,
+        _ => LogSeverity::Default,
+    }
+}
+
+fn payload(body: AnyValue) -> Payload {
+    match body {
+        AnyValue::String(text) => Payload::TextPayload(text.into()),
+        AnyValue::Map(map) => Payload::JsonPayload(to_struct(map)),
+        other => Payload::JsonPayload(prost_types::Struct {
+            fields: [("message".to_owned(), to_value(other))].into(),
+        }),
+    }
+}
+
+fn to_struct(map: HashMap<Key, AnyValue>) -> prost_types::Struct {
+    prost_types::Struct {
+        fields: map
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), to_value(value)))
+            .collect(),
+    }
+}
+
+fn to_value(value: AnyValue) -> prost_types::Value {
+    use prost_types::value::Kind;
+
+    let kind = match value {
+        AnyValue::Int(i) => Kind::NumberValue(i as f64),
+        AnyValue::Double(f) => Kind::NumberValue(f),
+        AnyValue::String(s) => Kind::StringValue(s.into()),
+        AnyValue::Boolean(b) => Kind::BoolValue(b),
+        AnyValue::Bytes(bytes) => Kind::StringValue(hex::encode(bytes)),
+        AnyValue::ListAny(values) => Kind::ListValue(prost_types::ListValue {
+            values: values.into_iter().map(to_value).collect(),
+        }),
+        AnyValue::Map(map) => Kind::StructValue(to_struct(map)),
+    };
+
+    prost_types::Value { kind: Some(kind) }
+}
+
+/// Labels are plain strings: lists and maps are rendered as JSON, with map keys sorted.
+fn label_value(value: &AnyValue) -> String {
+    match value {
+        AnyValue::String(s) => s.as_str().to_owned(),
+        other => {
+            let mut out = String::new();
+            write_json(&mut out, other);
+            out
+        }
+    }
+}
+
+fn write_json(out: &mut String, value: &AnyValue) {
+    match value {
+        AnyValue::Int(i) => write!(out, "{i}").unwrap(),
+        AnyValue::Double(f) => write!(out, "{f}").unwrap(),
+        AnyValue::String(s) => write!(out, "{:?}", s.as_str()).unwrap(),
+        AnyValue::Boolean(b) => write!(out, "{b}").unwrap(),
+        AnyValue::Bytes(bytes) => write!(out, "{:?}", hex::encode(bytes)).unwrap(),
+        AnyValue::ListAny(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_json(out, value);
+            }
+            out.push(']');
+        }
+        AnyValue::Map(map) => {
+            let mut entries = map.iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write!(out, "{:?}:", key.as_str()).unwrap();
+                write_json(out, value);
+            }
+            out.push('}');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use opentelemetry::trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState};
+
+    use super::*;
+
+    #[test]
+    fn test_log_entry_mapping() {
+        let timestamp = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let span_context = SpanContext::new(
+            TraceId::from_u128(0x0102030405060708090a0b0c0d0e0f10),
+            SpanId::from_u64(0x0102030405060708),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        );
+        let record = LogRecord::builder()
+            .with_timestamp(timestamp)
+            .with_severity_number(Severity::Warn2)
+            .with_severity_text("INFO")
+            .with_body("disk almost full")
+            .with_span_context(&span_context)
+            .with_attribute(semconv::trace::CODE_FILEPATH, "src/main.rs")
+            .with_attribute(semconv::trace::CODE_LINENO, 42)
+            .with_attribute(semconv::trace::CODE_NAMESPACE, "my_crate::disk")
+            .with_attribute("disk.free", 0.05)
+            .with_attribute("disk.name", "sda")
+            .build();
+
+        let entry = log_entry(record, "test-project");
+
+        assert_eq!(entry.timestamp, Some(timestamp.into()));
+        assert_eq!(entry.severity, LogSeverity::Warning as i32);
+        assert_eq!(
+            entry.payload,
+            Some(Payload::TextPayload("disk almost full".to_owned()))
+        );
+        assert_eq!(
+            entry.trace,
+            "projects/test-project/traces/0102030405060708090a0b0c0d0e0f10"
+        );
+        assert_eq!(entry.span_id, "0102030405060708");
+        assert!(entry.trace_sampled);
+        assert_eq!(
+            entry.source_location,
+            Some(LogEntrySourceLocation {
+                file: "src/main.rs".to_owned(),
+                line: 42,
+                function: "my_crate::disk".to_owned(),
+            })
+        );
+        assert_eq!(
+            entry.labels,
+            HashMap::from([
+                ("disk.free".to_owned(), "0.05".to_owned()),
+                ("disk.name".to_owned(), "sda".to_owned()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_untraced_log_entry() {
+        let observed = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let record = LogRecord::builder()
+            .with_observed_timestamp(observed)
+            .with_severity_text("error")
+            .build();
+
+        let entry = log_entry(record, "test-project");
+
+        assert_eq!(entry.timestamp, Some(observed.into()));
+        assert_eq!(entry.severity, LogSeverity::Error as i32);
+        assert_eq!(entry.trace, "");
+        assert_eq!(entry.span_id, "");
+        assert_eq!(entry.source_location, None);
+        assert_eq!(entry.payload, None);
+    }
+
+    #[test]
+    fn test_json_payload() {
+        use prost_types::value::Kind;
+
+        let body = AnyValue::from_iter([
+            ("message", AnyValue::from("request served")),
+            ("status", AnyValue::from(200)),
+            ("tags", AnyValue::from_iter(["a", "b"])),
+        ]);
+        let record = LogRecord::builder().with_body(body).build();
+
+        let fields = match log_entry(record, "test-project").payload {
+            Some(Payload::JsonPayload(payload)) => payload.fields,
+            payload => panic!("unexpected payload {:?}", payload),
+        };
+        assert_eq!(
+            fields["message"].kind,
+            Some(Kind::StringValue("request served".to_owned()))
+        );
+        assert_eq!(fields["status"].kind, Some(Kind::NumberValue(200.0)));
+        assert_eq!(
+            fields["tags"].kind,
+            Some(Kind::ListValue(prost_types::ListValue {
+                values: vec![
+                    prost_types::Value {
+                        kind: Some(Kind::StringValue("a".to_owned())),
+                    },
+                    prost_types::Value {
+                        kind: Some(Kind::StringValue("b".to_owned())),
+                    },
+                ],
+            }))
+        );
+
+        // other bodies are wrapped as the message of a JSON payload
+        let record = LogRecord::builder().with_body(true).build();
+        let payload = match log_entry(record, "test-project").payload {
+            Some(Payload::JsonPayload(payload)) => payload,
+            payload => panic!("unexpected payload {:?}", payload),
+        };
+        assert_eq!(payload.fields["message"].kind, Some(Kind::BoolValue(true)));
+    }
+
+    #[test]
+    fn test_label_value() {
+        let map = AnyValue::from_iter([("b", AnyValue::from(1)), ("a", AnyValue::from("x"))]);
+        assert_eq!(label_value(&map), r#"{"a":"x","b":1}"#);
+        assert_eq!(
+            label_value(&AnyValue::from_iter([true, false])),
+            "[true,false]"
+        );
+        assert_eq!(label_value(&AnyValue::Bytes(vec![0xca, 0xfe])), r#""cafe""#);
+    }
+}
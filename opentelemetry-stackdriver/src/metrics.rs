@@ -0,0 +1,907 @@
+//! Export metrics to [Google Cloud Monitoring](https://cloud.google.com/monitoring).
+//!
+//! Sums, gauges and histograms are written as time series with `CreateTimeSeries` calls, after
+//! creating the descriptor of each metric on its first export. The descriptor is created again
+//! whenever a later export brings new labels, with the labels of both.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use async_trait::async_trait;
+use opentelemetry::global::handle_error;
+use opentelemetry::metrics::{MetricsError, Result};
+use opentelemetry_sdk::metrics::data::{
+    ExponentialHistogram, Gauge, Histogram, Metric as SdkMetric, ResourceMetrics, Sum, Temporality,
+};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::metrics::reader::{
+    AggregationSelector, DefaultAggregationSelector, TemporalitySelector,
+};
+use opentelemetry_sdk::metrics::{Aggregation, InstrumentKind};
+use opentelemetry_sdk::AttributeSet;
+use tonic::transport::Channel;
+use tonic::Request;
+
+use crate::proto::api::distribution::{bucket_options, BucketOptions, Range};
+use crate::proto::api::metric_descriptor::{MetricKind, ValueType};
+use crate::proto::api::{Distribution, LabelDescriptor, Metric, MetricDescriptor};
+use crate::proto::monitoring::v3::metric_service_client::MetricServiceClient;
+use crate::proto::monitoring::v3::{
+    typed_value, CreateMetricDescriptorRequest, CreateTimeSeriesRequest, Point, TimeInterval,
+    TimeSeries, TypedValue,
+};
+use crate::{connect, Authorizer, Error, MonitoredResource};
+
+const MONITORING_ENDPOINT: &str = "https://monitoring.googleapis.com:443";
+const MONITORING_WRITE: &str = "https://www.googleapis.com/auth/monitoring.write";
+const DEFAULT_METRIC_PREFIX: &str = "workload.googleapis.com/";
+/// Cloud Monitoring accepts at most 200 time series per `CreateTimeSeries` call.
+const MAX_TIME_SERIES_PER_REQUEST: usize = 200;
+
+/// Exports metrics to Google Cloud Monitoring.
+///
+/// Every metric is exported as a custom metric type, its name prefixed by
+/// `workload.googleapis.com/` unless configured otherwise, with the data point attributes as
+/// metric labels. Time series are attached to the configured [`MonitoredResource`], or the
+/// `global` resource of the project by default.
+///
+/// Cloud Monitoring doesn't accept delta custom metrics, so sums and histograms are always
+/// aggregated with cumulative temporality.
+///
+/// ```no_run
+/// use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+/// use opentelemetry_sdk::runtime;
+/// use opentelemetry_stackdriver::metrics::CloudMonitoringExporter;
+/// use opentelemetry_stackdriver::GcpAuthorizer;
+///
+/// # async fn run() -> Result<(), opentelemetry_stackdriver::Error> {
+/// let exporter = CloudMonitoringExporter::builder()
+///     .build(GcpAuthorizer::new().await?)
+///     .await?;
+/// let reader = PeriodicReader::builder(exporter, runtime::Tokio).build();
+/// let provider = SdkMeterProvider::builder().with_reader(reader).build();
+/// # Ok(())
+/// # }
+/// ```
+pub struct CloudMonitoringExporter<A> {
+    client: MetricServiceClient<Channel>,
+    authorizer: A,
+    resource: crate::proto::api::MonitoredResource,
+    metric_prefix: String,
+    /// Label keys of the metric descriptors created so far, by metric type
+    descriptors: Mutex<HashMap<String, BTreeSet<String>>>,
+}
+
+impl CloudMonitoringExporter<()> {
+    pub fn builder() -> CloudMonitoringExporterBuilder {
+        CloudMonitoringExporterBuilder::default()
+    }
+}
+
+impl<A: Authorizer> CloudMonitoringExporter<A> {
+    async fn create_descriptor(&self, descriptor: MetricDescriptor) -> Result<()> {
+        let mut req = Request::new(CreateMetricDescriptorRequest {
+            name: format!("projects/{}", self.authorizer.project_id()),
+            metric_descriptor: Some(descriptor),
+        });
+        self.authorizer
+            .authorize(&mut req, &[MONITORING_WRITE])
+            .await
+            .map_err(|e| export_error(Error::Authorizer(e.into())))?;
+        self.client
+            .clone()
+            .create_metric_descriptor(req)
+            .await
+            .map_err(|e| export_error(Error::Transport(e.into())))?;
+        Ok(())
+    }
+
+    async fn create_time_series(&self, time_series: Vec<TimeSeries>) -> Result<()> {
+        let mut req = Request::new(CreateTimeSeriesRequest {
+            name: format!("projects/{}", self.authorizer.project_id()),
+            time_series,
+        });
+        self.authorizer
+            .authorize(&mut req, &[MONITORING_WRITE])
+            .await
+            .map_err(|e| export_error(Error::Authorizer(e.into())))?;
+        self.client
+            .clone()
+            .create_time_series(req)
+            .await
+            .map_err(|e| export_error(Error::Transport(e.into())))?;
+        Ok(())
+    }
+}
+
+impl<A> fmt::Debug for CloudMonitoringExporter<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CloudMonitoringExporter")
+            .field("resource", &self.resource)
+            .field("metric_prefix", &self.metric_prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<A: Authorizer> TemporalitySelector for CloudMonitoringExporter<A> {
+    fn temporality(&self, _kind: InstrumentKind) -> Temporality {
+        Temporality::Cumulative
+    }
+}
+
+impl<A: Authorizer> AggregationSelector for CloudMonitoringExporter<A> {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        DefaultAggregationSelector::new().aggregation(kind)
+    }
+}
+
+#[async_trait]
+impl<A: Authorizer> PushMetricsExporter for CloudMonitoringExporter<A> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> Result<()> {
+        let mut all_time_series = Vec::new();
+        for metric in metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| scope.metrics.iter())
+        {
+            let (descriptor, time_series) =
+                match convert_metric(metric, &self.metric_prefix, &self.resource) {
+                    Some(converted) => converted,
+                    None => continue,
+                };
+
+            // label keys only seen in later exports are merged into the created ones, and the
+            // descriptor is created again with all of them
+            let label_keys = self.descriptors.lock().ok().and_then(|descriptors| {
+                let created = descriptors.get(&descriptor.r#type);
+                if created.map_or(false, |created| {
+                    descriptor
+                        .labels
+                        .iter()
+                        .all(|label| created.contains(&label.key))
+                }) {
+                    return None;
+                }
+                let mut label_keys = created.cloned().unwrap_or_default();
+                label_keys.extend(descriptor.labels.iter().map(|label| label.key.clone()));
+                Some(label_keys)
+            });
+            if let Some(label_keys) = label_keys {
+                let metric_type = descriptor.r#type.clone();
+                let descriptor = MetricDescriptor {
+                    labels: label_descriptors(label_keys.iter().cloned()),
+                    ..descriptor
+                };
+                // time series of a missing descriptor are still written, creating a default one
+                match self.create_descriptor(descriptor).await {
+                    Ok(()) => {
+                        if let Ok(mut descriptors) = self.descriptors.lock() {
+                            descriptors.insert(metric_type, label_keys);
+                        }
+                    }
+                    Err(err) => handle_error(err),
+                }
+            }
+
+            all_time_series.extend(time_series);
+        }
+
+        let mut result = Ok(());
+        while !all_time_series.is_empty() {
+            let rest =
+                all_time_series.split_off(all_time_series.len().min(MAX_TIME_SERIES_PER_REQUEST));
+            if let Err(err) = self.create_time_series(all_time_series).await {
+                result = Err(err);
+            }
+            all_time_series = rest;
+        }
+        result
+    }
+
+    async fn force_flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn export_error(err: Error) -> MetricsError {
+    MetricsError::ExportErr(Box::new(err))
+}
+
+/// Helper type to build a `CloudMonitoringExporter`.
+#[derive(Clone, Default)]
+pub struct CloudMonitoringExporterBuilder {
+    endpoint: Option<String>,
+    insecure: bool,
+    channel: Option<Channel>,
+    resource: Option<MonitoredResource>,
+    metric_prefix: Option<String>,
+}
+
+impl CloudMonitoringExporterBuilder {
+    /// Set the endpoint of the Cloud Monitoring API.
+    ///
+    /// If not set, defaults to `https://monitoring.googleapis.com:443`.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Connect to the endpoint without TLS, e.g. to reach a local emulator.
+    pub fn insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    /// Send metrics over the given `channel` instead of connecting to the endpoint.
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    /// Set the resource the time series are attached to.
+    ///
+    /// If not set, defaults to the `global` resource of the authorizer's project.
+    pub fn monitored_resource(mut self, resource: MonitoredResource) -> Self {
+        self.resource = Some(resource);
+        self
+    }
+
+    /// Set the prefix of the metric types, the metric name being appended to it.
+    ///
+    /// If not set, defaults to `workload.googleapis.com/`.
+    pub fn metric_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.metric_prefix = Some(prefix.into());
+        self
+    }
+
+    pub async fn build<A: Authorizer>(
+        self,
+        authorizer: A,
+    ) -> std::result::Result<CloudMonitoringExporter<A>, Error> {
+        let channel = match self.channel {
+            Some(channel) => channel,
+            None => {
+                connect(
+                    self.endpoint.as_deref().unwrap_or(MONITORING_ENDPOINT),
+                    self.insecure,
+                )
+                .await?
+            }
+        };
+        let resource = self.resource.unwrap_or_else(|| MonitoredResource::Global {
+            project_id: authorizer.project_id().to_owned(),
+        });
+
+        Ok(CloudMonitoringExporter {
+            client: MetricServiceClient::new(channel),
+            authorizer,
+            resource: resource.into(),
+            metric_prefix: self
+                .metric_prefix
+                .unwrap_or_else(|| DEFAULT_METRIC_PREFIX.to_owned()),
+            descriptors: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+/// Convert a metric into its descriptor and a time series per data point, or `None` if its
+/// aggregation isn't supported.
+fn convert_metric(
+    metric: &SdkMetric,
+    prefix: &str,
+    resource: &crate::proto::api::MonitoredResource,
+) -> Option<(MetricDescriptor, Vec<TimeSeries>)> {
+    let data = metric.data.as_any();
+    let points = if let Some(sum) = data.downcast_ref::<Sum<u64>>() {
+        sum_points(sum)
+    } else if let Some(sum) = data.downcast_ref::<Sum<i64>>() {
+        sum_points(sum)
+    } else if let Some(sum) = data.downcast_ref::<Sum<f64>>() {
+        sum_points(sum)
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<u64>>() {
+        gauge_points(gauge)
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<i64>>() {
+        gauge_points(gauge)
+    } else if let Some(gauge) = data.downcast_ref::<Gauge<f64>>() {
+        gauge_points(gauge)
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<u64>>() {
+        histogram_points(histogram)
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<i64>>() {
+        histogram_points(histogram)
+    } else if let Some(histogram) = data.downcast_ref::<Histogram<f64>>() {
+        histogram_points(histogram)
+    } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<u64>>() {
+        exponential_histogram_points(histogram)
+    } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<i64>>() {
+        exponential_histogram_points(histogram)
+    } else if let Some(histogram) = data.downcast_ref::<ExponentialHistogram<f64>>() {
+        exponential_histogram_points(histogram)
+    } else {
+        return None;
+    };
+
+    let metric_type = format!("{prefix}{}", metric.name);
+    let unit = metric.unit.as_str().to_owned();
+    let label_keys = points
+        .points
+        .iter()
+        .flat_map(|(attributes, _)| attributes.iter().map(|(key, _)| label_key(key.as_str())))
+        .collect::<BTreeSet<_>>();
+
+    let descriptor = MetricDescriptor {
+        name: String::new(),
+        r#type: metric_type.clone(),
+        labels: label_descriptors(label_keys),
+        metric_kind: points.kind as i32,
+        value_type: points.value_type as i32,
+        unit: unit.clone(),
+        description: metric.description.to_string(),
+        display_name: metric.name.to_string(),
+        ..Default::default()
+    };
+
+    let time_series = points
+        .points
+        .into_iter()
+        .map(|(attributes, point)| TimeSeries {
+            metric: Some(Metric {
+                r#type: metric_type.clone(),
+                labels: attributes
+                    .iter()
+                    .map(|(key, value)| (label_key(key.as_str()), value.as_str().into_owned()))
+                    .collect(),
+            }),
+            resource: Some(resource.clone()),
+            metric_kind: points.kind as i32,
+            value_type: points.value_type as i32,
+            points: vec![point],
+            unit: unit.clone(),
+            ..Default::default()
+        })
+        .collect();
+
+    Some((descriptor, time_series))
+}
+
+fn label_descriptors(keys: impl IntoIterator<Item = String>) -> Vec<LabelDescriptor> {
+    keys.into_iter()
+        .map(|key| LabelDescriptor {
+            key,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Data points of a metric, along with their kind and value type.
+struct Points<'a> {
+    kind: MetricKind,
+    value_type: ValueType,
+    points: Vec<(&'a AttributeSet, Point)>,
+}
+
+trait ToTypedValue: Copy {
+    const VALUE_TYPE: ValueType;
+
+    fn typed_value(self) -> typed_value::Value;
+
+    fn to_f64(self) -> f64;
+}
+
+impl ToTypedValue for u64 {
+    const VALUE_TYPE: ValueType = ValueType::Int64;
+
+    fn typed_value(self) -> typed_value::Value {
+        typed_value::Value::Int64Value(i64::try_from(self).unwrap_or(i64::MAX))
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl ToTypedValue for i64 {
+    const VALUE_TYPE: ValueType = ValueType::Int64;
+
+    fn typed_value(self) -> typed_value::Value {
+        typed_value::Value::Int64Value(self)
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl ToTypedValue for f64 {
+    const VALUE_TYPE: ValueType = ValueType::Double;
+
+    fn typed_value(self) -> typed_value::Value {
+        typed_value::Value::DoubleValue(self)
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+fn sum_points<T: ToTypedValue>(sum: &Sum<T>) -> Points<'_> {
+    let kind = match (sum.is_monotonic, sum.temporality) {
+        (false, _) => MetricKind::Gauge,
+        (true, Temporality::Delta) => MetricKind::Delta,
+        (true, _) => MetricKind::Cumulative,
+    };
+    Points {
+        kind,
+        value_type: T::VALUE_TYPE,
+        points: sum
+            .data_points
+            .iter()
+            .map(|point| {
+                let end = point.time.unwrap_or_else(SystemTime::now);
+                (
+                    &point.attributes,
+                    Point {
+                        interval: Some(interval(kind, point.start_time, end)),
+                        value: Some(TypedValue {
+                            value: Some(point.value.typed_value()),
+                        }),
+                    },
+                )
+            })
+            .collect(),
+    }
+}
+
+fn gauge_points<T: ToTypedValue>(gauge: &Gauge<T>) -> Points<'_> {
+    Points {
+        kind: MetricKind::Gauge,
+        value_type: T::VALUE_TYPE,
+        points: gauge
+            .data_points
+            .iter()
+            .map(|point| {
+                let end = point.time.unwrap_or_else(SystemTime::now);
+                (
+                    &point.attributes,
+                    Point {
+                        interval: Some(interval(MetricKind::Gauge, None, end)),
+                        value: Some(TypedValue {
+                            value: Some(point.value.typed_value()),
+                        }),
+                    },
+                )
+            })
+            .collect(),
+    }
+}
+
+fn histogram_points<T: ToTypedValue>(histogram: &Histogram<T>) -> Points<'_> {
+    let kind = histogram_kind(histogram.temporality);
+    Points {
+        kind,
+        value_type: ValueType::Distribution,
+        points: histogram
+            .data_points
+            .iter()
+            .map(|point| {
+                let bucket_options = (!point.bounds.is_empty()).then(|| BucketOptions {
+                    options: Some(bucket_options::Options::ExplicitBuckets(
+                        bucket_options::Explicit {
+                            bounds: point.bounds.clone(),
+                        },
+                    )),
+                });
+                let bucket_counts = match bucket_options {
+                    Some(_) => point
+                        .bucket_counts
+                        .iter()
+                        .map(|count| *count as i64)
+                        .collect(),
+                    None => vec![],
+                };
+                let distribution = distribution(
+                    point.count as i64,
+                    point.sum.to_f64(),
+                    point.min.zip(point.max),
+                    bucket_options,
+                    bucket_counts,
+                );
+                (
+                    &point.attributes,
+                    distribution_point(kind, point.start_time, point.time, distribution),
+                )
+            })
+            .collect(),
+    }
+}
+
+fn exponential_histogram_points<T: ToTypedValue>(
+    histogram: &ExponentialHistogram<T>,
+) -> Points<'_> {
+    let kind = histogram_kind(histogram.temporality);
+    Points {
+        kind,
+        value_type: ValueType::Distribution,
+        points: histogram
+            .data_points
+            .iter()
+            .map(|point| {
+                // Bucket `i` of the positive range holds values within
+                // `(base^(offset + i), base^(offset + i + 1)]`. Values below go to the underflow
+                // bucket.
+                let positive = &point.positive_bucket;
+                let (bucket_options, bucket_counts) = if positive.counts.is_empty() {
+                    (None, vec![])
+                } else {
+                    let growth_factor = 2f64.powf(2f64.powi(-i32::from(point.scale)));
+                    let options = BucketOptions {
+                        options: Some(bucket_options::Options::ExponentialBuckets(
+                            bucket_options::Exponential {
+                                num_finite_buckets: positive.counts.len() as i32,
+                                growth_factor,
+                                scale: growth_factor.powi(positive.offset),
+                            },
+                        )),
+                    };
+                    let underflow =
+                        point.zero_count + point.negative_bucket.counts.iter().sum::<u64>();
+                    let counts = std::iter::once(underflow)
+                        .chain(positive.counts.iter().copied())
+                        .chain(std::iter::once(0))
+                        .map(|count| count as i64)
+                        .collect();
+                    (Some(options), counts)
+                };
+                let distribution = distribution(
+                    point.count as i64,
+                    point.sum.to_f64(),
+                    point.min.zip(point.max),
+                    bucket_options,
+                    bucket_counts,
+                );
+                (
+                    &point.attributes,
+                    distribution_point(kind, point.start_time, point.time, distribution),
+                )
+            })
+            .collect(),
+    }
+}
+
+fn histogram_kind(temporality: Temporality) -> MetricKind {
+    match temporality {
+        Temporality::Delta => MetricKind::Delta,
+        _ => MetricKind::Cumulative,
+    }
+}
+
+fn distribution<T: ToTypedValue>(
+    count: i64,
+    sum: f64,
+    range: Option<(T, T)>,
+    bucket_options: Option<BucketOptions>,
+    bucket_counts: Vec<i64>,
+) -> Distribution {
+    Distribution {
+        count,
+        mean: if count > 0 { sum / count as f64 } else { 0.0 },
+        // not tracked by OpenTelemetry
+        sum_of_squared_deviation: 0.0,
+        range: range.filter(|_| count > 0).map(|(min, max)| Range {
+            min: min.to_f64(),
+            max: max.to_f64(),
+        }),
+        bucket_options,
+        bucket_counts,
+        exemplars: vec![],
+    }
+}
+
+fn distribution_point(
+    kind: MetricKind,
+    start: SystemTime,
+    end: SystemTime,
+    distribution: Distribution,
+) -> Point {
+    Point {
+        interval: Some(interval(kind, Some(start), end)),
+        value: Some(TypedValue {
+            value: Some(typed_value::Value::DistributionValue(distribution)),
+        }),
+    }
+}
+
+/// Gauges are a single point in time, while cumulative and delta points must cover a non-zero
+/// interval.
+fn interval(kind: MetricKind, start: Option<SystemTime>, end: SystemTime) -> TimeInterval {
+    match (kind, start) {
+        (MetricKind::Gauge, _) | (_, None) => TimeInterval {
+            start_time: None,
+            end_time: Some(end.into()),
+        },
+        (_, Some(start)) => {
+            let end = if end > start {
+                end
+            } else {
+                start + Duration::from_millis(1)
+            };
+            TimeInterval {
+                start_time: Some(start.into()),
+                end_time: Some(end.into()),
+            }
+        }
+    }
+}
+
+/// Label keys may only contain letters, digits and underscores, and must not start with a digit.
+fn label_key(key: &str) -> String {
+    let key = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    match key.chars().next() {
+        Some(first) if first.is_ascii_digit() => format!("key_{key}"),
+        _ => key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::metrics::Unit;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::metrics::data::{
+        self, DataPoint, ExponentialBucket, ExponentialHistogramDataPoint, HistogramDataPoint,
+    };
+
+    fn resource() -> crate::proto::api::MonitoredResource {
+        MonitoredResource::Global {
+            project_id: "test-project".to_owned(),
+        }
+        .into()
+    }
+
+    fn metric(name: &'static str, data: Box<dyn data::Aggregation>) -> SdkMetric {
+        SdkMetric {
+            name: name.into(),
+            description: "description".into(),
+            unit: Unit::new("ms"),
+            data,
+        }
+    }
+
+    fn times() -> (SystemTime, SystemTime) {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        (start, start + Duration::from_secs(60))
+    }
+
+    #[test]
+    fn test_cumulative_sum() {
+        let (start, end) = times();
+        let sum = Sum {
+            data_points: vec![DataPoint {
+                attributes: AttributeSet::from(&[KeyValue::new("http.method", "GET")][..]),
+                start_time: Some(start),
+                time: Some(end),
+                value: 3u64,
+                exemplars: vec![],
+            }],
+            temporality: Temporality::Cumulative,
+            is_monotonic: true,
+        };
+
+        let (descriptor, time_series) = convert_metric(
+            &metric("requests", Box::new(sum)),
+            DEFAULT_METRIC_PREFIX,
+            &resource(),
+        )
+        .unwrap();
+
+        assert_eq!(descriptor.r#type, "workload.googleapis.com/requests");
+        assert_eq!(descriptor.metric_kind, MetricKind::Cumulative as i32);
+        assert_eq!(descriptor.value_type, ValueType::Int64 as i32);
+        assert_eq!(descriptor.unit, "ms");
+        assert_eq!(descriptor.labels.len(), 1);
+        assert_eq!(descriptor.labels[0].key, "http_method");
+
+        assert_eq!(time_series.len(), 1);
+        let series = &time_series[0];
+        assert_eq!(
+            series.metric.as_ref().unwrap().labels.get("http_method"),
+            Some(&"GET".to_owned())
+        );
+        assert_eq!(series.resource, Some(resource()));
+        assert_eq!(
+            series.points[0].interval,
+            Some(TimeInterval {
+                start_time: Some(start.into()),
+                end_time: Some(end.into()),
+            })
+        );
+        assert_eq!(
+            series.points[0].value,
+            Some(TypedValue {
+                value: Some(typed_value::Value::Int64Value(3)),
+            })
+        );
+    }
+
+    #[test]
+    fn test_gauges() {
+        let (start, end) = times();
+        let gauge = Gauge {
+            data_points: vec![DataPoint {
+                attributes: AttributeSet::default(),
+                start_time: Some(start),
+                time: Some(end),
+                value: 0.5f64,
+                exemplars: vec![],
+            }],
+        };
+        let (descriptor, time_series) = convert_metric(
+            &metric("load", Box::new(gauge)),
+            "custom.googleapis.com/",
+            &resource(),
+        )
+        .unwrap();
+        assert_eq!(descriptor.r#type, "custom.googleapis.com/load");
+        assert_eq!(descriptor.metric_kind, MetricKind::Gauge as i32);
+        assert_eq!(descriptor.value_type, ValueType::Double as i32);
+        // gauges are a single point in time
+        assert_eq!(
+            time_series[0].points[0].interval,
+            Some(TimeInterval {
+                start_time: None,
+                end_time: Some(end.into()),
+            })
+        );
+
+        // up down counters are gauges too
+        let sum = Sum {
+            data_points: vec![DataPoint {
+                attributes: AttributeSet::default(),
+                start_time: Some(start),
+                time: Some(end),
+                value: -2i64,
+                exemplars: vec![],
+            }],
+            temporality: Temporality::Cumulative,
+            is_monotonic: false,
+        };
+        let (descriptor, _) =
+            convert_metric(&metric("queue", Box::new(sum)), "", &resource()).unwrap();
+        assert_eq!(descriptor.metric_kind, MetricKind::Gauge as i32);
+    }
+
+    #[test]
+    fn test_histogram_distribution() {
+        let (start, _) = times();
+        let histogram = Histogram {
+            data_points: vec![HistogramDataPoint {
+                attributes: AttributeSet::default(),
+                // cumulative points must cover a non-zero interval
+                start_time: start,
+                time: start,
+                count: 4,
+                bounds: vec![10.0, 100.0],
+                bucket_counts: vec![1, 2, 1],
+                min: Some(5.0),
+                max: Some(150.0),
+                sum: 200.0f64,
+                exemplars: vec![],
+            }],
+            temporality: Temporality::Cumulative,
+        };
+
+        let (descriptor, time_series) = convert_metric(
+            &metric("latency", Box::new(histogram)),
+            DEFAULT_METRIC_PREFIX,
+            &resource(),
+        )
+        .unwrap();
+
+        assert_eq!(descriptor.metric_kind, MetricKind::Cumulative as i32);
+        assert_eq!(descriptor.value_type, ValueType::Distribution as i32);
+        let point = &time_series[0].points[0];
+        assert_eq!(
+            point.interval,
+            Some(TimeInterval {
+                start_time: Some(start.into()),
+                end_time: Some((start + Duration::from_millis(1)).into()),
+            })
+        );
+        assert_eq!(
+            point.value,
+            Some(TypedValue {
+                value: Some(typed_value::Value::DistributionValue(Distribution {
+                    count: 4,
+                    mean: 50.0,
+                    sum_of_squared_deviation: 0.0,
+                    range: Some(Range {
+                        min: 5.0,
+                        max: 150.0,
+                    }),
+                    bucket_options: Some(BucketOptions {
+                        options: Some(bucket_options::Options::ExplicitBuckets(
+                            bucket_options::Explicit {
+                                bounds: vec![10.0, 100.0],
+                            },
+                        )),
+                    }),
+                    bucket_counts: vec![1, 2, 1],
+                    exemplars: vec![],
+                })),
+            })
+        );
+    }
+
+    #[test]
+    fn test_exponential_histogram_distribution() {
+        let (start, end) = times();
+        let histogram = ExponentialHistogram {
+            data_points: vec![ExponentialHistogramDataPoint {
+                attributes: AttributeSet::default(),
+                start_time: start,
+                time: end,
+                count: 6,
+                min: Some(0),
+                max: Some(7),
+                sum: 20u64,
+                scale: 0,
+                zero_count: 1,
+                positive_bucket: ExponentialBucket {
+                    offset: 1,
+                    counts: vec![2, 3],
+                },
+                negative_bucket: ExponentialBucket {
+                    offset: 0,
+                    counts: vec![],
+                },
+                zero_threshold: 0.0,
+                exemplars: vec![],
+            }],
+            temporality: Temporality::Cumulative,
+        };
+
+        let (_, time_series) = convert_metric(
+            &metric("sizes", Box::new(histogram)),
+            DEFAULT_METRIC_PREFIX,
+            &resource(),
+        )
+        .unwrap();
+
+        let distribution = match &time_series[0].points[0].value {
+            Some(TypedValue {
+                value: Some(typed_value::Value::DistributionValue(distribution)),
+            }) => distribution,
+            value => panic!("unexpected value {:?}", value),
+        };
+        // buckets of scale 0 double in size, the first one starting at 2^1
+        assert_eq!(
+            distribution.bucket_options,
+            Some(BucketOptions {
+                options: Some(bucket_options::Options::ExponentialBuckets(
+                    bucket_options::Exponential {
+                        num_finite_buckets: 2,
+                        growth_factor: 2.0,
+                        scale: 2.0,
+                    },
+                )),
+            })
+        );
+        assert_eq!(distribution.bucket_counts, vec![1, 2, 3, 0]);
+        assert_eq!(distribution.count, 6);
+    }
+
+    #[test]
+    fn test_label_key() {
+        assert_eq!(label_key("http.route"), "http_route");
+        assert_eq!(label_key("valid_key1"), "valid_key1");
+        assert_eq!(label_key("1st"), "key_1st");
+    }
+}
@@ -11,4 +11,8 @@ pub mod logging {
     pub mod v2;
 }
 
+pub mod monitoring {
+    pub mod v3;
+}
+
 pub mod rpc;
@@ -0,0 +1,352 @@
+/// A single strongly-typed value.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TypedValue {
+    /// The typed value field.
+    #[prost(oneof = "typed_value::Value", tags = "1, 2, 3, 4, 5")]
+    pub value: ::core::option::Option<typed_value::Value>,
+}
+/// Nested message and enum types in `TypedValue`.
+pub mod typed_value {
+    /// The typed value field.
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Value {
+        /// A Boolean value: `true` or `false`.
+        #[prost(bool, tag = "1")]
+        BoolValue(bool),
+        /// A 64-bit integer. Its range is approximately &plusmn;9.2x10<sup>18</sup>.
+        #[prost(int64, tag = "2")]
+        Int64Value(i64),
+        /// A 64-bit double-precision floating-point number. Its magnitude
+        /// is approximately &plusmn;10<sup>&plusmn;300</sup> and it has 16
+        /// significant digits of precision.
+        #[prost(double, tag = "3")]
+        DoubleValue(f64),
+        /// A variable-length string value.
+        #[prost(string, tag = "4")]
+        StringValue(::prost::alloc::string::String),
+        /// A distribution value.
+        #[prost(message, tag = "5")]
+        DistributionValue(super::super::super::api::Distribution),
+    }
+}
+/// Describes a time interval:
+///
+///    * Reads: A half-open time interval. It includes the end time but
+///      excludes the start time: `(startTime, endTime]`. The start time
+///      must be specified, must be earlier than the end time, and should be
+///      no older than the data retention period for the metric.
+///    * Writes: A closed time interval. It extends from the start time to the end
+///      time, and includes both: `\[startTime, endTime\]`. Valid time intervals
+///      depend on the
+///      [`MetricKind`](<https://cloud.google.com/monitoring/api/ref_v3/rest/v3/projects.metricDescriptors#MetricKind>)
+///      of the metric value. The end time must not be earlier than the start
+///      time, and the end time must not be more than 25 hours in the past or more
+///      than five minutes in the future.
+///      * For `GAUGE` metrics, the `startTime` value is technically optional; if
+///        no value is specified, the start time defaults to the value of the
+///        end time, and the interval represents a single point in time. If both
+///        start and end times are specified, they must be identical. Such an
+///        interval is valid only for `GAUGE` metrics, which are point-in-time
+///        measurements. The end time of a new interval must be at least a
+///        millisecond after the end time of the previous interval.
+///      * For `DELTA` metrics, the start time and end time must specify a
+///        non-zero interval, with subsequent points specifying contiguous and
+///        non-overlapping intervals. For `DELTA` metrics, the start time of
+///        the next interval must be at least a millisecond after the end time
+///        of the previous interval.
+///      * For `CUMULATIVE` metrics, the start time and end time must specify a
+///        non-zero interval, with subsequent points specifying the same
+///        start time and increasing end times, until an event resets the
+///        cumulative value to zero and sets a new start time for the following
+///        points. The new start time must be at least a millisecond after the
+///        end time of the previous interval.
+///      * The start time of a new interval must be at least a millisecond after
+///        the end time of the previous interval because intervals are closed.
+///        If the start time of a new interval is the same as the end time of the
+///        previous interval, then data written at the new start time could
+///        overwrite data written at the previous end time.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TimeInterval {
+    /// Required. The end of the time interval.
+    #[prost(message, optional, tag = "2")]
+    pub end_time: ::core::option::Option<::prost_types::Timestamp>,
+    /// Optional. The beginning of the time interval.  The default value
+    /// for the start time is the end time. The start time must not be
+    /// later than the end time.
+    #[prost(message, optional, tag = "1")]
+    pub start_time: ::core::option::Option<::prost_types::Timestamp>,
+}
+/// A single data point in a time series.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Point {
+    /// The time interval to which the data point applies.  For `GAUGE` metrics,
+    /// the start time is optional, but if it is supplied, it must equal the
+    /// end time.  For `DELTA` metrics, the start
+    /// and end time should specify a non-zero interval, with subsequent points
+    /// specifying contiguous and non-overlapping intervals.  For `CUMULATIVE`
+    /// metrics, the start and end time should specify a non-zero interval, with
+    /// subsequent points specifying the same start time and increasing end times,
+    /// until an event resets the cumulative value to zero and sets a new start
+    /// time for the following points.
+    #[prost(message, optional, tag = "1")]
+    pub interval: ::core::option::Option<TimeInterval>,
+    /// The value of the data point.
+    #[prost(message, optional, tag = "2")]
+    pub value: ::core::option::Option<TypedValue>,
+}
+/// A collection of data points that describes the time-varying values
+/// of a metric. A time series is identified by a combination of a
+/// fully-specified monitored resource and a fully-specified metric.
+/// This type is used for both listing and creating time series.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TimeSeries {
+    /// The associated metric. A fully-specified metric used to identify the time
+    /// series.
+    #[prost(message, optional, tag = "1")]
+    pub metric: ::core::option::Option<super::super::api::Metric>,
+    /// The associated monitored resource.  Custom metrics can use only certain
+    /// monitored resource types in their time series data. For more information,
+    /// see [Monitored resources for custom
+    /// metrics](<https://cloud.google.com/monitoring/custom-metrics/creating-metrics#custom-metric-resources>).
+    #[prost(message, optional, tag = "2")]
+    pub resource: ::core::option::Option<super::super::api::MonitoredResource>,
+    /// Output only. The associated monitored resource metadata. When reading a
+    /// time series, this field will include metadata labels that are explicitly
+    /// named in the reduction. When creating a time series, this field is ignored.
+    #[prost(message, optional, tag = "7")]
+    pub metadata: ::core::option::Option<super::super::api::MonitoredResourceMetadata>,
+    /// The metric kind of the time series. When listing time series, this metric
+    /// kind might be different from the metric kind of the associated metric if
+    /// this time series is an alignment or reduction of other time series.
+    ///
+    /// When creating a time series, this field is optional. If present, it must be
+    /// the same as the metric kind of the associated metric. If the associated
+    /// metric's descriptor must be auto-created, then this field specifies the
+    /// metric kind of the new descriptor and must be either `GAUGE` (the default)
+    /// or `CUMULATIVE`.
+    #[prost(
+        enumeration = "super::super::api::metric_descriptor::MetricKind",
+        tag = "3"
+    )]
+    pub metric_kind: i32,
+    /// The value type of the time series. When listing time series, this value
+    /// type might be different from the value type of the associated metric if
+    /// this time series is an alignment or reduction of other time series.
+    ///
+    /// When creating a time series, this field is optional. If present, it must be
+    /// the same as the type of the data in the `points` field.
+    #[prost(
+        enumeration = "super::super::api::metric_descriptor::ValueType",
+        tag = "4"
+    )]
+    pub value_type: i32,
+    /// The data points of this time series. When listing time series, points are
+    /// returned in reverse time order.
+    ///
+    /// When creating a time series, this field must contain exactly one point and
+    /// the point's type must be the same as the value type of the associated
+    /// metric. If the associated metric's descriptor must be auto-created, then
+    /// the value type of the descriptor is determined by the point's type, which
+    /// must be `BOOL`, `INT64`, `DOUBLE`, or `DISTRIBUTION`.
+    #[prost(message, repeated, tag = "5")]
+    pub points: ::prost::alloc::vec::Vec<Point>,
+    /// The units in which the metric value is reported. It is only applicable
+    /// if the `value_type` is `INT64`, `DOUBLE`, or `DISTRIBUTION`. The `unit`
+    /// defines the representation of the stored metric values.
+    #[prost(string, tag = "8")]
+    pub unit: ::prost::alloc::string::String,
+    /// Input only. A detailed description of the time series that will be
+    /// associated with the
+    /// [google.api.MetricDescriptor][google.api.MetricDescriptor] for the metric.
+    /// Once set, this field cannot be changed through CreateTimeSeries.
+    #[prost(string, tag = "9")]
+    pub description: ::prost::alloc::string::String,
+}
+/// The `CreateMetricDescriptor` request.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateMetricDescriptorRequest {
+    /// Required. The
+    /// [project](<https://cloud.google.com/monitoring/api/v3#project_name>) on which
+    /// to execute the request. The format is:
+    ///
+    ///      projects/\[PROJECT_ID_OR_NUMBER\]
+    #[prost(string, tag = "3")]
+    pub name: ::prost::alloc::string::String,
+    /// Required. The new [custom
+    /// metric](<https://cloud.google.com/monitoring/custom-metrics>) descriptor.
+    #[prost(message, optional, tag = "2")]
+    pub metric_descriptor: ::core::option::Option<super::super::api::MetricDescriptor>,
+}
+/// The `CreateTimeSeries` request.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CreateTimeSeriesRequest {
+    /// Required. The
+    /// [project](<https://cloud.google.com/monitoring/api/v3#project_name>) on which
+    /// to execute the request. The format is:
+    ///
+    ///      projects/\[PROJECT_ID_OR_NUMBER\]
+    #[prost(string, tag = "3")]
+    pub name: ::prost::alloc::string::String,
+    /// Required. The new data to be added to a list of time series.
+    /// Adds at most one data point to each of several time series.  The new data
+    /// point must be more recent than any other point in its time series.  Each
+    /// `TimeSeries` value must fully specify a unique time series by supplying
+    /// all label values for the metric and the monitored resource.
+    ///
+    /// The maximum number of `TimeSeries` objects per `Create` request is 200.
+    #[prost(message, repeated, tag = "2")]
+    pub time_series: ::prost::alloc::vec::Vec<TimeSeries>,
+}
+/// Generated client implementations.
+pub mod metric_service_client {
+    #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
+    use tonic::codegen::http::Uri;
+    use tonic::codegen::*;
+    /// Manages metric descriptors, monitored resource descriptors, and
+    /// time series data.
+    #[derive(Debug, Clone)]
+    pub struct MetricServiceClient<T> {
+        inner: tonic::client::Grpc<T>,
+    }
+    impl MetricServiceClient<tonic::transport::Channel> {
+        /// Attempt to create a new client by connecting to a given endpoint.
+        pub async fn connect<D>(dst: D) -> Result<Self, tonic::transport::Error>
+        where
+            D: TryInto<tonic::transport::Endpoint>,
+            D::Error: Into<StdError>,
+        {
+            let conn = tonic::transport::Endpoint::new(dst)?.connect().await?;
+            Ok(Self::new(conn))
+        }
+    }
+    impl<T> MetricServiceClient<T>
+    where
+        T: tonic::client::GrpcService<tonic::body::BoxBody>,
+        T::Error: Into<StdError>,
+        T::ResponseBody: Body<Data = Bytes> + Send + 'static,
+        <T::ResponseBody as Body>::Error: Into<StdError> + Send,
+    {
+        pub fn new(inner: T) -> Self {
+            let inner = tonic::client::Grpc::new(inner);
+            Self { inner }
+        }
+        pub fn with_origin(inner: T, origin: Uri) -> Self {
+            let inner = tonic::client::Grpc::with_origin(inner, origin);
+            Self { inner }
+        }
+        pub fn with_interceptor<F>(
+            inner: T,
+            interceptor: F,
+        ) -> MetricServiceClient<InterceptedService<T, F>>
+        where
+            F: tonic::service::Interceptor,
+            T::ResponseBody: Default,
+            T: tonic::codegen::Service<
+                http::Request<tonic::body::BoxBody>,
+                Response = http::Response<
+                    <T as tonic::client::GrpcService<tonic::body::BoxBody>>::ResponseBody,
+                >,
+            >,
+            <T as tonic::codegen::Service<http::Request<tonic::body::BoxBody>>>::Error:
+                Into<StdError> + Send + Sync,
+        {
+            MetricServiceClient::new(InterceptedService::new(inner, interceptor))
+        }
+        /// Compress requests with the given encoding.
+        ///
+        /// This requires the server to support it otherwise it might respond with an
+        /// error.
+        #[must_use]
+        pub fn send_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.send_compressed(encoding);
+            self
+        }
+        /// Enable decompressing responses.
+        #[must_use]
+        pub fn accept_compressed(mut self, encoding: CompressionEncoding) -> Self {
+            self.inner = self.inner.accept_compressed(encoding);
+            self
+        }
+        /// Limits the maximum size of a decoded message.
+        ///
+        /// Default: `4MB`
+        #[must_use]
+        pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_decoding_message_size(limit);
+            self
+        }
+        /// Limits the maximum size of an encoded message.
+        ///
+        /// Default: `usize::MAX`
+        #[must_use]
+        pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+            self.inner = self.inner.max_encoding_message_size(limit);
+            self
+        }
+        /// Creates a new metric descriptor.
+        /// The creation is executed asynchronously.
+        /// User-created metric descriptors define
+        /// [custom metrics](https://cloud.google.com/monitoring/custom-metrics).
+        /// The metric descriptor is updated if it already exists,
+        /// except that metric labels are never removed.
+        pub async fn create_metric_descriptor(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CreateMetricDescriptorRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::super::super::api::MetricDescriptor>,
+            tonic::Status,
+        > {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/google.monitoring.v3.MetricService/CreateMetricDescriptor",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new(
+                "google.monitoring.v3.MetricService",
+                "CreateMetricDescriptor",
+            ));
+            self.inner.unary(req, path, codec).await
+        }
+        /// Creates or adds data to one or more time series.
+        /// The response is empty if all time series in the request were written.
+        /// If any time series could not be written, a corresponding failure message is
+        /// included in the error response.
+        /// This method does not support
+        /// [resource locations constraint of an organization
+        /// policy](https://cloud.google.com/resource-manager/docs/organization-policy/defining-locations#setting_the_organization_policy).
+        pub async fn create_time_series(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CreateTimeSeriesRequest>,
+        ) -> std::result::Result<tonic::Response<()>, tonic::Status> {
+            self.inner.ready().await.map_err(|e| {
+                tonic::Status::new(
+                    tonic::Code::Unknown,
+                    format!("Service was not ready: {}", e.into()),
+                )
+            })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/google.monitoring.v3.MetricService/CreateTimeSeries",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut().insert(GrpcMethod::new(
+                "google.monitoring.v3.MetricService",
+                "CreateTimeSeries",
+            ));
+            self.inner.unary(req, path, codec).await
+        }
+    }
+}
@@ -240,6 +240,7 @@ const GENERATE_FROM_SCHEMAS: &[&str] = &[
     "logging/type/http_request.proto",
     "logging/v2/log_entry.proto",
     "logging/v2/logging.proto",
+    "monitoring/v3/metric_service.proto",
     "rpc/status.proto",
 ];
 
@@ -255,6 +256,10 @@ const PREREQUISITE_SCHEMAS: &[&str] = &[
     "api/label.proto",
     "api/launch_stage.proto",
     "logging/v2/logging_config.proto",
+    "api/distribution.proto",
+    "api/metric.proto",
+    "monitoring/v3/common.proto",
+    "monitoring/v3/metric.proto",
 ];
 
 const BASE_URI: &str = "https://raw.githubusercontent.com/googleapis/googleapis/master/google";
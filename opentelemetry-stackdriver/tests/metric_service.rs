@@ -0,0 +1,275 @@
+//! Export metrics to an in-process `MetricService`, as one would to an emulator.
+#![cfg(feature = "metrics")]
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use futures_core::future::BoxFuture;
+use opentelemetry::metrics::Unit;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::metrics::data::{
+    DataPoint, Metric as SdkMetric, ResourceMetrics, ScopeMetrics, Sum, Temporality,
+};
+use opentelemetry_sdk::metrics::exporter::PushMetricsExporter;
+use opentelemetry_sdk::{AttributeSet, Resource};
+use opentelemetry_stackdriver::metrics::CloudMonitoringExporter;
+use opentelemetry_stackdriver::proto::api::MetricDescriptor;
+use opentelemetry_stackdriver::proto::monitoring::v3::{
+    CreateMetricDescriptorRequest, CreateTimeSeriesRequest,
+};
+use opentelemetry_stackdriver::{Authorizer, Error};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Context, Poll, Service};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::transport::{Body, Server};
+use tonic::{Request, Response, Status};
+
+/// Records the metric descriptors and time series written to it.
+#[derive(Clone)]
+struct MockMetricService {
+    descriptors: mpsc::UnboundedSender<CreateMetricDescriptorRequest>,
+    time_series: mpsc::UnboundedSender<CreateTimeSeriesRequest>,
+}
+
+impl NamedService for MockMetricService {
+    const NAME: &'static str = "google.monitoring.v3.MetricService";
+}
+
+impl Service<http::Request<Body>> for MockMetricService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            match request.uri().path() {
+                "/google.monitoring.v3.MetricService/CreateMetricDescriptor" => {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    Ok(grpc.unary(CreateMetricDescriptor(service), request).await)
+                }
+                "/google.monitoring.v3.MetricService/CreateTimeSeries" => {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    Ok(grpc.unary(CreateTimeSeries(service), request).await)
+                }
+                _ => Ok(Status::unimplemented("").to_http()),
+            }
+        })
+    }
+}
+
+struct CreateMetricDescriptor(MockMetricService);
+
+impl UnaryService<CreateMetricDescriptorRequest> for CreateMetricDescriptor {
+    type Response = MetricDescriptor;
+    type Future = BoxFuture<'static, Result<Response<MetricDescriptor>, Status>>;
+
+    fn call(&mut self, request: Request<CreateMetricDescriptorRequest>) -> Self::Future {
+        let request = request.into_inner();
+        let descriptor = request.metric_descriptor.clone().unwrap_or_default();
+        let _ = self.0.descriptors.send(request);
+        Box::pin(async { Ok(Response::new(descriptor)) })
+    }
+}
+
+struct CreateTimeSeries(MockMetricService);
+
+impl UnaryService<CreateTimeSeriesRequest> for CreateTimeSeries {
+    type Response = ();
+    type Future = BoxFuture<'static, Result<Response<()>, Status>>;
+
+    fn call(&mut self, request: Request<CreateTimeSeriesRequest>) -> Self::Future {
+        let _ = self.0.time_series.send(request.into_inner());
+        Box::pin(async { Ok(Response::new(())) })
+    }
+}
+
+struct Received {
+    descriptors: mpsc::UnboundedReceiver<CreateMetricDescriptorRequest>,
+    time_series: mpsc::UnboundedReceiver<CreateTimeSeriesRequest>,
+}
+
+/// Serve a `MockMetricService` on a local port, returning its address and the received requests.
+async fn serve() -> (SocketAddr, Received) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (descriptors, received_descriptors) = mpsc::unbounded_channel();
+    let (time_series, received_time_series) = mpsc::unbounded_channel();
+    tokio::spawn(
+        Server::builder()
+            .add_service(MockMetricService {
+                descriptors,
+                time_series,
+            })
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    (
+        addr,
+        Received {
+            descriptors: received_descriptors,
+            time_series: received_time_series,
+        },
+    )
+}
+
+struct TestAuthorizer;
+
+#[async_trait::async_trait]
+impl Authorizer for TestAuthorizer {
+    type Error = Error;
+
+    fn project_id(&self) -> &str {
+        "test-project"
+    }
+
+    async fn authorize<T: Send + Sync>(
+        &self,
+        _request: &mut Request<T>,
+        _scopes: &[&str],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A cumulative `requests` sum with a data point per set of attributes.
+fn requests(attributes: &[&[KeyValue]]) -> ResourceMetrics {
+    let now = SystemTime::now();
+    let sum = Sum {
+        data_points: attributes
+            .iter()
+            .map(|attributes| DataPoint {
+                attributes: AttributeSet::from(*attributes),
+                start_time: Some(now),
+                time: Some(now),
+                value: 1u64,
+                exemplars: vec![],
+            })
+            .collect(),
+        temporality: Temporality::Cumulative,
+        is_monotonic: true,
+    };
+    ResourceMetrics {
+        resource: Resource::empty(),
+        scope_metrics: vec![ScopeMetrics {
+            scope: Default::default(),
+            metrics: vec![SdkMetric {
+                name: "requests".into(),
+                description: "".into(),
+                unit: Unit::new("1"),
+                data: Box::new(sum),
+            }],
+        }],
+    }
+}
+
+async fn exporter(addr: SocketAddr) -> CloudMonitoringExporter<TestAuthorizer> {
+    CloudMonitoringExporter::builder()
+        .endpoint(format!("http://{addr}"))
+        .insecure(true)
+        .build(TestAuthorizer)
+        .await
+        .unwrap()
+}
+
+fn label_keys(request: &CreateMetricDescriptorRequest) -> Vec<&str> {
+    request
+        .metric_descriptor
+        .as_ref()
+        .unwrap()
+        .labels
+        .iter()
+        .map(|label| label.key.as_str())
+        .collect()
+}
+
+#[tokio::test]
+async fn export_descriptor_and_time_series() {
+    let (addr, mut received) = serve().await;
+    let exporter = exporter(addr).await;
+
+    exporter
+        .export(&mut requests(&[&[KeyValue::new("http.method", "GET")]]))
+        .await
+        .unwrap();
+
+    let descriptor = received.descriptors.try_recv().unwrap();
+    assert_eq!(descriptor.name, "projects/test-project");
+    assert_eq!(
+        descriptor.metric_descriptor.as_ref().unwrap().r#type,
+        "workload.googleapis.com/requests"
+    );
+    assert_eq!(label_keys(&descriptor), ["http_method"]);
+
+    let request = received.time_series.try_recv().unwrap();
+    assert_eq!(request.name, "projects/test-project");
+    assert_eq!(request.time_series.len(), 1);
+    assert_eq!(
+        request.time_series[0]
+            .metric
+            .as_ref()
+            .unwrap()
+            .labels
+            .get("http_method"),
+        Some(&"GET".to_owned())
+    );
+
+    // the descriptor is only created once
+    exporter
+        .export(&mut requests(&[&[KeyValue::new("http.method", "GET")]]))
+        .await
+        .unwrap();
+    assert!(received.descriptors.try_recv().is_err());
+    assert!(received.time_series.try_recv().is_ok());
+}
+
+#[tokio::test]
+async fn merge_labels_of_later_exports() {
+    let (addr, mut received) = serve().await;
+    let exporter = exporter(addr).await;
+
+    exporter
+        .export(&mut requests(&[&[KeyValue::new("http.method", "GET")]]))
+        .await
+        .unwrap();
+    assert_eq!(
+        label_keys(&received.descriptors.try_recv().unwrap()),
+        ["http_method"]
+    );
+
+    exporter
+        .export(&mut requests(&[&[
+            KeyValue::new("http.method", "GET"),
+            KeyValue::new("http.status_code", 200),
+        ]]))
+        .await
+        .unwrap();
+    assert_eq!(
+        label_keys(&received.descriptors.try_recv().unwrap()),
+        ["http_method", "http_status_code"]
+    );
+
+    // known labels, even a subset of them, don't create the descriptor again
+    exporter
+        .export(&mut requests(&[&[KeyValue::new("http.status_code", 500)]]))
+        .await
+        .unwrap();
+    assert!(received.descriptors.try_recv().is_err());
+
+    exporter
+        .export(&mut requests(&[&[KeyValue::new("rpc.system", "grpc")]]))
+        .await
+        .unwrap();
+    assert_eq!(
+        label_keys(&received.descriptors.try_recv().unwrap()),
+        ["http_method", "http_status_code", "rpc_system"]
+    );
+}
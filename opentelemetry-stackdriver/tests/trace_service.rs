@@ -0,0 +1,319 @@
+//! Export spans to an in-process `TraceService`, as one would to an emulator.
+
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use futures_core::future::BoxFuture;
+use opentelemetry::trace::{
+    SpanContext, SpanId, SpanKind, Status as SpanStatus, TraceFlags, TraceId, TraceState,
+};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
+use opentelemetry_sdk::{InstrumentationLibrary, Resource};
+use opentelemetry_stackdriver::proto::devtools::cloudtrace::v2::BatchWriteSpansRequest;
+use opentelemetry_stackdriver::{Authorizer, Builder, DropPolicy, Error, StackDriverExporter};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, Context, Poll, Service};
+use tonic::server::{Grpc, NamedService, UnaryService};
+use tonic::transport::{Body, Channel, Server};
+use tonic::{Code, Request, Response, Status};
+
+/// Records the spans written to it, failing requests with the queued `failures` first.
+#[derive(Clone)]
+struct MockTraceService {
+    requests: mpsc::UnboundedSender<BatchWriteSpansRequest>,
+    failures: Arc<Mutex<VecDeque<Code>>>,
+}
+
+impl NamedService for MockTraceService {
+    const NAME: &'static str = "google.devtools.cloudtrace.v2.TraceService";
+}
+
+impl Service<http::Request<Body>> for MockTraceService {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            match request.uri().path() {
+                "/google.devtools.cloudtrace.v2.TraceService/BatchWriteSpans" => {
+                    let mut grpc = Grpc::new(ProstCodec::default());
+                    Ok(grpc.unary(BatchWriteSpans(service), request).await)
+                }
+                _ => Ok(Status::unimplemented("").to_http()),
+            }
+        })
+    }
+}
+
+struct BatchWriteSpans(MockTraceService);
+
+impl UnaryService<BatchWriteSpansRequest> for BatchWriteSpans {
+    type Response = ();
+    type Future = BoxFuture<'static, Result<Response<()>, Status>>;
+
+    fn call(&mut self, request: Request<BatchWriteSpansRequest>) -> Self::Future {
+        let _ = self.0.requests.send(request.into_inner());
+        let result = match self.0.failures.lock().unwrap().pop_front() {
+            Some(code) => Err(Status::new(code, "mock failure")),
+            None => Ok(Response::new(())),
+        };
+        Box::pin(async { result })
+    }
+}
+
+/// Serve a `MockTraceService` on a local port, returning its address and the received requests.
+async fn serve() -> (SocketAddr, mpsc::UnboundedReceiver<BatchWriteSpansRequest>) {
+    serve_failing(&[]).await
+}
+
+/// Serve a `MockTraceService` failing its first requests with the given `failures`.
+async fn serve_failing(
+    failures: &[Code],
+) -> (SocketAddr, mpsc::UnboundedReceiver<BatchWriteSpansRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (requests, received) = mpsc::unbounded_channel();
+    let failures = Arc::new(Mutex::new(failures.iter().copied().collect()));
+    tokio::spawn(
+        Server::builder()
+            .add_service(MockTraceService { requests, failures })
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    (addr, received)
+}
+
+struct TestAuthorizer;
+
+#[async_trait::async_trait]
+impl Authorizer for TestAuthorizer {
+    type Error = Error;
+
+    fn project_id(&self) -> &str {
+        "test-project"
+    }
+
+    async fn authorize<T: Send + Sync>(
+        &self,
+        _request: &mut Request<T>,
+        _scopes: &[&str],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+fn span(name: &'static str) -> SpanData {
+    SpanData {
+        span_context: SpanContext::new(
+            TraceId::from_u128(1),
+            SpanId::from_u64(1),
+            TraceFlags::SAMPLED,
+            false,
+            TraceState::default(),
+        ),
+        parent_span_id: SpanId::INVALID,
+        span_kind: SpanKind::Internal,
+        name: Cow::Borrowed(name),
+        start_time: SystemTime::now(),
+        end_time: SystemTime::now(),
+        attributes: Vec::new(),
+        dropped_attributes_count: 0,
+        events: SpanEvents::default(),
+        links: SpanLinks::default(),
+        status: SpanStatus::Unset,
+        resource: Cow::Owned(Resource::empty()),
+        instrumentation_lib: InstrumentationLibrary::default(),
+    }
+}
+
+/// Export a span through `builder`, returning the export result.
+async fn export(builder: Builder) -> ExportResult {
+    let (mut exporter, future): (StackDriverExporter, _) =
+        builder.build(TestAuthorizer).await.unwrap();
+    tokio::spawn(future);
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        exporter.export(vec![span("test-span")]),
+    )
+    .await
+    .unwrap()
+}
+
+/// Export a span through `builder` and return the request the service received.
+async fn export_span(
+    builder: Builder,
+    received: &mut mpsc::UnboundedReceiver<BatchWriteSpansRequest>,
+) -> BatchWriteSpansRequest {
+    export(builder).await.unwrap();
+    received.try_recv().unwrap()
+}
+
+#[tokio::test]
+async fn export_to_insecure_endpoint() {
+    let (addr, mut received) = serve().await;
+
+    let request = export_span(
+        StackDriverExporter::builder()
+            .trace_endpoint(format!("http://{addr}"))
+            .insecure(true),
+        &mut received,
+    )
+    .await;
+
+    assert_eq!(request.name, "projects/test-project");
+    assert_eq!(request.spans.len(), 1);
+    assert!(request.spans[0]
+        .name
+        .starts_with("projects/test-project/traces/"));
+    assert_eq!(
+        request.spans[0]
+            .display_name
+            .as_ref()
+            .map(|name| name.value.as_str()),
+        Some("test-span")
+    );
+}
+
+#[tokio::test]
+async fn export_through_custom_channel() {
+    let (addr, mut received) = serve().await;
+    let channel = Channel::from_shared(format!("http://{addr}"))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let request = export_span(
+        StackDriverExporter::builder()
+            // the channel takes precedence over the endpoint
+            .trace_endpoint("https://unreachable.invalid:443")
+            .trace_channel(channel),
+        &mut received,
+    )
+    .await;
+
+    assert_eq!(request.spans.len(), 1);
+}
+
+#[tokio::test]
+async fn retry_retryable_errors() {
+    let (addr, mut received) = serve_failing(&[Code::Unavailable, Code::ResourceExhausted]).await;
+
+    export(
+        StackDriverExporter::builder()
+            .trace_endpoint(format!("http://{addr}"))
+            .insecure(true)
+            .initial_backoff(Duration::from_millis(1)),
+    )
+    .await
+    .unwrap();
+
+    // two failed attempts, then the successful one
+    for _ in 0..3 {
+        assert_eq!(received.try_recv().unwrap().spans.len(), 1);
+    }
+    assert!(received.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn return_export_errors() {
+    let (addr, mut received) =
+        serve_failing(&[Code::PermissionDenied, Code::Unavailable, Code::Unavailable]).await;
+
+    // not retryable
+    let result = export(
+        StackDriverExporter::builder()
+            .trace_endpoint(format!("http://{addr}"))
+            .insecure(true),
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(received.try_recv().is_ok());
+    assert!(received.try_recv().is_err());
+
+    // out of retries
+    let result = export(
+        StackDriverExporter::builder()
+            .trace_endpoint(format!("http://{addr}"))
+            .insecure(true)
+            .max_retries(1)
+            .initial_backoff(Duration::from_millis(1)),
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(received.try_recv().is_ok());
+    assert!(received.try_recv().is_ok());
+    assert!(received.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn drop_batches_when_the_queue_is_full() {
+    let (addr, mut received) = serve().await;
+    let (mut exporter, future): (StackDriverExporter, _) = StackDriverExporter::builder()
+        .trace_endpoint(format!("http://{addr}"))
+        .insecure(true)
+        .queue_size(1)
+        .build(TestAuthorizer)
+        .await
+        .unwrap();
+
+    // the driver isn't running yet, so the first batch fills the queue
+    let first = exporter.export(vec![span("first")]);
+    let second = exporter.export(vec![span("second")]);
+    assert!(matches!(
+        second.await,
+        Err(opentelemetry::trace::TraceError::ExportFailed(_))
+    ));
+    assert_eq!(exporter.pending_count(), 1);
+
+    tokio::spawn(future);
+    first.await.unwrap();
+    assert_eq!(
+        received.try_recv().unwrap().spans[0].span_id,
+        "0000000000000001"
+    );
+    assert!(received.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn wait_for_room_in_the_queue() {
+    let (addr, mut received) = serve().await;
+    let (mut exporter, future): (StackDriverExporter, _) = StackDriverExporter::builder()
+        .trace_endpoint(format!("http://{addr}"))
+        .insecure(true)
+        .queue_size(1)
+        .drop_policy(DropPolicy::Wait)
+        .build(TestAuthorizer)
+        .await
+        .unwrap();
+
+    let first = exporter.export(vec![span("first")]);
+    let mut second = exporter.export(vec![span("second")]);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), &mut second)
+            .await
+            .is_err(),
+        "the second batch should wait for the first one to be sent"
+    );
+
+    tokio::spawn(future);
+    first.await.unwrap();
+    second.await.unwrap();
+    assert!(received.try_recv().is_ok());
+    assert!(received.try_recv().is_ok());
+}